@@ -213,6 +213,42 @@ pub struct LanguageServerConfiguration {
     #[serde(default = "default_timeout")]
     pub timeout: u64,
     pub language_id: Option<String>,
+    /// Position encodings offered to the server, in order of preference.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub offset_encodings: Vec<String>,
+    /// Withhold the `initialized` notification until the server's first
+    /// `workspace/configuration` request was answered.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub initialized_after_configuration: bool,
+    /// How to talk to the server, over the standard streams of `command` by default.
+    #[serde(default, skip_serializing_if = "LanguageServerTransport::is_stdio")]
+    pub transport: LanguageServerTransport,
+    /// The most completion items of a response that are shown, all of them if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_items: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LanguageServerTransport {
+    /// Spawn `command` and talk over its stdin and stdout.
+    #[default]
+    Stdio,
+    /// Connect to a server that is already listening on `host:port`. `command` isn't run.
+    /// With `reconnect` the connection is re-established when it breaks, but not when the
+    /// server closes it.
+    Tcp {
+        host: String,
+        port: u16,
+        #[serde(default)]
+        reconnect: bool,
+    },
+}
+
+impl LanguageServerTransport {
+    fn is_stdio(&self) -> bool {
+        *self == Self::Stdio
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,43 +1,103 @@
 use crate::{
+    file_operations::FileOperationsInterest,
+    file_watcher::FileWatchers,
     jsonrpc,
-    transport::{Payload, Transport},
-    Call, Error, OffsetEncoding, Result,
+    notebook::{self, NotebookDocumentSyncOptions},
+    protocol_version::{ProtocolFeature, ProtocolVersion},
+    selection_range::SelectionRangeCache,
+    transport::{Payload, StartedTransport, Transport},
+    util, Call, Error, OffsetEncoding, Result, ServerErrorCode,
 };
 
 use helix_core::{find_root, ChangeSet, Rope};
 use helix_loader::{self, VERSION_AND_GIT_HASH};
-use lsp::PositionEncodingKind;
 use lsp_types as lsp;
 use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::{
     atomic::{AtomicU64, Ordering},
     Arc,
 };
+use std::time::{Duration, Instant};
 use tokio::{
-    io::{BufReader, BufWriter},
+    io::{AsyncBufRead, AsyncWrite, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
     process::{Child, Command},
     sync::{
         mpsc::{channel, UnboundedReceiver, UnboundedSender},
-        Notify, OnceCell,
+        oneshot, watch, Mutex, Notify, OnceCell,
     },
 };
 
+/// The halves of a TCP stream to a server.
+type TcpConnection = (BufReader<OwnedReadHalf>, BufWriter<OwnedWriteHalf>);
+
+/// A server reachable over TCP that wasn't connected to yet. The transport of the client
+/// waits for the stream on the other end of `connection`.
+#[derive(Debug)]
+struct PendingTcpConnection {
+    host: String,
+    port: u16,
+    connection: oneshot::Sender<TcpConnection>,
+}
+
+/// A connection to a language server.
+///
+/// Requests fail with [`Error::NotInitialized`] while the server is still initializing.
+/// [`Client::initialized`] resolves once it is ready, and
+/// [`Client::request_after_initialized`] sends a request once it is.
 #[derive(Debug)]
 pub struct Client {
     id: usize,
-    _process: Child,
+    /// The server process, `None` for servers that aren't a child process.
+    process: Arc<Mutex<Option<Child>>>,
     server_tx: UnboundedSender<Payload>,
-    request_counter: AtomicU64,
+    request_counter: Arc<AtomicU64>,
     pub(crate) capabilities: OnceCell<lsp::ServerCapabilities>,
+    /// Encodings offered to the server during initialization, in order of preference.
+    offset_encodings: Vec<OffsetEncoding>,
+    /// The encoding negotiated with the server, set alongside `capabilities`.
+    pub(crate) offset_encoding: OnceCell<OffsetEncoding>,
+    /// The newest protocol version the server likely supports, set alongside `capabilities`.
+    protocol_version: OnceCell<ProtocolVersion>,
+    /// The name and version the server reported in its `initialize` response.
+    server_info: OnceCell<lsp::ServerInfo>,
+    /// How the server syncs notebooks, set during initialization if it does.
+    notebook_document_sync: OnceCell<NotebookDocumentSyncOptions>,
+    /// Why initializing the server failed. The server is useless afterwards.
+    initialize_error: OnceCell<String>,
+    /// Whether initialization finished, successfully or not.
+    initialize_done: watch::Sender<bool>,
+    file_operation_interest: OnceCell<FileOperationsInterest>,
+    file_watchers: std::sync::Mutex<FileWatchers>,
+    /// Capabilities registered dynamically through `client/registerCapability`, keyed by id.
+    registrations: std::sync::Mutex<HashMap<String, lsp::Registration>>,
+    selection_ranges: Arc<std::sync::Mutex<SelectionRangeCache>>,
+    /// The versions of the open documents as last sent to the server.
+    synced_versions: std::sync::Mutex<HashMap<lsp::Url, i32>>,
+    /// Signals the background initialization task to give up.
+    cancel_initialize: Notify,
+    /// The server to connect to over TCP, until the connection is established.
+    tcp_connection: std::sync::Mutex<Option<PendingTcpConnection>>,
+    /// Whether `initialized` is withheld until the first `workspace/configuration` request of
+    /// the server was answered.
+    initialized_after_configuration: bool,
+    transport: Arc<Transport>,
     config: Option<Value>,
     root_path: std::path::PathBuf,
     root_uri: Option<lsp::Url>,
-    workspace_folders: Vec<lsp::WorkspaceFolder>,
+    /// The workspace folders, updated as folders are added and removed at runtime.
+    workspace_folders: std::sync::Mutex<Vec<lsp::WorkspaceFolder>>,
     req_timeout: u64,
+    /// Timeouts of methods that take longer or shorter than `req_timeout`, keyed by method.
+    method_timeouts: HashMap<&'static str, Duration>,
 }
 
 impl Client {
@@ -51,12 +111,15 @@ pub fn start(
         root_markers: &[String],
         id: usize,
         req_timeout: u64,
-        doc_path: Option<&std::path::PathBuf>,
+        method_timeouts: HashMap<&'static str, Duration>,
+        doc_paths: &[&Path],
+        offset_encodings: Vec<OffsetEncoding>,
+        initialized_after_configuration: bool,
     ) -> Result<(Self, UnboundedReceiver<(usize, Call)>, Arc<Notify>)> {
         // Resolve path to the binary
-        let cmd = which::which(cmd).map_err(|err| anyhow::anyhow!(err))?;
+        let path = which::which(cmd).map_err(|err| anyhow::anyhow!(err))?;
 
-        let process = Command::new(cmd)
+        let process = Command::new(path)
             .envs(server_environment)
             .args(args)
             .stdin(Stdio::piped())
@@ -73,46 +136,171 @@ pub fn start(
         let reader = BufReader::new(process.stdout.take().expect("Failed to open stdout"));
         let stderr = BufReader::new(process.stderr.take().expect("Failed to open stderr"));
 
-        let (server_rx, server_tx, initialize_notify) =
-            Transport::start(reader, writer, stderr, id);
+        Ok(Self::connect(
+            Some(process),
+            reader,
+            writer,
+            stderr,
+            cmd,
+            config,
+            root_markers,
+            id,
+            req_timeout,
+            method_timeouts,
+            doc_paths,
+            offset_encodings,
+            initialized_after_configuration,
+        ))
+    }
 
-        let root_path = find_root(
-            doc_path.and_then(|x| x.parent().and_then(|x| x.to_str())),
+    /// Creates a client for a server that is listening on `host:port`. The messages are framed
+    /// the same way as over stdio.
+    ///
+    /// The connection is only established by [`Client::connect_tcp`], at the start of the
+    /// initialization, so that resolving and connecting doesn't block the caller.
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn start_tcp(
+        name: &str,
+        host: &str,
+        port: u16,
+        config: Option<Value>,
+        root_markers: &[String],
+        id: usize,
+        req_timeout: u64,
+        method_timeouts: HashMap<&'static str, Duration>,
+        doc_paths: &[&Path],
+        offset_encodings: Vec<OffsetEncoding>,
+        initialized_after_configuration: bool,
+    ) -> (Self, UnboundedReceiver<(usize, Call)>, Arc<Notify>) {
+        let (connection_tx, connection) = oneshot::channel();
+        let (mut client, incoming, initialize_notify) = Self::connect_deferred(
+            None,
+            connection,
+            tokio::io::empty(),
+            name,
+            config,
             root_markers,
+            id,
+            req_timeout,
+            method_timeouts,
+            doc_paths,
+            offset_encodings,
+            initialized_after_configuration,
         );
+        *client.tcp_connection.get_mut().unwrap() = Some(PendingTcpConnection {
+            host: host.to_string(),
+            port,
+            connection: connection_tx,
+        });
 
-        let root_uri = lsp::Url::from_file_path(root_path.clone()).ok();
+        (client, incoming, initialize_notify)
+    }
 
-        // TODO: support multiple workspace folders
-        let workspace_folders = root_uri
-            .clone()
-            .map(|root| {
-                vec![lsp::WorkspaceFolder {
-                    name: root
-                        .path_segments()
-                        .and_then(|segments| segments.last())
-                        .map(|basename| basename.to_string())
-                        .unwrap_or_default(),
-                    uri: root,
-                }]
-            })
-            .unwrap_or_default();
+    /// Creates a client talking to the server through `reader`, `writer` and `stderr`.
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn connect(
+        process: Option<Child>,
+        reader: impl AsyncBufRead + Unpin + Send + 'static,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+        stderr: impl AsyncBufRead + Unpin + Send + 'static,
+        name: &str,
+        config: Option<Value>,
+        root_markers: &[String],
+        id: usize,
+        req_timeout: u64,
+        method_timeouts: HashMap<&'static str, Duration>,
+        doc_paths: &[&Path],
+        offset_encodings: Vec<OffsetEncoding>,
+        initialized_after_configuration: bool,
+    ) -> (Self, UnboundedReceiver<(usize, Call)>, Arc<Notify>) {
+        let (connection_tx, connection) = oneshot::channel();
+        let _ = connection_tx.send((reader, writer));
+        Self::connect_deferred(
+            process,
+            connection,
+            stderr,
+            name,
+            config,
+            root_markers,
+            id,
+            req_timeout,
+            method_timeouts,
+            doc_paths,
+            offset_encodings,
+            initialized_after_configuration,
+        )
+    }
+
+    /// Like [`Client::connect`], but the reader and writer are only sent through `connection`
+    /// once the stream to the server is established.
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    fn connect_deferred<R, W>(
+        process: Option<Child>,
+        connection: oneshot::Receiver<(R, W)>,
+        stderr: impl AsyncBufRead + Unpin + Send + 'static,
+        name: &str,
+        config: Option<Value>,
+        root_markers: &[String],
+        id: usize,
+        req_timeout: u64,
+        method_timeouts: HashMap<&'static str, Duration>,
+        doc_paths: &[&Path],
+        offset_encodings: Vec<OffsetEncoding>,
+        initialized_after_configuration: bool,
+    ) -> (Self, UnboundedReceiver<(usize, Call)>, Arc<Notify>)
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let process = Arc::new(Mutex::new(process));
+        let StartedTransport {
+            server_rx,
+            server_tx,
+            initialize_notify,
+            transport,
+        } = Transport::start(connection, stderr, id, name.to_string(), process.clone());
+
+        let roots = workspace_roots(root_markers, doc_paths);
+
+        let root_path = roots[0].clone();
+        let root_uri = lsp::Url::from_file_path(root_path.clone()).ok();
+        let workspace_folders = roots.into_iter().filter_map(workspace_folder).collect();
 
         let client = Self {
             id,
-            _process: process,
+            process,
             server_tx,
-            request_counter: AtomicU64::new(0),
+            request_counter: Arc::new(AtomicU64::new(0)),
             capabilities: OnceCell::new(),
+            offset_encodings,
+            offset_encoding: OnceCell::new(),
+            protocol_version: OnceCell::new(),
+            server_info: OnceCell::new(),
+            notebook_document_sync: OnceCell::new(),
+            initialize_error: OnceCell::new(),
+            initialize_done: watch::channel(false).0,
+            file_operation_interest: OnceCell::new(),
+            file_watchers: std::sync::Mutex::new(FileWatchers::default()),
+            registrations: std::sync::Mutex::new(HashMap::new()),
+            selection_ranges: Arc::new(std::sync::Mutex::new(SelectionRangeCache::default())),
+            synced_versions: std::sync::Mutex::new(HashMap::new()),
+            cancel_initialize: Notify::new(),
+            tcp_connection: std::sync::Mutex::new(None),
+            initialized_after_configuration,
+            transport,
             config,
             req_timeout,
+            method_timeouts,
 
             root_path,
             root_uri,
-            workspace_folders,
+            workspace_folders: std::sync::Mutex::new(workspace_folders),
         };
 
-        Ok((client, server_rx, initialize_notify))
+        (client, server_rx, initialize_notify)
     }
 
     pub fn id(&self) -> usize {
@@ -139,34 +327,268 @@ pub fn is_initialized(&self) -> bool {
         self.capabilities.get().is_some()
     }
 
+    /// The number of requests sent to the server that weren't answered yet.
+    pub fn pending_requests(&self) -> usize {
+        self.transport.pending_requests()
+    }
+
+    /// Whether the connection to the server broke or couldn't be established, as opposed to
+    /// the server closing it. Only servers reached over TCP lose their connection.
+    pub fn connection_lost(&self) -> bool {
+        self.transport.connection_lost()
+    }
+
+    /// When the server last answered a request. A server with pending requests that
+    /// didn't answer in a long time is likely hung.
+    pub fn last_response_at(&self) -> Option<Instant> {
+        self.transport.last_response_at()
+    }
+
+    /// The last lines the server wrote to stderr, e.g. the panic message of a server that
+    /// crashed.
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.transport.stderr_tail()
+    }
+
     pub fn capabilities(&self) -> &lsp::ServerCapabilities {
         self.capabilities
             .get()
             .expect("language server not yet initialized!")
     }
 
+    /// The offset encoding negotiated with this language server.
+    ///
+    /// Positions exchanged with this server must always be converted with this
+    /// encoding: other servers attached to the same document may use a different one.
     pub fn offset_encoding(&self) -> OffsetEncoding {
-        self.capabilities()
-            .position_encoding
-            .as_ref()
-            .and_then(|encoding| match encoding.as_str() {
-                "utf-8" => Some(OffsetEncoding::Utf8),
-                "utf-16" => Some(OffsetEncoding::Utf16),
-                "utf-32" => Some(OffsetEncoding::Utf32),
-                encoding => {
-                    log::error!("Server provided invalid position encording {encoding}, defaulting to utf-16");
-                    None
-                },
-            })
-            .unwrap_or_default()
+        *self
+            .offset_encoding
+            .get()
+            .expect("language server not yet initialized!")
+    }
+
+    /// Negotiates the offset encoding from the capabilities returned by `initialize`.
+    pub(crate) fn negotiate_offset_encoding(&self, capabilities: &lsp::ServerCapabilities) {
+        let encoding = OffsetEncoding::negotiate(
+            capabilities.position_encoding.as_ref(),
+            &self.offset_encodings,
+        );
+        // The encoding can only be negotiated once per server, subsequent calls are no-ops.
+        let _ = self.offset_encoding.set(encoding);
+    }
+
+    pub(crate) fn detect_protocol_version(&self, response: &lsp::InitializeResult) {
+        let _ = self.protocol_version.set(ProtocolVersion::detect(response));
+    }
+
+    pub(crate) fn store_server_info(&self, response: &lsp::InitializeResult) {
+        if let Some(server_info) = &response.server_info {
+            let _ = self.server_info.set(server_info.clone());
+        }
+    }
+
+    /// Records that initializing the server failed with `error`.
+    pub(crate) fn set_initialize_error(&self, error: String) {
+        let _ = self.initialize_error.set(error);
+    }
+
+    /// Why initializing the server failed, if it did. Requests to a server that failed to
+    /// initialize never get a response.
+    pub fn initialize_error(&self) -> Option<&str> {
+        self.initialize_error.get().map(String::as_str)
+    }
+
+    /// Marks initialization as finished, whether it succeeded, failed or was cancelled.
+    pub(crate) fn finish_initialize(&self) {
+        self.initialize_done.send_replace(true);
+    }
+
+    /// Resolves once the server is initialized and ready for requests, or with an error if
+    /// initializing it failed or was cancelled.
+    pub async fn initialized(&self) -> Result<()> {
+        let mut done = self.initialize_done.subscribe();
+        while !*done.borrow_and_update() {
+            // the sender lives as long as the client
+            if done.changed().await.is_err() {
+                break;
+            }
+        }
+
+        if let Some(err) = self.initialize_error() {
+            return Err(Error::Other(anyhow::anyhow!(
+                "language server failed to initialize: {}",
+                err
+            )));
+        }
+        if !self.is_initialized() {
+            // initialization was cancelled by shutting down the server
+            return Err(Error::StreamClosed);
+        }
+        Ok(())
+    }
+
+    /// The name and version of the server, if it reported them during initialization.
+    pub fn server_info(&self) -> Option<&lsp::ServerInfo> {
+        self.server_info.get()
+    }
+
+    /// Whether the protocol version the server likely supports includes `feature`. This is
+    /// a best-effort guess from the capabilities of the server, which remain the real gate
+    /// for each request. It only prevents sending requests older servers don't know.
+    pub fn min_protocol_supports(&self, feature: ProtocolFeature) -> bool {
+        self.protocol_version
+            .get()
+            .map_or(false, |version| version.supports(feature))
+    }
+
+    /// The file operations the server registered interest in, compiled on first use.
+    fn file_operations_interest(&self) -> &FileOperationsInterest {
+        if let Some(interest) = self.file_operation_interest.get() {
+            return interest;
+        }
+        let _ = self
+            .file_operation_interest
+            .set(FileOperationsInterest::new(self.capabilities()));
+        self.file_operation_interest.get().unwrap()
+    }
+
+    /// Stores a capability registered dynamically by the server.
+    pub fn register_capability(&self, registration: lsp::Registration) {
+        use lsp::notification::Notification as _;
+
+        if registration.method == lsp::notification::DidChangeWatchedFiles::METHOD {
+            let options = registration.register_options.clone().and_then(|options| {
+                serde_json::from_value::<lsp::DidChangeWatchedFilesRegistrationOptions>(options)
+                    .map_err(|err| log::error!("invalid file watcher registration: {}", err))
+                    .ok()
+            });
+            if let Some(options) = options {
+                self.register_file_watchers(registration.id.clone(), &options.watchers);
+            }
+        }
+
+        self.registrations
+            .lock()
+            .unwrap()
+            .insert(registration.id.clone(), registration);
+    }
+
+    /// Removes a capability previously registered with [`Client::register_capability`].
+    pub fn unregister_capability(&self, unregistration: &lsp::Unregistration) {
+        self.unregister_file_watchers(&unregistration.id);
+        self.registrations
+            .lock()
+            .unwrap()
+            .remove(&unregistration.id);
+    }
+
+    /// Whether the server dynamically registered a capability for `method`.
+    pub fn has_registration(&self, method: &str) -> bool {
+        self.registrations
+            .lock()
+            .unwrap()
+            .values()
+            .any(|registration| registration.method == method)
+    }
+
+    /// Whether the server handles requests for `method`, according to the capabilities it
+    /// reported or registered later. Always `false` before the server is initialized, and
+    /// for methods that aren't tied to a capability unless they were registered.
+    pub fn supports_method(&self, method: &str) -> bool {
+        use lsp::OneOf;
+
+        let capabilities = match self.capabilities.get() {
+            Some(capabilities) => capabilities,
+            None => return false,
+        };
+
+        fn one_of<T>(capability: &Option<OneOf<bool, T>>) -> bool {
+            !matches!(capability, None | Some(OneOf::Left(false)))
+        }
+
+        let supported = match method {
+            "textDocument/hover" => !matches!(
+                capabilities.hover_provider,
+                None | Some(lsp::HoverProviderCapability::Simple(false))
+            ),
+            "textDocument/completion" => capabilities.completion_provider.is_some(),
+            "textDocument/signatureHelp" => capabilities.signature_help_provider.is_some(),
+            "textDocument/definition" => one_of(&capabilities.definition_provider),
+            "textDocument/declaration" => !matches!(
+                capabilities.declaration_provider,
+                None | Some(lsp::DeclarationCapability::Simple(false))
+            ),
+            "textDocument/typeDefinition" => !matches!(
+                capabilities.type_definition_provider,
+                None | Some(lsp::TypeDefinitionProviderCapability::Simple(false))
+            ),
+            "textDocument/implementation" => !matches!(
+                capabilities.implementation_provider,
+                None | Some(lsp::ImplementationProviderCapability::Simple(false))
+            ),
+            "textDocument/references" => one_of(&capabilities.references_provider),
+            "textDocument/documentHighlight" => one_of(&capabilities.document_highlight_provider),
+            "textDocument/documentSymbol" => one_of(&capabilities.document_symbol_provider),
+            "workspace/symbol" => one_of(&capabilities.workspace_symbol_provider),
+            "textDocument/codeAction" => !matches!(
+                capabilities.code_action_provider,
+                None | Some(lsp::CodeActionProviderCapability::Simple(false))
+            ),
+            "textDocument/codeLens" => capabilities.code_lens_provider.is_some(),
+            "textDocument/formatting" => one_of(&capabilities.document_formatting_provider),
+            "textDocument/rangeFormatting" => {
+                one_of(&capabilities.document_range_formatting_provider)
+            }
+            "textDocument/onTypeFormatting" => {
+                capabilities.document_on_type_formatting_provider.is_some()
+            }
+            "textDocument/rename" => one_of(&capabilities.rename_provider),
+            "textDocument/prepareRename" => matches!(
+                capabilities.rename_provider,
+                Some(OneOf::Right(lsp::RenameOptions {
+                    prepare_provider: Some(true),
+                    ..
+                }))
+            ),
+            "textDocument/documentLink" => capabilities.document_link_provider.is_some(),
+            "textDocument/selectionRange" => !matches!(
+                capabilities.selection_range_provider,
+                None | Some(lsp::SelectionRangeProviderCapability::Simple(false))
+            ),
+            "textDocument/prepareCallHierarchy" => !matches!(
+                capabilities.call_hierarchy_provider,
+                None | Some(lsp::CallHierarchyServerCapability::Simple(false))
+            ),
+            "textDocument/inlayHint" => one_of(&capabilities.inlay_hint_provider),
+            "workspace/executeCommand" => capabilities.execute_command_provider.is_some(),
+            _ => false,
+        };
+
+        supported || self.has_registration(method)
+    }
+
+    /// The timeout of requests for `method`, which falls back to the timeout of the
+    /// language server if the method has no timeout of its own.
+    pub fn request_timeout(&self, method: &str) -> Duration {
+        self.method_timeouts
+            .get(method)
+            .copied()
+            .unwrap_or_else(|| Duration::from_secs(self.req_timeout))
     }
 
     pub fn config(&self) -> Option<&Value> {
         self.config.as_ref()
     }
 
-    pub fn workspace_folders(&self) -> &[lsp::WorkspaceFolder] {
-        &self.workspace_folders
+    pub fn workspace_folders(&self) -> Vec<lsp::WorkspaceFolder> {
+        self.workspace_folders.lock().unwrap().clone()
+    }
+
+    /// The reply to a `workspace/workspaceFolders` request of the server, `None` (`null`)
+    /// if no folder is open.
+    pub fn workspace_folders_response(&self) -> Option<Vec<lsp::WorkspaceFolder>> {
+        let folders = self.workspace_folders();
+        (!folders.is_empty()).then_some(folders)
     }
 
     /// Execute a RPC request on the language server.
@@ -181,6 +603,20 @@ async fn request<R: lsp::request::Request>(&self, params: R::Params) -> Result<R
         Ok(response)
     }
 
+    /// Like [`Client::request`], but waits for the server to finish initializing instead of
+    /// failing with [`Error::NotInitialized`] while it is still starting.
+    pub async fn request_after_initialized<R: lsp::request::Request>(
+        &self,
+        params: R::Params,
+    ) -> Result<R::Result>
+    where
+        R::Params: serde::Serialize,
+        R::Result: core::fmt::Debug,
+    {
+        self.initialized().await?;
+        self.request::<R>(params).await
+    }
+
     /// Execute a RPC request on the language server.
     fn call<R: lsp::request::Request>(
         &self,
@@ -189,40 +625,149 @@ fn call<R: lsp::request::Request>(
     where
         R::Params: serde::Serialize,
     {
-        let server_tx = self.server_tx.clone();
-        let id = self.next_request_id();
-        let timeout_secs = self.req_timeout;
+        self.call_with_timeout::<R>(params, self.request_timeout(R::METHOD))
+    }
+
+    /// Like [`Client::call`] but assembles the partial results the server streams for
+    /// `partial_result_token` and the final response into a single list. The token must
+    /// also be set in the `partialResultToken` of `params`.
+    fn call_with_partial_results<R: lsp::request::Request>(
+        &self,
+        params: R::Params,
+        partial_result_token: Option<lsp::ProgressToken>,
+    ) -> impl Future<Output = Result<Value>>
+    where
+        R::Params: serde::Serialize,
+    {
+        let transport = self.transport.clone();
+        let partials = partial_result_token
+            .clone()
+            .map(|token| transport.register_partial_results(token));
+        let request = self.call::<R>(params);
 
         async move {
-            use std::time::Duration;
-            use tokio::time::timeout;
+            let response = request.await;
+            if let Some(token) = &partial_result_token {
+                transport.unregister_partial_results(token);
+            }
+            match partials {
+                Some(partials) => Ok(Self::assemble_partial_results(partials, response?)),
+                None => response,
+            }
+        }
+    }
 
-            let params = serde_json::to_value(params)?;
+    /// Prepends the partial results received so far to the final `response`. Servers that
+    /// stream partial results leave them out of the final response.
+    pub(crate) fn assemble_partial_results(
+        mut partials: UnboundedReceiver<Value>,
+        response: Value,
+    ) -> Value {
+        let mut results = Vec::new();
+        while let Ok(partial) = partials.try_recv() {
+            if let Value::Array(values) = partial {
+                results.extend(values);
+            }
+        }
+        if results.is_empty() {
+            return response;
+        }
+        if let Value::Array(values) = response {
+            results.extend(values);
+        }
+        Value::Array(results)
+    }
 
-            let request = jsonrpc::MethodCall {
-                jsonrpc: Some(jsonrpc::Version::V2),
-                id: id.clone(),
-                method: R::METHOD.to_string(),
-                params: Self::value_into_params(params),
-            };
+    /// Execute a RPC request on the language server with a deadline. If the server
+    /// doesn't respond within `timeout` the request is cancelled on the server so it
+    /// stops working on it.
+    ///
+    /// Requests the server drops with `ContentModified` because the document changed while
+    /// it was working on them are retried once.
+    fn call_with_timeout<R: lsp::request::Request>(
+        &self,
+        params: R::Params,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Value>>
+    where
+        R::Params: serde::Serialize,
+    {
+        use lsp::request::{Initialize, Request as _, Shutdown};
 
-            let (tx, mut rx) = channel::<Result<Value>>(1);
+        // Requests are held back by the transport until the server is initialized, fail
+        // right away instead of waiting for the timeout.
+        let initialized = self.is_initialized()
+            || R::METHOD == Initialize::METHOD
+            || R::METHOD == Shutdown::METHOD;
+        let server_tx = self.server_tx.clone();
+        let request_counter = self.request_counter.clone();
+        let id = self.next_request_id();
+        let params = serde_json::to_value(params);
 
-            server_tx
-                .send(Payload::Request {
-                    chan: tx,
-                    value: request,
-                })
-                .map_err(|e| Error::Other(e.into()))?;
+        async move {
+            if !initialized {
+                return Err(Error::NotInitialized);
+            }
+            let params = params?;
+            match Self::send_request::<R>(server_tx.clone(), id, &params, timeout).await {
+                Err(err) if err.server_error_code() == Some(ServerErrorCode::ContentModified) => {
+                    // Changes are queued as soon as they are made, so they reach the server
+                    // before the retry and it answers for the current contents.
+                    let id = jsonrpc::Id::Num(request_counter.fetch_add(1, Ordering::Relaxed));
+                    Self::send_request::<R>(server_tx, id, params, timeout).await
+                }
+                response => response,
+            }
+        }
+    }
 
-            // TODO: delay other calls until initialize success
-            timeout(Duration::from_secs(timeout_secs), rx.recv())
-                .await
-                .map_err(|_| Error::Timeout(id))? // return Timeout
-                .ok_or(Error::StreamClosed)?
+    async fn send_request<R: lsp::request::Request>(
+        server_tx: UnboundedSender<Payload>,
+        id: jsonrpc::Id,
+        params: impl serde::Serialize,
+        timeout: Duration,
+    ) -> Result<Value> {
+        let params = serde_json::to_value(params)?;
+
+        let request = jsonrpc::MethodCall {
+            jsonrpc: Some(jsonrpc::Version::V2),
+            id: id.clone(),
+            method: R::METHOD.to_string(),
+            params: Self::value_into_params(params),
+        };
+
+        let (tx, mut rx) = channel::<Result<Value>>(1);
+
+        server_tx
+            .send(Payload::Request {
+                chan: tx,
+                value: request,
+            })
+            .map_err(|e| Error::Other(e.into()))?;
+
+        // TODO: delay other calls until initialize success
+        match tokio::time::timeout(timeout, rx.recv()).await {
+            Ok(response) => response.ok_or(Error::StreamClosed)?,
+            Err(_) => {
+                // Let the server know that nobody is waiting for the response anymore.
+                if let Some(params) = Self::cancel_params(&id) {
+                    let notification = Self::notification::<lsp::notification::Cancel>(params)?;
+                    let _ = server_tx.send(Payload::Notification(notification));
+                }
+                Err(Error::Timeout(id, R::METHOD))
+            }
         }
     }
 
+    fn cancel_params(id: &jsonrpc::Id) -> Option<lsp::CancelParams> {
+        let id = match id {
+            jsonrpc::Id::Num(id) => lsp::NumberOrString::Number(i32::try_from(*id).ok()?),
+            jsonrpc::Id::Str(id) => lsp::NumberOrString::String(id.clone()),
+            jsonrpc::Id::Null => return None,
+        };
+        Some(lsp::CancelParams { id })
+    }
+
     /// Send a RPC notification to the language server.
     pub fn notify<R: lsp::notification::Notification>(
         &self,
@@ -234,13 +779,7 @@ pub fn notify<R: lsp::notification::Notification>(
         let server_tx = self.server_tx.clone();
 
         async move {
-            let params = serde_json::to_value(params)?;
-
-            let notification = jsonrpc::Notification {
-                jsonrpc: Some(jsonrpc::Version::V2),
-                method: R::METHOD.to_string(),
-                params: Self::value_into_params(params),
-            };
+            let notification = Self::notification::<R>(params)?;
 
             server_tx
                 .send(Payload::Notification(notification))
@@ -250,6 +789,22 @@ pub fn notify<R: lsp::notification::Notification>(
         }
     }
 
+    /// Builds the JSON-RPC message for a notification of type `R`.
+    fn notification<R: lsp::notification::Notification>(
+        params: R::Params,
+    ) -> Result<jsonrpc::Notification>
+    where
+        R::Params: serde::Serialize,
+    {
+        let params = serde_json::to_value(params)?;
+
+        Ok(jsonrpc::Notification {
+            jsonrpc: Some(jsonrpc::Version::V2),
+            method: R::METHOD.to_string(),
+            params: Self::value_into_params(params),
+        })
+    }
+
     /// Reply to a language server RPC call.
     pub fn reply(
         &self,
@@ -282,6 +837,16 @@ pub fn reply(
         }
     }
 
+    /// Replies to a `workspace/applyEdit` request of the server with the outcome of applying
+    /// the edit, which unblocks the server.
+    pub fn reply_apply_edit(
+        &self,
+        id: jsonrpc::Id,
+        response: lsp::ApplyWorkspaceEditResponse,
+    ) -> impl Future<Output = Result<()>> {
+        self.reply(id, Ok(serde_json::json!(response)))
+    }
+
     // -------------------------------------------------------------------------------------------
     // General messages
     // -------------------------------------------------------------------------------------------
@@ -294,7 +859,7 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
         #[allow(deprecated)]
         let params = lsp::InitializeParams {
             process_id: Some(std::process::id()),
-            workspace_folders: Some(self.workspace_folders.clone()),
+            workspace_folders: Some(self.workspace_folders()),
             // root_path is obsolete, but some clients like pyright still use it so we specify both.
             // clients will prefer _uri if possible
             root_path: self.root_path.to_str().map(|path| path.to_owned()),
@@ -308,6 +873,16 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
                     }),
                     workspace_folders: Some(true),
                     apply_edit: Some(true),
+                    workspace_edit: Some(lsp::WorkspaceEditClientCapabilities {
+                        document_changes: Some(true),
+                        resource_operations: Some(vec![
+                            lsp::ResourceOperationKind::Create,
+                            lsp::ResourceOperationKind::Rename,
+                            lsp::ResourceOperationKind::Delete,
+                        ]),
+                        failure_handling: Some(lsp::FailureHandlingKind::Abort),
+                        ..Default::default()
+                    }),
                     symbol: Some(lsp::WorkspaceSymbolClientCapabilities {
                         dynamic_registration: Some(false),
                         ..Default::default()
@@ -315,10 +890,27 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
                     execute_command: Some(lsp::DynamicRegistrationClientCapabilities {
                         dynamic_registration: Some(false),
                     }),
+                    file_operations: Some(lsp::WorkspaceFileOperationsClientCapabilities {
+                        dynamic_registration: Some(false),
+                        did_create: Some(true),
+                        will_create: Some(true),
+                        did_rename: Some(true),
+                        will_rename: Some(true),
+                        did_delete: Some(true),
+                        will_delete: Some(true),
+                    }),
                     ..Default::default()
                 }),
                 text_document: Some(lsp::TextDocumentClientCapabilities {
                     completion: Some(lsp::CompletionClientCapabilities {
+                        completion_list: Some(lsp::CompletionListCapability {
+                            item_defaults: Some(
+                                util::COMPLETION_ITEM_DEFAULTS
+                                    .iter()
+                                    .map(|&property| property.to_string())
+                                    .collect(),
+                            ),
+                        }),
                         completion_item: Some(lsp::CompletionItemCapability {
                             snippet_support: Some(true),
                             resolve_support: Some(lsp::CompletionItemCapabilityResolveSupport {
@@ -336,7 +928,7 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
                             ..Default::default()
                         }),
                         completion_item_kind: Some(lsp::CompletionItemKindCapability {
-                            ..Default::default()
+                            value_set: Some(util::COMPLETION_ITEM_KINDS.to_vec()),
                         }),
                         context_support: None, // additional context information Some(true)
                         ..Default::default()
@@ -355,6 +947,7 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
                             }),
                             active_parameter_support: Some(true),
                         }),
+                        context_support: Some(true),
                         ..Default::default()
                     }),
                     rename: Some(lsp::RenameClientCapabilities {
@@ -381,11 +974,34 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
                                 .collect(),
                             },
                         }),
+                        data_support: Some(true),
+                        disabled_support: Some(true),
+                        resolve_support: Some(lsp::CodeActionCapabilityResolveSupport {
+                            properties: vec!["edit".to_owned(), "command".to_owned()],
+                        }),
                         ..Default::default()
                     }),
+                    selection_range: Some(lsp::SelectionRangeClientCapabilities {
+                        dynamic_registration: Some(false),
+                    }),
+                    moniker: Some(lsp::MonikerClientCapabilities {
+                        dynamic_registration: Some(false),
+                    }),
+                    code_lens: Some(lsp::CodeLensClientCapabilities {
+                        dynamic_registration: Some(false),
+                    }),
                     publish_diagnostics: Some(lsp::PublishDiagnosticsClientCapabilities {
                         ..Default::default()
                     }),
+                    formatting: Some(lsp::DocumentFormattingClientCapabilities {
+                        dynamic_registration: Some(true),
+                    }),
+                    range_formatting: Some(lsp::DocumentRangeFormattingClientCapabilities {
+                        dynamic_registration: Some(true),
+                    }),
+                    on_type_formatting: Some(lsp::DocumentOnTypeFormattingClientCapabilities {
+                        dynamic_registration: Some(false),
+                    }),
                     ..Default::default()
                 }),
                 window: Some(lsp::WindowClientCapabilities {
@@ -393,11 +1009,12 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
                     ..Default::default()
                 }),
                 general: Some(lsp::GeneralClientCapabilities {
-                    position_encodings: Some(vec![
-                        PositionEncodingKind::UTF32,
-                        PositionEncodingKind::UTF8,
-                        PositionEncodingKind::UTF16,
-                    ]),
+                    position_encodings: Some(
+                        self.offset_encodings
+                            .iter()
+                            .map(|encoding| encoding.to_position_encoding_kind())
+                            .collect(),
+                    ),
                     ..Default::default()
                 }),
                 ..Default::default()
@@ -410,27 +1027,170 @@ pub(crate) async fn initialize(&self) -> Result<lsp::InitializeResult> {
             locale: None, // TODO
         };
 
-        self.request::<lsp::request::Initialize>(params).await
+        // lsp-types doesn't know about notebooks, their capabilities are added to the JSON
+        let mut params = serde_json::to_value(params)?;
+        notebook::advertise_capabilities(&mut params);
+        let response = self.call::<notebook::RawInitialize>(params).await?;
+        if let Some(options) = NotebookDocumentSyncOptions::from_initialize_result(&response) {
+            let _ = self.notebook_document_sync.set(options);
+        }
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Sends the `initialized` notification once the server is initialized.
+    ///
+    /// Servers with `initialized_after_configuration` set only get it after their first
+    /// `workspace/configuration` request was answered, or the request timeout passed.
+    pub(crate) async fn notify_initialized(&self) -> Result<()> {
+        if self.initialized_after_configuration {
+            let timeout = Duration::from_secs(self.req_timeout);
+            let answered = self.transport.configuration_answered.notified();
+            if tokio::time::timeout(timeout, answered).await.is_err() {
+                log::warn!(
+                    "language server {} didn't request its configuration within {:?}",
+                    self.id,
+                    timeout
+                );
+            }
+        }
+
+        self.notify::<lsp::notification::Initialized>(lsp::InitializedParams {})
+            .await
     }
 
-    pub async fn shutdown(&self) -> Result<()> {
+    async fn request_shutdown(&self) -> Result<()> {
         self.request::<lsp::request::Shutdown>(()).await
     }
 
+    /// Shuts down the language server gracefully, e.g. when quitting: sends `shutdown`, then
+    /// `exit`, and waits for the server process to exit. The server gets up to `timeout` to
+    /// answer the `shutdown` request and another `timeout` to exit before it is killed.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        if !self.is_initialized() {
+            return self.cancel_initialization().await;
+        }
+
+        match tokio::time::timeout(timeout, self.request_shutdown()).await {
+            Ok(Ok(())) => (),
+            Ok(Err(err)) => log::warn!(
+                "language server {} failed to shut down gracefully - {}",
+                self.id,
+                err
+            ),
+            Err(_) => log::warn!(
+                "language server {} didn't answer shutdown within {:?}",
+                self.id,
+                timeout
+            ),
+        }
+        // a server that didn't answer is still asked to exit before it is killed
+        self.exit().await?;
+
+        let mut process = self.process.lock().await;
+        let process = match process.as_mut() {
+            Some(process) => process,
+            None => return Ok(()),
+        };
+        if tokio::time::timeout(timeout, process.wait()).await.is_err() {
+            log::warn!(
+                "language server {} didn't exit within {:?}, killing it",
+                self.id,
+                timeout
+            );
+            process.kill().await?;
+        }
+        Ok(())
+    }
+
     pub fn exit(&self) -> impl Future<Output = Result<()>> {
         self.notify::<lsp::notification::Exit>(())
     }
 
+    /// Waits for the initialization of the language server to finish unless the
+    /// client is shut down first.
+    ///
+    /// Returns `None` if initialization was cancelled.
+    pub(crate) async fn initialize_or_cancel<T>(
+        &self,
+        initialize: impl Future<Output = T>,
+    ) -> Option<T> {
+        tokio::select! {
+            value = initialize => Some(value),
+            _ = self.cancel_initialize.notified() => None,
+        }
+    }
+
+    /// Connects to the server if it is reached over TCP and wasn't connected to yet. Gives up
+    /// after the request timeout.
+    pub(crate) async fn connect_tcp(&self) -> Result<()> {
+        let (host, port) = match &*self.tcp_connection.lock().unwrap() {
+            Some(PendingTcpConnection { host, port, .. }) => (host.clone(), *port),
+            None => return Ok(()),
+        };
+
+        let timeout = Duration::from_secs(self.req_timeout);
+        let stream =
+            match tokio::time::timeout(timeout, TcpStream::connect((host.as_str(), port))).await {
+                Ok(Ok(stream)) => stream,
+                // the server may just be unreachable for a moment
+                Ok(Err(err)) => {
+                    self.transport.set_connection_lost();
+                    return Err(
+                        anyhow::anyhow!("could not connect to {}:{}: {}", host, port, err).into(),
+                    );
+                }
+                Err(_) => {
+                    self.transport.set_connection_lost();
+                    return Err(anyhow::anyhow!("timed out connecting to {}:{}", host, port).into());
+                }
+            };
+
+        let (reader, writer) = stream.into_split();
+        if let Some(pending) = self.tcp_connection.lock().unwrap().take() {
+            // the transport is gone if the client was dropped in the meantime
+            let _ = pending
+                .connection
+                .send((BufReader::new(reader), BufWriter::new(writer)));
+        }
+        Ok(())
+    }
+
+    /// Aborts a pending initialization and kills the server process.
+    ///
+    /// The server only processes a `shutdown` request once it is initialized, which can
+    /// take a long time, so a server that is still initializing is killed instead.
+    async fn cancel_initialization(&self) -> Result<()> {
+        log::info!(
+            "language server {} is still initializing, killing it",
+            self.id
+        );
+        // `notify_one` stores a permit so this also works if the initialization task
+        // didn't start waiting yet.
+        self.cancel_initialize.notify_one();
+        // a server that wasn't connected to yet is reported as exited by the transport
+        self.tcp_connection.lock().unwrap().take();
+        if let Some(process) = self.process.lock().await.as_mut() {
+            process.start_kill()?;
+        }
+        Ok(())
+    }
+
     /// Tries to shut down the language server but returns
     /// early if server responds with an error.
     pub async fn shutdown_and_exit(&self) -> Result<()> {
-        self.shutdown().await?;
+        if !self.is_initialized() {
+            return self.cancel_initialization().await;
+        }
+        self.request_shutdown().await?;
         self.exit().await
     }
 
     /// Forcefully shuts down the language server ignoring any errors.
     pub async fn force_shutdown(&self) -> Result<()> {
-        if let Err(e) = self.shutdown().await {
+        if !self.is_initialized() {
+            return self.cancel_initialization().await;
+        }
+        if let Err(e) = self.request_shutdown().await {
             log::warn!("language server failed to terminate gracefully - {}", e);
         }
         self.exit().await
@@ -440,23 +1200,324 @@ pub async fn force_shutdown(&self) -> Result<()> {
     // Workspace
     // -------------------------------------------------------------------------------------------
 
+    /// Notifies the server that the user's configuration changed.
+    ///
+    /// `settings` is sent as-is. Servers that support `workspace/configuration` usually
+    /// ignore its contents and treat the notification as a signal to pull the sections they
+    /// care about, which is answered from the same config through
+    /// [`MethodCall::WorkspaceConfiguration`](crate::MethodCall::WorkspaceConfiguration).
     pub fn did_change_configuration(&self, settings: Value) -> impl Future<Output = Result<()>> {
         self.notify::<lsp::notification::DidChangeConfiguration>(
             lsp::DidChangeConfigurationParams { settings },
         )
     }
 
-    // -------------------------------------------------------------------------------------------
-    // Text document
-    // -------------------------------------------------------------------------------------------
+    /// Adds and removes workspace folders, e.g. when another project is opened. The folders
+    /// are kept to answer `workspace/workspaceFolders`, but the server is only notified if
+    /// it asked for `changeNotifications`.
+    pub fn did_change_workspace_folders(
+        &self,
+        added: Vec<lsp::WorkspaceFolder>,
+        removed: Vec<lsp::WorkspaceFolder>,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        {
+            let mut folders = self.workspace_folders.lock().unwrap();
+            folders.retain(|folder| !removed.contains(folder));
+            for folder in &added {
+                if !folders.contains(folder) {
+                    folders.push(folder.clone());
+                }
+            }
+        }
 
-    pub fn text_document_did_open(
+        match self
+            .capabilities
+            .get()?
+            .workspace
+            .as_ref()?
+            .workspace_folders
+        {
+            Some(lsp::WorkspaceFoldersServerCapabilities {
+                supported: Some(true),
+                change_notifications: Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)),
+            }) => (),
+            _ => return None,
+        }
+
+        Some(self.notify::<lsp::notification::DidChangeWorkspaceFolders>(
+            lsp::DidChangeWorkspaceFoldersParams {
+                event: lsp::WorkspaceFoldersChangeEvent { added, removed },
+            },
+        ))
+    }
+
+    /// Asks the server for edits to apply before `path` is created.
+    ///
+    /// Returns `None` if the server didn't register interest in the file.
+    pub fn will_create(
+        &self,
+        path: &Path,
+        is_dir: bool,
+    ) -> Option<impl Future<Output = Result<Option<lsp::WorkspaceEdit>>>> {
+        if !self
+            .file_operations_interest()
+            .will_create
+            .matches(path, is_dir)
+        {
+            return None;
+        }
+
+        let files = vec![lsp::FileCreate {
+            uri: lsp::Url::from_file_path(path).ok()?.to_string(),
+        }];
+        let request = self.call::<lsp::request::WillCreateFiles>(lsp::CreateFilesParams { files });
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::WorkspaceEdit> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    pub fn did_create(
+        &self,
+        path: &Path,
+        is_dir: bool,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        if !self
+            .file_operations_interest()
+            .did_create
+            .matches(path, is_dir)
+        {
+            return None;
+        }
+
+        let files = vec![lsp::FileCreate {
+            uri: lsp::Url::from_file_path(path).ok()?.to_string(),
+        }];
+        Some(self.notify::<lsp::notification::DidCreateFiles>(lsp::CreateFilesParams { files }))
+    }
+
+    /// Asks the server for edits to apply before `old_path` is renamed to `new_path`,
+    /// for example to update imports.
+    ///
+    /// Returns `None` if the server didn't register interest in the file.
+    pub fn will_rename(
+        &self,
+        old_path: &Path,
+        new_path: &Path,
+        is_dir: bool,
+    ) -> Option<impl Future<Output = Result<Option<lsp::WorkspaceEdit>>>> {
+        if !self
+            .file_operations_interest()
+            .will_rename
+            .matches(old_path, is_dir)
+        {
+            return None;
+        }
+
+        let files = vec![lsp::FileRename {
+            old_uri: lsp::Url::from_file_path(old_path).ok()?.to_string(),
+            new_uri: lsp::Url::from_file_path(new_path).ok()?.to_string(),
+        }];
+        let request = self.call::<lsp::request::WillRenameFiles>(lsp::RenameFilesParams { files });
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::WorkspaceEdit> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    pub fn did_rename(
+        &self,
+        old_path: &Path,
+        new_path: &Path,
+        is_dir: bool,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        if !self
+            .file_operations_interest()
+            .did_rename
+            .matches(old_path, is_dir)
+        {
+            return None;
+        }
+
+        let files = vec![lsp::FileRename {
+            old_uri: lsp::Url::from_file_path(old_path).ok()?.to_string(),
+            new_uri: lsp::Url::from_file_path(new_path).ok()?.to_string(),
+        }];
+        Some(self.notify::<lsp::notification::DidRenameFiles>(lsp::RenameFilesParams { files }))
+    }
+
+    /// Asks the server for edits to apply before `path` is deleted.
+    ///
+    /// Returns `None` if the server didn't register interest in the file.
+    pub fn will_delete(
+        &self,
+        path: &Path,
+        is_dir: bool,
+    ) -> Option<impl Future<Output = Result<Option<lsp::WorkspaceEdit>>>> {
+        if !self
+            .file_operations_interest()
+            .will_delete
+            .matches(path, is_dir)
+        {
+            return None;
+        }
+
+        let files = vec![lsp::FileDelete {
+            uri: lsp::Url::from_file_path(path).ok()?.to_string(),
+        }];
+        let request = self.call::<lsp::request::WillDeleteFiles>(lsp::DeleteFilesParams { files });
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::WorkspaceEdit> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    pub fn did_delete(
+        &self,
+        path: &Path,
+        is_dir: bool,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        if !self
+            .file_operations_interest()
+            .did_delete
+            .matches(path, is_dir)
+        {
+            return None;
+        }
+
+        let files = vec![lsp::FileDelete {
+            uri: lsp::Url::from_file_path(path).ok()?.to_string(),
+        }];
+        Some(self.notify::<lsp::notification::DidDeleteFiles>(lsp::DeleteFilesParams { files }))
+    }
+
+    /// Stores the file watchers of a `workspace/didChangeWatchedFiles` registration.
+    pub fn register_file_watchers(&self, id: String, watchers: &[lsp::FileSystemWatcher]) {
+        self.file_watchers.lock().unwrap().register(id, watchers);
+    }
+
+    pub fn unregister_file_watchers(&self, id: &str) {
+        self.file_watchers.lock().unwrap().unregister(id);
+    }
+
+    /// Notifies the server about file system events matching the watchers it registered.
+    ///
+    /// Returns `None` if none of the `events` match.
+    pub fn did_change_watched_files(
+        &self,
+        mut events: Vec<lsp::FileEvent>,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        {
+            let watchers = self.file_watchers.lock().unwrap();
+            events.retain(|event| watchers.matches(event));
+        }
+        if events.is_empty() {
+            return None;
+        }
+
+        Some(self.notify::<lsp::notification::DidChangeWatchedFiles>(
+            lsp::DidChangeWatchedFilesParams { changes: events },
+        ))
+    }
+
+    /// Pulls the diagnostics of all documents in the workspace. Documents whose diagnostics
+    /// didn't change since one of the `previous_result_ids` are reported as unchanged.
+    ///
+    /// Reports the server streams for `partial_result_token` are merged into the result.
+    pub fn workspace_diagnostic(
+        &self,
+        previous_result_ids: Vec<lsp::PreviousResultId>,
+        partial_result_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<lsp::WorkspaceDiagnosticReport>>> {
+        // Return early if the server does not support workspace diagnostics.
+        let mut identifier = None;
+        if let Some(capabilities) = self.capabilities.get() {
+            let options = match &capabilities.diagnostic_provider {
+                Some(lsp::DiagnosticServerCapabilities::Options(options)) => options,
+                Some(lsp::DiagnosticServerCapabilities::RegistrationOptions(options)) => {
+                    &options.diagnostic_options
+                }
+                None => return None,
+            };
+            if !options.workspace_diagnostics {
+                return None;
+            }
+            identifier = options.identifier.clone();
+        }
+
+        let params = lsp::WorkspaceDiagnosticParams {
+            identifier,
+            previous_result_ids,
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: partial_result_token.clone(),
+            },
+        };
+
+        let transport = self.transport.clone();
+        let partials = partial_result_token
+            .clone()
+            .map(|token| transport.register_partial_results(token));
+        let request = self.call::<lsp::request::WorkspaceDiagnosticRequest>(params);
+
+        Some(async move {
+            let response = request.await;
+            if let Some(token) = &partial_result_token {
+                transport.unregister_partial_results(token);
+            }
+            let report: lsp::WorkspaceDiagnosticReport = serde_json::from_value(response?)?;
+            Ok(match partials {
+                Some(partials) => Self::assemble_workspace_diagnostic_report(partials, report),
+                None => report,
+            })
+        })
+    }
+
+    /// Prepends the reports streamed as partial results to the final `report`. The first
+    /// partial result is a full report, the following ones only contain more documents.
+    pub(crate) fn assemble_workspace_diagnostic_report(
+        mut partials: UnboundedReceiver<Value>,
+        report: lsp::WorkspaceDiagnosticReport,
+    ) -> lsp::WorkspaceDiagnosticReport {
+        let mut items = Vec::new();
+        while let Ok(partial) = partials.try_recv() {
+            match serde_json::from_value::<lsp::WorkspaceDiagnosticReportPartialResult>(partial) {
+                Ok(partial) => items.extend(partial.items),
+                Err(err) => log::error!("invalid partial workspace diagnostic report: {}", err),
+            }
+        }
+        items.extend(report.items);
+        lsp::WorkspaceDiagnosticReport { items }
+    }
+
+    // -------------------------------------------------------------------------------------------
+    // Text document
+    // -------------------------------------------------------------------------------------------
+
+    /// The version of the document at `uri` the server knows about, `None` if the document
+    /// isn't open.
+    pub fn synced_version(&self, uri: &lsp::Url) -> Option<i32> {
+        self.synced_versions.lock().unwrap().get(uri).copied()
+    }
+
+    pub fn text_document_did_open(
         &self,
         uri: lsp::Url,
         version: i32,
         doc: &Rope,
         language_id: String,
     ) -> impl Future<Output = Result<()>> {
+        self.synced_versions
+            .lock()
+            .unwrap()
+            .insert(uri.clone(), version);
+
         self.notify::<lsp::notification::DidOpenTextDocument>(lsp::DidOpenTextDocumentParams {
             text_document: lsp::TextDocumentItem {
                 uri,
@@ -575,7 +1636,13 @@ pub fn text_document_did_change(
         new_text: &Rope,
         changes: &ChangeSet,
     ) -> Option<impl Future<Output = Result<()>>> {
-        let capabilities = self.capabilities.get().unwrap();
+        self.selection_ranges
+            .lock()
+            .unwrap()
+            .invalidate(&text_document.uri);
+
+        // the sync kind is only known once the server is initialized
+        let capabilities = self.capabilities.get()?;
 
         // Return early if the server does not support document sync.
         let sync_capabilities = match capabilities.text_document_sync {
@@ -606,31 +1673,230 @@ pub fn text_document_did_change(
             kind => unimplemented!("{:?}", kind),
         };
 
-        Some(self.notify::<lsp::notification::DidChangeTextDocument>(
+        let previous = self
+            .synced_versions
+            .lock()
+            .unwrap()
+            .insert(text_document.uri.clone(), text_document.version);
+        // servers discard changes that don't increase the version
+        if let Some(previous) = previous.filter(|&previous| previous >= text_document.version) {
+            log::warn!(
+                "Version {} of {} isn't newer than version {} sent before",
+                text_document.version,
+                text_document.uri,
+                previous
+            );
+        }
+
+        // The change is queued right away rather than once the future is polled, so that
+        // requests sent after it, like retries of requests the server dropped with
+        // `ContentModified`, are answered for the changed document.
+        let sent = Self::notification::<lsp::notification::DidChangeTextDocument>(
             lsp::DidChangeTextDocumentParams {
                 text_document,
                 content_changes: changes,
             },
-        ))
+        )
+        .and_then(|notification| {
+            self.server_tx
+                .send(Payload::Notification(notification))
+                .map_err(|e| Error::Other(e.into()))
+        });
+
+        Some(async move { sent })
     }
 
     pub fn text_document_did_close(
         &self,
         text_document: lsp::TextDocumentIdentifier,
     ) -> impl Future<Output = Result<()>> {
+        self.selection_ranges
+            .lock()
+            .unwrap()
+            .invalidate(&text_document.uri);
+        self.synced_versions
+            .lock()
+            .unwrap()
+            .remove(&text_document.uri);
+
         self.notify::<lsp::notification::DidCloseTextDocument>(lsp::DidCloseTextDocumentParams {
             text_document,
         })
     }
 
+    /// How the server syncs notebooks, `None` if it doesn't.
+    pub fn notebook_document_sync(&self) -> Option<&NotebookDocumentSyncOptions> {
+        self.notebook_document_sync.get()
+    }
+
+    /// Opens `notebook`, whose cells are the text documents `cell_text_documents`. Returns
+    /// `None` if the server doesn't sync notebooks.
+    pub fn notebook_document_did_open(
+        &self,
+        notebook: notebook::NotebookDocument,
+        cell_text_documents: Vec<lsp::TextDocumentItem>,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        self.notebook_document_sync()?;
+
+        let mut synced_versions = self.synced_versions.lock().unwrap();
+        for cell in &cell_text_documents {
+            synced_versions.insert(cell.uri.clone(), cell.version);
+        }
+
+        Some(self.notify::<notebook::DidOpenNotebookDocument>(
+            notebook::DidOpenNotebookDocumentParams {
+                notebook_document: notebook,
+                cell_text_documents,
+            },
+        ))
+    }
+
+    /// Notifies the server that the notebook `uri` changed to `version`, by `change`.
+    /// Returns `None` if the server doesn't sync notebooks.
+    pub fn notebook_document_did_change(
+        &self,
+        uri: lsp::Url,
+        version: i32,
+        change: notebook::NotebookDocumentChangeEvent,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        self.notebook_document_sync()?;
+
+        if let Some(cells) = &change.cells {
+            let mut synced_versions = self.synced_versions.lock().unwrap();
+            if let Some(structure) = &cells.structure {
+                for cell in structure.did_close.iter().flatten() {
+                    synced_versions.remove(&cell.uri);
+                }
+                for cell in structure.did_open.iter().flatten() {
+                    synced_versions.insert(cell.uri.clone(), cell.version);
+                }
+            }
+            for content in cells.text_content.iter().flatten() {
+                synced_versions.insert(content.document.uri.clone(), content.document.version);
+            }
+        }
+
+        Some(self.notify::<notebook::DidChangeNotebookDocument>(
+            notebook::DidChangeNotebookDocumentParams {
+                notebook_document: notebook::VersionedNotebookDocumentIdentifier { version, uri },
+                change,
+            },
+        ))
+    }
+
+    /// Notifies the server that the notebook `uri` was saved. Returns `None` if the server
+    /// isn't interested in saves of notebooks.
+    pub fn notebook_document_did_save(
+        &self,
+        uri: lsp::Url,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        if self.notebook_document_sync()?.save != Some(true) {
+            return None;
+        }
+
+        Some(self.notify::<notebook::DidSaveNotebookDocument>(
+            notebook::DidSaveNotebookDocumentParams {
+                notebook_document: notebook::NotebookDocumentIdentifier { uri },
+            },
+        ))
+    }
+
+    /// Closes the notebook `uri` along with its cells, the text documents `cell_uris`.
+    /// Returns `None` if the server doesn't sync notebooks.
+    pub fn notebook_document_did_close(
+        &self,
+        uri: lsp::Url,
+        cell_uris: Vec<lsp::Url>,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        self.notebook_document_sync()?;
+
+        let mut synced_versions = self.synced_versions.lock().unwrap();
+        for cell_uri in &cell_uris {
+            synced_versions.remove(cell_uri);
+        }
+
+        Some(
+            self.notify::<notebook::DidCloseNotebookDocument>(
+                notebook::DidCloseNotebookDocumentParams {
+                    notebook_document: notebook::NotebookDocumentIdentifier { uri },
+                    cell_text_documents: cell_uris
+                        .into_iter()
+                        .map(lsp::TextDocumentIdentifier::new)
+                        .collect(),
+                },
+            ),
+        )
+    }
+
     // will_save / will_save_wait_until
 
+    fn text_document_sync_options(&self) -> Option<&lsp::TextDocumentSyncOptions> {
+        match self.capabilities.get()?.text_document_sync.as_ref()? {
+            lsp::TextDocumentSyncCapability::Options(options) => Some(options),
+            lsp::TextDocumentSyncCapability::Kind(_) => None,
+        }
+    }
+
+    /// Notifies the server that the document at `uri` is about to be saved. Returns `None` if
+    /// the server isn't interested in `willSave`.
+    pub fn will_save(
+        &self,
+        uri: lsp::Url,
+        reason: lsp::TextDocumentSaveReason,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        if self.text_document_sync_options()?.will_save != Some(true) {
+            return None;
+        }
+
+        Some(self.notify::<lsp::notification::WillSaveTextDocument>(
+            lsp::WillSaveTextDocumentParams {
+                text_document: lsp::TextDocumentIdentifier { uri },
+                reason,
+            },
+        ))
+    }
+
+    /// Requests the edits to apply to the document at `uri` before it's written, e.g. to
+    /// format it. Returns `None` if the server doesn't support `willSaveWaitUntil`.
+    pub fn will_save_wait_until(
+        &self,
+        uri: lsp::Url,
+        reason: lsp::TextDocumentSaveReason,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
+        if self.text_document_sync_options()?.will_save_wait_until != Some(true) {
+            return None;
+        }
+
+        let request =
+            self.call::<lsp::request::WillSaveWaitUntil>(lsp::WillSaveTextDocumentParams {
+                text_document: lsp::TextDocumentIdentifier { uri },
+                reason,
+            });
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<Vec<lsp::TextEdit>> = serde_json::from_value(json)?;
+            Ok(response.unwrap_or_default())
+        })
+    }
+
     pub fn text_document_did_save(
         &self,
         text_document: lsp::TextDocumentIdentifier,
         text: &Rope,
     ) -> Option<impl Future<Output = Result<()>>> {
-        let capabilities = self.capabilities.get().unwrap();
+        self.did_save(text_document.uri, Some(text))
+    }
+
+    /// Notifies the server that the document at `uri` was saved. `text` is only sent if the
+    /// server asked for it with `textDocumentSync.save.includeText`. Returns `None` if the
+    /// server isn't interested in saves or isn't initialized yet.
+    pub fn did_save(
+        &self,
+        uri: lsp::Url,
+        text: Option<&Rope>,
+    ) -> Option<impl Future<Output = Result<()>>> {
+        let capabilities = self.capabilities.get()?;
 
         let include_text = match &capabilities.text_document_sync {
             Some(lsp::TextDocumentSyncCapability::Options(lsp::TextDocumentSyncOptions {
@@ -648,10 +1914,17 @@ pub fn text_document_did_save(
             _ => return None,
         };
 
+        // only build the string when it's sent, straight from the chunks of the rope
+        let text = text.filter(|_| include_text).map(|text| {
+            let mut content = String::with_capacity(text.len_bytes());
+            content.extend(text.chunks());
+            content
+        });
+
         Some(self.notify::<lsp::notification::DidSaveTextDocument>(
             lsp::DidSaveTextDocumentParams {
-                text_document,
-                text: include_text.then_some(text.into()),
+                text_document: lsp::TextDocumentIdentifier { uri },
+                text,
             },
         ))
     }
@@ -662,10 +1935,10 @@ pub fn completion(
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support completion.
-        capabilities.completion_provider.as_ref()?;
+        if let Some(capabilities) = self.capabilities.get() {
+            capabilities.completion_provider.as_ref()?;
+        }
 
         let params = lsp::CompletionParams {
             text_document_position: lsp::TextDocumentPositionParams {
@@ -688,30 +1961,49 @@ pub fn resolve_completion_item(
         &self,
         completion_item: lsp::CompletionItem,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support resolving completion items.
-        match capabilities.completion_provider {
-            Some(lsp::CompletionOptions {
-                resolve_provider: Some(true),
-                ..
-            }) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.completion_provider {
+                Some(lsp::CompletionOptions {
+                    resolve_provider: Some(true),
+                    ..
+                }) => (),
+                _ => return None,
+            }
         }
 
         Some(self.call::<lsp::request::ResolveCompletionItem>(completion_item))
     }
 
+    /// Characters that open signature help when typed.
+    pub fn signature_help_trigger_characters(&self) -> &[String] {
+        self.capabilities
+            .get()
+            .and_then(|capabilities| capabilities.signature_help_provider.as_ref())
+            .and_then(|options| options.trigger_characters.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Characters that update signature help when typed while it is shown.
+    pub fn signature_help_retrigger_characters(&self) -> &[String] {
+        self.capabilities
+            .get()
+            .and_then(|capabilities| capabilities.signature_help_provider.as_ref())
+            .and_then(|options| options.retrigger_characters.as_deref())
+            .unwrap_or_default()
+    }
+
     pub fn text_document_signature_help(
         &self,
         text_document: lsp::TextDocumentIdentifier,
         position: lsp::Position,
+        context: Option<lsp::SignatureHelpContext>,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support signature help.
-        capabilities.signature_help_provider.as_ref()?;
+        if let Some(capabilities) = self.capabilities.get() {
+            capabilities.signature_help_provider.as_ref()?;
+        }
 
         let params = lsp::SignatureHelpParams {
             text_document_position_params: lsp::TextDocumentPositionParams {
@@ -719,8 +2011,7 @@ pub fn text_document_signature_help(
                 position,
             },
             work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
-            context: None,
-            // lsp::SignatureHelpContext
+            context,
         };
 
         Some(self.call::<lsp::request::SignatureHelpRequest>(params))
@@ -732,15 +2023,15 @@ pub fn text_document_hover(
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support hover.
-        match capabilities.hover_provider {
-            Some(
-                lsp::HoverProviderCapability::Simple(true)
-                | lsp::HoverProviderCapability::Options(_),
-            ) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.hover_provider {
+                Some(
+                    lsp::HoverProviderCapability::Simple(true)
+                    | lsp::HoverProviderCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
         }
 
         let params = lsp::HoverParams {
@@ -755,30 +2046,85 @@ pub fn text_document_hover(
         Some(self.call::<lsp::request::HoverRequest>(params))
     }
 
-    // formatting
-
-    pub fn text_document_formatting(
+    /// Requests the hovers of all `positions` in a document at once, with at most
+    /// `concurrency` requests in flight. The hovers are returned in the order of `positions`,
+    /// `None` for positions the server has nothing to show for.
+    pub fn hover_batch(
         &self,
         text_document: lsp::TextDocumentIdentifier,
-        options: lsp::FormattingOptions,
-        work_done_token: Option<lsp::ProgressToken>,
-    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
-        let capabilities = self.capabilities.get().unwrap();
+        positions: Vec<lsp::Position>,
+        concurrency: usize,
+    ) -> Option<impl Future<Output = Result<Vec<Option<lsp::Hover>>>>> {
+        use futures_util::{stream, StreamExt, TryStreamExt};
 
-        // Return early if the server does not support formatting.
-        match capabilities.document_formatting_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
-            _ => return None,
-        };
+        // Return early if the server does not support hover.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.hover_provider {
+                Some(
+                    lsp::HoverProviderCapability::Simple(true)
+                    | lsp::HoverProviderCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
+        }
+
+        // The requests are only sent once they are polled, so at most `concurrency` of them
+        // are in flight.
+        let requests: Vec<_> = positions
+            .into_iter()
+            .map(|position| {
+                let params = lsp::HoverParams {
+                    text_document_position_params: lsp::TextDocumentPositionParams {
+                        text_document: text_document.clone(),
+                        position,
+                    },
+                    work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+                };
+                let request = self.call::<lsp::request::HoverRequest>(params);
+                async move {
+                    let response: Option<lsp::Hover> = serde_json::from_value(request.await?)?;
+                    Ok(response)
+                }
+            })
+            .collect();
+
+        Some(
+            stream::iter(requests)
+                // `buffered` keeps the responses in the order of the requests
+                .buffered(concurrency.max(1))
+                .try_collect(),
+        )
+    }
+
+    /// Requests the hover of `position` in the document at `uri`. The contents can be turned
+    /// into markdown with [`hover_contents_to_markdown`](crate::util::hover_contents_to_markdown).
+    /// Resolves to `None` if the server has nothing to show.
+    pub fn hover(
+        &self,
+        uri: lsp::Url,
+        position: lsp::Position,
+    ) -> Option<impl Future<Output = Result<Option<lsp::Hover>>>> {
+        let request =
+            self.text_document_hover(lsp::TextDocumentIdentifier { uri }, position, None)?;
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::Hover> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    // formatting
 
-        // merge FormattingOptions with 'config.format'
+    /// Merges `options` with the `format` section of the server's config.
+    fn formatting_options(&self, options: lsp::FormattingOptions) -> lsp::FormattingOptions {
         let config_format = self
             .config
             .as_ref()
             .and_then(|cfg| cfg.get("format"))
             .and_then(|fmt| HashMap::<String, lsp::FormattingProperty>::deserialize(fmt).ok());
 
-        let options = if let Some(mut properties) = config_format {
+        if let Some(mut properties) = config_format {
             // passed in options take precedence over 'config.format'
             properties.extend(options.properties);
             lsp::FormattingOptions {
@@ -787,42 +2133,109 @@ pub fn text_document_formatting(
             }
         } else {
             options
-        };
-
-        let params = lsp::DocumentFormattingParams {
-            text_document,
-            options,
-            work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
-        };
-
-        let request = self.call::<lsp::request::Formatting>(params);
-
-        Some(async move {
-            let json = request.await?;
-            let response: Option<Vec<lsp::TextEdit>> = serde_json::from_value(json)?;
-            Ok(response.unwrap_or_default())
-        })
+        }
     }
 
-    pub fn text_document_range_formatting(
+    fn formatting_params(
         &self,
         text_document: lsp::TextDocumentIdentifier,
-        range: lsp::Range,
         options: lsp::FormattingOptions,
         work_done_token: Option<lsp::ProgressToken>,
-    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
-        let capabilities = self.capabilities.get().unwrap();
+    ) -> Option<lsp::DocumentFormattingParams> {
+        use lsp::request::Request as _;
 
-        // Return early if the server does not support range formatting.
-        match capabilities.document_range_formatting_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
-            _ => return None,
-        };
+        // Return early if the server does not support formatting.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.document_formatting_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ if self.has_registration(lsp::request::Formatting::METHOD) => (),
+                _ => return None,
+            };
+        }
+
+        Some(lsp::DocumentFormattingParams {
+            text_document,
+            options: self.formatting_options(options),
+            work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
+        })
+    }
+
+    pub fn text_document_formatting(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        options: lsp::FormattingOptions,
+        work_done_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
+        let params = self.formatting_params(text_document, options, work_done_token)?;
+
+        let request = self.call::<lsp::request::Formatting>(params);
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<Vec<lsp::TextEdit>> = serde_json::from_value(json)?;
+            Ok(response.unwrap_or_default())
+        })
+    }
+
+    /// Format the document, giving up if the server doesn't respond within `timeout`.
+    ///
+    /// Resolves to `Ok(None)` on timeout, in which case the request is cancelled and the
+    /// document should be left as is, and to `Ok(Some(edits))` otherwise. `edits` may be
+    /// empty if the document is already formatted.
+    pub fn format_document_with_timeout(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        options: lsp::FormattingOptions,
+        timeout: Duration,
+    ) -> Option<impl Future<Output = Result<Option<Vec<lsp::TextEdit>>>>> {
+        let params = self.formatting_params(text_document, options, None)?;
+
+        Some(Self::send_formatting_request(
+            self.server_tx.clone(),
+            self.next_request_id(),
+            params,
+            timeout,
+        ))
+    }
+
+    async fn send_formatting_request(
+        server_tx: UnboundedSender<Payload>,
+        id: jsonrpc::Id,
+        params: lsp::DocumentFormattingParams,
+        timeout: Duration,
+    ) -> Result<Option<Vec<lsp::TextEdit>>> {
+        match Self::send_request::<lsp::request::Formatting>(server_tx, id, params, timeout).await {
+            Ok(json) => {
+                let response: Option<Vec<lsp::TextEdit>> = serde_json::from_value(json)?;
+                Ok(Some(response.unwrap_or_default()))
+            }
+            Err(Error::Timeout(..)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn text_document_range_formatting(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        range: lsp::Range,
+        options: lsp::FormattingOptions,
+        work_done_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
+        use lsp::request::Request as _;
+
+        // Return early if the server does not support range formatting.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.document_range_formatting_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ if self.has_registration(lsp::request::RangeFormatting::METHOD) => (),
+                _ => return None,
+            };
+        }
 
         let params = lsp::DocumentRangeFormattingParams {
             text_document,
             range,
-            options,
+            options: self.formatting_options(options),
             work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
         };
 
@@ -835,18 +2248,70 @@ pub fn text_document_range_formatting(
         })
     }
 
+    /// Formats the selected `range` of the document `uri`, whose contents are `text`. The
+    /// edits of the server may still touch text around the selection.
+    pub fn range_formatting(
+        &self,
+        uri: lsp::Url,
+        text: &Rope,
+        range: helix_core::Range,
+        options: lsp::FormattingOptions,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
+        let range = util::range_to_lsp_range(text, range, self.offset_encoding());
+        self.text_document_range_formatting(
+            lsp::TextDocumentIdentifier { uri },
+            range,
+            options,
+            None,
+        )
+    }
+
+    pub fn text_document_on_type_formatting(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        position: lsp::Position,
+        ch: char,
+        options: lsp::FormattingOptions,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::TextEdit>>>> {
+        // Return early if the server does not support on-type formatting or `ch` isn't one of
+        // its trigger characters.
+        if let Some(capabilities) = self.capabilities.get() {
+            match &capabilities.document_on_type_formatting_provider {
+                Some(provider) if is_on_type_formatting_trigger(provider, ch) => (),
+                _ => return None,
+            };
+        }
+
+        let params = lsp::DocumentOnTypeFormattingParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document,
+                position,
+            },
+            ch: ch.to_string(),
+            options: self.formatting_options(options),
+        };
+
+        let request = self.call::<lsp::request::OnTypeFormatting>(params);
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<Vec<lsp::TextEdit>> = serde_json::from_value(json)?;
+            Ok(response.unwrap_or_default())
+        })
+    }
+
     pub fn text_document_document_highlight(
         &self,
         text_document: lsp::TextDocumentIdentifier,
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support document highlight.
-        match capabilities.document_highlight_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.document_highlight_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
         }
 
         let params = lsp::DocumentHighlightParams {
@@ -863,6 +2328,64 @@ pub fn text_document_document_highlight(
         Some(self.call::<lsp::request::DocumentHighlightRequest>(params))
     }
 
+    /// Request the chain of ranges enclosing `position`. The chain is cached until the
+    /// document is edited, so walking it up and down only needs a single request.
+    pub fn text_document_selection_range(
+        &self,
+        text_document: lsp::VersionedTextDocumentIdentifier,
+        position: lsp::Position,
+        work_done_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<Option<lsp::SelectionRange>>>> {
+        // Return early if the server does not support selection ranges.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.selection_range_provider {
+                Some(
+                    lsp::SelectionRangeProviderCapability::Simple(true)
+                    | lsp::SelectionRangeProviderCapability::Options(_)
+                    | lsp::SelectionRangeProviderCapability::RegistrationOptions(_),
+                ) => (),
+                _ => return None,
+            }
+        }
+
+        let lsp::VersionedTextDocumentIdentifier { uri, version } = text_document;
+        let cache = self.selection_ranges.clone();
+        let cached = cache.lock().unwrap().get(&uri, version, position);
+
+        let request = match cached {
+            Some(_) => None,
+            None => {
+                let params = lsp::SelectionRangeParams {
+                    text_document: lsp::TextDocumentIdentifier { uri: uri.clone() },
+                    positions: vec![position],
+                    work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
+                    partial_result_params: lsp::PartialResultParams {
+                        partial_result_token: None,
+                    },
+                };
+                Some(self.call::<lsp::request::SelectionRangeRequest>(params))
+            }
+        };
+
+        Some(async move {
+            let request = match request {
+                Some(request) => request,
+                None => return Ok(cached),
+            };
+
+            let json = request.await?;
+            let response: Option<Vec<lsp::SelectionRange>> = serde_json::from_value(json)?;
+            let selection_range = response.and_then(|ranges| ranges.into_iter().next());
+            if let Some(selection_range) = &selection_range {
+                cache
+                    .lock()
+                    .unwrap()
+                    .insert(uri, version, position, selection_range.clone());
+            }
+            Ok(selection_range)
+        })
+    }
+
     fn goto_request<
         T: lsp::request::Request<
             Params = lsp::GotoDefinitionParams,
@@ -894,12 +2417,12 @@ pub fn goto_definition(
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support goto-definition.
-        match capabilities.definition_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.definition_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
         }
 
         Some(self.goto_request::<lsp::request::GotoDefinition>(
@@ -915,16 +2438,16 @@ pub fn goto_declaration(
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support goto-declaration.
-        match capabilities.declaration_provider {
-            Some(
-                lsp::DeclarationCapability::Simple(true)
-                | lsp::DeclarationCapability::RegistrationOptions(_)
-                | lsp::DeclarationCapability::Options(_),
-            ) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.declaration_provider {
+                Some(
+                    lsp::DeclarationCapability::Simple(true)
+                    | lsp::DeclarationCapability::RegistrationOptions(_)
+                    | lsp::DeclarationCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
         }
 
         Some(self.goto_request::<lsp::request::GotoDeclaration>(
@@ -940,15 +2463,15 @@ pub fn goto_type_definition(
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support goto-type-definition.
-        match capabilities.type_definition_provider {
-            Some(
-                lsp::TypeDefinitionProviderCapability::Simple(true)
-                | lsp::TypeDefinitionProviderCapability::Options(_),
-            ) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.type_definition_provider {
+                Some(
+                    lsp::TypeDefinitionProviderCapability::Simple(true)
+                    | lsp::TypeDefinitionProviderCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
         }
 
         Some(self.goto_request::<lsp::request::GotoTypeDefinition>(
@@ -964,15 +2487,15 @@ pub fn goto_implementation(
         position: lsp::Position,
         work_done_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support goto-definition.
-        match capabilities.implementation_provider {
-            Some(
-                lsp::ImplementationProviderCapability::Simple(true)
-                | lsp::ImplementationProviderCapability::Options(_),
-            ) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.implementation_provider {
+                Some(
+                    lsp::ImplementationProviderCapability::Simple(true)
+                    | lsp::ImplementationProviderCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
         }
 
         Some(self.goto_request::<lsp::request::GotoImplementation>(
@@ -986,14 +2509,16 @@ pub fn goto_reference(
         &self,
         text_document: lsp::TextDocumentIdentifier,
         position: lsp::Position,
+        include_declaration: bool,
         work_done_token: Option<lsp::ProgressToken>,
+        partial_result_token: Option<lsp::ProgressToken>,
     ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
         // Return early if the server does not support goto-reference.
-        match capabilities.references_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
-            _ => return None,
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.references_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
         }
 
         let params = lsp::ReferenceParams {
@@ -1002,157 +2527,2898 @@ pub fn goto_reference(
                 position,
             },
             context: lsp::ReferenceContext {
-                include_declaration: true,
+                include_declaration,
             },
             work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
             partial_result_params: lsp::PartialResultParams {
-                partial_result_token: None,
+                partial_result_token: partial_result_token.clone(),
             },
         };
 
-        Some(self.call::<lsp::request::References>(params))
+        Some(
+            self.call_with_partial_results::<lsp::request::References>(
+                params,
+                partial_result_token,
+            ),
+        )
     }
 
-    pub fn document_symbols(
+    /// Requests the references to the symbol at `position` in the document at `uri`.
+    /// `include_declaration` controls whether the declaration of the symbol is one of them.
+    pub fn references(
         &self,
-        text_document: lsp::TextDocumentIdentifier,
-    ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
+        uri: lsp::Url,
+        position: lsp::Position,
+        include_declaration: bool,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::Location>>>> {
+        let request = self.goto_reference(
+            lsp::TextDocumentIdentifier { uri },
+            position,
+            include_declaration,
+            None,
+            None,
+        )?;
 
-        // Return early if the server does not support document symbols.
-        match capabilities.document_symbol_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
-            _ => return None,
+        Some(async move {
+            let json = request.await?;
+            let response: Option<Vec<lsp::Location>> = serde_json::from_value(json)?;
+            Ok(response.unwrap_or_default())
+        })
+    }
+
+    /// Prepares the call hierarchy at each of `positions` in the document at `uri`, e.g. one
+    /// per cursor. The requests are sent concurrently and the items of all of them are merged,
+    /// an item found from several positions is only returned once.
+    pub fn prepare_call_hierarchy(
+        &self,
+        uri: lsp::Url,
+        positions: Vec<lsp::Position>,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::CallHierarchyItem>>>> {
+        // Return early if the server does not support call hierarchies.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.call_hierarchy_provider {
+                Some(
+                    lsp::CallHierarchyServerCapability::Simple(true)
+                    | lsp::CallHierarchyServerCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
         }
 
-        let params = lsp::DocumentSymbolParams {
-            text_document,
-            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
-            partial_result_params: lsp::PartialResultParams::default(),
-        };
+        let requests: Vec<_> = positions
+            .into_iter()
+            .map(|position| {
+                let params = lsp::CallHierarchyPrepareParams {
+                    text_document_position_params: lsp::TextDocumentPositionParams {
+                        text_document: lsp::TextDocumentIdentifier { uri: uri.clone() },
+                        position,
+                    },
+                    work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+                };
+                let request = self.call::<lsp::request::CallHierarchyPrepare>(params);
+                async move {
+                    let response: Option<Vec<lsp::CallHierarchyItem>> =
+                        serde_json::from_value(request.await?)?;
+                    Ok::<_, Error>(response.unwrap_or_default())
+                }
+            })
+            .collect();
 
-        Some(self.call::<lsp::request::DocumentSymbolRequest>(params))
+        Some(async move {
+            let mut items = Vec::new();
+            for item in futures_util::future::try_join_all(requests)
+                .await?
+                .into_iter()
+                .flatten()
+            {
+                // `CallHierarchyItem` isn't hashable, there are only a few items per position
+                if !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+            Ok(items)
+        })
     }
 
-    pub fn prepare_rename(
-        &self,
-        text_document: lsp::TextDocumentIdentifier,
-        position: lsp::Position,
-    ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
+    fn semantic_tokens_options(&self) -> Option<&lsp::SemanticTokensOptions> {
+        match self.capabilities.get()?.semantic_tokens_provider.as_ref()? {
+            lsp::SemanticTokensServerCapabilities::SemanticTokensOptions(options) => Some(options),
+            lsp::SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(options) => {
+                Some(&options.semantic_tokens_options)
+            }
+        }
+    }
 
-        match capabilities.rename_provider {
-            Some(lsp::OneOf::Right(lsp::RenameOptions {
-                prepare_provider: Some(true),
-                ..
-            })) => (),
+    /// The legend to decode the token types and modifiers of semantic tokens with, see
+    /// [`decode_semantic_tokens`](crate::util::decode_semantic_tokens).
+    pub fn semantic_tokens_legend(&self) -> Option<&lsp::SemanticTokensLegend> {
+        self.semantic_tokens_options()
+            .map(|options| &options.legend)
+    }
+
+    /// Requests the semantic tokens of the whole document at `uri`.
+    pub fn semantic_tokens_full(
+        &self,
+        uri: lsp::Url,
+    ) -> Option<impl Future<Output = Result<Option<lsp::SemanticTokens>>>> {
+        // Return early if the server does not support semantic tokens for whole documents.
+        match self.semantic_tokens_options()?.full {
+            Some(
+                lsp::SemanticTokensFullOptions::Bool(true)
+                | lsp::SemanticTokensFullOptions::Delta { .. },
+            ) => (),
             _ => return None,
         }
 
-        let params = lsp::TextDocumentPositionParams {
-            text_document,
-            position,
+        let params = lsp::SemanticTokensParams {
+            text_document: lsp::TextDocumentIdentifier { uri },
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+            partial_result_params: lsp::PartialResultParams::default(),
         };
+        let request = self.call::<lsp::request::SemanticTokensFullRequest>(params);
 
-        Some(self.call::<lsp::request::PrepareRenameRequest>(params))
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::SemanticTokensResult> = serde_json::from_value(json)?;
+            Ok(response.map(|result| match result {
+                lsp::SemanticTokensResult::Tokens(tokens) => tokens,
+                lsp::SemanticTokensResult::Partial(partial) => lsp::SemanticTokens {
+                    result_id: None,
+                    data: partial.data,
+                },
+            }))
+        })
     }
 
-    // empty string to get all symbols
-    pub fn workspace_symbols(&self, query: String) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
-        // Return early if the server does not support workspace symbols.
-        match capabilities.workspace_symbol_provider {
-            Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+    /// Requests the changes to the semantic tokens of the document at `uri` since the
+    /// response with `previous_result_id`. Servers may answer with all tokens instead.
+    pub fn semantic_tokens_full_delta(
+        &self,
+        uri: lsp::Url,
+        previous_result_id: String,
+    ) -> Option<impl Future<Output = Result<Option<lsp::SemanticTokensFullDeltaResult>>>> {
+        // Return early if the server does not support semantic token deltas.
+        match self.semantic_tokens_options()?.full {
+            Some(lsp::SemanticTokensFullOptions::Delta { delta: Some(true) }) => (),
             _ => return None,
         }
 
-        let params = lsp::WorkspaceSymbolParams {
-            query,
+        let params = lsp::SemanticTokensDeltaParams {
+            text_document: lsp::TextDocumentIdentifier { uri },
+            previous_result_id,
             work_done_progress_params: lsp::WorkDoneProgressParams::default(),
             partial_result_params: lsp::PartialResultParams::default(),
         };
+        let request = self.call::<lsp::request::SemanticTokensFullDeltaRequest>(params);
 
-        Some(self.call::<lsp::request::WorkspaceSymbolRequest>(params))
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::SemanticTokensFullDeltaResult> =
+                serde_json::from_value(json)?;
+            Ok(response)
+        })
     }
 
-    pub fn code_actions(
+    /// Requests the semantic tokens of `range` in the document at `uri`, e.g. for the visible
+    /// part of a large document. The positions of the tokens are still relative to the start
+    /// of the document.
+    pub fn semantic_tokens_range(
         &self,
-        text_document: lsp::TextDocumentIdentifier,
-        range: lsp::Range,
-        context: lsp::CodeActionContext,
-    ) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
-        // Return early if the server does not support code actions.
-        match capabilities.code_action_provider {
-            Some(
-                lsp::CodeActionProviderCapability::Simple(true)
-                | lsp::CodeActionProviderCapability::Options(_),
-            ) => (),
-            _ => return None,
+        uri: lsp::Url,
+        text: &Rope,
+        range: helix_core::Range,
+    ) -> Option<impl Future<Output = Result<Option<lsp::SemanticTokens>>>> {
+        // Return early if the server does not support semantic tokens for ranges.
+        if self.semantic_tokens_options()?.range != Some(true) {
+            return None;
         }
 
-        let params = lsp::CodeActionParams {
-            text_document,
-            range,
-            context,
+        let params = lsp::SemanticTokensRangeParams {
+            text_document: lsp::TextDocumentIdentifier { uri },
+            range: util::range_to_lsp_range(text, range, self.offset_encoding()),
             work_done_progress_params: lsp::WorkDoneProgressParams::default(),
             partial_result_params: lsp::PartialResultParams::default(),
         };
+        let request = self.call::<lsp::request::SemanticTokensRangeRequest>(params);
 
-        Some(self.call::<lsp::request::CodeActionRequest>(params))
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::SemanticTokensRangeResult> = serde_json::from_value(json)?;
+            Ok(response.map(|result| match result {
+                lsp::SemanticTokensRangeResult::Tokens(tokens) => tokens,
+                lsp::SemanticTokensRangeResult::Partial(partial) => lsp::SemanticTokens {
+                    result_id: None,
+                    data: partial.data,
+                },
+            }))
+        })
     }
 
-    pub fn rename_symbol(
+    /// Requests the inlay hints of `range` in the document at `uri`, usually the visible part
+    /// of the document.
+    pub fn inlay_hint(
         &self,
-        text_document: lsp::TextDocumentIdentifier,
-        position: lsp::Position,
-        new_name: String,
-    ) -> Option<impl Future<Output = Result<lsp::WorkspaceEdit>>> {
-        let capabilities = self.capabilities.get().unwrap();
-
-        // Return early if the language server does not support renaming.
-        match capabilities.rename_provider {
-            Some(lsp::OneOf::Left(true)) | Some(lsp::OneOf::Right(_)) => (),
-            // None | Some(false)
-            _ => return None,
-        };
+        uri: lsp::Url,
+        text: &Rope,
+        range: helix_core::Range,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::InlayHint>>>> {
+        // Return early if the server does not support inlay hints.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.inlay_hint_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
+        }
 
-        let params = lsp::RenameParams {
-            text_document_position: lsp::TextDocumentPositionParams {
-                text_document,
-                position,
-            },
-            new_name,
-            work_done_progress_params: lsp::WorkDoneProgressParams {
-                work_done_token: None,
-            },
+        let params = lsp::InlayHintParams {
+            text_document: lsp::TextDocumentIdentifier { uri },
+            range: util::range_to_lsp_range(text, range, self.offset_encoding()),
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
         };
-
-        let request = self.call::<lsp::request::Rename>(params);
+        let request = self.call::<lsp::request::InlayHintRequest>(params);
 
         Some(async move {
             let json = request.await?;
-            let response: Option<lsp::WorkspaceEdit> = serde_json::from_value(json)?;
+            let response: Option<Vec<lsp::InlayHint>> = serde_json::from_value(json)?;
             Ok(response.unwrap_or_default())
         })
     }
 
-    pub fn command(&self, command: lsp::Command) -> Option<impl Future<Output = Result<Value>>> {
-        let capabilities = self.capabilities.get().unwrap();
+    /// Resolves the tooltip, text edits or label parts of an inlay hint returned without
+    /// them. The `data` of `hint` is kept if the server doesn't send it back.
+    pub fn resolve_inlay_hint(
+        &self,
+        hint: lsp::InlayHint,
+    ) -> Option<impl Future<Output = Result<lsp::InlayHint>>> {
+        // Return early if the server does not support resolving inlay hints.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.inlay_hint_provider {
+                Some(lsp::OneOf::Right(
+                    lsp::InlayHintServerCapabilities::Options(lsp::InlayHintOptions {
+                        resolve_provider: Some(true),
+                        ..
+                    })
+                    | lsp::InlayHintServerCapabilities::RegistrationOptions(
+                        lsp::InlayHintRegistrationOptions {
+                            inlay_hint_options:
+                                lsp::InlayHintOptions {
+                                    resolve_provider: Some(true),
+                                    ..
+                                },
+                            ..
+                        },
+                    ),
+                )) => (),
+                _ => return None,
+            }
+        }
 
-        // Return early if the language server does not support executing commands.
-        capabilities.execute_command_provider.as_ref()?;
+        let data = hint.data.clone();
+        let request = self.call::<lsp::request::InlayHintResolveRequest>(hint);
 
-        let params = lsp::ExecuteCommandParams {
-            command: command.command,
-            arguments: command.arguments.unwrap_or_default(),
-            work_done_progress_params: lsp::WorkDoneProgressParams {
-                work_done_token: None,
-            },
+        Some(async move {
+            let json = request.await?;
+            let mut response: lsp::InlayHint = serde_json::from_value(json)?;
+            if response.data.is_none() {
+                response.data = data;
+            }
+            Ok(response)
+        })
+    }
+
+    pub fn text_document_document_symbol(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+    ) -> Option<impl Future<Output = Result<Value>>> {
+        // Return early if the server does not support document symbols.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.document_symbol_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
+        }
+
+        let params = lsp::DocumentSymbolParams {
+            text_document,
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+            partial_result_params: lsp::PartialResultParams::default(),
+        };
+
+        Some(self.call::<lsp::request::DocumentSymbolRequest>(params))
+    }
+
+    /// Requests the symbols of the document at `uri`. Servers respond with either a flat list
+    /// or a hierarchy of symbols,
+    /// [`document_symbols_to_tree`](crate::util::document_symbols_to_tree) turns both into a
+    /// tree.
+    pub fn document_symbols(
+        &self,
+        uri: lsp::Url,
+    ) -> Option<impl Future<Output = Result<Option<lsp::DocumentSymbolResponse>>>> {
+        let request = self.text_document_document_symbol(lsp::TextDocumentIdentifier { uri })?;
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::DocumentSymbolResponse> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    /// Requests the monikers of the symbol at `position`. Each moniker names the symbol by an
+    /// `identifier` within a `scheme` (e.g. `tsc` or `npm`), its `unique`ness tells how far the
+    /// identifier is unique (document, project, scheme or globally) and its `kind` whether the
+    /// symbol is imported from or exported to other projects.
+    pub fn moniker(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        position: lsp::Position,
+        work_done_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<Vec<lsp::Moniker>>>> {
+        // Return early if the server does not support monikers.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.moniker_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
+        }
+
+        let params = lsp::MonikerParams {
+            text_document_position_params: lsp::TextDocumentPositionParams {
+                text_document,
+                position,
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: None,
+            },
+        };
+
+        let request = self.call::<lsp::request::MonikerRequest>(params);
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<Vec<lsp::Moniker>> = serde_json::from_value(json)?;
+            Ok(response.unwrap_or_default())
+        })
+    }
+
+    /// Requests the code lenses of a document. The range of each lens can be converted with
+    /// [`lsp_range_to_range`](crate::util::lsp_range_to_range).
+    pub fn code_lens(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        work_done_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<Value>>> {
+        // Return early if the server does not support code lenses.
+        if let Some(capabilities) = self.capabilities.get() {
+            capabilities.code_lens_provider.as_ref()?;
+        }
+
+        let params = lsp::CodeLensParams {
+            text_document,
+            work_done_progress_params: lsp::WorkDoneProgressParams { work_done_token },
+            partial_result_params: lsp::PartialResultParams::default(),
+        };
+
+        Some(self.call::<lsp::request::CodeLensRequest>(params))
+    }
+
+    /// Resolves the `command` of a code lens returned without one.
+    pub fn resolve_code_lens(
+        &self,
+        code_lens: lsp::CodeLens,
+    ) -> Option<impl Future<Output = Result<Value>>> {
+        // Return early if the server does not support resolving code lenses.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.code_lens_provider {
+                Some(lsp::CodeLensOptions {
+                    resolve_provider: Some(true),
+                    ..
+                }) => (),
+                _ => return None,
+            }
+        }
+
+        Some(self.call::<lsp::request::CodeLensResolve>(code_lens))
+    }
+
+    pub fn prepare_rename(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        position: lsp::Position,
+    ) -> Option<impl Future<Output = Result<Value>>> {
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.rename_provider {
+                Some(lsp::OneOf::Right(lsp::RenameOptions {
+                    prepare_provider: Some(true),
+                    ..
+                })) => (),
+                _ => return None,
+            }
+        }
+
+        let params = lsp::TextDocumentPositionParams {
+            text_document,
+            position,
+        };
+
+        Some(self.call::<lsp::request::PrepareRenameRequest>(params))
+    }
+
+    /// Searches the symbols of the workspace matching `query`, an empty query matches all
+    /// symbols. Results the server streams for `partial_result_token` are merged.
+    ///
+    /// Servers respond with either [`lsp::SymbolInformation`]s or [`lsp::WorkspaceSymbol`]s,
+    /// the latter may only carry the uri of their location until they are resolved.
+    pub fn workspace_symbols(
+        &self,
+        query: String,
+        partial_result_token: Option<lsp::ProgressToken>,
+    ) -> Option<impl Future<Output = Result<Option<lsp::WorkspaceSymbolResponse>>>> {
+        // Return early if the server does not support workspace symbols.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.workspace_symbol_provider {
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_)) => (),
+                _ => return None,
+            }
+        }
+
+        let params = lsp::WorkspaceSymbolParams {
+            query,
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+            partial_result_params: lsp::PartialResultParams {
+                partial_result_token: partial_result_token.clone(),
+            },
+        };
+
+        let request = self.call_with_partial_results::<lsp::request::WorkspaceSymbolRequest>(
+            params,
+            partial_result_token,
+        );
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::WorkspaceSymbolResponse> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    /// Like [`Client::workspace_symbols`] but only keeps symbols of one of `kinds`,
+    /// since servers can't filter by kind themselves. Symbols stay in the order the server
+    /// returned them in.
+    pub fn workspace_symbols_of_kind(
+        &self,
+        query: String,
+        kinds: Vec<lsp::SymbolKind>,
+    ) -> Option<impl Future<Output = Result<Option<lsp::WorkspaceSymbolResponse>>>> {
+        let request = self.workspace_symbols(query, None)?;
+
+        Some(async move {
+            let response = request.await?;
+            Ok(response.map(|response| util::filter_symbols_by_kind(response, &kinds)))
+        })
+    }
+
+    pub fn code_actions(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        range: lsp::Range,
+        context: lsp::CodeActionContext,
+    ) -> Option<impl Future<Output = Result<Value>>> {
+        // Return early if the server does not support code actions.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.code_action_provider {
+                Some(
+                    lsp::CodeActionProviderCapability::Simple(true)
+                    | lsp::CodeActionProviderCapability::Options(_),
+                ) => (),
+                _ => return None,
+            }
+        }
+
+        let params = lsp::CodeActionParams {
+            text_document,
+            range,
+            context,
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+            partial_result_params: lsp::PartialResultParams::default(),
+        };
+
+        Some(self.call::<lsp::request::CodeActionRequest>(params))
+    }
+
+    /// Requests the code actions for `range` in the document at `uri`. `context` carries the
+    /// diagnostics overlapping the range, and may restrict the actions to some kinds.
+    pub fn code_action(
+        &self,
+        uri: lsp::Url,
+        range: lsp::Range,
+        context: lsp::CodeActionContext,
+    ) -> Option<impl Future<Output = Result<lsp::CodeActionResponse>>> {
+        let request = self.code_actions(lsp::TextDocumentIdentifier { uri }, range, context)?;
+
+        Some(async move {
+            let json = request.await?;
+            let response: Option<lsp::CodeActionResponse> = serde_json::from_value(json)?;
+            Ok(response.unwrap_or_default())
+        })
+    }
+
+    /// Resolves the `edit` and `command` of a code action returned without them. The `data`
+    /// of `code_action` is kept if the server doesn't send it back, and `code_action` is
+    /// returned unchanged if the server does not support resolving code actions.
+    pub fn resolve_code_action(
+        &self,
+        code_action: lsp::CodeAction,
+    ) -> impl Future<Output = Result<lsp::CodeAction>> {
+        // a server that isn't initialized yet fails the request
+        let resolve_provider = self.capabilities.get().map_or(true, |capabilities| {
+            matches!(
+                capabilities.code_action_provider,
+                Some(lsp::CodeActionProviderCapability::Options(
+                    lsp::CodeActionOptions {
+                        resolve_provider: Some(true),
+                        ..
+                    }
+                ))
+            )
+        });
+        let request = resolve_provider
+            .then(|| self.call::<lsp::request::CodeActionResolveRequest>(code_action.clone()));
+
+        async move {
+            let Some(request) = request else {
+                return Ok(code_action);
+            };
+            let json = request.await?;
+            let mut response: lsp::CodeAction = serde_json::from_value(json)?;
+            if response.data.is_none() {
+                response.data = code_action.data;
+            }
+            Ok(response)
+        }
+    }
+
+    /// Organizes the imports of a document, see [`Client::source_action`].
+    pub fn organize_imports(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        text: &Rope,
+    ) -> Option<impl Future<Output = Result<Option<lsp::CodeAction>>>> {
+        self.source_action(
+            text_document,
+            text,
+            lsp::CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+        )
+    }
+
+    /// Fixes all auto-fixable problems of a document, see [`Client::source_action`].
+    pub fn fix_all(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        text: &Rope,
+    ) -> Option<impl Future<Output = Result<Option<lsp::CodeAction>>>> {
+        self.source_action(text_document, text, lsp::CodeActionKind::SOURCE_FIX_ALL)
+    }
+
+    /// Requests the source action of `kind` for the whole document and resolves it if the
+    /// server left out its edit. Resolves to `None` if the server has no such action, e.g.
+    /// because the imports are already organized.
+    pub fn source_action(
+        &self,
+        text_document: lsp::TextDocumentIdentifier,
+        text: &Rope,
+        kind: lsp::CodeActionKind,
+    ) -> Option<impl Future<Output = Result<Option<lsp::CodeAction>>>> {
+        use lsp::request::Request as _;
+
+        // Return early if the server does not support code actions.
+        let resolve = match self.capabilities.get().map(|c| &c.code_action_provider) {
+            // the request fails before there is anything to resolve
+            None => false,
+            Some(Some(lsp::CodeActionProviderCapability::Simple(true))) => false,
+            Some(Some(lsp::CodeActionProviderCapability::Options(options))) => {
+                options.resolve_provider == Some(true)
+            }
+            _ => return None,
+        };
+
+        let range = util::range_to_lsp_range(
+            text,
+            helix_core::Range::new(0, text.len_chars()),
+            self.offset_encoding(),
+        );
+        let params = lsp::CodeActionParams {
+            text_document,
+            range,
+            context: lsp::CodeActionContext {
+                diagnostics: Vec::new(),
+                only: Some(vec![kind.clone()]),
+                trigger_kind: Some(lsp::CodeActionTriggerKind::INVOKED),
+            },
+            work_done_progress_params: lsp::WorkDoneProgressParams::default(),
+            partial_result_params: lsp::PartialResultParams::default(),
+        };
+
+        Some(Self::send_source_action(
+            self.server_tx.clone(),
+            [self.next_request_id(), self.next_request_id()],
+            params,
+            kind,
+            resolve,
+            self.request_timeout(lsp::request::CodeActionRequest::METHOD),
+        ))
+    }
+
+    /// Sends the code action request of [`Client::source_action`] with the first of `ids`
+    /// and, if `resolve` is set and the action comes without an edit, resolves it with the
+    /// second one.
+    async fn send_source_action(
+        server_tx: UnboundedSender<Payload>,
+        [request_id, resolve_id]: [jsonrpc::Id; 2],
+        params: lsp::CodeActionParams,
+        kind: lsp::CodeActionKind,
+        resolve: bool,
+        timeout: Duration,
+    ) -> Result<Option<lsp::CodeAction>> {
+        let json = Self::send_request::<lsp::request::CodeActionRequest>(
+            server_tx.clone(),
+            request_id,
+            params,
+            timeout,
+        )
+        .await?;
+        let response: Option<lsp::CodeActionResponse> = serde_json::from_value(json)?;
+        let code_action = match util::find_source_action(response.unwrap_or_default(), &kind) {
+            Some(code_action) => code_action,
+            None => return Ok(None),
+        };
+        if code_action.edit.is_some() || !resolve {
+            return Ok(Some(code_action));
+        }
+
+        let data = code_action.data.clone();
+        let json = Self::send_request::<lsp::request::CodeActionResolveRequest>(
+            server_tx,
+            resolve_id,
+            code_action,
+            timeout,
+        )
+        .await?;
+        let mut response: lsp::CodeAction = serde_json::from_value(json)?;
+        if response.data.is_none() {
+            response.data = data;
+        }
+        Ok(Some(response))
+    }
+
+    /// Renames the symbol at `position` in the document at `uri` to `new_name`. Resolves to
+    /// `None` if there is nothing the server can rename at `position`, and fails without
+    /// asking the server if `new_name` is empty.
+    pub fn rename(
+        &self,
+        uri: lsp::Url,
+        position: lsp::Position,
+        new_name: String,
+    ) -> Option<impl Future<Output = Result<Option<lsp::WorkspaceEdit>>>> {
+        // Return early if the language server does not support renaming.
+        if let Some(capabilities) = self.capabilities.get() {
+            match capabilities.rename_provider {
+                Some(lsp::OneOf::Left(true)) | Some(lsp::OneOf::Right(_)) => (),
+                // None | Some(false)
+                _ => return None,
+            };
+        }
+
+        let is_empty = new_name.trim().is_empty();
+        let params = lsp::RenameParams {
+            text_document_position: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier { uri },
+                position,
+            },
+            new_name,
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
+        };
+
+        let request = (!is_empty).then(|| self.call::<lsp::request::Rename>(params));
+
+        Some(async move {
+            let request =
+                request.ok_or_else(|| Error::Other(anyhow::anyhow!("the new name is empty")))?;
+            let json = request.await?;
+            let response: Option<lsp::WorkspaceEdit> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+
+    pub fn command(&self, command: lsp::Command) -> Option<impl Future<Output = Result<Value>>> {
+        // Return early if the language server does not support executing commands.
+        if let Some(capabilities) = self.capabilities.get() {
+            capabilities.execute_command_provider.as_ref()?;
+        }
+
+        let params = lsp::ExecuteCommandParams {
+            command: command.command,
+            arguments: command.arguments.unwrap_or_default(),
+            work_done_progress_params: lsp::WorkDoneProgressParams {
+                work_done_token: None,
+            },
         };
 
         Some(self.call::<lsp::request::ExecuteCommand>(params))
     }
+
+    /// Executes `command` with `arguments` through `workspace/executeCommand` and returns
+    /// the result of the command, if any.
+    ///
+    /// Fails with [`Error::UnsupportedCommand`] without contacting the server if the server
+    /// advertised the commands it supports and `command` isn't one of them.
+    pub fn execute_command(
+        &self,
+        command: String,
+        arguments: Vec<Value>,
+    ) -> Option<impl Future<Output = Result<Option<Value>>>> {
+        // Return early if the language server does not support executing commands.
+        let commands = match self.capabilities.get() {
+            Some(capabilities) => capabilities
+                .execute_command_provider
+                .as_ref()?
+                .commands
+                .as_slice(),
+            None => &[],
+        };
+
+        let request = if commands.is_empty() || commands.contains(&command) {
+            let params = lsp::ExecuteCommandParams {
+                command,
+                arguments,
+                work_done_progress_params: lsp::WorkDoneProgressParams {
+                    work_done_token: None,
+                },
+            };
+            Ok(self.call::<lsp::request::ExecuteCommand>(params))
+        } else {
+            Err(Error::UnsupportedCommand(command))
+        };
+
+        Some(async move {
+            let json = request?.await?;
+            let response: Option<Value> = serde_json::from_value(json)?;
+            Ok(response)
+        })
+    }
+}
+
+/// Finds the workspace roots of the documents at `doc_paths`, the first one is the root of
+/// the server.
+pub(crate) fn workspace_roots(root_markers: &[String], doc_paths: &[&Path]) -> Vec<PathBuf> {
+    let roots: Vec<_> = if doc_paths.is_empty() {
+        vec![find_root(None, root_markers)]
+    } else {
+        doc_paths
+            .iter()
+            .map(|path| find_root(path.parent().and_then(|path| path.to_str()), root_markers))
+            .collect()
+    };
+    outermost_roots(roots)
+}
+
+/// Deduplicates workspace `roots`, dropping any root nested inside another one.
+fn outermost_roots(roots: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut outermost: Vec<PathBuf> = Vec::with_capacity(roots.len());
+    for root in roots {
+        if outermost.iter().any(|existing| root.starts_with(existing)) {
+            continue;
+        }
+        outermost.retain(|existing| !existing.starts_with(&root));
+        outermost.push(root);
+    }
+    outermost
+}
+
+fn is_on_type_formatting_trigger(options: &lsp::DocumentOnTypeFormattingOptions, ch: char) -> bool {
+    let mut ch_buf = [0; 4];
+    let ch = &*ch.encode_utf8(&mut ch_buf);
+    options.first_trigger_character == ch
+        || options
+            .more_trigger_character
+            .iter()
+            .flatten()
+            .any(|trigger| trigger == ch)
+}
+
+fn workspace_folder(root: PathBuf) -> Option<lsp::WorkspaceFolder> {
+    let uri = lsp::Url::from_file_path(root).ok()?;
+    Some(lsp::WorkspaceFolder {
+        name: uri
+            .path_segments()
+            .and_then(|segments| segments.last())
+            .map(|basename| basename.to_string())
+            .unwrap_or_default(),
+        uri,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lsp, Client, Payload};
+    use crate::mock::{MockConnection, MockServer};
+    use crate::{jsonrpc, util, OffsetEncoding, ServerErrorCode};
+    use helix_core::Rope;
+    use serde_json::json;
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+    use tokio::time::timeout;
+
+    /// A client connected to a mock server with `capabilities` that answers no requests.
+    async fn initialized_client(capabilities: serde_json::Value) -> MockConnection {
+        MockServer::with_capabilities(capabilities)
+            .initialize()
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn deserialize_code_lens() {
+        let range = lsp::Range::new(lsp::Position::new(3, 0), lsp::Position::new(3, 8));
+
+        let unresolved: lsp::CodeLens = serde_json::from_value(json!({
+            "range": range,
+            "data": { "id": 1 },
+        }))
+        .unwrap();
+        assert_eq!(unresolved.range, range);
+        assert!(unresolved.command.is_none());
+
+        let resolved: lsp::CodeLens = serde_json::from_value(json!({
+            "range": range,
+            "command": { "title": "▶ Run", "command": "rust-analyzer.runSingle" },
+            "data": { "id": 1 },
+        }))
+        .unwrap();
+        let command = resolved.command.unwrap();
+        assert_eq!(command.title, "▶ Run");
+        assert_eq!(command.command, "rust-analyzer.runSingle");
+        assert_eq!(resolved.data, unresolved.data);
+    }
+
+    #[test]
+    fn workspace_diagnostic_report_with_unchanged_documents() {
+        let report: lsp::WorkspaceDiagnosticReport = serde_json::from_value(json!({
+            "items": [
+                {
+                    "kind": "full",
+                    "uri": "file:///project/src/main.rs",
+                    "version": 4,
+                    "resultId": "2",
+                    "items": [{
+                        "range": {
+                            "start": { "line": 1, "character": 8 },
+                            "end": { "line": 1, "character": 9 },
+                        },
+                        "severity": 2,
+                        "message": "unused variable: `x`",
+                    }],
+                },
+                {
+                    "kind": "unchanged",
+                    "uri": "file:///project/src/lib.rs",
+                    "version": null,
+                    "resultId": "1",
+                },
+            ],
+        }))
+        .unwrap();
+
+        match &report.items[0] {
+            lsp::WorkspaceDocumentDiagnosticReport::Full(full) => {
+                assert_eq!(full.uri.path(), "/project/src/main.rs");
+                assert_eq!(full.version, Some(4));
+                let report = &full.full_document_diagnostic_report;
+                assert_eq!(report.result_id.as_deref(), Some("2"));
+                assert_eq!(report.items[0].message, "unused variable: `x`");
+            }
+            item => panic!("expected a full report, got {:?}", item),
+        }
+        match &report.items[1] {
+            lsp::WorkspaceDocumentDiagnosticReport::Unchanged(unchanged) => {
+                assert_eq!(unchanged.uri.path(), "/project/src/lib.rs");
+                assert_eq!(
+                    unchanged.unchanged_document_diagnostic_report.result_id,
+                    "1"
+                );
+            }
+            item => panic!("expected an unchanged report, got {:?}", item),
+        }
+
+        // streamed reports come before the documents of the final response
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tx.send(json!({ "items": [serde_json::to_value(&report.items[1]).unwrap()] }))
+            .unwrap();
+        let first = lsp::WorkspaceDiagnosticReport {
+            items: vec![report.items[0].clone()],
+        };
+        let assembled = Client::assemble_workspace_diagnostic_report(rx, first);
+        assert_eq!(
+            assembled.items,
+            [report.items[1].clone(), report.items[0].clone()]
+        );
+    }
+
+    #[test]
+    fn on_type_formatting_only_for_trigger_characters() {
+        let options = lsp::DocumentOnTypeFormattingOptions {
+            first_trigger_character: "}".to_string(),
+            more_trigger_character: Some(vec![";".to_string(), "\n".to_string()]),
+        };
+
+        assert!(super::is_on_type_formatting_trigger(&options, '}'));
+        assert!(super::is_on_type_formatting_trigger(&options, ';'));
+        assert!(super::is_on_type_formatting_trigger(&options, '\n'));
+        assert!(!super::is_on_type_formatting_trigger(&options, 'a'));
+        assert!(!super::is_on_type_formatting_trigger(&options, '{'));
+    }
+
+    #[test]
+    fn deserialize_monikers() {
+        let monikers: Option<Vec<lsp::Moniker>> = serde_json::from_value(json!([
+            {
+                "scheme": "tsc",
+                "identifier": "lib:src/index.ts:parse",
+                "unique": "global",
+                "kind": "export",
+            },
+            {
+                "scheme": "tsc",
+                "identifier": "local-1",
+                "unique": "document",
+            },
+        ]))
+        .unwrap();
+        let monikers = monikers.unwrap();
+
+        assert_eq!(monikers[0].scheme, "tsc");
+        assert_eq!(monikers[0].identifier, "lib:src/index.ts:parse");
+        assert_eq!(monikers[0].unique, lsp::UniquenessLevel::Global);
+        assert_eq!(monikers[0].kind, Some(lsp::MonikerKind::Export));
+        assert_eq!(monikers[1].unique, lsp::UniquenessLevel::Document);
+        assert_eq!(monikers[1].kind, None);
+    }
+
+    #[tokio::test]
+    async fn timed_out_request_is_cancelled() {
+        let (server_tx, mut server_rx) = tokio::sync::mpsc::unbounded_channel();
+        let id = jsonrpc::Id::Num(7);
+
+        let response = Client::send_request::<lsp::request::Shutdown>(
+            server_tx,
+            id.clone(),
+            (),
+            Duration::from_millis(10),
+        )
+        .await;
+        assert!(matches!(
+            response,
+            Err(crate::Error::Timeout(timed_out, "shutdown")) if timed_out == id
+        ));
+
+        match server_rx.recv().await {
+            Some(Payload::Request { value, .. }) => assert_eq!(value.id, id),
+            payload => panic!("expected the request, got {:?}", payload),
+        }
+        match server_rx.recv().await {
+            Some(Payload::Notification(notification)) => {
+                assert_eq!(notification.method, "$/cancelRequest");
+                assert_eq!(
+                    serde_json::Value::from(notification.params),
+                    json!({ "id": 7 })
+                );
+            }
+            payload => panic!("expected a cancellation, got {:?}", payload),
+        }
+    }
+
+    fn formatting_params() -> lsp::DocumentFormattingParams {
+        lsp::DocumentFormattingParams {
+            text_document: lsp::TextDocumentIdentifier {
+                uri: lsp::Url::parse("file:///project/src/main.rs").unwrap(),
+            },
+            options: lsp::FormattingOptions {
+                tab_size: 4,
+                insert_spaces: true,
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn formatting_within_timeout_returns_edits() {
+        let (server_tx, mut server_rx) = tokio::sync::mpsc::unbounded_channel();
+        let edit = lsp::TextEdit {
+            range: lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(0, 4)),
+            new_text: "fn".to_string(),
+        };
+
+        let server = tokio::spawn({
+            let edit = edit.clone();
+            async move {
+                match server_rx.recv().await {
+                    Some(Payload::Request { chan, value }) => {
+                        assert_eq!(value.method, "textDocument/formatting");
+                        chan.send(Ok(json!([edit]))).await.unwrap();
+                    }
+                    payload => panic!("expected the request, got {:?}", payload),
+                }
+            }
+        });
+
+        let edits = Client::send_formatting_request(
+            server_tx,
+            jsonrpc::Id::Num(1),
+            formatting_params(),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        server.await.unwrap();
+        assert_eq!(edits, Some(vec![edit]));
+    }
+
+    #[tokio::test]
+    async fn formatting_timeout_leaves_document_unformatted() {
+        let (server_tx, mut server_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let edits = Client::send_formatting_request(
+            server_tx,
+            jsonrpc::Id::Num(2),
+            formatting_params(),
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap();
+        assert_eq!(edits, None);
+
+        assert!(matches!(
+            server_rx.recv().await,
+            Some(Payload::Request { .. })
+        ));
+        match server_rx.recv().await {
+            Some(Payload::Notification(notification)) => {
+                assert_eq!(notification.method, "$/cancelRequest");
+            }
+            payload => panic!("expected a cancellation, got {:?}", payload),
+        }
+    }
+
+    #[tokio::test]
+    async fn range_formatting_request_carries_selection() {
+        use helix_core::Range;
+
+        let mut connection = MockServer::with_capabilities(json!({
+            "documentRangeFormattingProvider": true,
+            "positionEncoding": "utf-8",
+        }))
+        .respond(
+            "textDocument/rangeFormatting",
+            json!([{
+                "range": {
+                    "start": { "line": 1, "character": 7 },
+                    "end": { "line": 1, "character": 9 },
+                },
+                "newText": " ",
+            }]),
+        )
+        .initialize()
+        .await
+        .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let doc = Rope::from("fn main() {\n    let  x = 1;\n}\n");
+        let options = formatting_params().options;
+        // select `let  x = 1;` on the second line
+        let edits = connection
+            .client
+            .range_formatting(uri, &doc, Range::new(16, 27), options)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, " ");
+
+        let params = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .find_map(|call| match call {
+                jsonrpc::Call::MethodCall(call)
+                    if call.method == "textDocument/rangeFormatting" =>
+                {
+                    Some(serde_json::Value::from(call.params))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            params["range"],
+            json!({
+                "start": { "line": 1, "character": 4 },
+                "end": { "line": 1, "character": 15 },
+            })
+        );
+
+        // servers that only format whole documents aren't asked
+        let connection = initialized_client(json!({ "documentFormattingProvider": true })).await;
+        assert!(connection
+            .client
+            .range_formatting(
+                lsp::Url::parse("file:///project/src/main.rs").unwrap(),
+                &doc,
+                Range::new(16, 27),
+                formatting_params().options,
+            )
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn organize_imports_applies_resolved_edit() {
+        use crate::util::generate_transaction_from_edits;
+        use helix_core::Rope;
+
+        let (server_tx, mut server_rx) = tokio::sync::mpsc::unbounded_channel();
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let params = |uri: &lsp::Url| lsp::CodeActionParams {
+            text_document: lsp::TextDocumentIdentifier { uri: uri.clone() },
+            range: lsp::Range::default(),
+            context: lsp::CodeActionContext {
+                only: Some(vec![lsp::CodeActionKind::SOURCE_ORGANIZE_IMPORTS]),
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let edit = json!({
+            "changes": {
+                uri.as_str(): [{
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 2, "character": 0 },
+                    },
+                    "newText": "use a;\nuse b;\n",
+                }],
+            },
+        });
+
+        let server = tokio::spawn(async move {
+            match server_rx.recv().await {
+                Some(Payload::Request { chan, value }) => {
+                    assert_eq!(value.method, "textDocument/codeAction");
+                    let params = serde_json::Value::from(value.params);
+                    assert_eq!(params["context"]["only"], json!(["source.organizeImports"]));
+                    // servers may ignore `only`
+                    let actions = json!([
+                        { "title": "Fix typo", "kind": "quickfix" },
+                        {
+                            "title": "Organize imports",
+                            "kind": "source.organizeImports.rust",
+                            "data": 7,
+                        },
+                    ]);
+                    chan.send(Ok(actions)).await.unwrap();
+                }
+                payload => panic!("expected a request, got {:?}", payload),
+            }
+            match server_rx.recv().await {
+                Some(Payload::Request { chan, value }) => {
+                    assert_eq!(value.method, "codeAction/resolve");
+                    let mut action = serde_json::Value::from(value.params);
+                    assert_eq!(action["data"], json!(7));
+                    action["edit"] = edit;
+                    chan.send(Ok(action)).await.unwrap();
+                }
+                payload => panic!("expected a request, got {:?}", payload),
+            }
+            match server_rx.recv().await {
+                // nothing left to organize
+                Some(Payload::Request { chan, .. }) => chan.send(Ok(json!([]))).await.unwrap(),
+                payload => panic!("expected a request, got {:?}", payload),
+            }
+        });
+
+        let ids = [jsonrpc::Id::Num(1), jsonrpc::Id::Num(2)];
+        let code_action = Client::send_source_action(
+            server_tx.clone(),
+            ids.clone(),
+            params(&uri),
+            lsp::CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+            true,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(code_action.title, "Organize imports");
+
+        let mut changes = code_action.edit.unwrap().changes.unwrap();
+        let edits = changes.remove(&uri).unwrap();
+        let mut text = Rope::from("use b;\nuse a;\nfn main() {}\n");
+        let transaction = generate_transaction_from_edits(&text, edits, OffsetEncoding::Utf8);
+        assert!(transaction.apply(&mut text));
+        assert_eq!(text, "use a;\nuse b;\nfn main() {}\n");
+
+        let code_action = Client::send_source_action(
+            server_tx,
+            ids,
+            params(&uri),
+            lsp::CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+            true,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(code_action, None);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn hover_batch_results_follow_positions() {
+        fn hover(params: &serde_json::Value) -> serde_json::Value {
+            match params["position"]["line"].as_u64().unwrap() {
+                // there is nothing to show on line 5
+                5 => json!(null),
+                line => json!({ "contents": format!("line {}", line) }),
+            }
+        }
+
+        let mut connection = MockServer::with_capabilities(json!({ "hoverProvider": true }))
+            .respond_with("textDocument/hover", hover)
+            .fail_once("textDocument/hover", lsp::error_codes::CONTENT_MODIFIED)
+            .initialize()
+            .await
+            .unwrap();
+        let text_document = lsp::TextDocumentIdentifier {
+            uri: lsp::Url::parse("file:///project/src/main.rs").unwrap(),
+        };
+        let positions = [2, 5, 9]
+            .into_iter()
+            .map(|line| lsp::Position::new(line, 4))
+            .collect();
+
+        let hovers = connection
+            .client
+            .hover_batch(text_document, positions, 2)
+            .unwrap()
+            .await
+            .unwrap();
+
+        let contents: Vec<_> = hovers
+            .into_iter()
+            .map(|hover| {
+                hover.map(|hover| match hover.contents {
+                    lsp::HoverContents::Scalar(lsp::MarkedString::String(contents)) => contents,
+                    contents => panic!("unexpected hover contents {:?}", contents),
+                })
+            })
+            .collect();
+        assert_eq!(
+            contents,
+            [Some("line 2".to_string()), None, Some("line 9".to_string())]
+        );
+
+        // the hover the server dropped with `ContentModified` was retried
+        let hovers = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .filter(|call| {
+                matches!(call, jsonrpc::Call::MethodCall(call) if call.method == "textDocument/hover")
+            })
+            .count();
+        assert_eq!(hovers, 4);
+    }
+
+    #[test]
+    fn workspace_folders_from_sibling_projects() {
+        let base = std::env::temp_dir();
+        let roots = super::outermost_roots(vec![
+            base.join("monorepo/frontend"),
+            base.join("monorepo/backend"),
+            base.join("monorepo/frontend/nested"),
+        ]);
+        assert_eq!(
+            roots,
+            vec![
+                base.join("monorepo/frontend"),
+                base.join("monorepo/backend")
+            ]
+        );
+
+        let folders: Vec<_> = roots
+            .into_iter()
+            .filter_map(super::workspace_folder)
+            .collect();
+        assert_eq!(folders.len(), 2);
+        assert_eq!(folders[0].name, "frontend");
+        assert_eq!(folders[1].name, "backend");
+
+        // a root containing the others replaces them
+        let roots =
+            super::outermost_roots(vec![base.join("monorepo/frontend"), base.join("monorepo")]);
+        assert_eq!(roots, vec![base.join("monorepo")]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn shutdown_cancels_initialization() {
+        // `cat` echoes the initialize request back instead of answering it, so the
+        // server never finishes initializing.
+        let (client, _incoming, initialize_notify) = Client::start(
+            "cat",
+            &[],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+        let client = Arc::new(client);
+        let initialize = crate::initialize_client(client.clone(), initialize_notify);
+
+        timeout(Duration::from_secs(5), client.force_shutdown())
+            .await
+            .expect("shutdown waited for initialization")
+            .unwrap();
+        timeout(Duration::from_secs(5), initialize)
+            .await
+            .expect("initialization was not cancelled")
+            .unwrap();
+        assert!(!client.is_initialized());
+
+        let mut process = client.process.lock().await;
+        let status = timeout(Duration::from_secs(5), process.as_mut().unwrap().wait())
+            .await
+            .expect("server process was not killed")
+            .unwrap();
+        assert!(!status.success());
+    }
+
+    #[tokio::test]
+    async fn requests_fail_fast_before_initialization() {
+        let (client_io, _server_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        // requests time out after a minute
+        let (client, _incoming, _initialize_notify) = Client::connect(
+            None,
+            tokio::io::BufReader::new(client_reader),
+            client_writer,
+            tokio::io::empty(),
+            "mock",
+            None,
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        );
+
+        let response = timeout(
+            Duration::from_secs(1),
+            client.call::<lsp::request::WorkspaceSymbolRequest>(lsp::WorkspaceSymbolParams {
+                query: String::new(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            }),
+        )
+        .await
+        .expect("the request waited for the timeout");
+        assert!(matches!(response, Err(crate::Error::NotInitialized)));
+        assert_eq!(client.pending_requests(), 0);
+
+        // the helpers don't know the capabilities of the server yet and fail the same way
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let hover = client
+            .text_document_hover(
+                lsp::TextDocumentIdentifier::new(uri.clone()),
+                lsp::Position::new(0, 0),
+                None,
+            )
+            .expect("the hover request was skipped");
+        let response = timeout(Duration::from_secs(1), hover)
+            .await
+            .expect("the request waited for the timeout");
+        assert!(matches!(response, Err(crate::Error::NotInitialized)));
+        let hovers = client
+            .hover_batch(
+                lsp::TextDocumentIdentifier::new(uri.clone()),
+                vec![lsp::Position::new(0, 0), lsp::Position::new(1, 0)],
+                2,
+            )
+            .expect("the hover requests were skipped");
+        let response = timeout(Duration::from_secs(1), hovers)
+            .await
+            .expect("the requests waited for the timeout");
+        assert!(matches!(response, Err(crate::Error::NotInitialized)));
+        // notifications need the capabilities to be built
+        assert!(client.did_save(uri, None).is_none());
+        assert!(client.signature_help_trigger_characters().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_apply_edit_reply() {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let (client, _incoming, _initialize_notify) = Client::connect(
+            None,
+            BufReader::new(client_reader),
+            client_writer,
+            tokio::io::empty(),
+            "mock",
+            None,
+            &[],
+            0,
+            5,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        );
+
+        // replies are sent even before the server finished initializing
+        client
+            .reply_apply_edit(
+                jsonrpc::Id::Num(7),
+                lsp::ApplyWorkspaceEditResponse {
+                    applied: false,
+                    failure_reason: Some("document version mismatch".to_string()),
+                    failed_change: Some(1),
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut server = BufReader::new(server_io);
+        let mut header = String::new();
+        server.read_line(&mut header).await.unwrap();
+        let content_length: usize = header
+            .trim()
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        while header != "\r\n" {
+            header.clear();
+            server.read_line(&mut header).await.unwrap();
+        }
+        let mut content = vec![0; content_length];
+        server.read_exact(&mut content).await.unwrap();
+
+        let output: serde_json::Value = serde_json::from_slice(&content).unwrap();
+        assert_eq!(
+            output,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "result": {
+                    "applied": false,
+                    "failureReason": "document version mismatch",
+                    "failedChange": 1,
+                },
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn workspace_folders_response_lists_every_root() {
+        let workspace =
+            std::env::temp_dir().join(format!("helix-lsp-folders-{}", std::process::id()));
+        let mut doc_paths = Vec::new();
+        for project in ["frontend", "backend"] {
+            let root = workspace.join(project);
+            std::fs::create_dir_all(root.join("src")).unwrap();
+            std::fs::write(root.join("Cargo.toml"), "").unwrap();
+            doc_paths.push(root.join("src").join("main.rs"));
+        }
+        let doc_paths: Vec<_> = doc_paths.iter().map(std::path::PathBuf::as_path).collect();
+
+        let (client, _incoming, _initialize_notify) = Client::start(
+            "cat",
+            &[],
+            None,
+            HashMap::new(),
+            &["Cargo.toml".to_string()],
+            0,
+            60,
+            HashMap::new(),
+            &doc_paths,
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let folders = client.workspace_folders_response().unwrap();
+        let names: Vec<_> = folders.iter().map(|folder| folder.name.as_str()).collect();
+        assert_eq!(names, ["frontend", "backend"]);
+        assert_eq!(
+            folders[1].uri,
+            lsp::Url::from_file_path(workspace.join("backend")).unwrap()
+        );
+
+        // a server asking after every folder was closed gets `null`
+        client.did_change_workspace_folders(Vec::new(), folders);
+        assert_eq!(client.workspace_folders_response(), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stderr_tail_keeps_the_last_lines() {
+        let (client, _incoming, _initialize_notify) = Client::start(
+            "sh",
+            &["-c".to_string(), "seq 60 >&2; cat".to_string()],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+
+        timeout(Duration::from_secs(5), async {
+            while client.stderr_tail().last().map(String::as_str) != Some("60") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let expected: Vec<_> = (11..=60).map(|line| line.to_string()).collect();
+        assert_eq!(client.stderr_tail(), expected);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn crashed_server_reports_exit_status() {
+        use crate::{Call, Notification, ServerExitStatus};
+
+        let (_client, mut incoming, _initialize_notify) = Client::start(
+            "sh",
+            &["-c".to_string(), "exit 3".to_string()],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+
+        let notification = match timeout(Duration::from_secs(5), incoming.recv()).await {
+            Ok(Some((_, Call::Notification(notification)))) => notification,
+            call => panic!("expected the exit notification, got {:?}", call),
+        };
+        let status = match Notification::parse(&notification.method, notification.params) {
+            Ok(Notification::Exit(Some(status))) => status,
+            notification => panic!("expected an exit status, got {:?}", notification),
+        };
+        assert_eq!(
+            status,
+            ServerExitStatus {
+                code: Some(3),
+                signal: None
+            }
+        );
+        assert!(!status.success());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unsupported_command_is_not_sent() {
+        let (client, mut incoming, _initialize_notify) = Client::start(
+            "cat",
+            &[],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+        client
+            .capabilities
+            .set(lsp::ServerCapabilities {
+                execute_command_provider: Some(lsp::ExecuteCommandOptions {
+                    commands: vec!["rust-analyzer.runSingle".to_string()],
+                    work_done_progress_options: Default::default(),
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let response = client
+            .execute_command("rust-analyzer.debugSingle".to_string(), Vec::new())
+            .unwrap()
+            .await;
+        assert!(matches!(
+            response,
+            Err(crate::Error::UnsupportedCommand(command)) if command == "rust-analyzer.debugSingle"
+        ));
+        assert_eq!(client.pending_requests(), 0);
+        // `cat` would echo back anything that was sent
+        assert!(timeout(Duration::from_millis(100), incoming.recv())
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn initialized_waits_for_configuration_reply() {
+        use crate::Call;
+
+        // `cat` echoes everything back, so requests sent by the client also arrive as
+        // requests from the server.
+        let (client, mut incoming, initialize_notify) = Client::start(
+            "cat",
+            &[],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            true,
+        )
+        .unwrap();
+        let client = Arc::new(client);
+        // let the transport send requests as if the server was initialized
+        initialize_notify.notify_one();
+        client
+            .capabilities
+            .set(lsp::ServerCapabilities::default())
+            .unwrap();
+
+        let initialized = tokio::spawn({
+            let client = client.clone();
+            async move { client.notify_initialized().await }
+        });
+        let configuration = tokio::spawn(client.call::<lsp::request::WorkspaceConfiguration>(
+            lsp::ConfigurationParams { items: Vec::new() },
+        ));
+
+        // skips the `initialized` notification injected by the transport
+        async fn next_call(
+            incoming: &mut tokio::sync::mpsc::UnboundedReceiver<(usize, Call)>,
+        ) -> Option<Call> {
+            loop {
+                match timeout(Duration::from_millis(200), incoming.recv()).await {
+                    Ok(Some((_, Call::Notification(notification))))
+                        if notification.jsonrpc.is_none() => {}
+                    Ok(Some((_, call))) => return Some(call),
+                    Ok(None) | Err(_) => return None,
+                }
+            }
+        }
+
+        let id = match next_call(&mut incoming).await {
+            Some(Call::MethodCall(call)) => {
+                assert_eq!(call.method, "workspace/configuration");
+                call.id
+            }
+            call => panic!("expected a configuration request, got {:?}", call),
+        };
+        // nothing else is sent until the configuration request is answered
+        assert_eq!(next_call(&mut incoming).await, None);
+
+        client.reply(id, Ok(json!([]))).await.unwrap();
+        match next_call(&mut incoming).await {
+            Some(Call::Notification(notification)) => {
+                assert_eq!(notification.method, "initialized")
+            }
+            call => panic!("expected the initialized notification, got {:?}", call),
+        }
+        initialized.await.unwrap().unwrap();
+        configuration.await.unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn pending_requests_are_counted() {
+        use crate::Call;
+
+        // `cat` echoes the request back as a request from the server, which lets the test
+        // answer it.
+        let (client, mut incoming, initialize_notify) = Client::start(
+            "cat",
+            &[],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            60,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+        // let the transport send requests as if the server was initialized
+        initialize_notify.notify_one();
+        assert_eq!(client.pending_requests(), 0);
+        assert_eq!(client.last_response_at(), None);
+
+        let request = tokio::spawn(client.call::<lsp::request::Shutdown>(()));
+        let id = loop {
+            match timeout(Duration::from_secs(5), incoming.recv())
+                .await
+                .unwrap()
+            {
+                Some((_, Call::MethodCall(call))) => break call.id,
+                Some(_) => (),
+                None => panic!("server exited"),
+            }
+        };
+        assert_eq!(client.pending_requests(), 1);
+
+        client.reply(id, Ok(json!(null))).await.unwrap();
+        timeout(Duration::from_secs(5), request)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.pending_requests(), 0);
+        assert!(client.last_response_at().is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn method_timeout_overrides_global_timeout() {
+        use crate::Call;
+
+        let method_timeouts = HashMap::from([("shutdown", Duration::from_secs(5))]);
+        // `cat` echoes the request back as a request from the server, which lets the test
+        // answer it. With a global timeout of zero every other request times out at once.
+        let (client, mut incoming, initialize_notify) = Client::start(
+            "cat",
+            &[],
+            None,
+            HashMap::new(),
+            &[],
+            0,
+            0,
+            method_timeouts,
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        )
+        .unwrap();
+        // let the transport send requests as if the server was initialized
+        initialize_notify.notify_one();
+        client
+            .capabilities
+            .set(lsp::ServerCapabilities::default())
+            .unwrap();
+
+        let response = client
+            .call::<lsp::request::WorkspaceSymbolRequest>(lsp::WorkspaceSymbolParams {
+                query: String::new(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await;
+        assert!(matches!(
+            response,
+            Err(crate::Error::Timeout(_, "workspace/symbol"))
+        ));
+
+        let request = tokio::spawn(client.call::<lsp::request::Shutdown>(()));
+        let id = loop {
+            match timeout(Duration::from_secs(5), incoming.recv())
+                .await
+                .unwrap()
+            {
+                Some((_, Call::MethodCall(call))) if call.method == "shutdown" => break call.id,
+                Some(_) => (),
+                None => panic!("server exited"),
+            }
+        };
+        client.reply(id, Ok(json!(null))).await.unwrap();
+        timeout(Duration::from_secs(5), request)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn initialize_result_populates_server_info() {
+        let client = MockServer::new()
+            .respond(
+                "initialize",
+                json!({
+                    "capabilities": {},
+                    "serverInfo": { "name": "rust-analyzer", "version": "0.3.1402" },
+                }),
+            )
+            .initialize()
+            .await
+            .unwrap()
+            .client;
+
+        let server_info = client.server_info().unwrap();
+        assert_eq!(server_info.name, "rust-analyzer");
+        assert_eq!(server_info.version.as_deref(), Some("0.3.1402"));
+
+        // servers don't have to tell who they are
+        let client = initialized_client(json!({})).await.client;
+        assert_eq!(client.server_info(), None);
+    }
+
+    #[test]
+    fn did_change_configuration_serializes_settings() {
+        let settings = json!({ "rust-analyzer": { "checkOnSave": false } });
+        let notification = Client::notification::<lsp::notification::DidChangeConfiguration>(
+            lsp::DidChangeConfigurationParams {
+                settings: settings.clone(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(notification.method, "workspace/didChangeConfiguration");
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "workspace/didChangeConfiguration",
+                "params": { "settings": settings },
+            })
+        );
+        assert!(matches!(notification.params, jsonrpc::Params::Map(_)));
+    }
+
+    #[tokio::test]
+    async fn references_pass_include_declaration() {
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let location = lsp::Location::new(
+            uri.clone(),
+            lsp::Range::new(lsp::Position::new(4, 8), lsp::Position::new(4, 12)),
+        );
+        let mut connection = MockServer::with_capabilities(json!({ "referencesProvider": true }))
+            .respond("textDocument/references", json!([location]))
+            .initialize()
+            .await
+            .unwrap();
+
+        for include_declaration in [false, true] {
+            let references = connection
+                .client
+                .references(uri.clone(), lsp::Position::new(0, 3), include_declaration)
+                .unwrap()
+                .await
+                .unwrap();
+            assert_eq!(references, [location.clone()]);
+
+            let params = std::iter::from_fn(|| connection.received.try_recv().ok())
+                .find_map(|call| match call {
+                    jsonrpc::Call::MethodCall(call) if call.method == "textDocument/references" => {
+                        Some(serde_json::Value::from(call.params))
+                    }
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(
+                params["context"],
+                json!({ "includeDeclaration": include_declaration })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn code_action_sends_context() {
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let range = lsp::Range::new(lsp::Position::new(2, 4), lsp::Position::new(2, 9));
+        let diagnostic = lsp::Diagnostic {
+            range,
+            severity: Some(lsp::DiagnosticSeverity::WARNING),
+            message: "unused variable: `count`".to_string(),
+            ..Default::default()
+        };
+        let mut connection = MockServer::with_capabilities(json!({ "codeActionProvider": true }))
+            .respond(
+                "textDocument/codeAction",
+                json!([
+                    { "title": "Prefix with an underscore", "kind": "quickfix" },
+                    { "title": "Run clippy", "command": "clippy" },
+                ]),
+            )
+            .initialize()
+            .await
+            .unwrap();
+
+        let context = lsp::CodeActionContext {
+            diagnostics: vec![diagnostic.clone()],
+            only: Some(vec![lsp::CodeActionKind::QUICKFIX]),
+            trigger_kind: Some(lsp::CodeActionTriggerKind::INVOKED),
+        };
+        let actions = connection
+            .client
+            .code_action(uri, range, context)
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(
+            actions,
+            [
+                lsp::CodeActionOrCommand::CodeAction(lsp::CodeAction {
+                    title: "Prefix with an underscore".to_string(),
+                    kind: Some(lsp::CodeActionKind::QUICKFIX),
+                    ..Default::default()
+                }),
+                lsp::CodeActionOrCommand::Command(lsp::Command {
+                    title: "Run clippy".to_string(),
+                    command: "clippy".to_string(),
+                    arguments: None,
+                }),
+            ]
+        );
+
+        let params = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .find_map(|call| match call {
+                jsonrpc::Call::MethodCall(call) if call.method == "textDocument/codeAction" => {
+                    Some(serde_json::Value::from(call.params))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            params["context"],
+            json!({
+                "diagnostics": [diagnostic],
+                "only": ["quickfix"],
+                "triggerKind": 1,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_code_action_fills_in_edit() {
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let edit = lsp::WorkspaceEdit::new(
+            [(
+                uri,
+                vec![lsp::TextEdit::new(
+                    lsp::Range::new(lsp::Position::new(2, 8), lsp::Position::new(2, 8)),
+                    "_".to_string(),
+                )],
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let code_action = lsp::CodeAction {
+            title: "Prefix with an underscore".to_string(),
+            kind: Some(lsp::CodeActionKind::QUICKFIX),
+            data: Some(json!({ "id": 1 })),
+            ..Default::default()
+        };
+        let connection = MockServer::with_capabilities(
+            json!({ "codeActionProvider": { "resolveProvider": true } }),
+        )
+        .respond(
+            "codeAction/resolve",
+            json!({
+                "title": "Prefix with an underscore",
+                "kind": "quickfix",
+                "edit": edit,
+            }),
+        )
+        .initialize()
+        .await
+        .unwrap();
+
+        let resolved = connection
+            .client
+            .resolve_code_action(code_action.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            resolved,
+            lsp::CodeAction {
+                edit: Some(edit),
+                ..code_action.clone()
+            }
+        );
+
+        // servers that can't resolve code actions leave them as they are
+        let connection = initialized_client(json!({ "codeActionProvider": true })).await;
+        let resolved = connection
+            .client
+            .resolve_code_action(code_action.clone())
+            .await
+            .unwrap();
+        assert_eq!(resolved, code_action);
+    }
+
+    #[tokio::test]
+    async fn semantic_tokens_range_positions_are_absolute() {
+        let mut connection = MockServer::with_capabilities(json!({
+            "semanticTokensProvider": {
+                "legend": { "tokenTypes": ["function"], "tokenModifiers": [] },
+                "range": true,
+            },
+        }))
+        .respond(
+            "textDocument/semanticTokens/range",
+            json!({ "data": [2, 3, 4, 0, 0] }),
+        )
+        .initialize()
+        .await
+        .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let text = Rope::from("use std::io;\n\nfn main() {\n}\n");
+        // from the start of line 2 to the end of the document
+        let range = helix_core::Range::new(14, text.len_chars());
+        let tokens = connection
+            .client
+            .semantic_tokens_range(uri, &text, range)
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            util::decode_semantic_tokens(&tokens.data)[0].range,
+            lsp::Range::new(lsp::Position::new(2, 3), lsp::Position::new(2, 7))
+        );
+
+        let params = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .find_map(|call| match call {
+                jsonrpc::Call::MethodCall(call)
+                    if call.method == "textDocument/semanticTokens/range" =>
+                {
+                    Some(serde_json::Value::from(call.params))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            params["range"],
+            json!({
+                "start": { "line": 2, "character": 0 },
+                "end": { "line": 4, "character": 0 },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_inlay_hint_adds_tooltip() {
+        let connection = MockServer::with_capabilities(
+            json!({ "inlayHintProvider": { "resolveProvider": true } }),
+        )
+        .respond(
+            "inlayHint/resolve",
+            json!({
+                "position": { "line": 1, "character": 9 },
+                "label": ": i32",
+                "tooltip": "the type of `x`",
+            }),
+        )
+        .initialize()
+        .await
+        .unwrap();
+
+        let hint: lsp::InlayHint = serde_json::from_value(json!({
+            "position": { "line": 1, "character": 9 },
+            "label": ": i32",
+            "data": { "id": 7 },
+        }))
+        .unwrap();
+        let resolved = connection
+            .client
+            .resolve_inlay_hint(hint)
+            .unwrap()
+            .await
+            .unwrap();
+        // inlay hints can't be compared
+        assert_eq!(
+            serde_json::to_value(resolved).unwrap(),
+            json!({
+                "position": { "line": 1, "character": 9 },
+                "label": ": i32",
+                "tooltip": "the type of `x`",
+                "data": { "id": 7 },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn inlay_hint_with_label_parts() {
+        let mut connection = MockServer::with_capabilities(json!({ "inlayHintProvider": true }))
+            .respond(
+                "textDocument/inlayHint",
+                json!([
+                    {
+                        "position": { "line": 1, "character": 9 },
+                        "label": [
+                            { "value": ": " },
+                            {
+                                "value": "Vec",
+                                "location": {
+                                    "uri": "file:///rust/library/alloc/src/vec/mod.rs",
+                                    "range": {
+                                        "start": { "line": 396, "character": 11 },
+                                        "end": { "line": 396, "character": 14 },
+                                    },
+                                },
+                            },
+                            { "value": "<i32>" },
+                        ],
+                        "kind": 1,
+                    },
+                    {
+                        "position": { "line": 2, "character": 8 },
+                        "label": "value:",
+                        "kind": 2,
+                        "paddingRight": true,
+                    },
+                ]),
+            )
+            .initialize()
+            .await
+            .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let text = Rope::from("fn main() {\n    let x = vec![1];\n    dbg!(x);\n}\n");
+        let hints = connection
+            .client
+            .inlay_hint(uri, &text, helix_core::Range::new(0, text.len_chars()))
+            .unwrap()
+            .await
+            .unwrap();
+        let labels: Vec<_> = hints.iter().map(util::inlay_hint_text).collect();
+        assert_eq!(labels, [": Vec<i32>", "value: "]);
+        match &hints[0].label {
+            lsp::InlayHintLabel::LabelParts(parts) => {
+                assert_eq!(parts[1].location.as_ref().unwrap().range.start.line, 396)
+            }
+            label => panic!("expected label parts, got {:?}", label),
+        }
+        assert_eq!(hints[1].kind, Some(lsp::InlayHintKind::PARAMETER));
+
+        let params = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .find_map(|call| match call {
+                jsonrpc::Call::MethodCall(call) if call.method == "textDocument/inlayHint" => {
+                    Some(serde_json::Value::from(call.params))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            params["range"],
+            json!({
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 4, "character": 0 },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn call_hierarchy_items_of_several_positions_are_merged() {
+        let item = |name: &str, line: u32| {
+            json!({
+                "name": name,
+                "kind": 12,
+                "uri": "file:///project/src/main.rs",
+                "range": {
+                    "start": { "line": line, "character": 0 },
+                    "end": { "line": line + 2, "character": 1 },
+                },
+                "selectionRange": {
+                    "start": { "line": line, "character": 3 },
+                    "end": { "line": line, "character": 3 + name.len() },
+                },
+            })
+        };
+        let mut connection =
+            MockServer::with_capabilities(json!({ "callHierarchyProvider": true }))
+                // both cursors are in calls to the same functions
+                .respond(
+                    "textDocument/prepareCallHierarchy",
+                    json!([item("main", 0), item("helper", 4)]),
+                )
+                .initialize()
+                .await
+                .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let items = connection
+            .client
+            .prepare_call_hierarchy(
+                uri,
+                vec![lsp::Position::new(1, 4), lsp::Position::new(2, 4)],
+            )
+            .unwrap()
+            .await
+            .unwrap();
+        let names: Vec<_> = items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, ["main", "helper"]);
+
+        let lines: Vec<_> = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .filter_map(|call| match call {
+                jsonrpc::Call::MethodCall(call)
+                    if call.method == "textDocument/prepareCallHierarchy" =>
+                {
+                    serde_json::Value::from(call.params)["position"]["line"].as_u64()
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.contains(&1) && lines.contains(&2));
+    }
+
+    #[tokio::test]
+    async fn did_save_includes_text_only_if_requested() {
+        async fn saved_text(save: serde_json::Value) -> serde_json::Value {
+            let mut connection =
+                initialized_client(json!({ "textDocumentSync": { "save": save } })).await;
+
+            let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+            let text = Rope::from("fn main() {}\n");
+            connection
+                .client
+                .did_save(uri, Some(&text))
+                .unwrap()
+                .await
+                .unwrap();
+
+            loop {
+                match connection.received.recv().await.unwrap() {
+                    jsonrpc::Call::Notification(notification)
+                        if notification.method == "textDocument/didSave" =>
+                    {
+                        break serde_json::Value::from(notification.params)
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let params = saved_text(json!({ "includeText": true })).await;
+        assert_eq!(params["text"], "fn main() {}\n");
+
+        let params = saved_text(json!(true)).await;
+        assert_eq!(params["textDocument"]["uri"], "file:///project/src/main.rs");
+        assert!(params.get("text").is_none());
+    }
+
+    #[tokio::test]
+    async fn will_save_wait_until_edits_apply_to_the_document() {
+        let mut connection = MockServer::with_capabilities(json!({
+            "textDocumentSync": { "willSave": false, "willSaveWaitUntil": true },
+        }))
+        .respond(
+            "textDocument/willSaveWaitUntil",
+            json!([{
+                "range": {
+                    "start": { "line": 0, "character": 9 },
+                    "end": { "line": 0, "character": 11 },
+                },
+                "newText": " {}",
+            }]),
+        )
+        .initialize()
+        .await
+        .unwrap();
+        let client = connection.client.clone();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let reason = lsp::TextDocumentSaveReason::AFTER_DELAY;
+        assert!(client.will_save(uri.clone(), reason).is_none());
+        let edits = client
+            .will_save_wait_until(uri, reason)
+            .unwrap()
+            .await
+            .unwrap();
+
+        let mut text = Rope::from("fn main(){}\n");
+        let transaction =
+            util::generate_transaction_from_edits(&text, edits, client.offset_encoding());
+        assert!(transaction.apply(&mut text));
+        assert_eq!(text, "fn main() {}\n");
+
+        let params = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .find_map(|call| match call {
+                jsonrpc::Call::MethodCall(call)
+                    if call.method == "textDocument/willSaveWaitUntil" =>
+                {
+                    Some(serde_json::Value::from(call.params))
+                }
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(params["reason"], 2);
+    }
+
+    #[tokio::test]
+    async fn added_workspace_folder_is_stored_and_notified() {
+        let mut connection = initialized_client(json!({
+            "workspace": {
+                "workspaceFolders": { "supported": true, "changeNotifications": true },
+            },
+        }))
+        .await;
+        let client = connection.client.clone();
+
+        let folder = lsp::WorkspaceFolder {
+            uri: lsp::Url::parse("file:///projects/backend").unwrap(),
+            name: "backend".to_string(),
+        };
+        client
+            .did_change_workspace_folders(vec![folder.clone()], Vec::new())
+            .unwrap()
+            .await
+            .unwrap();
+        assert!(client.workspace_folders().contains(&folder));
+
+        let params = loop {
+            match connection.received.recv().await.unwrap() {
+                jsonrpc::Call::Notification(notification)
+                    if notification.method == "workspace/didChangeWorkspaceFolders" =>
+                {
+                    break serde_json::Value::from(notification.params)
+                }
+                _ => (),
+            }
+        };
+        assert_eq!(
+            params["event"],
+            json!({
+                "added": [{ "uri": "file:///projects/backend", "name": "backend" }],
+                "removed": [],
+            })
+        );
+
+        // without the capability the folders are still tracked, but nothing is sent
+        let client = initialized_client(json!({})).await.client;
+        assert!(client
+            .did_change_workspace_folders(vec![folder.clone()], Vec::new())
+            .is_none());
+        assert!(client.workspace_folders().contains(&folder));
+        assert!(client
+            .did_change_workspace_folders(Vec::new(), vec![folder.clone()])
+            .is_none());
+        assert!(!client.workspace_folders().contains(&folder));
+    }
+
+    #[tokio::test]
+    async fn workspace_symbols_of_both_shapes() {
+        async fn workspace_symbols(result: serde_json::Value) -> lsp::WorkspaceSymbolResponse {
+            let connection =
+                MockServer::with_capabilities(json!({ "workspaceSymbolProvider": true }))
+                    .respond("workspace/symbol", result)
+                    .initialize()
+                    .await
+                    .unwrap();
+            connection
+                .client
+                .workspace_symbols("Point".to_string(), None)
+                .unwrap()
+                .await
+                .unwrap()
+                .unwrap()
+        }
+
+        let location = json!({
+            "uri": "file:///project/src/geometry.rs",
+            "range": {
+                "start": { "line": 3, "character": 11 },
+                "end": { "line": 3, "character": 16 },
+            },
+        });
+        let response = workspace_symbols(json!([
+            { "name": "Point", "kind": 23, "location": location, "containerName": "geometry" },
+        ]))
+        .await;
+        match response {
+            lsp::WorkspaceSymbolResponse::Flat(symbols) => {
+                assert_eq!(symbols.len(), 1);
+                assert_eq!(symbols[0].location.range.start, lsp::Position::new(3, 11));
+                assert_eq!(symbols[0].container_name.as_deref(), Some("geometry"));
+            }
+            response => panic!("expected symbol information, got {:?}", response),
+        }
+
+        // the location of the second symbol is only resolved on request
+        let response = workspace_symbols(json!([
+            { "name": "Point", "kind": 23, "location": location, "data": 1 },
+            {
+                "name": "PointCloud",
+                "kind": 23,
+                "location": { "uri": "file:///project/src/cloud.rs" },
+                "data": 2,
+            },
+        ]))
+        .await;
+        match response {
+            lsp::WorkspaceSymbolResponse::Nested(symbols) => {
+                assert_eq!(symbols.len(), 2);
+                assert!(matches!(symbols[0].location, lsp::OneOf::Left(_)));
+                assert_eq!(
+                    symbols[1].location,
+                    lsp::OneOf::Right(lsp::WorkspaceLocation {
+                        uri: lsp::Url::parse("file:///project/src/cloud.rs").unwrap(),
+                    })
+                );
+                assert_eq!(symbols[1].data, Some(json!(2)));
+            }
+            response => panic!("expected workspace symbols, got {:?}", response),
+        }
+    }
+
+    #[tokio::test]
+    async fn content_modified_request_is_retried() {
+        let mut connection = MockServer::with_capabilities(json!({ "hoverProvider": true }))
+            .respond("textDocument/hover", json!({ "contents": "fn main()" }))
+            .fail_once("textDocument/hover", lsp::error_codes::CONTENT_MODIFIED)
+            .initialize()
+            .await
+            .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let hover = connection
+            .client
+            .hover(uri, lsp::Position::new(0, 3))
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            hover.contents,
+            lsp::HoverContents::Scalar(lsp::MarkedString::String("fn main()".to_string()))
+        );
+
+        let ids: Vec<_> = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .filter_map(|call| match call {
+                jsonrpc::Call::MethodCall(call) if call.method == "textDocument/hover" => {
+                    Some(call.id)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[tokio::test]
+    async fn changes_are_sent_before_retried_requests() {
+        use helix_core::Transaction;
+
+        let mut connection = MockServer::with_capabilities(json!({
+            "hoverProvider": true,
+            "textDocumentSync": 2,
+        }))
+        .respond("textDocument/hover", json!({ "contents": "fn main()" }))
+        .fail_once("textDocument/hover", lsp::error_codes::CONTENT_MODIFIED)
+        .initialize()
+        .await
+        .unwrap();
+        let client = connection.client.clone();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let mut text = Rope::from("fn main() {}\n");
+        let old_text = text.clone();
+        let transaction = Transaction::insert(&text, &helix_core::Selection::point(11), "x".into());
+        assert!(transaction.apply(&mut text));
+        // the editor spawns the notification, the request may be sent before that task runs
+        let _change = client
+            .text_document_did_change(
+                lsp::VersionedTextDocumentIdentifier::new(uri.clone(), 1),
+                &old_text,
+                &text,
+                transaction.changes(),
+            )
+            .unwrap();
+        client
+            .hover(uri, lsp::Position::new(0, 3))
+            .unwrap()
+            .await
+            .unwrap();
+
+        let methods: Vec<_> = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .filter_map(|call| match call {
+                jsonrpc::Call::MethodCall(call) => Some(call.method),
+                jsonrpc::Call::Notification(notification) => Some(notification.method),
+                _ => None,
+            })
+            .skip_while(|method| method != "textDocument/didChange")
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                "textDocument/didChange",
+                "textDocument/hover",
+                "textDocument/hover"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_reports_its_error_code() {
+        let connection = MockServer::with_capabilities(json!({ "hoverProvider": true }))
+            .fail_once("textDocument/hover", lsp::error_codes::REQUEST_CANCELLED)
+            .initialize()
+            .await
+            .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let err = connection
+            .client
+            .hover(uri, lsp::Position::new(0, 3))
+            .unwrap()
+            .await
+            .unwrap_err();
+        match err.server_error_code() {
+            Some(ServerErrorCode::RequestCancelled) => (),
+            code => panic!("expected the request to be cancelled, got {:?}", code),
+        }
+        assert_eq!(err.code(), Some(-32800));
+        assert_eq!(err.data(), None);
+    }
+
+    #[tokio::test]
+    async fn awaiting_initialized_unblocks_after_initialization() {
+        use crate::mock::{read_message, write_message};
+        use tokio::io::BufReader;
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let (client, _incoming, initialize_notify) = Client::connect(
+            None,
+            BufReader::new(client_reader),
+            client_writer,
+            tokio::io::empty(),
+            "mock",
+            None,
+            &[],
+            0,
+            5,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        );
+        let client = Arc::new(client);
+        crate::initialize_client(client.clone(), initialize_notify);
+        let initialized = tokio::spawn({
+            let client = client.clone();
+            async move { client.initialized().await }
+        });
+
+        let (server_reader, mut server_writer) = tokio::io::split(server_io);
+        let mut server_reader = BufReader::new(server_reader);
+        let id = match read_message(&mut server_reader).await.unwrap() {
+            jsonrpc::Call::MethodCall(call) if call.method == "initialize" => call.id,
+            call => panic!("expected the initialize request, got {:?}", call),
+        };
+        // the server didn't answer yet
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!initialized.is_finished());
+
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": { "capabilities": { "hoverProvider": true } },
+        });
+        write_message(&mut server_writer, &response.to_string())
+            .await
+            .unwrap();
+        initialized.await.unwrap().unwrap();
+        assert!(client.is_initialized());
+
+        // a server failing to initialize unblocks the caller too
+        let client = MockServer::new().initialize().await.unwrap().client;
+        assert!(client.initialized().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn supported_methods_follow_capabilities() {
+        let connection = initialized_client(json!({
+            "documentFormattingProvider": true,
+            "documentRangeFormattingProvider": false,
+            "renameProvider": { "prepareProvider": false },
+        }))
+        .await;
+        let client = connection.client;
+
+        assert!(client.supports_method("textDocument/formatting"));
+        assert!(!client.supports_method("textDocument/rangeFormatting"));
+        assert!(client.supports_method("textDocument/rename"));
+        assert!(!client.supports_method("textDocument/prepareRename"));
+        assert!(!client.supports_method("textDocument/hover"));
+        assert!(!client.supports_method("helix/unknown"));
+
+        // methods registered later are supported too
+        client.register_capability(lsp::Registration {
+            id: "unknown".to_string(),
+            method: "helix/unknown".to_string(),
+            register_options: None,
+        });
+        assert!(client.supports_method("helix/unknown"));
+    }
+
+    #[tokio::test]
+    async fn successive_changes_increment_the_version() {
+        use helix_core::Transaction;
+
+        let mut connection = initialized_client(json!({ "textDocumentSync": 2 })).await;
+        let client = connection.client.clone();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let mut text = Rope::from("fn main() {}\n");
+        client
+            .text_document_did_open(uri.clone(), 0, &text, "rust".to_string())
+            .await
+            .unwrap();
+        assert_eq!(client.synced_version(&uri), Some(0));
+
+        for version in [1, 2] {
+            let old_text = text.clone();
+            let transaction =
+                Transaction::insert(&text, &helix_core::Selection::point(11), "x".into());
+            assert!(transaction.apply(&mut text));
+            client
+                .text_document_did_change(
+                    lsp::VersionedTextDocumentIdentifier::new(uri.clone(), version),
+                    &old_text,
+                    &text,
+                    transaction.changes(),
+                )
+                .unwrap()
+                .await
+                .unwrap();
+        }
+
+        let mut versions = Vec::new();
+        while versions.len() < 2 {
+            match connection.received.recv().await.unwrap() {
+                jsonrpc::Call::Notification(notification)
+                    if notification.method == "textDocument/didChange" =>
+                {
+                    let params = serde_json::Value::from(notification.params);
+                    versions.push(params["textDocument"]["version"].clone());
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(versions, [json!(1), json!(2)]);
+        assert_eq!(client.synced_version(&uri), Some(2));
+
+        client
+            .text_document_did_close(lsp::TextDocumentIdentifier::new(uri.clone()))
+            .await
+            .unwrap();
+        assert_eq!(client.synced_version(&uri), None);
+    }
+
+    #[tokio::test]
+    async fn rename_touches_every_file_of_the_edit() {
+        let range = |line: u32| {
+            json!({
+                "start": { "line": line, "character": 4 },
+                "end": { "line": line, "character": 9 },
+            })
+        };
+        let mut connection = MockServer::with_capabilities(json!({ "renameProvider": true }))
+            .respond(
+                "textDocument/rename",
+                json!({
+                    "changes": {
+                        "file:///project/src/lib.rs": [{ "range": range(1), "newText": "total" }],
+                        "file:///project/src/main.rs": [
+                            { "range": range(3), "newText": "total" },
+                            { "range": range(5), "newText": "total" },
+                        ],
+                    },
+                }),
+            )
+            .initialize()
+            .await
+            .unwrap();
+        let client = connection.client.clone();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let position = lsp::Position::new(3, 6);
+        let edit = client
+            .rename(uri.clone(), position, "total".to_string())
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut changes: Vec<_> = util::workspace_edit_changes(&edit)
+            .into_iter()
+            .map(|change| match change {
+                util::WorkspaceEditChange::Edit { uri, edits, .. } => (uri.path(), edits.len()),
+                change => panic!("expected text edits, got {:?}", change),
+            })
+            .collect();
+        changes.sort_unstable();
+        assert_eq!(
+            changes,
+            [("/project/src/lib.rs", 1), ("/project/src/main.rs", 2)]
+        );
+
+        // an empty name isn't sent to the server
+        let renamed = client.rename(uri, position, " ".to_string()).unwrap().await;
+        assert!(renamed.is_err());
+        let renames = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .filter(|call| {
+                matches!(call, jsonrpc::Call::MethodCall(call) if call.method == "textDocument/rename")
+            })
+            .count();
+        assert_eq!(renames, 1);
+    }
+
+    #[tokio::test]
+    async fn notebook_cells_are_opened_as_text_documents() {
+        use crate::notebook::{NotebookCell, NotebookCellKind, NotebookDocument};
+
+        let mut connection = initialized_client(json!({
+            "notebookDocumentSync": {
+                "notebookSelector": [{ "notebook": "jupyter-notebook" }],
+            },
+        }))
+        .await;
+        let client = connection.client.clone();
+        assert!(client.notebook_document_sync().is_some());
+
+        // the client advertised notebook sync
+        match connection.received.recv().await.unwrap() {
+            jsonrpc::Call::MethodCall(call) => {
+                let params = serde_json::Value::from(call.params);
+                assert!(params
+                    .pointer("/capabilities/notebookDocument/synchronization")
+                    .is_some());
+            }
+            call => panic!("expected initialize, got {:?}", call),
+        }
+
+        let notebook = lsp::Url::parse("file:///project/analysis.ipynb").unwrap();
+        let cell = |n: u32| {
+            lsp::Url::parse(&format!(
+                "vscode-notebook-cell:/project/analysis.ipynb#{}",
+                n
+            ))
+            .unwrap()
+        };
+        let cells = vec![
+            lsp::TextDocumentItem::new(cell(0), "markdown".into(), 1, "# Analysis\n".into()),
+            lsp::TextDocumentItem::new(cell(1), "python".into(), 1, "import numpy\n".into()),
+        ];
+        client
+            .notebook_document_did_open(
+                NotebookDocument {
+                    uri: notebook.clone(),
+                    notebook_type: "jupyter-notebook".to_string(),
+                    version: 0,
+                    metadata: None,
+                    cells: vec![
+                        NotebookCell {
+                            kind: NotebookCellKind::MARKUP,
+                            document: cell(0),
+                            metadata: None,
+                        },
+                        NotebookCell {
+                            kind: NotebookCellKind::CODE,
+                            document: cell(1),
+                            metadata: None,
+                        },
+                    ],
+                },
+                cells,
+            )
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(client.synced_version(&cell(1)), Some(1));
+
+        let params = loop {
+            match connection.received.recv().await.unwrap() {
+                jsonrpc::Call::Notification(notification)
+                    if notification.method == "notebookDocument/didOpen" =>
+                {
+                    break serde_json::Value::from(notification.params)
+                }
+                _ => (),
+            }
+        };
+        assert_eq!(
+            params,
+            json!({
+                "notebookDocument": {
+                    "uri": notebook,
+                    "notebookType": "jupyter-notebook",
+                    "version": 0,
+                    "cells": [
+                        { "kind": 1, "document": cell(0) },
+                        { "kind": 2, "document": cell(1) },
+                    ],
+                },
+                "cellTextDocuments": [
+                    {
+                        "uri": cell(0),
+                        "languageId": "markdown",
+                        "version": 1,
+                        "text": "# Analysis\n",
+                    },
+                    {
+                        "uri": cell(1),
+                        "languageId": "python",
+                        "version": 1,
+                        "text": "import numpy\n",
+                    },
+                ],
+            })
+        );
+
+        // the server didn't ask for saves
+        assert!(client.notebook_document_did_save(notebook).is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_is_followed_by_exit() {
+        async fn shutdown_methods(server: MockServer) -> Vec<String> {
+            let mut connection = server.initialize().await.unwrap();
+            connection
+                .client
+                .shutdown(Duration::from_secs(1))
+                .await
+                .unwrap();
+
+            // `exit` may still be on its way to the server
+            let mut methods = Vec::new();
+            let received = async {
+                while let Some(call) = connection.received.recv().await {
+                    let method = match call {
+                        jsonrpc::Call::MethodCall(call) => call.method,
+                        jsonrpc::Call::Notification(notification) => notification.method,
+                        _ => continue,
+                    };
+                    if method == "shutdown" || method == "exit" {
+                        methods.push(method.clone());
+                    }
+                    if method == "exit" {
+                        break;
+                    }
+                }
+            };
+            timeout(Duration::from_secs(5), received)
+                .await
+                .expect("the server wasn't asked to exit");
+            methods
+        }
+
+        let server =
+            MockServer::with_capabilities(json!({})).respond("shutdown", serde_json::Value::Null);
+        assert_eq!(shutdown_methods(server).await, ["shutdown", "exit"]);
+
+        // a server failing the shutdown request is still asked to exit
+        let server = MockServer::with_capabilities(json!({})).fail_once("shutdown", -32603);
+        assert_eq!(shutdown_methods(server).await, ["shutdown", "exit"]);
+    }
 }
@@ -0,0 +1,159 @@
+//! Matching of file paths against the `workspace.fileOperations` filters registered by a server.
+
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::lsp;
+
+#[derive(Debug)]
+struct Filter {
+    matcher: GlobMatcher,
+    kind: Option<lsp::FileOperationPatternKind>,
+}
+
+/// The compiled filters of a single file operation.
+#[derive(Debug, Default)]
+pub struct FileOperationFilter {
+    filters: Vec<Filter>,
+}
+
+impl FileOperationFilter {
+    fn new(options: Option<&lsp::FileOperationRegistrationOptions>) -> Self {
+        let filters = options
+            .map(|options| options.filters.as_slice())
+            .unwrap_or_default()
+            .iter()
+            // helix only works with files on disk
+            .filter(|filter| {
+                filter
+                    .scheme
+                    .as_deref()
+                    .map_or(true, |scheme| scheme == "file")
+            })
+            .filter_map(|filter| {
+                let ignore_case = filter
+                    .pattern
+                    .options
+                    .as_ref()
+                    .and_then(|options| options.ignore_case)
+                    .unwrap_or(false);
+                let glob = GlobBuilder::new(&filter.pattern.glob)
+                    .literal_separator(true)
+                    .case_insensitive(ignore_case)
+                    .build();
+                match glob {
+                    Ok(glob) => Some(Filter {
+                        matcher: glob.compile_matcher(),
+                        kind: filter.pattern.matches.clone(),
+                    }),
+                    Err(err) => {
+                        log::error!(
+                            "invalid file operation glob {:?}: {}",
+                            filter.pattern.glob,
+                            err
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Self { filters }
+    }
+
+    /// Whether the server registered interest in an operation on `path`.
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        self.filters.iter().any(|filter| {
+            let kind_matches = match filter.kind {
+                Some(lsp::FileOperationPatternKind::File) => !is_dir,
+                Some(lsp::FileOperationPatternKind::Folder) => is_dir,
+                None => true,
+            };
+            kind_matches && filter.matcher.is_match(path)
+        })
+    }
+}
+
+/// The file operations a server is interested in, compiled from its capabilities.
+#[derive(Debug, Default)]
+pub struct FileOperationsInterest {
+    pub did_create: FileOperationFilter,
+    pub will_create: FileOperationFilter,
+    pub did_rename: FileOperationFilter,
+    pub will_rename: FileOperationFilter,
+    pub did_delete: FileOperationFilter,
+    pub will_delete: FileOperationFilter,
+}
+
+impl FileOperationsInterest {
+    pub fn new(capabilities: &lsp::ServerCapabilities) -> Self {
+        let file_operations = match capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.file_operations.as_ref())
+        {
+            Some(file_operations) => file_operations,
+            None => return Self::default(),
+        };
+
+        Self {
+            did_create: FileOperationFilter::new(file_operations.did_create.as_ref()),
+            will_create: FileOperationFilter::new(file_operations.will_create.as_ref()),
+            did_rename: FileOperationFilter::new(file_operations.did_rename.as_ref()),
+            will_rename: FileOperationFilter::new(file_operations.will_rename.as_ref()),
+            did_delete: FileOperationFilter::new(file_operations.did_delete.as_ref()),
+            will_delete: FileOperationFilter::new(file_operations.will_delete.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(glob: &str) -> lsp::ServerCapabilities {
+        let options = lsp::FileOperationRegistrationOptions {
+            filters: vec![lsp::FileOperationFilter {
+                scheme: Some("file".to_string()),
+                pattern: lsp::FileOperationPattern {
+                    glob: glob.to_string(),
+                    matches: Some(lsp::FileOperationPatternKind::File),
+                    options: None,
+                },
+            }],
+        };
+
+        lsp::ServerCapabilities {
+            workspace: Some(lsp::WorkspaceServerCapabilities {
+                workspace_folders: None,
+                file_operations: Some(lsp::WorkspaceFileOperationsServerCapabilities {
+                    will_rename: Some(options),
+                    ..Default::default()
+                }),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rename_matches_registered_glob() {
+        let interest = FileOperationsInterest::new(&capabilities("**/*.rs"));
+
+        assert!(interest
+            .will_rename
+            .matches(Path::new("/project/src/main.rs"), false));
+        // a non-matching extension doesn't trigger a request
+        assert!(!interest
+            .will_rename
+            .matches(Path::new("/project/README.md"), false));
+        // the filter only matches files
+        assert!(!interest
+            .will_rename
+            .matches(Path::new("/project/src/dir.rs"), true));
+        // operations the server didn't register for never match
+        assert!(!interest
+            .did_rename
+            .matches(Path::new("/project/src/main.rs"), false));
+    }
+}
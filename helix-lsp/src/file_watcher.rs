@@ -0,0 +1,129 @@
+//! Filtering of file system events against the watchers a server registered for
+//! `workspace/didChangeWatchedFiles`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::lsp;
+
+#[derive(Debug)]
+struct Watcher {
+    /// Paths are matched relative to this directory for relative patterns.
+    base: Option<PathBuf>,
+    matcher: GlobMatcher,
+    kind: lsp::WatchKind,
+}
+
+impl Watcher {
+    fn new(watcher: &lsp::FileSystemWatcher) -> Option<Self> {
+        let (base, pattern) = match &watcher.glob_pattern {
+            lsp::GlobPattern::String(pattern) => (None, pattern),
+            lsp::GlobPattern::Relative(relative) => {
+                let base_uri = match &relative.base_uri {
+                    lsp::OneOf::Left(folder) => &folder.uri,
+                    lsp::OneOf::Right(uri) => uri,
+                };
+                (Some(base_uri.to_file_path().ok()?), &relative.pattern)
+            }
+        };
+
+        let glob = match GlobBuilder::new(pattern).literal_separator(true).build() {
+            Ok(glob) => glob,
+            Err(err) => {
+                log::error!("invalid file watcher glob {:?}: {}", pattern, err);
+                return None;
+            }
+        };
+
+        Some(Self {
+            base,
+            matcher: glob.compile_matcher(),
+            // the default is to watch all kinds of events
+            kind: watcher.kind.unwrap_or_else(lsp::WatchKind::all),
+        })
+    }
+
+    fn matches(&self, path: &Path, kind: lsp::WatchKind) -> bool {
+        if !self.kind.intersects(kind) {
+            return false;
+        }
+        match &self.base {
+            Some(base) => path
+                .strip_prefix(base)
+                .map_or(false, |path| self.matcher.is_match(path)),
+            None => self.matcher.is_match(path),
+        }
+    }
+}
+
+/// The file watchers registered by a server, keyed by registration id.
+#[derive(Debug, Default)]
+pub struct FileWatchers {
+    registrations: HashMap<String, Vec<Watcher>>,
+}
+
+impl FileWatchers {
+    pub fn register(&mut self, id: String, watchers: &[lsp::FileSystemWatcher]) {
+        let watchers = watchers.iter().filter_map(Watcher::new).collect();
+        self.registrations.insert(id, watchers);
+    }
+
+    pub fn unregister(&mut self, id: &str) {
+        self.registrations.remove(id);
+    }
+
+    /// Whether a registered watcher is interested in `event`.
+    pub fn matches(&self, event: &lsp::FileEvent) -> bool {
+        let kind = match event.typ {
+            lsp::FileChangeType::CREATED => lsp::WatchKind::Create,
+            lsp::FileChangeType::CHANGED => lsp::WatchKind::Change,
+            lsp::FileChangeType::DELETED => lsp::WatchKind::Delete,
+            _ => return false,
+        };
+        let path = match event.uri.to_file_path() {
+            Ok(path) => path,
+            Err(_) => return false,
+        };
+
+        self.registrations
+            .values()
+            .flatten()
+            .any(|watcher| watcher.matches(&path, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(path: &str, typ: lsp::FileChangeType) -> lsp::FileEvent {
+        lsp::FileEvent {
+            uri: lsp::Url::from_file_path(path).unwrap(),
+            typ,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_matching_events_are_forwarded() {
+        let mut watchers = FileWatchers::default();
+        watchers.register(
+            "rust".to_string(),
+            &[lsp::FileSystemWatcher {
+                glob_pattern: lsp::GlobPattern::String("**/*.rs".to_string()),
+                kind: Some(lsp::WatchKind::Create | lsp::WatchKind::Delete),
+            }],
+        );
+
+        assert!(watchers.matches(&event("/project/src/lib.rs", lsp::FileChangeType::CREATED)));
+        assert!(watchers.matches(&event("/project/src/lib.rs", lsp::FileChangeType::DELETED)));
+        // changes weren't requested by the watcher
+        assert!(!watchers.matches(&event("/project/src/lib.rs", lsp::FileChangeType::CHANGED)));
+        assert!(!watchers.matches(&event("/project/Cargo.toml", lsp::FileChangeType::CREATED)));
+
+        watchers.unregister("rust");
+        assert!(!watchers.matches(&event("/project/src/lib.rs", lsp::FileChangeType::CREATED)));
+    }
+}
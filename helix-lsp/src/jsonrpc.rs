@@ -254,7 +254,49 @@ fn from(notification: Notification) -> Self {
 #[serde(untagged)]
 pub enum Request {
     Single(Call),
-    Batch(Vec<Call>),
+    Batch(#[serde(deserialize_with = "non_empty_batch")] Vec<Call>),
+}
+
+impl Request {
+    /// Creates a batch of `calls`. The spec doesn't allow empty batches.
+    pub fn batch(calls: Vec<Call>) -> Result<Self, Error> {
+        if calls.is_empty() {
+            return Err(Error {
+                code: ErrorCode::InvalidRequest,
+                message: "Empty batch".to_string(),
+                data: None,
+            });
+        }
+        Ok(Request::Batch(calls))
+    }
+
+    /// The ids of the method calls of the request, in order. Notifications don't get a
+    /// response so they have no id.
+    pub fn ids(&self) -> Vec<&Id> {
+        let calls = match self {
+            Request::Single(call) => std::slice::from_ref(call),
+            Request::Batch(calls) => calls.as_slice(),
+        };
+        calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::MethodCall(method_call) => Some(&method_call.id),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn non_empty_batch<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let batch = Vec::<T>::deserialize(deserializer)?;
+    if batch.is_empty() {
+        return Err(de::Error::invalid_length(0, &"a non-empty batch"));
+    }
+    Ok(batch)
 }
 
 // https://www.jsonrpc.org/specification#response_object
@@ -285,6 +327,15 @@ pub enum Output {
     Success(Success),
 }
 
+impl Output {
+    pub fn id(&self) -> &Id {
+        match self {
+            Output::Success(success) => &success.id,
+            Output::Failure(failure) => &failure.id,
+        }
+    }
+}
+
 impl From<Output> for Result<Value, Error> {
     fn from(output: Output) -> Self {
         match output {
@@ -298,7 +349,25 @@ fn from(output: Output) -> Self {
 #[serde(untagged)]
 pub enum Response {
     Single(Output),
-    Batch(Vec<Output>),
+    Batch(#[serde(deserialize_with = "non_empty_batch")] Vec<Output>),
+}
+
+impl Response {
+    /// Matches the outputs to the `ids` of the method calls of a request, see
+    /// [`Request::ids`]. The outputs of a batch may come in any order; calls without an
+    /// output are `None`.
+    pub fn correlate(self, ids: &[&Id]) -> Vec<Option<Output>> {
+        let mut outputs = match self {
+            Response::Single(output) => vec![output],
+            Response::Batch(outputs) => outputs,
+        };
+        ids.iter()
+            .map(|id| {
+                let index = outputs.iter().position(|output| output.id() == *id)?;
+                Some(outputs.swap_remove(index))
+            })
+            .collect()
+    }
 }
 
 impl From<Failure> for Response {
@@ -409,3 +478,78 @@ fn success_output_deserialize_with_extra_fields() {
         })
     );
 }
+
+#[test]
+fn batch_round_trip() {
+    use serde_json;
+
+    let request = Request::batch(vec![
+        Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            method: "initialize".to_owned(),
+            params: Params::None,
+            id: Id::Num(1),
+        }),
+        Call::Notification(Notification {
+            jsonrpc: Some(Version::V2),
+            method: "initialized".to_owned(),
+            params: Params::None,
+        }),
+        Call::MethodCall(MethodCall {
+            jsonrpc: Some(Version::V2),
+            method: "threads".to_owned(),
+            params: Params::None,
+            id: Id::Str("threads".to_owned()),
+        }),
+    ])
+    .unwrap();
+
+    let serialized = serde_json::to_string(&request).unwrap();
+    assert_eq!(
+        serialized,
+        r#"[{"jsonrpc":"2.0","method":"initialize","id":1},{"jsonrpc":"2.0","method":"initialized"},{"jsonrpc":"2.0","method":"threads","id":"threads"}]"#
+    );
+    let deserialized: Request = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized, request);
+
+    // the outputs of a batch can come in any order
+    let response = r#"[{"jsonrpc":"2.0","result":[],"id":"threads"},{"jsonrpc":"2.0","error":{"code":-32601,"message":"unknown"},"id":1}]"#;
+    let response: Response = serde_json::from_str(response).unwrap();
+    let outputs = response.correlate(&request.ids());
+    assert_eq!(outputs.len(), 2);
+    assert!(matches!(
+        &outputs[0],
+        Some(Output::Failure(Failure {
+            error: Error {
+                code: ErrorCode::MethodNotFound,
+                ..
+            },
+            id: Id::Num(1),
+            ..
+        }))
+    ));
+    assert_eq!(
+        outputs[1],
+        Some(Output::Success(Success {
+            jsonrpc: Some(Version::V2),
+            result: Value::Array(Vec::new()),
+            id: Id::Str("threads".to_owned()),
+        }))
+    );
+}
+
+#[test]
+fn empty_batch_is_rejected() {
+    use serde_json;
+
+    assert_eq!(
+        Request::batch(Vec::new()).unwrap_err().code,
+        ErrorCode::InvalidRequest
+    );
+    // an empty array can at best be an invalid call
+    assert!(!matches!(
+        serde_json::from_str::<Request>("[]"),
+        Ok(Request::Batch(_))
+    ));
+    assert!(serde_json::from_str::<Response>("[]").is_err());
+}
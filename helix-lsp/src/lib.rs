@@ -1,5 +1,12 @@
 mod client;
+pub mod file_operations;
+pub mod file_watcher;
 pub mod jsonrpc;
+#[cfg(test)]
+mod mock;
+pub mod notebook;
+pub mod protocol_version;
+pub mod selection_range;
 pub mod snippet;
 mod transport;
 
@@ -10,15 +17,19 @@
 pub use lsp_types as lsp;
 
 use futures_util::stream::select_all::SelectAll;
-use helix_core::syntax::{LanguageConfiguration, LanguageServerConfiguration};
+use helix_core::syntax::{
+    LanguageConfiguration, LanguageServerConfiguration, LanguageServerTransport,
+};
 use tokio::sync::mpsc::UnboundedReceiver;
 
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{btree_map, hash_map::Entry, BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use thiserror::Error;
@@ -35,17 +46,91 @@ pub enum Error {
     Parse(#[from] serde_json::Error),
     #[error("IO Error: {0}")]
     IO(#[from] std::io::Error),
-    #[error("request {0} timed out")]
-    Timeout(jsonrpc::Id),
+    #[error("request {0} ({1}) timed out")]
+    Timeout(jsonrpc::Id, &'static str),
     #[error("server closed the stream")]
     StreamClosed,
+    #[error("language server is not initialized yet")]
+    NotInitialized,
+    #[error("language server was restarted too recently, try again in {0:?}")]
+    RestartTooSoon(Duration),
+    #[error("gave up reconnecting to the language server after {0} attempts")]
+    ReconnectLimit(usize),
+    #[error("command {0} is not supported by the language server")]
+    UnsupportedCommand(String),
     #[error("Unhandled")]
     Unhandled,
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+impl Error {
+    /// The code of the error the server responded with, if it did.
+    pub fn code(&self) -> Option<i64> {
+        match self {
+            Error::Rpc(error) => Some(error.code.code()),
+            _ => None,
+        }
+    }
+
+    /// The additional information the server attached to its error response.
+    pub fn data(&self) -> Option<&serde_json::Value> {
+        match self {
+            Error::Rpc(error) => error.data.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The LSP specific code of the error the server responded with.
+    pub fn server_error_code(&self) -> Option<ServerErrorCode> {
+        self.code().and_then(ServerErrorCode::from_code)
+    }
+}
+
+/// Error codes the LSP defines in addition to the JSON-RPC ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerErrorCode {
+    /// A request was sent before the server was initialized.
+    ServerNotInitialized,
+    /// An error without a more specific code.
+    UnknownErrorCode,
+    /// The request was valid but the server couldn't handle it.
+    RequestFailed,
+    /// The server cancelled the request.
+    ServerCancelled,
+    /// The document changed while the server was working on the request.
+    ContentModified,
+    /// The client cancelled the request.
+    RequestCancelled,
+}
+
+impl ServerErrorCode {
+    pub fn from_code(code: i64) -> Option<Self> {
+        let code = match code {
+            -32002 => ServerErrorCode::ServerNotInitialized,
+            -32001 => ServerErrorCode::UnknownErrorCode,
+            -32803 => ServerErrorCode::RequestFailed,
+            lsp::error_codes::SERVER_CANCELLED => ServerErrorCode::ServerCancelled,
+            lsp::error_codes::CONTENT_MODIFIED => ServerErrorCode::ContentModified,
+            lsp::error_codes::REQUEST_CANCELLED => ServerErrorCode::RequestCancelled,
+            _ => return None,
+        };
+        Some(code)
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            ServerErrorCode::ServerNotInitialized => -32002,
+            ServerErrorCode::UnknownErrorCode => -32001,
+            ServerErrorCode::RequestFailed => -32803,
+            ServerErrorCode::ServerCancelled => lsp::error_codes::SERVER_CANCELLED,
+            ServerErrorCode::ContentModified => lsp::error_codes::CONTENT_MODIFIED,
+            ServerErrorCode::RequestCancelled => lsp::error_codes::REQUEST_CANCELLED,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum OffsetEncoding {
     /// UTF-8 code units aka bytes
     Utf8,
@@ -56,11 +141,71 @@ pub enum OffsetEncoding {
     Utf16,
 }
 
+impl OffsetEncoding {
+    /// The encodings offered to language servers when none are configured, in order of preference.
+    pub const DEFAULT_FALLBACK_CHAIN: &'static [OffsetEncoding] = &[
+        OffsetEncoding::Utf32,
+        OffsetEncoding::Utf8,
+        OffsetEncoding::Utf16,
+    ];
+
+    /// Parses the LSP name of a position encoding (`utf-8`, `utf-16` or `utf-32`).
+    pub fn from_lsp_name(name: &str) -> Option<Self> {
+        match name {
+            "utf-8" => Some(OffsetEncoding::Utf8),
+            "utf-16" => Some(OffsetEncoding::Utf16),
+            "utf-32" => Some(OffsetEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn to_position_encoding_kind(self) -> lsp::PositionEncodingKind {
+        match self {
+            OffsetEncoding::Utf8 => lsp::PositionEncodingKind::UTF8,
+            OffsetEncoding::Utf16 => lsp::PositionEncodingKind::UTF16,
+            OffsetEncoding::Utf32 => lsp::PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// Determines the encoding used to talk to a server from the `position_encoding`
+    /// it returned in its capabilities and the encodings that were `offered` to it.
+    ///
+    /// The spec requires servers to fall back to UTF-16 when they can't use any of the
+    /// offered encodings, so that's what we assume when the server picked something else.
+    pub fn negotiate(
+        server_encoding: Option<&lsp::PositionEncodingKind>,
+        offered: &[OffsetEncoding],
+    ) -> OffsetEncoding {
+        let encoding = match server_encoding {
+            Some(encoding) => encoding,
+            None => return OffsetEncoding::Utf16,
+        };
+
+        match Self::from_lsp_name(encoding.as_str()) {
+            Some(encoding) if offered.contains(&encoding) => encoding,
+            Some(encoding) => {
+                log::error!(
+                    "Server picked position encoding {} which was not offered, defaulting to utf-16",
+                    encoding.to_position_encoding_kind().as_str()
+                );
+                OffsetEncoding::Utf16
+            }
+            None => {
+                log::error!(
+                    "Server provided invalid position encoding {}, defaulting to utf-16",
+                    encoding.as_str()
+                );
+                OffsetEncoding::Utf16
+            }
+        }
+    }
+}
+
 pub mod util {
     use super::*;
-    use helix_core::line_ending::{line_end_byte_index, line_end_char_index};
-    use helix_core::{diagnostic::NumberOrString, Range, Rope, Selection, Tendril, Transaction};
-    use helix_core::{smallvec, SmallVec};
+    use helix_core::{
+        diagnostic::NumberOrString, Range, Rope, Selection, SmallVec, Tendril, Transaction,
+    };
 
     /// Converts a diagnostic in the document to [`lsp::Diagnostic`].
     ///
@@ -118,6 +263,53 @@ pub fn diagnostic_to_lsp_diagnostic(
         }
     }
 
+    /// Compares the diagnostics published for a document with the previous ones, returning
+    /// the diagnostics that were added and the ones that were removed, in their original
+    /// order. Diagnostics are identified by their range, message and code, so diagnostics that
+    /// were only reordered appear in neither.
+    pub fn diagnostic_diff<'a>(
+        old: &'a [lsp::Diagnostic],
+        new: &'a [lsp::Diagnostic],
+    ) -> (Vec<&'a lsp::Diagnostic>, Vec<&'a lsp::Diagnostic>) {
+        // `lsp::Range` isn't hashable
+        type Identity<'a> = ([u32; 4], &'a str, Option<&'a lsp::NumberOrString>);
+        fn identity(diag: &lsp::Diagnostic) -> Identity<'_> {
+            let lsp::Range { start, end } = diag.range;
+            (
+                [start.line, start.character, end.line, end.character],
+                &diag.message,
+                diag.code.as_ref(),
+            )
+        }
+
+        // indices of the old diagnostics by identity, the first one on top
+        let mut unmatched: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, diag) in old.iter().enumerate().rev() {
+            unmatched.entry(identity(diag)).or_default().push(i);
+        }
+
+        let mut matched = vec![false; old.len()];
+        let added = new
+            .iter()
+            .filter(
+                |diag| match unmatched.get_mut(&identity(diag)).and_then(Vec::pop) {
+                    Some(i) => {
+                        matched[i] = true;
+                        false
+                    }
+                    None => true,
+                },
+            )
+            .collect();
+        let removed = old
+            .iter()
+            .zip(matched)
+            .filter_map(|(diag, matched)| (!matched).then_some(diag))
+            .collect();
+
+        (added, removed)
+    }
+
     /// Converts [`lsp::Position`] to a position in the document.
     ///
     /// Returns `None` if position.line is out of bounds or an overflow occurs
@@ -126,11 +318,6 @@ pub fn lsp_pos_to_pos(
         pos: lsp::Position,
         offset_encoding: OffsetEncoding,
     ) -> Option<usize> {
-        let pos_line = pos.line as usize;
-        if pos_line > doc.len_lines() - 1 {
-            return None;
-        }
-
         // We need to be careful here to fully comply ith the LSP spec.
         // Two relevant quotes from the spec:
         //
@@ -146,35 +333,24 @@ pub fn lsp_pos_to_pos(
         //
         // This means that while the line must be in bounds the `charater`
         // must be capped to the end of the line.
-        // Note that the end of the line here is **before** the line terminator
-        // so we must use `line_end_char_index` istead of `doc.line_to_char(pos_line + 1)`
+        // Note that the end of the line here is **before** the line terminator.
         //
-        // FIXME: Helix does not fully comply with the LSP spec for line terminators.
-        // The LSP standard requires that line terminators are ['\n', '\r\n', '\r'].
-        // Without the unicode-linebreak feature disabled, the `\r` terminator is not handled by helix.
-        // With the unicode-linebreak feature, helix recognizes multiple extra line break chars
-        // which means that positions will be decoded/encoded incorrectly in their presence
-
+        // Ropey's line indexing can't be used here: without the unicode-lines feature it
+        // doesn't recognize the `\r` terminator and with it, it recognizes multiple extra
+        // line break chars, so lines are split by `lsp_line_byte_range` instead.
+        let line = lsp_line_byte_range(doc, pos.line as usize)?;
         let line = match offset_encoding {
-            OffsetEncoding::Utf8 => {
-                let line_start = doc.line_to_byte(pos_line);
-                let line_end = line_end_byte_index(&doc.slice(..), pos_line);
-                line_start..line_end
-            }
+            OffsetEncoding::Utf8 => line,
             OffsetEncoding::Utf16 => {
                 // TODO directly translate line index to char-idx
                 // ropey can do this just as easily as utf-8 byte translation
                 // but the functions are just missing.
                 // Translate to char first and then utf-16 as a workaround
-                let line_start = doc.line_to_char(pos_line);
-                let line_end = line_end_char_index(&doc.slice(..), pos_line);
+                let line_start = doc.byte_to_char(line.start);
+                let line_end = doc.byte_to_char(line.end);
                 doc.char_to_utf16_cu(line_start)..doc.char_to_utf16_cu(line_end)
             }
-            OffsetEncoding::Utf32 => {
-                let line_start = doc.line_to_char(pos_line);
-                let line_end = line_end_char_index(&doc.slice(..), pos_line);
-                line_start..line_end
-            }
+            OffsetEncoding::Utf32 => doc.byte_to_char(line.start)..doc.byte_to_char(line.end),
         };
 
         // The LSP spec demands that the offset is capped to the end of the line
@@ -187,8 +363,43 @@ pub fn lsp_pos_to_pos(
         match offset_encoding {
             OffsetEncoding::Utf8 => doc.try_byte_to_char(pos).ok(),
             OffsetEncoding::Utf16 => doc.try_utf16_cu_to_char(pos).ok(),
-            OffsetEncoding::Utf32 => Some(pos),
+            // the line was capped to the document, but the char index is checked like the
+            // conversions of the other encodings
+            OffsetEncoding::Utf32 => (pos <= doc.len_chars()).then_some(pos),
+        }
+    }
+
+    /// Returns the byte range of `line`, excluding its terminator, with lines split only at
+    /// the terminators allowed by the LSP spec: `\n`, `\r\n` and `\r`.
+    fn lsp_line_byte_range(doc: &Rope, line: usize) -> Option<std::ops::Range<usize>> {
+        let mut current_line = 0;
+        let mut line_start = 0;
+        // a `\n` directly after a `\r` belongs to the same terminator
+        let mut after_cr = false;
+        let mut offset = 0;
+
+        // the terminators are ascii so they can't be part of a multi-byte char
+        for chunk in doc.chunks() {
+            for (i, byte) in chunk.bytes().enumerate() {
+                let idx = offset + i;
+                match byte {
+                    b'\n' if after_cr => line_start = idx + 1,
+                    b'\n' | b'\r' => {
+                        if current_line == line {
+                            return Some(line_start..idx);
+                        }
+                        current_line += 1;
+                        line_start = idx + 1;
+                    }
+                    _ => (),
+                }
+                after_cr = byte == b'\r';
+            }
+            offset += chunk.len();
         }
+
+        // the last line isn't terminated
+        (current_line == line).then_some(line_start..offset)
     }
 
     /// Converts position in the document to [`lsp::Position`].
@@ -224,7 +435,33 @@ pub fn pos_to_lsp_pos(
         }
     }
 
+    /// Encodes the changes `transaction` makes to `old` for a `didChange` notification. The
+    /// changes are ranged unless the server only syncs the full text with `sync_kind`
+    /// [`lsp::TextDocumentSyncKind::FULL`], in which case the new text is sent whole.
+    pub fn transaction_to_content_changes(
+        old: &Rope,
+        transaction: &Transaction,
+        sync_kind: lsp::TextDocumentSyncKind,
+        offset_encoding: OffsetEncoding,
+    ) -> Vec<lsp::TextDocumentContentChangeEvent> {
+        let mut new = old.clone();
+        transaction.apply(&mut new);
+
+        if sync_kind == lsp::TextDocumentSyncKind::FULL {
+            vec![lsp::TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: new.to_string(),
+            }]
+        } else {
+            Client::changeset_to_changes(old, &new, transaction.changes(), offset_encoding)
+        }
+    }
+
     /// Converts a range in the document to [`lsp::Range`].
+    ///
+    /// The direction of `range` is lost: the start of the result is always before its end,
+    /// even for a range whose head is before its anchor.
     pub fn range_to_lsp_range(
         doc: &Rope,
         range: Range,
@@ -232,8 +469,15 @@ pub fn range_to_lsp_range(
     ) -> lsp::Range {
         let start = pos_to_lsp_pos(doc, range.from(), offset_encoding);
         let end = pos_to_lsp_pos(doc, range.to(), offset_encoding);
+        debug_assert!(start <= end, "reversed range {:?}", range);
+
+        ordered_lsp_range(start, end)
+    }
 
-        lsp::Range::new(start, end)
+    /// Creates an [`lsp::Range`] between two positions in either order. Servers may reject
+    /// ranges that end before they start.
+    pub fn ordered_lsp_range(a: lsp::Position, b: lsp::Position) -> lsp::Range {
+        lsp::Range::new(a.min(b), a.max(b))
     }
 
     pub fn lsp_range_to_range(
@@ -274,8 +518,184 @@ pub fn generate_transaction_from_completion_edit(
         })
     }
 
+    /// Picks the edit of a completion item. An [`lsp::InsertReplaceEdit`] only replaces the
+    /// text before the cursor with its insert range, unless `replace` is set in which case
+    /// its replace range also covers the rest of the word after the cursor.
+    pub fn completion_text_edit(edit: &lsp::CompletionTextEdit, replace: bool) -> lsp::TextEdit {
+        match edit {
+            lsp::CompletionTextEdit::Edit(edit) => edit.clone(),
+            lsp::CompletionTextEdit::InsertAndReplace(edit) => {
+                let range = if replace { edit.replace } else { edit.insert };
+                lsp::TextEdit::new(range, edit.new_text.clone())
+            }
+        }
+    }
+
+    /// Converts the range of a completion edit into the offsets from `cursor` expected by
+    /// [`generate_transaction_from_completion_edit`].
+    ///
+    /// Returns `None` if the range is out of bounds.
+    pub fn completion_edit_offsets(
+        doc: &Rope,
+        range: lsp::Range,
+        cursor: usize,
+        offset_encoding: OffsetEncoding,
+    ) -> Option<(i128, i128)> {
+        let start = lsp_pos_to_pos(doc, range.start, offset_encoding)?;
+        let end = lsp_pos_to_pos(doc, range.end, offset_encoding)?;
+        Some((start as i128 - cursor as i128, end as i128 - cursor as i128))
+    }
+
+    /// The items of a completion response. `lsp::CompletionResponse` doesn't know about the
+    /// `itemDefaults` of a completion list yet, this applies them to the items that omit the
+    /// corresponding property.
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct CompletionItems {
+        pub is_incomplete: bool,
+        pub items: Vec<lsp::CompletionItem>,
+    }
+
+    /// The `itemDefaults` of a completion list, see [`CompletionItems`].
+    #[derive(Debug, Default, Clone, PartialEq, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct CompletionItemDefaults {
+        pub commit_characters: Option<Vec<String>>,
+        pub edit_range: Option<CompletionItemDefaultsEditRange>,
+        pub insert_text_format: Option<lsp::InsertTextFormat>,
+        pub insert_text_mode: Option<lsp::InsertTextMode>,
+        pub data: Option<serde_json::Value>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+    #[serde(untagged)]
+    pub enum CompletionItemDefaultsEditRange {
+        Range(lsp::Range),
+        InsertAndReplace {
+            insert: lsp::Range,
+            replace: lsp::Range,
+        },
+    }
+
+    /// The item defaults the client can handle, advertised in its capabilities.
+    pub const COMPLETION_ITEM_DEFAULTS: &[&str] = &[
+        "commitCharacters",
+        "editRange",
+        "insertTextFormat",
+        "insertTextMode",
+        "data",
+    ];
+
+    impl CompletionItemDefaults {
+        /// Fills in the properties `item` omits. The default edit range gets the
+        /// `textEditText` of the item, or its label.
+        fn apply(&self, item: &mut lsp::CompletionItem, text_edit_text: Option<String>) {
+            if item.text_edit.is_none() {
+                if let Some(edit_range) = self.edit_range {
+                    let new_text = text_edit_text.unwrap_or_else(|| item.label.clone());
+                    item.text_edit = Some(match edit_range {
+                        CompletionItemDefaultsEditRange::Range(range) => {
+                            lsp::CompletionTextEdit::Edit(lsp::TextEdit::new(range, new_text))
+                        }
+                        CompletionItemDefaultsEditRange::InsertAndReplace { insert, replace } => {
+                            lsp::CompletionTextEdit::InsertAndReplace(lsp::InsertReplaceEdit {
+                                new_text,
+                                insert,
+                                replace,
+                            })
+                        }
+                    });
+                }
+            }
+            if item.commit_characters.is_none() {
+                item.commit_characters = self.commit_characters.clone();
+            }
+            if item.insert_text_format.is_none() {
+                item.insert_text_format = self.insert_text_format;
+            }
+            if item.insert_text_mode.is_none() {
+                item.insert_text_mode = self.insert_text_mode;
+            }
+            if item.data.is_none() {
+                item.data = self.data.clone();
+            }
+        }
+    }
+
+    impl CompletionItems {
+        /// Keeps the first `max` items. The list is marked as incomplete if items were
+        /// dropped, so that the completion is requested again as the user keeps typing.
+        pub fn truncate(&mut self, max: usize) {
+            if self.items.len() > max {
+                self.items.truncate(max);
+                self.is_incomplete = true;
+            }
+        }
+    }
+
+    impl<'de> serde::Deserialize<'de> for CompletionItems {
+        fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            #[derive(serde::Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            struct Item {
+                #[serde(flatten)]
+                item: lsp::CompletionItem,
+                text_edit_text: Option<String>,
+            }
+
+            #[derive(serde::Deserialize)]
+            #[serde(untagged)]
+            enum Response {
+                Array(Vec<lsp::CompletionItem>),
+                #[serde(rename_all = "camelCase")]
+                List {
+                    is_incomplete: bool,
+                    item_defaults: Option<CompletionItemDefaults>,
+                    items: Vec<Item>,
+                },
+            }
+
+            Ok(match Response::deserialize(deserializer)? {
+                Response::Array(items) => CompletionItems {
+                    is_incomplete: false,
+                    items,
+                },
+                Response::List {
+                    is_incomplete,
+                    item_defaults,
+                    items,
+                } => {
+                    let defaults = item_defaults.unwrap_or_default();
+                    let items = items
+                        .into_iter()
+                        .map(
+                            |Item {
+                                 mut item,
+                                 text_edit_text,
+                             }| {
+                                defaults.apply(&mut item, text_edit_text);
+                                item
+                            },
+                        )
+                        .collect();
+                    CompletionItems {
+                        is_incomplete,
+                        items,
+                    }
+                }
+            })
+        }
+    }
+
     /// Creates a [Transaction] from the [snippet::Snippet] in a completion response.
-    /// The transaction applies the edit to all cursors.
+    /// The transaction applies the edit to all cursors and selects the first tabstop.
+    ///
+    /// Also returns the selections of all tabstops in the order they are visited, so the
+    /// editor can cycle through them. Choices select their first choice. Variables are
+    /// resolved with `ctx`, on the line of each cursor.
+    #[allow(clippy::too_many_arguments)]
     pub fn generate_transaction_from_snippet(
         doc: &Rope,
         selection: &Selection,
@@ -284,11 +704,12 @@ pub fn generate_transaction_from_snippet(
         snippet: snippet::Snippet,
         line_ending: &str,
         include_placeholder: bool,
-    ) -> Transaction {
+        ctx: &snippet::SnippetContext,
+    ) -> (Transaction, Vec<Selection>) {
         let text = doc.slice(..);
 
-        // For each cursor store offsets for the first tabstop
-        let mut cursor_tabstop_offsets = Vec::<SmallVec<[(i128, i128); 1]>>::new();
+        // For each cursor store the offsets of every tabstop
+        let mut cursor_tabstop_offsets = Vec::<Vec<SmallVec<[(i128, i128); 1]>>>::new();
         let transaction = Transaction::change_by_selection(doc, selection, |range| {
             let cursor = range.cursor(text);
             let replacement_start = (cursor as i128 + start_offset) as usize;
@@ -300,20 +721,28 @@ pub fn generate_transaction_from_snippet(
                 blank = ""
             );
 
+            let ctx = snippet::SnippetContext {
+                line: doc.char_to_line(replacement_start),
+                ..*ctx
+            };
+            // the tabstops are ordered `$1`, `$2`, ..., `$0`, there's always a `$0`
             let (replacement, tabstops) =
-                snippet::render(&snippet, newline_with_offset, include_placeholder);
+                snippet::render(&snippet, newline_with_offset, include_placeholder, &ctx);
 
             let replacement_len = replacement.chars().count();
             cursor_tabstop_offsets.push(
                 tabstops
-                    .first()
-                    .unwrap_or(&smallvec![(replacement_len, replacement_len)])
                     .iter()
-                    .map(|(from, to)| -> (i128, i128) {
-                        (
-                            *from as i128 - replacement_len as i128,
-                            *to as i128 - replacement_len as i128,
-                        )
+                    .map(|tabstop| {
+                        tabstop
+                            .iter()
+                            .map(|(from, to)| -> (i128, i128) {
+                                (
+                                    *from as i128 - replacement_len as i128,
+                                    *to as i128 - replacement_len as i128,
+                                )
+                            })
+                            .collect()
                     })
                     .collect(),
             );
@@ -321,25 +750,32 @@ pub fn generate_transaction_from_snippet(
             (replacement_start, replacement_end, Some(replacement.into()))
         });
 
-        // Create new selection based on the cursor tabstop from above
-        let mut cursor_tabstop_offsets_iter = cursor_tabstop_offsets.iter();
-        let selection = selection
-            .clone()
-            .map(transaction.changes())
-            .transform_iter(|range| {
-                cursor_tabstop_offsets_iter
-                    .next()
-                    .unwrap()
-                    .iter()
-                    .map(move |(from, to)| {
-                        Range::new(
-                            (range.anchor as i128 + *from) as usize,
-                            (range.anchor as i128 + *to) as usize,
-                        )
-                    })
-            });
+        // Create a selection for every tabstop based on the cursor tabstops from above. Every
+        // cursor rendered the same snippet so they all have the same number of tabstops.
+        let mapped_selection = selection.clone().map(transaction.changes());
+        let tabstop_count = cursor_tabstop_offsets.first().map_or(0, Vec::len);
+        let tabstop_selections: Vec<_> = (0..tabstop_count)
+            .map(|tabstop| {
+                let mut cursor_tabstop_offsets_iter = cursor_tabstop_offsets.iter();
+                mapped_selection.clone().transform_iter(|range| {
+                    cursor_tabstop_offsets_iter.next().unwrap()[tabstop]
+                        .iter()
+                        .map(move |(from, to)| {
+                            Range::new(
+                                (range.anchor as i128 + *from) as usize,
+                                (range.anchor as i128 + *to) as usize,
+                            )
+                        })
+                })
+            })
+            .collect();
 
-        transaction.with_selection(selection)
+        // start at the lowest-numbered tabstop, or the final cursor position if there is none
+        let transaction = match tabstop_selections.first() {
+            Some(selection) => transaction.with_selection(selection.clone()),
+            None => transaction,
+        };
+        (transaction, tabstop_selections)
     }
 
     pub fn generate_transaction_from_edits(
@@ -390,353 +826,3803 @@ pub fn generate_transaction_from_edits(
             }),
         )
     }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum MethodCall {
-    WorkDoneProgressCreate(lsp::WorkDoneProgressCreateParams),
-    ApplyWorkspaceEdit(lsp::ApplyWorkspaceEditParams),
-    WorkspaceFolders,
-    WorkspaceConfiguration(lsp::ConfigurationParams),
-}
+    /// Returns the ranges of `doc` that `edits` replace, e.g. to preview the locations a
+    /// workspace edit touches before applying it. Edits with positions outside of `doc` are
+    /// skipped.
+    pub fn lsp_edits_to_ranges(
+        doc: &Rope,
+        edits: &[lsp::TextEdit],
+        offset_encoding: OffsetEncoding,
+    ) -> Vec<Range> {
+        edits
+            .iter()
+            .filter_map(|edit| lsp_range_to_range(doc, edit.range, offset_encoding))
+            .collect()
+    }
 
-impl MethodCall {
-    pub fn parse(method: &str, params: jsonrpc::Params) -> Result<MethodCall> {
-        use lsp::request::Request;
-        let request = match method {
-            lsp::request::WorkDoneProgressCreate::METHOD => {
-                let params: lsp::WorkDoneProgressCreateParams = params.parse()?;
-                Self::WorkDoneProgressCreate(params)
-            }
-            lsp::request::ApplyWorkspaceEdit::METHOD => {
-                let params: lsp::ApplyWorkspaceEditParams = params.parse()?;
-                Self::ApplyWorkspaceEdit(params)
-            }
-            lsp::request::WorkspaceFoldersRequest::METHOD => Self::WorkspaceFolders,
-            lsp::request::WorkspaceConfiguration::METHOD => {
-                let params: lsp::ConfigurationParams = params.parse()?;
-                Self::WorkspaceConfiguration(params)
-            }
-            _ => {
-                return Err(Error::Unhandled);
+    /// Converts the changes of `transaction` to `old_doc` into [`lsp::TextEdit`]s, which is
+    /// the inverse of [`generate_transaction_from_edits`]. The edits are sorted, don't overlap
+    /// and their ranges refer to `old_doc`, like the edits of a [`lsp::WorkspaceEdit`].
+    pub fn transaction_to_lsp_edits(
+        old_doc: &Rope,
+        transaction: &Transaction,
+        offset_encoding: OffsetEncoding,
+    ) -> Vec<lsp::TextEdit> {
+        use helix_core::Operation::*;
+
+        let mut edits = Vec::new();
+        let mut pos = 0;
+        // The start and text of the edit covering the current run of deletions and insertions
+        let mut pending: Option<(usize, String)> = None;
+
+        let mut finish_edit = |pending: &mut Option<(usize, String)>, end: usize| {
+            if let Some((start, new_text)) = pending.take() {
+                edits.push(lsp::TextEdit {
+                    range: range_to_lsp_range(old_doc, Range::new(start, end), offset_encoding),
+                    new_text,
+                });
             }
         };
-        Ok(request)
-    }
-}
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum Notification {
-    // we inject this notification to signal the LSP is ready
-    Initialized,
-    // and this notification to signal that the LSP exited
-    Exit,
-    PublishDiagnostics(lsp::PublishDiagnosticsParams),
-    ShowMessage(lsp::ShowMessageParams),
-    LogMessage(lsp::LogMessageParams),
-    ProgressMessage(lsp::ProgressParams),
-}
+        for change in transaction.changes().changes() {
+            match change {
+                Retain(len) => {
+                    finish_edit(&mut pending, pos);
+                    pos += len;
+                }
+                Delete(len) => {
+                    pending.get_or_insert_with(|| (pos, String::new()));
+                    pos += len;
+                }
+                Insert(text) => {
+                    pending
+                        .get_or_insert_with(|| (pos, String::new()))
+                        .1
+                        .push_str(text);
+                }
+            }
+        }
+        finish_edit(&mut pending, pos);
 
-impl Notification {
-    pub fn parse(method: &str, params: jsonrpc::Params) -> Result<Notification> {
-        use lsp::notification::Notification as _;
+        edits
+    }
 
-        let notification = match method {
-            lsp::notification::Initialized::METHOD => Self::Initialized,
-            lsp::notification::Exit::METHOD => Self::Exit,
-            lsp::notification::PublishDiagnostics::METHOD => {
-                let params: lsp::PublishDiagnosticsParams = params.parse()?;
-                Self::PublishDiagnostics(params)
-            }
+    /// A change of a [`lsp::WorkspaceEdit`], see [`workspace_edit_changes`].
+    #[derive(Debug, PartialEq)]
+    pub enum WorkspaceEditChange<'a> {
+        /// Edits to the document at `uri`. `version` is the version of the document the server
+        /// computed the edits for, if it specified one.
+        Edit {
+            uri: &'a lsp::Url,
+            version: Option<i32>,
+            edits: Vec<lsp::TextEdit>,
+        },
+        /// Creates, renames or deletes a file, which is up to the editor.
+        ResourceOp(&'a lsp::ResourceOp),
+    }
 
-            lsp::notification::ShowMessage::METHOD => {
-                let params: lsp::ShowMessageParams = params.parse()?;
-                Self::ShowMessage(params)
+    /// Normalizes the `documentChanges` of `workspace_edit`, or its `changes` for servers that
+    /// don't send document changes, into the changes to apply in order.
+    pub fn workspace_edit_changes(
+        workspace_edit: &lsp::WorkspaceEdit,
+    ) -> Vec<WorkspaceEditChange<'_>> {
+        fn text_document_edit(document_edit: &lsp::TextDocumentEdit) -> WorkspaceEditChange<'_> {
+            WorkspaceEditChange::Edit {
+                uri: &document_edit.text_document.uri,
+                version: document_edit.text_document.version,
+                edits: document_edit
+                    .edits
+                    .iter()
+                    .map(|edit| match edit {
+                        lsp::OneOf::Left(text_edit) => text_edit.clone(),
+                        lsp::OneOf::Right(annotated_text_edit) => {
+                            annotated_text_edit.text_edit.clone()
+                        }
+                    })
+                    .collect(),
             }
-            lsp::notification::LogMessage::METHOD => {
-                let params: lsp::LogMessageParams = params.parse()?;
-                Self::LogMessage(params)
+        }
+
+        match (&workspace_edit.document_changes, &workspace_edit.changes) {
+            (Some(lsp::DocumentChanges::Edits(document_edits)), _) => {
+                document_edits.iter().map(text_document_edit).collect()
             }
-            lsp::notification::Progress::METHOD => {
-                let params: lsp::ProgressParams = params.parse()?;
-                Self::ProgressMessage(params)
+            (Some(lsp::DocumentChanges::Operations(operations)), _) => operations
+                .iter()
+                .map(|operation| match operation {
+                    lsp::DocumentChangeOperation::Edit(document_edit) => {
+                        text_document_edit(document_edit)
+                    }
+                    lsp::DocumentChangeOperation::Op(op) => WorkspaceEditChange::ResourceOp(op),
+                })
+                .collect(),
+            (None, Some(changes)) => changes
+                .iter()
+                .map(|(uri, edits)| WorkspaceEditChange::Edit {
+                    uri,
+                    version: None,
+                    edits: edits.clone(),
+                })
+                .collect(),
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// Applies the changes of `workspace_edit` in order with `apply`, stopping at the first one
+    /// that fails with the reason it returns. `document_version` returns the current version of
+    /// open documents: nothing is applied if an edit was computed for another version of its
+    /// document, since its positions would be off.
+    pub fn apply_workspace_edit<'a>(
+        workspace_edit: &'a lsp::WorkspaceEdit,
+        document_version: impl Fn(&lsp::Url) -> Option<i32>,
+        mut apply: impl FnMut(WorkspaceEditChange<'a>) -> std::result::Result<(), String>,
+    ) -> lsp::ApplyWorkspaceEditResponse {
+        let failure = |index: usize, reason: String| lsp::ApplyWorkspaceEditResponse {
+            applied: false,
+            failure_reason: Some(reason),
+            failed_change: Some(index as u32),
+        };
+        let changes = workspace_edit_changes(workspace_edit);
+
+        for (index, change) in changes.iter().enumerate() {
+            if let WorkspaceEditChange::Edit {
+                uri,
+                version: Some(version),
+                ..
+            } = change
+            {
+                match document_version(uri) {
+                    Some(current_version) if current_version != *version => {
+                        return failure(
+                            index,
+                            format!(
+                                "edits for version {} of {} but the document is at version {}",
+                                version, uri, current_version
+                            ),
+                        );
+                    }
+                    _ => (),
+                }
             }
-            _ => {
-                return Err(Error::Unhandled);
+        }
+
+        for (index, change) in changes.into_iter().enumerate() {
+            if let Err(reason) = apply(change) {
+                return failure(index, reason);
             }
-        };
+        }
 
-        Ok(notification)
+        lsp::ApplyWorkspaceEditResponse {
+            applied: true,
+            failure_reason: None,
+            failed_change: None,
+        }
     }
-}
 
-#[derive(Debug)]
-pub struct Registry {
-    inner: HashMap<LanguageId, (usize, Arc<Client>)>,
+    /// The completion item kinds that [`completion_kind_label`] knows, advertised to servers
+    /// so they stick to these.
+    pub const COMPLETION_ITEM_KINDS: &[lsp::CompletionItemKind] = &[
+        lsp::CompletionItemKind::TEXT,
+        lsp::CompletionItemKind::METHOD,
+        lsp::CompletionItemKind::FUNCTION,
+        lsp::CompletionItemKind::CONSTRUCTOR,
+        lsp::CompletionItemKind::FIELD,
+        lsp::CompletionItemKind::VARIABLE,
+        lsp::CompletionItemKind::CLASS,
+        lsp::CompletionItemKind::INTERFACE,
+        lsp::CompletionItemKind::MODULE,
+        lsp::CompletionItemKind::PROPERTY,
+        lsp::CompletionItemKind::UNIT,
+        lsp::CompletionItemKind::VALUE,
+        lsp::CompletionItemKind::ENUM,
+        lsp::CompletionItemKind::KEYWORD,
+        lsp::CompletionItemKind::SNIPPET,
+        lsp::CompletionItemKind::COLOR,
+        lsp::CompletionItemKind::FILE,
+        lsp::CompletionItemKind::REFERENCE,
+        lsp::CompletionItemKind::FOLDER,
+        lsp::CompletionItemKind::ENUM_MEMBER,
+        lsp::CompletionItemKind::CONSTANT,
+        lsp::CompletionItemKind::STRUCT,
+        lsp::CompletionItemKind::EVENT,
+        lsp::CompletionItemKind::OPERATOR,
+        lsp::CompletionItemKind::TYPE_PARAMETER,
+    ];
 
-    counter: AtomicUsize,
-    pub incoming: SelectAll<UnboundedReceiverStream<(usize, Call)>>,
-}
+    /// Returns the label displayed for completion items of `kind`. Kinds added in newer
+    /// versions of the spec have an empty label.
+    pub fn completion_kind_label(kind: lsp::CompletionItemKind) -> &'static str {
+        match kind {
+            lsp::CompletionItemKind::TEXT => "text",
+            lsp::CompletionItemKind::METHOD => "method",
+            lsp::CompletionItemKind::FUNCTION => "function",
+            lsp::CompletionItemKind::CONSTRUCTOR => "constructor",
+            lsp::CompletionItemKind::FIELD => "field",
+            lsp::CompletionItemKind::VARIABLE => "variable",
+            lsp::CompletionItemKind::CLASS => "class",
+            lsp::CompletionItemKind::INTERFACE => "interface",
+            lsp::CompletionItemKind::MODULE => "module",
+            lsp::CompletionItemKind::PROPERTY => "property",
+            lsp::CompletionItemKind::UNIT => "unit",
+            lsp::CompletionItemKind::VALUE => "value",
+            lsp::CompletionItemKind::ENUM => "enum",
+            lsp::CompletionItemKind::KEYWORD => "keyword",
+            lsp::CompletionItemKind::SNIPPET => "snippet",
+            lsp::CompletionItemKind::COLOR => "color",
+            lsp::CompletionItemKind::FILE => "file",
+            lsp::CompletionItemKind::REFERENCE => "reference",
+            lsp::CompletionItemKind::FOLDER => "folder",
+            lsp::CompletionItemKind::ENUM_MEMBER => "enum_member",
+            lsp::CompletionItemKind::CONSTANT => "constant",
+            lsp::CompletionItemKind::STRUCT => "struct",
+            lsp::CompletionItemKind::EVENT => "event",
+            lsp::CompletionItemKind::OPERATOR => "operator",
+            lsp::CompletionItemKind::TYPE_PARAMETER => "type_param",
+            _ => "",
+        }
+    }
 
-impl Default for Registry {
-    fn default() -> Self {
-        Self::new()
+    /// A coarse grouping of the kinds of completion items, for styling the kinds that belong
+    /// together the same way.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum CompletionCategory {
+        Function,
+        Variable,
+        Constant,
+        Type,
+        Module,
+        Keyword,
+        Snippet,
+        Path,
+        Text,
+        /// Kinds added in newer versions of the spec.
+        Other,
     }
-}
 
-impl Registry {
-    pub fn new() -> Self {
-        Self {
-            inner: HashMap::new(),
-            counter: AtomicUsize::new(0),
-            incoming: SelectAll::new(),
+    impl CompletionCategory {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                Self::Function => "function",
+                Self::Variable => "variable",
+                Self::Constant => "constant",
+                Self::Type => "type",
+                Self::Module => "module",
+                Self::Keyword => "keyword",
+                Self::Snippet => "snippet",
+                Self::Path => "path",
+                Self::Text => "text",
+                Self::Other => "other",
+            }
         }
     }
 
-    pub fn get_by_id(&self, id: usize) -> Option<&Client> {
-        self.inner
-            .values()
-            .find(|(client_id, _)| client_id == &id)
-            .map(|(_, client)| client.as_ref())
+    /// Returns the category of completion items of `kind`.
+    pub fn completion_kind_category(kind: lsp::CompletionItemKind) -> CompletionCategory {
+        use lsp::CompletionItemKind as Kind;
+
+        match kind {
+            Kind::METHOD | Kind::FUNCTION | Kind::CONSTRUCTOR | Kind::EVENT => {
+                CompletionCategory::Function
+            }
+            Kind::FIELD | Kind::VARIABLE | Kind::PROPERTY | Kind::REFERENCE => {
+                CompletionCategory::Variable
+            }
+            Kind::VALUE | Kind::ENUM_MEMBER | Kind::CONSTANT | Kind::COLOR => {
+                CompletionCategory::Constant
+            }
+            Kind::CLASS
+            | Kind::INTERFACE
+            | Kind::UNIT
+            | Kind::ENUM
+            | Kind::STRUCT
+            | Kind::TYPE_PARAMETER => CompletionCategory::Type,
+            Kind::MODULE => CompletionCategory::Module,
+            Kind::KEYWORD | Kind::OPERATOR => CompletionCategory::Keyword,
+            Kind::SNIPPET => CompletionCategory::Snippet,
+            Kind::FILE | Kind::FOLDER => CompletionCategory::Path,
+            Kind::TEXT => CompletionCategory::Text,
+            _ => CompletionCategory::Other,
+        }
     }
 
-    pub fn remove_by_id(&mut self, id: usize) {
-        self.inner.retain(|_, (client_id, _)| client_id != &id)
+    /// Converts the response of a goto request (definition, declaration, type definition or
+    /// implementation) into the locations it points to. Location links point to their
+    /// target range, see [`goto_response_to_targets`] for their selection range.
+    pub fn goto_response_to_locations(
+        response: Option<lsp::GotoDefinitionResponse>,
+    ) -> Vec<lsp::Location> {
+        goto_response_to_targets(response)
+            .into_iter()
+            .map(|target| target.location)
+            .collect()
     }
 
-    pub fn restart(
-        &mut self,
-        language_config: &LanguageConfiguration,
-        doc_path: Option<&std::path::PathBuf>,
-    ) -> Result<Option<Arc<Client>>> {
-        let config = match &language_config.language_server {
-            Some(config) => config,
-            None => return Ok(None),
+    /// A place a goto request points to, see [`goto_response_to_targets`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct GotoTarget {
+        /// The document and the range of the whole target, e.g. a function including its body.
+        pub location: lsp::Location,
+        /// The range to select when navigating to the target, e.g. the name of the function.
+        pub selection_range: lsp::Range,
+        /// The range of the symbol in the requesting document the target is for, if the
+        /// server reported it.
+        pub origin_selection_range: Option<lsp::Range>,
+    }
+
+    impl GotoTarget {
+        /// The range to select in `doc`, the text of the target document.
+        pub fn selection(&self, doc: &Rope, offset_encoding: OffsetEncoding) -> Option<Range> {
+            lsp_range_to_range(doc, self.selection_range, offset_encoding)
+        }
+    }
+
+    /// Converts the response of a goto request into its targets. Unlike
+    /// [`goto_response_to_locations`] the target selection range of location links is kept,
+    /// plain locations are selected as a whole.
+    pub fn goto_response_to_targets(
+        response: Option<lsp::GotoDefinitionResponse>,
+    ) -> Vec<GotoTarget> {
+        let from_location = |location: lsp::Location| GotoTarget {
+            selection_range: location.range,
+            location,
+            origin_selection_range: None,
         };
+        match response {
+            Some(lsp::GotoDefinitionResponse::Scalar(location)) => vec![from_location(location)],
+            Some(lsp::GotoDefinitionResponse::Array(locations)) => {
+                locations.into_iter().map(from_location).collect()
+            }
+            Some(lsp::GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| GotoTarget {
+                    location: lsp::Location::new(link.target_uri, link.target_range),
+                    selection_range: link.target_selection_range,
+                    origin_selection_range: link.origin_selection_range,
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
 
-        let scope = language_config.scope.clone();
+    /// Keeps the symbols of one of `kinds`, or all symbols if `kinds` is empty. The order the
+    /// server sorted the symbols in is preserved.
+    pub fn filter_symbols_by_kind(
+        mut response: lsp::WorkspaceSymbolResponse,
+        kinds: &[lsp::SymbolKind],
+    ) -> lsp::WorkspaceSymbolResponse {
+        if !kinds.is_empty() {
+            match &mut response {
+                lsp::WorkspaceSymbolResponse::Flat(symbols) => {
+                    symbols.retain(|symbol| kinds.contains(&symbol.kind))
+                }
+                lsp::WorkspaceSymbolResponse::Nested(symbols) => {
+                    symbols.retain(|symbol| kinds.contains(&symbol.kind))
+                }
+            }
+        }
+        response
+    }
 
-        match self.inner.entry(scope) {
-            Entry::Vacant(_) => Ok(None),
-            Entry::Occupied(mut entry) => {
-                // initialize a new client
-                let id = self.counter.fetch_add(1, Ordering::Relaxed);
+    /// Flattens either shape of a workspace symbol response into [`lsp::SymbolInformation`]s.
+    /// A [`lsp::WorkspaceSymbol`] that only carries the uri of its location points at the
+    /// start of its document.
+    pub fn workspace_symbols_to_flat(
+        response: lsp::WorkspaceSymbolResponse,
+    ) -> Vec<lsp::SymbolInformation> {
+        match response {
+            lsp::WorkspaceSymbolResponse::Flat(symbols) => symbols,
+            lsp::WorkspaceSymbolResponse::Nested(symbols) => symbols
+                .into_iter()
+                .map(|symbol| {
+                    let location = match symbol.location {
+                        lsp::OneOf::Left(location) => location,
+                        lsp::OneOf::Right(location) => {
+                            lsp::Location::new(location.uri, lsp::Range::default())
+                        }
+                    };
+                    #[allow(deprecated)]
+                    lsp::SymbolInformation {
+                        name: symbol.name,
+                        kind: symbol.kind,
+                        tags: symbol.tags,
+                        deprecated: None,
+                        location,
+                        container_name: symbol.container_name,
+                    }
+                })
+                .collect(),
+        }
+    }
 
-                let NewClientResult(client, incoming) =
-                    start_client(id, language_config, config, doc_path)?;
-                self.incoming.push(UnboundedReceiverStream::new(incoming));
+    /// A symbol of a document and the symbols nested inside of it, see
+    /// [`document_symbols_to_tree`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct DocumentSymbolNode {
+        pub name: String,
+        pub kind: lsp::SymbolKind,
+        pub detail: Option<String>,
+        pub tags: Vec<lsp::SymbolTag>,
+        /// Whether the symbol is marked as deprecated, by its tags or the deprecated flag.
+        pub deprecated: bool,
+        /// The range of the whole symbol, including its body.
+        pub range: Range,
+        /// The range to select when the symbol is picked, e.g. its name.
+        pub selection_range: Range,
+        pub children: Vec<DocumentSymbolNode>,
+    }
 
-                let (_, old_client) = entry.insert((id, client.clone()));
+    /// Converts the response of a document symbol request into a tree of symbols in `doc`.
+    ///
+    /// Hierarchical responses keep their nesting. Flat responses are nested by range, a
+    /// symbol becomes a child of the innermost symbol containing it. Symbols with ranges
+    /// outside of `doc` are skipped.
+    #[allow(deprecated)]
+    pub fn document_symbols_to_tree(
+        doc: &Rope,
+        response: lsp::DocumentSymbolResponse,
+        offset_encoding: OffsetEncoding,
+    ) -> Vec<DocumentSymbolNode> {
+        fn is_deprecated(tags: &[lsp::SymbolTag], deprecated: Option<bool>) -> bool {
+            deprecated == Some(true) || tags.contains(&lsp::SymbolTag::DEPRECATED)
+        }
 
-                tokio::spawn(async move {
-                    let _ = old_client.force_shutdown().await;
-                });
+        fn nested(
+            doc: &Rope,
+            symbols: Vec<lsp::DocumentSymbol>,
+            offset_encoding: OffsetEncoding,
+        ) -> Vec<DocumentSymbolNode> {
+            symbols
+                .into_iter()
+                .filter_map(|symbol| {
+                    let tags = symbol.tags.unwrap_or_default();
+                    Some(DocumentSymbolNode {
+                        deprecated: is_deprecated(&tags, symbol.deprecated),
+                        range: lsp_range_to_range(doc, symbol.range, offset_encoding)?,
+                        selection_range: lsp_range_to_range(
+                            doc,
+                            symbol.selection_range,
+                            offset_encoding,
+                        )?,
+                        children: nested(doc, symbol.children.unwrap_or_default(), offset_encoding),
+                        name: symbol.name,
+                        kind: symbol.kind,
+                        detail: symbol.detail,
+                        tags,
+                    })
+                })
+                .collect()
+        }
 
-                Ok(Some(client))
+        fn nest_by_range(
+            symbols: &mut std::iter::Peekable<std::vec::IntoIter<DocumentSymbolNode>>,
+            parent: Option<Range>,
+        ) -> Vec<DocumentSymbolNode> {
+            let mut nested = Vec::new();
+            // the symbols are sorted by start, so a symbol is inside the parent if it ends
+            // within it
+            while let Some(mut symbol) = symbols
+                .next_if(|symbol| parent.map_or(true, |parent| symbol.range.to() <= parent.to()))
+            {
+                symbol.children = nest_by_range(symbols, Some(symbol.range));
+                nested.push(symbol);
             }
+            nested
         }
-    }
-
-    pub fn stop(&mut self, language_config: &LanguageConfiguration) {
-        let scope = language_config.scope.clone();
 
-        if let Some((_, client)) = self.inner.remove(&scope) {
-            tokio::spawn(async move {
-                let _ = client.force_shutdown().await;
-            });
+        match response {
+            lsp::DocumentSymbolResponse::Nested(symbols) => nested(doc, symbols, offset_encoding),
+            lsp::DocumentSymbolResponse::Flat(symbols) => {
+                let mut symbols: Vec<_> = symbols
+                    .into_iter()
+                    .filter_map(|symbol| {
+                        let range =
+                            lsp_range_to_range(doc, symbol.location.range, offset_encoding)?;
+                        let tags = symbol.tags.unwrap_or_default();
+                        Some(DocumentSymbolNode {
+                            deprecated: is_deprecated(&tags, symbol.deprecated),
+                            name: symbol.name,
+                            kind: symbol.kind,
+                            detail: None,
+                            tags,
+                            range,
+                            selection_range: range,
+                            children: Vec::new(),
+                        })
+                    })
+                    .collect();
+                // outer symbols come before the symbols they contain
+                symbols.sort_by_key(|symbol| {
+                    (symbol.range.from(), std::cmp::Reverse(symbol.range.to()))
+                });
+                nest_by_range(&mut symbols.into_iter().peekable(), None)
+            }
         }
     }
 
-    pub fn get(
-        &mut self,
-        language_config: &LanguageConfiguration,
-        doc_path: Option<&std::path::PathBuf>,
-    ) -> Result<Option<Arc<Client>>> {
-        let config = match &language_config.language_server {
-            Some(config) => config,
-            None => return Ok(None),
-        };
-
-        match self.inner.entry(language_config.scope.clone()) {
-            Entry::Occupied(entry) => Ok(Some(entry.get().1.clone())),
-            Entry::Vacant(entry) => {
-                // initialize a new client
-                let id = self.counter.fetch_add(1, Ordering::Relaxed);
+    /// A semantic token positioned in the document, see [`decode_semantic_tokens`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AbsoluteSemanticToken {
+        pub range: lsp::Range,
+        /// Index into the token types of the [`lsp::SemanticTokensLegend`].
+        pub token_type: u32,
+        /// Bitset of indices into the token modifiers of the [`lsp::SemanticTokensLegend`].
+        pub token_modifiers_bitset: u32,
+    }
 
-                let NewClientResult(client, incoming) =
-                    start_client(id, language_config, config, doc_path)?;
-                self.incoming.push(UnboundedReceiverStream::new(incoming));
+    /// Resolves the positions of `tokens`, which are relative to the previous token, into
+    /// ranges in the document. Tokens never span lines.
+    pub fn decode_semantic_tokens(tokens: &[lsp::SemanticToken]) -> Vec<AbsoluteSemanticToken> {
+        let mut line = 0;
+        let mut start = 0;
+        tokens
+            .iter()
+            .map(|token| {
+                if token.delta_line > 0 {
+                    line += token.delta_line;
+                    start = 0;
+                }
+                start += token.delta_start;
+                AbsoluteSemanticToken {
+                    range: lsp::Range::new(
+                        lsp::Position::new(line, start),
+                        lsp::Position::new(line, start + token.length),
+                    ),
+                    token_type: token.token_type,
+                    token_modifiers_bitset: token.token_modifiers_bitset,
+                }
+            })
+            .collect()
+    }
 
-                entry.insert((id, client.clone()));
-                Ok(Some(client))
+    /// Returns the text to display for an inlay hint: its label, or the values of its label
+    /// parts, with the padding the server asked for.
+    pub fn inlay_hint_text(hint: &lsp::InlayHint) -> String {
+        let mut text = String::new();
+        if hint.padding_left == Some(true) {
+            text.push(' ');
+        }
+        match &hint.label {
+            lsp::InlayHintLabel::String(label) => text.push_str(label),
+            lsp::InlayHintLabel::LabelParts(parts) => {
+                for part in parts {
+                    text.push_str(&part.value);
+                }
             }
         }
+        if hint.padding_right == Some(true) {
+            text.push(' ');
+        }
+        text
     }
 
-    pub fn iter_clients(&self) -> impl Iterator<Item = &Arc<Client>> {
-        self.inner.values().map(|(_, client)| client)
+    /// Something the editor has to do to carry out a code action.
+    #[derive(Debug, PartialEq)]
+    pub enum CodeActionEffect<'a> {
+        Edit(&'a lsp::WorkspaceEdit),
+        Command(&'a lsp::Command),
     }
-}
 
-#[derive(Debug)]
-pub enum ProgressStatus {
-    Created,
-    Started(lsp::WorkDoneProgress),
-}
+    /// Returns what the editor has to do to carry out a (resolved) code action. A code action
+    /// with both an edit and a command has the edit applied before the command is executed.
+    pub fn code_action_effects(
+        code_action: &lsp::CodeAction,
+    ) -> impl Iterator<Item = CodeActionEffect<'_>> {
+        let edit = code_action.edit.as_ref().map(CodeActionEffect::Edit);
+        let command = code_action.command.as_ref().map(CodeActionEffect::Command);
+        edit.into_iter().chain(command)
+    }
 
-impl ProgressStatus {
-    pub fn progress(&self) -> Option<&lsp::WorkDoneProgress> {
-        match &self {
-            ProgressStatus::Created => None,
-            ProgressStatus::Started(progress) => Some(progress),
+    /// Separates the code actions that can be applied from the ones the server marked as
+    /// `disabled`, which are only shown along with the reason they can't be applied. Both
+    /// keep the order of the response.
+    pub fn partition_code_actions(
+        actions: lsp::CodeActionResponse,
+    ) -> (Vec<lsp::CodeActionOrCommand>, Vec<lsp::CodeAction>) {
+        let mut enabled = Vec::new();
+        let mut disabled = Vec::new();
+        for action in actions {
+            match action {
+                lsp::CodeActionOrCommand::CodeAction(
+                    code_action @ lsp::CodeAction {
+                        disabled: Some(_), ..
+                    },
+                ) => disabled.push(code_action),
+                action => enabled.push(action),
+            }
         }
+        (enabled, disabled)
     }
-}
 
-#[derive(Default, Debug)]
-/// Acts as a container for progress reported by language servers. Each server
-/// has a unique id assigned at creation through [`Registry`]. This id is then used
-/// to store the progress in this map.
-pub struct LspProgressMap(HashMap<usize, HashMap<lsp::ProgressToken, ProgressStatus>>);
-
-impl LspProgressMap {
-    pub fn new() -> Self {
-        Self::default()
+    /// Picks the code action of `kind` (or one of its sub-kinds) out of the response to a
+    /// request for source actions. Servers are free to ignore the requested kinds, so other
+    /// code actions, commands and disabled actions are skipped.
+    pub fn find_source_action(
+        actions: lsp::CodeActionResponse,
+        kind: &lsp::CodeActionKind,
+    ) -> Option<lsp::CodeAction> {
+        actions.into_iter().find_map(|action| match action {
+            lsp::CodeActionOrCommand::CodeAction(
+                code_action @ lsp::CodeAction { disabled: None, .. },
+            ) => {
+                let action_kind = code_action.kind.as_ref()?.as_str();
+                let is_kind = action_kind == kind.as_str()
+                    || action_kind
+                        .strip_prefix(kind.as_str())
+                        .map_or(false, |rest| rest.starts_with('.'));
+                is_kind.then_some(code_action)
+            }
+            _ => None,
+        })
     }
 
-    /// Returns a map of all tokens corresponding to the language server with `id`.
-    pub fn progress_map(&self, id: usize) -> Option<&HashMap<lsp::ProgressToken, ProgressStatus>> {
-        self.0.get(&id)
+    /// A command that is implemented by the editor instead of the language server. Servers
+    /// attach them to completion items, e.g. to show the signature of a completed function.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum EditorCommand {
+        TriggerParameterHints,
+        TriggerSuggest,
     }
 
-    pub fn is_progressing(&self, id: usize) -> bool {
-        self.0.get(&id).map(|it| !it.is_empty()).unwrap_or_default()
+    impl EditorCommand {
+        /// Recognizes the names servers use for the built-in commands of editors.
+        pub fn from_lsp_name(name: &str) -> Option<Self> {
+            match name {
+                "editor.action.triggerParameterHints" => Some(Self::TriggerParameterHints),
+                "editor.action.triggerSuggest" => Some(Self::TriggerSuggest),
+                _ => None,
+            }
+        }
     }
 
-    /// Returns last progress status for a given server with `id` and `token`.
-    pub fn progress(&self, id: usize, token: &lsp::ProgressToken) -> Option<&ProgressStatus> {
-        self.0.get(&id).and_then(|values| values.get(token))
+    /// The command attached to a completion item.
+    #[derive(Debug, PartialEq)]
+    pub enum CompletionCommand<'a> {
+        /// Run by the editor itself.
+        Editor(EditorCommand),
+        /// Executed on the server with `workspace/executeCommand`.
+        Server(&'a lsp::Command),
     }
 
-    /// Checks if progress `token` for server with `id` is created.
-    pub fn is_created(&mut self, id: usize, token: &lsp::ProgressToken) -> bool {
-        self.0
-            .get(&id)
-            .map(|values| values.get(token).is_some())
-            .unwrap_or_default()
+    /// Returns the command to run after the edits of an accepted completion `item` were
+    /// applied, if it has one.
+    pub fn completion_command(item: &lsp::CompletionItem) -> Option<CompletionCommand<'_>> {
+        let command = item.command.as_ref()?;
+        let command = match EditorCommand::from_lsp_name(&command.command) {
+            Some(command) => CompletionCommand::Editor(command),
+            None => CompletionCommand::Server(command),
+        };
+        Some(command)
     }
 
-    pub fn create(&mut self, id: usize, token: lsp::ProgressToken) {
-        self.0
-            .entry(id)
-            .or_default()
-            .insert(token, ProgressStatus::Created);
+    /// Builds the context of a signature help request triggered by typing `ch`.
+    ///
+    /// `active_signature_help` is the signature help currently shown, if any. While it is
+    /// shown, retrigger characters update it instead of opening a new one. Returns `None`
+    /// if `ch` doesn't trigger signature help.
+    pub fn signature_help_context(
+        ch: char,
+        trigger_characters: &[String],
+        retrigger_characters: &[String],
+        active_signature_help: Option<lsp::SignatureHelp>,
+    ) -> Option<lsp::SignatureHelpContext> {
+        // TODO: what if trigger is multiple chars long
+        let is_trigger = trigger_characters
+            .iter()
+            .any(|trigger| trigger.contains(ch));
+        let is_retrigger = active_signature_help.is_some()
+            // all trigger characters also count as retrigger characters
+            && (is_trigger || retrigger_characters.iter().any(|trigger| trigger.contains(ch)));
+
+        if !(is_trigger || is_retrigger) {
+            return None;
+        }
+
+        Some(lsp::SignatureHelpContext {
+            trigger_kind: lsp::SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+            trigger_character: Some(ch.to_string()),
+            is_retrigger,
+            active_signature_help,
+        })
     }
 
-    /// Ends the progress by removing the `token` from server with `id`, if removed returns the value.
-    pub fn end_progress(
-        &mut self,
-        id: usize,
-        token: &lsp::ProgressToken,
-    ) -> Option<ProgressStatus> {
-        self.0.get_mut(&id).and_then(|vals| vals.remove(token))
+    /// How raw HTML embedded in markdown from a language server is treated.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum HtmlHandling {
+        /// Leave HTML tags untouched.
+        Keep,
+        /// Remove HTML tags, keeping the text between them.
+        Strip,
+        /// Escape HTML tags so they are rendered literally.
+        Escape,
     }
 
-    /// Updates the progress of `token` for server with `id` to `status`, returns the value replaced or `None`.
-    pub fn update(
-        &mut self,
-        id: usize,
-        token: lsp::ProgressToken,
-        status: lsp::WorkDoneProgress,
-    ) -> Option<ProgressStatus> {
-        self.0
-            .entry(id)
-            .or_default()
-            .insert(token, ProgressStatus::Started(status))
+    /// Converts [`lsp::HoverContents`] into a single markdown string suitable for rendering.
+    ///
+    /// [`lsp::MarkedString`]s with a language are turned into fenced code blocks tagged with
+    /// that language. Markdown content has raw HTML handled according to `html` and untagged
+    /// code fences tagged with `language`, the language of the hovered document.
+    pub fn hover_contents_to_markdown(
+        contents: lsp::HoverContents,
+        language: Option<&str>,
+        html: HtmlHandling,
+    ) -> String {
+        let marked_string_to_markdown = |contents: lsp::MarkedString| match contents {
+            lsp::MarkedString::String(contents) => normalize_markdown(&contents, language, html),
+            lsp::MarkedString::LanguageString(string) => {
+                if string.language == "markdown" {
+                    normalize_markdown(&string.value, language, html)
+                } else {
+                    format!("```{}\n{}\n```", string.language, string.value)
+                }
+            }
+        };
+
+        match contents {
+            lsp::HoverContents::Scalar(contents) => marked_string_to_markdown(contents),
+            lsp::HoverContents::Array(contents) => contents
+                .into_iter()
+                .map(marked_string_to_markdown)
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            lsp::HoverContents::Markup(contents) => match contents.kind {
+                lsp::MarkupKind::Markdown => normalize_markdown(&contents.value, language, html),
+                lsp::MarkupKind::PlainText => contents.value,
+            },
+        }
     }
-}
 
-struct NewClientResult(Arc<Client>, UnboundedReceiver<(usize, Call)>);
+    /// Tags code fences without an info string with `language` and handles raw HTML
+    /// outside of code according to `html`.
+    pub fn normalize_markdown(
+        markdown: &str,
+        language: Option<&str>,
+        html: HtmlHandling,
+    ) -> String {
+        let mut output = String::with_capacity(markdown.len());
+        // the character used by the currently open code fence, if any
+        let mut fence: Option<char> = None;
 
-/// start_client takes both a LanguageConfiguration and a LanguageServerConfiguration to ensure that
-/// it is only called when it makes sense.
-fn start_client(
-    id: usize,
-    config: &LanguageConfiguration,
-    ls_config: &LanguageServerConfiguration,
-    doc_path: Option<&std::path::PathBuf>,
-) -> Result<NewClientResult> {
-    let (client, incoming, initialize_notify) = Client::start(
-        &ls_config.command,
-        &ls_config.args,
-        config.config.clone(),
-        ls_config.environment.clone(),
-        &config.roots,
-        id,
-        ls_config.timeout,
-        doc_path,
-    )?;
+        for (i, line) in markdown.split('\n').enumerate() {
+            if i > 0 {
+                output.push('\n');
+            }
 
-    let client = Arc::new(client);
+            let trimmed = line.trim_start();
+            let marker = ["```", "~~~"]
+                .into_iter()
+                .find(|marker| trimmed.starts_with(marker))
+                .and_then(|marker| marker.chars().next());
 
-    // Initialize the client asynchronously
-    let _client = client.clone();
-    tokio::spawn(async move {
-        use futures_util::TryFutureExt;
-        let value = _client
-            .capabilities
-            .get_or_try_init(|| {
-                _client
-                    .initialize()
-                    .map_ok(|response| response.capabilities)
-            })
-            .await;
+            match (fence, marker) {
+                // closing fence
+                (Some(open), Some(marker))
+                    if open == marker && trimmed.trim_end().trim_matches(marker).is_empty() =>
+                {
+                    fence = None;
+                    output.push_str(line);
+                }
+                // code inside a fence is left untouched
+                (Some(_), _) => output.push_str(line),
+                // opening fence
+                (None, Some(marker)) => {
+                    fence = Some(marker);
+                    let info = trimmed.trim_start_matches(marker).trim();
+                    match language {
+                        Some(language) if info.is_empty() => {
+                            output.push_str(line.trim_end());
+                            output.push_str(language);
+                        }
+                        _ => output.push_str(line),
+                    }
+                }
+                (None, None) => push_markdown_line(&mut output, line, html),
+            }
+        }
 
-        if let Err(e) = value {
-            log::error!("failed to initialize language server: {}", e);
+        output
+    }
+
+    /// Pushes a line of markdown text, handling HTML tags outside of inline code spans.
+    fn push_markdown_line(output: &mut String, line: &str, html: HtmlHandling) {
+        if html == HtmlHandling::Keep || !line.contains('<') {
+            output.push_str(line);
             return;
         }
 
-        // next up, notify<initialized>
-        _client
-            .notify::<lsp::notification::Initialized>(lsp::InitializedParams {})
-            .await
-            .unwrap();
+        let mut in_code = false;
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            if c == '`' {
+                in_code = !in_code;
+            } else if c == '<' && !in_code {
+                let is_tag = rest[1..]
+                    .chars()
+                    .next()
+                    .map_or(false, |c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+                if let (true, Some(end)) = (is_tag, rest.find('>')) {
+                    if html == HtmlHandling::Escape {
+                        output.push_str("&lt;");
+                        output.push_str(&rest[1..end]);
+                        output.push_str("&gt;");
+                    }
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+}
 
-        initialize_notify.notify_one();
-    });
+#[derive(Debug, PartialEq, Clone)]
+pub enum MethodCall {
+    WorkDoneProgressCreate(lsp::WorkDoneProgressCreateParams),
+    ApplyWorkspaceEdit(lsp::ApplyWorkspaceEditParams),
+    WorkspaceFolders,
+    WorkspaceConfiguration(lsp::ConfigurationParams),
+    RegisterCapability(lsp::RegistrationParams),
+    UnregisterCapability(lsp::UnregistrationParams),
+}
 
-    Ok(NewClientResult(client, incoming))
+impl MethodCall {
+    pub fn parse(method: &str, params: jsonrpc::Params) -> Result<MethodCall> {
+        use lsp::request::Request;
+        let request = match method {
+            lsp::request::WorkDoneProgressCreate::METHOD => {
+                let params: lsp::WorkDoneProgressCreateParams = params.parse()?;
+                Self::WorkDoneProgressCreate(params)
+            }
+            lsp::request::ApplyWorkspaceEdit::METHOD => {
+                let params: lsp::ApplyWorkspaceEditParams = params.parse()?;
+                Self::ApplyWorkspaceEdit(params)
+            }
+            lsp::request::WorkspaceFoldersRequest::METHOD => Self::WorkspaceFolders,
+            lsp::request::WorkspaceConfiguration::METHOD => {
+                let params: lsp::ConfigurationParams = params.parse()?;
+                Self::WorkspaceConfiguration(params)
+            }
+            lsp::request::RegisterCapability::METHOD => {
+                let params: lsp::RegistrationParams = params.parse()?;
+                Self::RegisterCapability(params)
+            }
+            lsp::request::UnregisterCapability::METHOD => {
+                let params: lsp::UnregistrationParams = params.parse()?;
+                Self::UnregisterCapability(params)
+            }
+            _ => {
+                return Err(Error::Unhandled);
+            }
+        };
+        Ok(request)
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{lsp, util::*, OffsetEncoding};
-    use helix_core::Rope;
+/// How the process of a language server exited, sent along the injected `exit` notification.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct ServerExitStatus {
+    /// The exit code, `None` if the process was terminated by a signal.
+    pub code: Option<i32>,
+    /// The signal that terminated the process, only on Unix.
+    pub signal: Option<i32>,
+}
 
-    #[test]
-    fn converts_lsp_pos_to_pos() {
-        macro_rules! test_case {
-            ($doc:expr, ($x:expr, $y:expr) => $want:expr) => {
-                let doc = Rope::from($doc);
-                let pos = lsp::Position::new($x, $y);
-                assert_eq!($want, lsp_pos_to_pos(&doc, pos, OffsetEncoding::Utf16));
-                assert_eq!($want, lsp_pos_to_pos(&doc, pos, OffsetEncoding::Utf8))
-            };
+impl ServerExitStatus {
+    /// Whether the server exited cleanly rather than crashing.
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl From<std::process::ExitStatus> for ServerExitStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+
+        Self {
+            code: status.code(),
+            signal,
+        }
+    }
+}
+
+impl std::fmt::Display for ServerExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code {}", code),
+            (None, Some(signal)) => write!(f, "signal {}", signal),
+            (None, None) => write!(f, "unknown status"),
         }
+    }
+}
 
-        test_case!("", (0, 0) => Some(0));
-        test_case!("", (0, 1) => Some(0));
-        test_case!("", (1, 0) => None);
-        test_case!("\n\n", (0, 0) => Some(0));
-        test_case!("\n\n", (1, 0) => Some(1));
-        test_case!("\n\n", (1, 1) => Some(1));
-        test_case!("\n\n", (2, 0) => Some(2));
-        test_case!("\n\n", (3, 0) => None);
-        test_case!("test\n\n\n\ncase", (4, 3) => Some(11));
-        test_case!("test\n\n\n\ncase", (4, 4) => Some(12));
-        test_case!("test\n\n\n\ncase", (4, 5) => Some(12));
-        test_case!("", (u32::MAX, u32::MAX) => None);
+#[derive(Debug, PartialEq, Clone)]
+pub enum Notification {
+    // we inject this notification to signal the LSP is ready
+    Initialized,
+    // and this notification to signal that the LSP exited, with the exit status of its
+    // process if it is known
+    Exit(Option<ServerExitStatus>),
+    PublishDiagnostics(lsp::PublishDiagnosticsParams),
+    ShowMessage(lsp::ShowMessageParams),
+    LogMessage(lsp::LogMessageParams),
+    ProgressMessage(lsp::ProgressParams),
+    /// The server cancelled one of its requests. The transport already replaced the reply.
+    Cancel(lsp::CancelParams),
+}
+
+impl Notification {
+    pub fn parse(method: &str, params: jsonrpc::Params) -> Result<Notification> {
+        use lsp::notification::Notification as _;
+
+        let notification = match method {
+            lsp::notification::Initialized::METHOD => Self::Initialized,
+            lsp::notification::Exit::METHOD => match params {
+                jsonrpc::Params::None => Self::Exit(None),
+                params => Self::Exit(Some(params.parse()?)),
+            },
+            lsp::notification::PublishDiagnostics::METHOD => {
+                let params: lsp::PublishDiagnosticsParams = params.parse()?;
+                Self::PublishDiagnostics(params)
+            }
+
+            lsp::notification::ShowMessage::METHOD => {
+                let params: lsp::ShowMessageParams = params.parse()?;
+                Self::ShowMessage(params)
+            }
+            lsp::notification::LogMessage::METHOD => {
+                let params: lsp::LogMessageParams = params.parse()?;
+                Self::LogMessage(params)
+            }
+            lsp::notification::Progress::METHOD => {
+                let params: lsp::ProgressParams = params.parse()?;
+                Self::ProgressMessage(params)
+            }
+            lsp::notification::Cancel::METHOD => {
+                let params: lsp::CancelParams = params.parse()?;
+                Self::Cancel(params)
+            }
+            _ => {
+                return Err(Error::Unhandled);
+            }
+        };
+
+        Ok(notification)
+    }
+}
+
+/// Exponential backoff between restarts of the language server of a scope, so a server
+/// that crashes on startup isn't restarted in a tight loop.
+#[derive(Debug)]
+struct RestartBackoff {
+    limit: Duration,
+    /// The time of the last restart and the delay before the next one is allowed.
+    restarts: HashMap<LanguageId, (Instant, Duration)>,
+    /// The time of the last reconnect and the number of reconnects in a row.
+    reconnects: HashMap<LanguageId, (Instant, usize)>,
+}
+
+impl RestartBackoff {
+    const INITIAL_DELAY: Duration = Duration::from_secs(1);
+    const DEFAULT_LIMIT: Duration = Duration::from_secs(60);
+    /// A server that stayed up this long is considered stable and the backoff is reset.
+    const STABLE_UPTIME: Duration = Duration::from_secs(60);
+    /// Reconnecting gives up after this many reconnects in a row.
+    const MAX_RECONNECTS: usize = 5;
+
+    fn new() -> Self {
+        Self {
+            limit: Self::DEFAULT_LIMIT,
+            restarts: HashMap::new(),
+            reconnects: HashMap::new(),
+        }
+    }
+
+    /// The number of reconnects to the server of `scope` in a row at `now`. It starts over
+    /// once a connection stayed up long enough.
+    fn reconnects(&self, scope: &str, now: Instant) -> usize {
+        match self.reconnects.get(scope) {
+            Some(&(last_reconnect, reconnects))
+                if now.saturating_duration_since(last_reconnect) < Self::STABLE_UPTIME =>
+            {
+                reconnects
+            }
+            _ => 0,
+        }
+    }
+
+    /// Records a reconnect to the server of `scope` at `now`.
+    fn reconnect(&mut self, scope: &str, now: Instant) {
+        let reconnects = self.reconnects(scope, now) + 1;
+        self.reconnects.insert(scope.to_string(), (now, reconnects));
+    }
+
+    /// Records a restart of the server of `scope` at `now`. Returns how much longer to
+    /// wait if the previous restart was too recent.
+    fn restart(&mut self, scope: &str, now: Instant) -> std::result::Result<(), Duration> {
+        let delay = match self.restarts.get(scope) {
+            Some(&(last_restart, delay)) => {
+                let uptime = now.saturating_duration_since(last_restart);
+                if uptime < delay {
+                    return Err(delay - uptime);
+                }
+                if uptime >= Self::STABLE_UPTIME {
+                    Self::INITIAL_DELAY
+                } else {
+                    (delay * 2).min(self.limit)
+                }
+            }
+            None => Self::INITIAL_DELAY,
+        };
+        self.restarts.insert(scope.to_string(), (now, delay));
+        Ok(())
+    }
+}
+
+/// Resolves the initialization options of a language server from its language configuration
+/// and the root of the workspace it is started for, e.g. to configure the server differently
+/// for each project of a monorepo.
+pub type InitializationOptionsResolver =
+    fn(&LanguageConfiguration, &Path) -> Option<serde_json::Value>;
+
+/// Uses the `config` of the language for every root.
+pub fn default_initialization_options(
+    language_config: &LanguageConfiguration,
+    _root: &Path,
+) -> Option<serde_json::Value> {
+    language_config.config.clone()
+}
+
+#[derive(Debug)]
+pub struct Registry {
+    inner: HashMap<LanguageId, (usize, Arc<Client>)>,
+    /// The language configuration and documents each server was last started with, by scope.
+    launches: HashMap<LanguageId, (Arc<LanguageConfiguration>, Vec<PathBuf>)>,
+    restart_backoff: RestartBackoff,
+    /// Servers started by [`Registry::reconnect`] that didn't finish initializing yet.
+    pending_reconnects: HashSet<usize>,
+    initialization_options: InitializationOptionsResolver,
+
+    counter: AtomicUsize,
+    pub incoming: SelectAll<UnboundedReceiverStream<(usize, Call)>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            inner: HashMap::new(),
+            launches: HashMap::new(),
+            restart_backoff: RestartBackoff::new(),
+            pending_reconnects: HashSet::new(),
+            initialization_options: default_initialization_options,
+            counter: AtomicUsize::new(0),
+            incoming: SelectAll::new(),
+        }
+    }
+
+    pub fn get_by_id(&self, id: usize) -> Option<&Client> {
+        self.inner
+            .values()
+            .find(|(client_id, _)| client_id == &id)
+            .map(|(_, client)| client.as_ref())
+    }
+
+    pub fn remove_by_id(&mut self, id: usize) {
+        self.pending_reconnects.remove(&id);
+        self.inner.retain(|_, (client_id, _)| client_id != &id);
+        let inner = &self.inner;
+        self.launches.retain(|scope, _| inner.contains_key(scope));
+    }
+
+    /// Sets how the initialization options of servers started from now on are resolved.
+    pub fn set_initialization_options_resolver(&mut self, resolver: InitializationOptionsResolver) {
+        self.initialization_options = resolver;
+    }
+
+    /// Sets the longest delay enforced between restarts of a language server that keeps
+    /// getting restarted.
+    pub fn set_restart_backoff_limit(&mut self, limit: Duration) {
+        self.restart_backoff.limit = limit;
+    }
+
+    /// Restarts the language server for `language_config`. The workspace folders of the new
+    /// server are derived from the roots of all `doc_paths` that will be attached to it.
+    ///
+    /// Fails with [`Error::RestartTooSoon`] if the server was restarted too recently. The
+    /// delay between restarts doubles with every restart until the server stays up for
+    /// a while.
+    pub fn restart(
+        &mut self,
+        language_config: &LanguageConfiguration,
+        doc_paths: &[&Path],
+    ) -> Result<Option<Arc<Client>>> {
+        let config = match &language_config.language_server {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        let scope = language_config.scope.clone();
+
+        match self.inner.entry(scope) {
+            Entry::Vacant(_) => Ok(None),
+            Entry::Occupied(mut entry) => {
+                self.restart_backoff
+                    .restart(entry.key(), Instant::now())
+                    .map_err(Error::RestartTooSoon)?;
+
+                // initialize a new client
+                let id = self.counter.fetch_add(1, Ordering::Relaxed);
+
+                let NewClientResult(client, incoming) = start_client(
+                    id,
+                    language_config,
+                    config,
+                    doc_paths,
+                    self.initialization_options,
+                )?;
+                self.incoming.push(UnboundedReceiverStream::new(incoming));
+
+                let (_, old_client) = entry.insert((id, client.clone()));
+                if let Some((_, launch_paths)) = self.launches.get_mut(&language_config.scope) {
+                    *launch_paths = doc_paths.iter().map(|path| path.to_path_buf()).collect();
+                }
+
+                tokio::spawn(async move {
+                    let _ = old_client.force_shutdown().await;
+                });
+
+                Ok(Some(client))
+            }
+        }
+    }
+
+    /// Restarts the language server with `id` with the language configuration and documents
+    /// it was last started with, see [`Registry::restart`]. Returns `Ok(None)` if there is no
+    /// server with `id`.
+    pub fn restart_by_id(&mut self, id: usize) -> Result<Option<Arc<Client>>> {
+        let scope = match self
+            .inner
+            .iter()
+            .find(|(_, (client_id, _))| *client_id == id)
+        {
+            Some((scope, _)) => scope,
+            None => return Ok(None),
+        };
+        let (language_config, doc_paths) = match self.launches.get(scope) {
+            Some(launch) => launch.clone(),
+            None => return Ok(None),
+        };
+
+        let doc_paths: Vec<_> = doc_paths.iter().map(PathBuf::as_path).collect();
+        self.restart(&language_config, &doc_paths)
+    }
+
+    /// Whether the connection to the language server with `id` is re-established, which TCP
+    /// servers configured with `reconnect` ask for once their connection broke. A server that
+    /// closed the connection itself isn't reconnected.
+    pub fn reconnects(&self, id: usize) -> bool {
+        let (scope, client) = match self
+            .inner
+            .iter()
+            .find(|(_, (client_id, _))| *client_id == id)
+        {
+            Some((scope, (_, client))) => (scope, client),
+            None => return false,
+        };
+        let configured = matches!(
+            self.launches
+                .get(scope)
+                .and_then(|(config, _)| config.language_server.as_ref())
+                .map(|config| &config.transport),
+            Some(LanguageServerTransport::Tcp {
+                reconnect: true,
+                ..
+            })
+        );
+        configured && client.connection_lost()
+    }
+
+    /// Re-establishes the broken connection to the language server with `id`, see
+    /// [`Registry::reconnects`]. Reconnecting is restarting the server with
+    /// [`Registry::restart_by_id`], subject to the same backoff, and gives up with
+    /// [`Error::ReconnectLimit`] after a few reconnects in a row. Returns `Ok(None)` if the
+    /// server isn't reconnected.
+    pub fn reconnect(&mut self, id: usize) -> Result<Option<Arc<Client>>> {
+        if !self.reconnects(id) {
+            return Ok(None);
+        }
+        let scope = match self
+            .inner
+            .iter()
+            .find(|(_, (client_id, _))| *client_id == id)
+        {
+            Some((scope, _)) => scope.clone(),
+            None => return Ok(None),
+        };
+
+        // the previous attempt didn't get the server initialized
+        self.pending_reconnects.remove(&id);
+        let now = Instant::now();
+        if self.restart_backoff.reconnects(&scope, now) >= RestartBackoff::MAX_RECONNECTS {
+            return Err(Error::ReconnectLimit(RestartBackoff::MAX_RECONNECTS));
+        }
+        let client = self.restart_by_id(id)?;
+        if let Some(client) = &client {
+            self.restart_backoff.reconnect(&scope, now);
+            self.pending_reconnects.insert(client.id());
+        }
+        Ok(client)
+    }
+
+    /// Whether the language server with `id` that just finished initializing was started by
+    /// [`Registry::reconnect`], i.e. reconnecting to it succeeded. Only returns `true` once.
+    pub fn finish_reconnect(&mut self, id: usize) -> bool {
+        self.pending_reconnects.remove(&id)
+    }
+
+    pub fn stop(&mut self, language_config: &LanguageConfiguration) {
+        let scope = language_config.scope.clone();
+
+        self.launches.remove(&scope);
+        if let Some((_, client)) = self.inner.remove(&scope) {
+            tokio::spawn(async move {
+                let _ = client.force_shutdown().await;
+            });
+        }
+    }
+
+    /// Gracefully shuts down all language servers and waits for them to exit, see
+    /// [`Client::shutdown`].
+    ///
+    /// Servers that didn't answer or exit within `timeout` are killed. The registry is empty
+    /// afterwards.
+    pub async fn shutdown_all(&mut self, timeout: Duration) {
+        self.launches.clear();
+        let clients: Vec<_> = self.inner.drain().map(|(_, (_, client))| client).collect();
+
+        futures_util::future::join_all(clients.iter().map(|client| async move {
+            if let Err(err) = client.shutdown(timeout).await {
+                log::warn!(
+                    "failed to shut down language server {} - {}",
+                    client.id(),
+                    err
+                );
+            }
+        }))
+        .await;
+    }
+
+    pub fn get(
+        &mut self,
+        language_config: &Arc<LanguageConfiguration>,
+        doc_path: Option<&std::path::PathBuf>,
+    ) -> Result<Option<Arc<Client>>> {
+        let config = match &language_config.language_server {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+
+        // a server that failed to initialize is replaced, as often as restarts are allowed
+        if let Some((_, client)) = self.inner.get(&language_config.scope) {
+            if let Some(err) = client.initialize_error() {
+                log::info!(
+                    "replacing language server {} that failed to initialize: {}",
+                    client.id(),
+                    err
+                );
+                self.restart_backoff
+                    .restart(&language_config.scope, Instant::now())
+                    .map_err(Error::RestartTooSoon)?;
+                self.inner.remove(&language_config.scope);
+            }
+        }
+
+        match self.inner.entry(language_config.scope.clone()) {
+            Entry::Occupied(entry) => Ok(Some(entry.get().1.clone())),
+            Entry::Vacant(entry) => {
+                // initialize a new client
+                let id = self.counter.fetch_add(1, Ordering::Relaxed);
+
+                let doc_paths: Vec<_> = doc_path.into_iter().map(|path| path.as_path()).collect();
+                let NewClientResult(client, incoming) = start_client(
+                    id,
+                    language_config,
+                    config,
+                    &doc_paths,
+                    self.initialization_options,
+                )?;
+                self.incoming.push(UnboundedReceiverStream::new(incoming));
+
+                entry.insert((id, client.clone()));
+                self.launches.insert(
+                    language_config.scope.clone(),
+                    (
+                        language_config.clone(),
+                        doc_path.into_iter().cloned().collect(),
+                    ),
+                );
+                Ok(Some(client))
+            }
+        }
+    }
+
+    pub fn iter_clients(&self) -> impl Iterator<Item = &Arc<Client>> {
+        self.inner.values().map(|(_, client)| client)
+    }
+
+    /// The initialized language servers for `scope` whose capabilities satisfy `predicate`,
+    /// e.g. to send formatting requests only to a server that can format.
+    pub fn clients_with_capability(
+        &self,
+        scope: &str,
+        predicate: impl Fn(&lsp::ServerCapabilities) -> bool,
+    ) -> Vec<Arc<Client>> {
+        self.inner
+            .get(scope)
+            .map(|(_, client)| client)
+            .filter(|client| client.initialize_error().is_none())
+            .filter(|client| client.capabilities.get().map_or(false, &predicate))
+            .cloned()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ProgressStatus {
+    Created,
+    Started(lsp::WorkDoneProgress),
+}
+
+impl ProgressStatus {
+    pub fn progress(&self) -> Option<&lsp::WorkDoneProgress> {
+        match &self {
+            ProgressStatus::Created => None,
+            ProgressStatus::Started(progress) => Some(progress),
+        }
+    }
+}
+
+/// How a `$/progress` notification changed the [`LspProgressMap`], see
+/// [`LspProgressMap::apply`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProgressChange<'a> {
+    /// The progress began or was reported on. `title` is only set when it began.
+    Updated {
+        title: Option<&'a str>,
+        message: Option<&'a str>,
+        percentage: Option<u32>,
+    },
+    /// The progress ended and was removed, with its final message.
+    Ended { message: Option<String> },
+}
+
+#[derive(Default, Debug)]
+/// Acts as a container for progress reported by language servers. Each server
+/// has a unique id assigned at creation through [`Registry`]. This id is then used
+/// to store the progress in this map.
+pub struct LspProgressMap {
+    progress: HashMap<usize, HashMap<lsp::ProgressToken, ProgressStatus>>,
+    /// When each token was last created or updated, by server.
+    updated: HashMap<usize, HashMap<lsp::ProgressToken, Instant>>,
+}
+
+impl LspProgressMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a map of all tokens corresponding to the language server with `id`.
+    pub fn progress_map(&self, id: usize) -> Option<&HashMap<lsp::ProgressToken, ProgressStatus>> {
+        self.progress.get(&id)
+    }
+
+    pub fn is_progressing(&self, id: usize) -> bool {
+        self.progress
+            .get(&id)
+            .map(|it| !it.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// Combines the progress of all tokens of the server with `id` that have started, for
+    /// showing them as one. Returns a title like "indexing (2 tasks)" and the average of the
+    /// percentages the tokens report, `None` if none of them do.
+    ///
+    /// The title is the one of the first task in alphabetical order. A task that already
+    /// reported is named by its last message, since reports don't repeat the title, or by
+    /// its token.
+    pub fn aggregate(&self, id: usize) -> Option<(String, Option<u32>)> {
+        let mut titles = Vec::new();
+        let mut percentages = Vec::new();
+        for (token, status) in self.progress.get(&id)? {
+            let (title, percentage) = match status.progress() {
+                Some(lsp::WorkDoneProgress::Begin(begin)) => (Some(&begin.title), begin.percentage),
+                Some(lsp::WorkDoneProgress::Report(report)) => {
+                    (report.message.as_ref(), report.percentage)
+                }
+                _ => continue,
+            };
+            titles.push(match (title, token) {
+                (Some(title), _) => title.clone(),
+                (None, lsp::NumberOrString::Number(n)) => n.to_string(),
+                (None, lsp::NumberOrString::String(s)) => s.clone(),
+            });
+            percentages.extend(percentage);
+        }
+
+        titles.sort_unstable();
+        let title = match titles.len() {
+            0 => return None,
+            1 => titles.swap_remove(0),
+            n => format!("{} ({} tasks)", titles[0], n),
+        };
+        let percentage = (!percentages.is_empty())
+            .then(|| percentages.iter().sum::<u32>() / percentages.len() as u32);
+        Some((title, percentage))
+    }
+
+    /// Returns last progress status for a given server with `id` and `token`.
+    pub fn progress(&self, id: usize, token: &lsp::ProgressToken) -> Option<&ProgressStatus> {
+        self.progress.get(&id).and_then(|values| values.get(token))
+    }
+
+    /// Checks if progress `token` for server with `id` is created.
+    pub fn is_created(&mut self, id: usize, token: &lsp::ProgressToken) -> bool {
+        self.progress
+            .get(&id)
+            .map(|values| values.get(token).is_some())
+            .unwrap_or_default()
+    }
+
+    pub fn create(&mut self, id: usize, token: lsp::ProgressToken) {
+        self.touch(id, token.clone());
+        self.progress
+            .entry(id)
+            .or_default()
+            .insert(token, ProgressStatus::Created);
+    }
+
+    /// Ends the progress by removing the `token` from server with `id`, if removed returns the value.
+    pub fn end_progress(
+        &mut self,
+        id: usize,
+        token: &lsp::ProgressToken,
+    ) -> Option<ProgressStatus> {
+        if let Some(updated) = self.updated.get_mut(&id) {
+            updated.remove(token);
+        }
+        self.progress
+            .get_mut(&id)
+            .and_then(|vals| vals.remove(token))
+    }
+
+    /// Updates the progress of `token` for server with `id` to `status`, returns the value replaced or `None`.
+    pub fn update(
+        &mut self,
+        id: usize,
+        token: lsp::ProgressToken,
+        status: lsp::WorkDoneProgress,
+    ) -> Option<ProgressStatus> {
+        self.touch(id, token.clone());
+        self.progress
+            .entry(id)
+            .or_default()
+            .insert(token, ProgressStatus::Started(status))
+    }
+
+    fn touch(&mut self, id: usize, token: lsp::ProgressToken) {
+        self.updated
+            .entry(id)
+            .or_default()
+            .insert(token, Instant::now());
+    }
+
+    /// Removes the tokens that weren't updated for longer than `max_age`, e.g. because
+    /// a buggy server never ended them, and returns them with the id of their server.
+    pub fn prune_stale(&mut self, max_age: Duration) -> Vec<(usize, lsp::ProgressToken)> {
+        let now = Instant::now();
+        let stale: Vec<_> = self
+            .updated
+            .iter()
+            .flat_map(|(id, tokens)| {
+                tokens
+                    .iter()
+                    .filter(|(_, updated)| now.saturating_duration_since(**updated) > max_age)
+                    .map(move |(token, _)| (*id, token.clone()))
+            })
+            .collect();
+        for (id, token) in &stale {
+            self.end_progress(*id, token);
+        }
+        stale
+    }
+
+    /// Applies a `$/progress` notification of the server with `id`: `begin` and `report`
+    /// update the progress of the token, `end` ends it.
+    pub fn apply(&mut self, id: usize, params: lsp::ProgressParams) -> ProgressChange<'_> {
+        let lsp::ProgressParams { token, value } = params;
+        let lsp::ProgressParamsValue::WorkDone(work) = value;
+
+        if let lsp::WorkDoneProgress::End(lsp::WorkDoneProgressEnd { message }) = work {
+            self.end_progress(id, &token);
+            return ProgressChange::Ended { message };
+        }
+
+        self.touch(id, token.clone());
+        let progress = self.progress.entry(id).or_default();
+        progress.insert(token.clone(), ProgressStatus::Started(work));
+        match progress[&token].progress() {
+            Some(lsp::WorkDoneProgress::Begin(lsp::WorkDoneProgressBegin {
+                title,
+                message,
+                percentage,
+                ..
+            })) => ProgressChange::Updated {
+                title: Some(title),
+                message: message.as_deref(),
+                percentage: *percentage,
+            },
+            Some(lsp::WorkDoneProgress::Report(lsp::WorkDoneProgressReport {
+                message,
+                percentage,
+                ..
+            })) => ProgressChange::Updated {
+                title: None,
+                message: message.as_deref(),
+                percentage: *percentage,
+            },
+            // `end` was handled above
+            Some(lsp::WorkDoneProgress::End(_)) | None => unreachable!(),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+/// Stores the diagnostics published by language servers for each document. Diagnostics
+/// are tagged with the id of the server that published them, so that multiple servers
+/// attached to the same document don't overwrite each other's diagnostics.
+pub struct DiagnosticStore {
+    diagnostics: BTreeMap<lsp::Url, BTreeMap<usize, Vec<lsp::Diagnostic>>>,
+    /// Debounced updates by server and document, with the time they are due to be applied.
+    pending: HashMap<(usize, lsp::Url), (Instant, Vec<lsp::Diagnostic>)>,
+}
+
+impl DiagnosticStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the diagnostics published by the server with `id` for `uri`.
+    pub fn insert(&mut self, id: usize, uri: lsp::Url, diagnostics: Vec<lsp::Diagnostic>) {
+        // a debounced update that is still pending is older than this one
+        self.pending.remove(&(id, uri.clone()));
+        self.apply(id, uri, diagnostics);
+    }
+
+    /// Queues the diagnostics published by the server with `id` for `uri` to be applied
+    /// once `window` has passed since the first publish that is still pending. Publishes
+    /// within the window replace the queued diagnostics, so only the latest are applied
+    /// by [`DiagnosticStore::flush`].
+    pub fn insert_debounced(
+        &mut self,
+        id: usize,
+        uri: lsp::Url,
+        diagnostics: Vec<lsp::Diagnostic>,
+        window: Duration,
+        now: Instant,
+    ) {
+        match self.pending.entry((id, uri)) {
+            Entry::Occupied(mut entry) => entry.get_mut().1 = diagnostics,
+            Entry::Vacant(entry) => {
+                entry.insert((now + window, diagnostics));
+            }
+        }
+    }
+
+    /// Returns when the next debounced update is due, if any are pending.
+    pub fn next_flush(&self) -> Option<Instant> {
+        self.pending.values().map(|(due, _)| *due).min()
+    }
+
+    /// Applies the debounced updates that are due at `now` and returns the documents
+    /// whose diagnostics changed.
+    pub fn flush(&mut self, now: Instant) -> Vec<lsp::Url> {
+        let due: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, (due, _))| *due <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut uris = Vec::new();
+        for (id, uri) in due {
+            if let Some((_, diagnostics)) = self.pending.remove(&(id, uri.clone())) {
+                if !uris.contains(&uri) {
+                    uris.push(uri.clone());
+                }
+                self.apply(id, uri, diagnostics);
+            }
+        }
+        uris
+    }
+
+    fn apply(&mut self, id: usize, uri: lsp::Url, diagnostics: Vec<lsp::Diagnostic>) {
+        if diagnostics.is_empty() {
+            if let btree_map::Entry::Occupied(mut entry) = self.diagnostics.entry(uri) {
+                entry.get_mut().remove(&id);
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+            }
+        } else {
+            self.diagnostics
+                .entry(uri)
+                .or_default()
+                .insert(id, diagnostics);
+        }
+    }
+
+    /// Returns the diagnostics of all servers for `uri`.
+    pub fn get(&self, uri: &lsp::Url) -> impl Iterator<Item = &lsp::Diagnostic> {
+        self.diagnostics
+            .get(uri)
+            .into_iter()
+            .flat_map(|servers| servers.values().flatten())
+    }
+
+    /// Returns the diagnostics for `uri` grouped by the id of the server that published them.
+    pub fn diagnostics_by_server(&self, uri: &lsp::Url) -> HashMap<usize, Vec<&lsp::Diagnostic>> {
+        self.diagnostics
+            .get(uri)
+            .into_iter()
+            .flatten()
+            .map(|(id, diagnostics)| (*id, diagnostics.iter().collect()))
+            .collect()
+    }
+
+    /// Iterates over all diagnostics, ordered by document.
+    pub fn iter(&self) -> impl Iterator<Item = (&lsp::Url, &lsp::Diagnostic)> {
+        self.diagnostics.iter().flat_map(|(uri, servers)| {
+            servers
+                .values()
+                .flatten()
+                .map(move |diagnostic| (uri, diagnostic))
+        })
+    }
+
+    /// Removes the diagnostics of all servers for `uri`.
+    pub fn remove(&mut self, uri: &lsp::Url) {
+        self.diagnostics.remove(uri);
+        self.pending
+            .retain(|(_, pending_uri), _| pending_uri != uri);
+    }
+
+    /// Removes all diagnostics published by the server with `id`.
+    pub fn remove_server(&mut self, id: usize) {
+        self.diagnostics.retain(|_, servers| {
+            servers.remove(&id);
+            !servers.is_empty()
+        });
+        self.pending.retain(|(server_id, _), _| *server_id != id);
+    }
+}
+
+struct NewClientResult(Arc<Client>, UnboundedReceiver<(usize, Call)>);
+
+/// start_client takes both a LanguageConfiguration and a LanguageServerConfiguration to ensure that
+/// it is only called when it makes sense.
+fn start_client(
+    id: usize,
+    config: &LanguageConfiguration,
+    ls_config: &LanguageServerConfiguration,
+    doc_paths: &[&Path],
+    initialization_options: InitializationOptionsResolver,
+) -> Result<NewClientResult> {
+    let offset_encodings = if ls_config.offset_encodings.is_empty() {
+        OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec()
+    } else {
+        ls_config
+            .offset_encodings
+            .iter()
+            .filter_map(|name| {
+                let encoding = OffsetEncoding::from_lsp_name(name);
+                if encoding.is_none() {
+                    log::warn!(
+                        "Ignoring unknown offset encoding {} for {}",
+                        name,
+                        ls_config.command
+                    );
+                }
+                encoding
+            })
+            .collect()
+    };
+
+    let root = client::workspace_roots(&config.roots, doc_paths).remove(0);
+
+    let (client, incoming, initialize_notify) = match &ls_config.transport {
+        LanguageServerTransport::Stdio => Client::start(
+            &ls_config.command,
+            &ls_config.args,
+            initialization_options(config, &root),
+            ls_config.environment.clone(),
+            &config.roots,
+            id,
+            ls_config.timeout,
+            HashMap::new(),
+            doc_paths,
+            offset_encodings,
+            ls_config.initialized_after_configuration,
+        )?,
+        LanguageServerTransport::Tcp { host, port, .. } => Client::start_tcp(
+            &ls_config.command,
+            host,
+            *port,
+            initialization_options(config, &root),
+            &config.roots,
+            id,
+            ls_config.timeout,
+            HashMap::new(),
+            doc_paths,
+            offset_encodings,
+            ls_config.initialized_after_configuration,
+        ),
+    };
+
+    let client = Arc::new(client);
+
+    // Initialize the client asynchronously
+    initialize_client(client.clone(), initialize_notify);
+
+    Ok(NewClientResult(client, incoming))
+}
+
+/// Spawns the initialization of `client`. The task can be cancelled by shutting down
+/// the client while it is still initializing.
+fn initialize_client(
+    client: Arc<Client>,
+    initialize_notify: Arc<tokio::sync::Notify>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        use futures_util::TryFutureExt;
+        let initialize = client.capabilities.get_or_try_init(|| {
+            client
+                .connect_tcp()
+                .and_then(|()| client.initialize())
+                .map_ok(|response| {
+                    client.negotiate_offset_encoding(&response.capabilities);
+                    client.detect_protocol_version(&response);
+                    client.store_server_info(&response);
+                    response.capabilities
+                })
+        });
+
+        let value = match client.initialize_or_cancel(initialize).await {
+            Some(value) => value,
+            None => {
+                log::info!("language server {} initialization cancelled", client.id());
+                client.finish_initialize();
+                return;
+            }
+        };
+
+        if let Err(e) = value {
+            log::error!("failed to initialize language server: {}", e);
+            client.set_initialize_error(e.to_string());
+            client.finish_initialize();
+            // the transport lets the editor know once the server exited
+            let _ = client.force_shutdown().await;
+            return;
+        }
+
+        // next up, notify<initialized>
+        client.notify_initialized().await.unwrap();
+
+        initialize_notify.notify_one();
+        client.finish_initialize();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{jsonrpc, lsp, util::*, DiagnosticStore, MethodCall, OffsetEncoding};
+    use helix_core::Rope;
+    use serde_json::json;
+
+    fn params(value: serde_json::Value) -> jsonrpc::Params {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn diagnostics_are_stored_per_server() {
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let diagnostic = |message: &str| lsp::Diagnostic {
+            message: message.to_string(),
+            ..Default::default()
+        };
+
+        let mut store = DiagnosticStore::new();
+        store.insert(0, uri.clone(), vec![diagnostic("rust-analyzer")]);
+        store.insert(
+            1,
+            uri.clone(),
+            vec![diagnostic("clippy"), diagnostic("clippy")],
+        );
+
+        let by_server = store.diagnostics_by_server(&uri);
+        assert_eq!(by_server.len(), 2);
+        assert_eq!(by_server[&0][0].message, "rust-analyzer");
+        assert_eq!(by_server[&1].len(), 2);
+        assert_eq!(store.get(&uri).count(), 3);
+
+        // publishing again replaces only that server's diagnostics
+        store.insert(1, uri.clone(), Vec::new());
+        assert_eq!(store.get(&uri).count(), 1);
+
+        store.remove_server(0);
+        assert_eq!(store.get(&uri).count(), 0);
+        assert!(store.diagnostics_by_server(&uri).is_empty());
+    }
+
+    #[test]
+    fn stale_progress_is_pruned() {
+        use super::LspProgressMap;
+        use std::time::{Duration, Instant};
+
+        let old = lsp::ProgressToken::Number(1);
+        let fresh = lsp::ProgressToken::Number(2);
+        let mut map = LspProgressMap::new();
+        map.create(0, old.clone());
+        map.create(0, fresh.clone());
+        // the server never ended the old token
+        map.updated
+            .get_mut(&0)
+            .unwrap()
+            .insert(old.clone(), Instant::now() - Duration::from_secs(600));
+
+        assert_eq!(
+            map.prune_stale(Duration::from_secs(300)),
+            vec![(0, old.clone())]
+        );
+        assert!(!map.is_created(0, &old));
+        assert!(map.is_created(0, &fresh));
+        assert!(map.is_progressing(0));
+        assert!(map.prune_stale(Duration::from_secs(300)).is_empty());
+    }
+
+    #[test]
+    fn progress_lifecycle_is_applied() {
+        use super::{LspProgressMap, ProgressChange};
+
+        let token = lsp::ProgressToken::String("indexing".to_string());
+        let progress = |value: serde_json::Value| lsp::ProgressParams {
+            token: token.clone(),
+            value: serde_json::from_value(value).unwrap(),
+        };
+
+        let mut map = LspProgressMap::new();
+        map.create(0, token.clone());
+        assert_eq!(
+            map.apply(
+                0,
+                progress(json!({ "kind": "begin", "title": "Indexing", "percentage": 0 }))
+            ),
+            ProgressChange::Updated {
+                title: Some("Indexing"),
+                message: None,
+                percentage: Some(0),
+            }
+        );
+        assert!(map.is_progressing(0));
+
+        assert_eq!(
+            map.apply(
+                0,
+                progress(json!({ "kind": "report", "message": "1/2 crates", "percentage": 50 }))
+            ),
+            ProgressChange::Updated {
+                title: None,
+                message: Some("1/2 crates"),
+                percentage: Some(50),
+            }
+        );
+        assert!(matches!(
+            map.progress(0, &token).and_then(|status| status.progress()),
+            Some(lsp::WorkDoneProgress::Report(_))
+        ));
+
+        assert_eq!(
+            map.apply(0, progress(json!({ "kind": "end", "message": "done" }))),
+            ProgressChange::Ended {
+                message: Some("done".to_string())
+            }
+        );
+        assert!(!map.is_progressing(0));
+        assert!(map.progress(0, &token).is_none());
+    }
+
+    #[test]
+    fn progress_of_several_tokens_is_aggregated() {
+        use super::LspProgressMap;
+
+        let begin = |title: &str, percentage: Option<u32>| {
+            lsp::WorkDoneProgress::Begin(lsp::WorkDoneProgressBegin {
+                title: title.to_string(),
+                percentage,
+                ..Default::default()
+            })
+        };
+        let token = |token: &str| lsp::ProgressToken::String(token.to_string());
+
+        let mut map = LspProgressMap::new();
+        map.create(0, token("created"));
+        assert_eq!(map.aggregate(0), None);
+
+        map.update(0, token("index"), begin("indexing", Some(50)));
+        assert_eq!(map.aggregate(0), Some(("indexing".to_string(), Some(50))));
+
+        map.update(0, token("check"), begin("checking", None));
+        assert_eq!(
+            map.aggregate(0),
+            Some(("checking (2 tasks)".to_string(), Some(50)))
+        );
+
+        map.end_progress(0, &token("index"));
+        assert_eq!(map.aggregate(0), Some(("checking".to_string(), None)));
+        assert_eq!(map.aggregate(1), None);
+    }
+
+    fn diagnostic(line: u32, message: &str) -> lsp::Diagnostic {
+        lsp::Diagnostic {
+            range: lsp::Range::new(lsp::Position::new(line, 0), lsp::Position::new(line, 4)),
+            message: message.to_string(),
+            code: Some(lsp::NumberOrString::String("E0308".to_string())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diagnostic_diff_of_added_diagnostics() {
+        let old = vec![diagnostic(1, "mismatched types")];
+        let new = vec![
+            diagnostic(0, "unused import"),
+            diagnostic(1, "mismatched types"),
+            diagnostic(1, "mismatched types"),
+        ];
+
+        let (added, removed) = diagnostic_diff(&old, &new);
+        assert_eq!(added, vec![&new[0], &new[2]]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn diagnostic_diff_of_removed_diagnostics() {
+        let old = vec![
+            diagnostic(0, "unused import"),
+            diagnostic(1, "mismatched types"),
+            diagnostic(2, "unused variable"),
+        ];
+        let mut changed = diagnostic(1, "mismatched types");
+        changed.code = Some(lsp::NumberOrString::String("E0599".to_string()));
+        let new = vec![diagnostic(0, "unused import"), changed];
+
+        // a diagnostic with a different code is a different diagnostic
+        let (added, removed) = diagnostic_diff(&old, &new);
+        assert_eq!(added, vec![&new[1]]);
+        assert_eq!(removed, vec![&old[1], &old[2]]);
+
+        let (added, removed) = diagnostic_diff(&old, &[]);
+        assert!(added.is_empty());
+        assert_eq!(removed.len(), 3);
+    }
+
+    #[test]
+    fn diagnostic_diff_ignores_reordering() {
+        let old = vec![
+            diagnostic(0, "unused import"),
+            diagnostic(1, "mismatched types"),
+            diagnostic(2, "unused variable"),
+        ];
+        let mut new: Vec<_> = old.iter().rev().cloned().collect();
+        // only the identity is compared
+        new[0].severity = Some(lsp::DiagnosticSeverity::WARNING);
+
+        let (added, removed) = diagnostic_diff(&old, &new);
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn completion_commands_are_run_by_editor_or_server() {
+        let item = |command: Option<&str>| lsp::CompletionItem {
+            label: "println!".to_string(),
+            command: command.map(|command| lsp::Command {
+                title: String::new(),
+                command: command.to_string(),
+                arguments: None,
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(completion_command(&item(None)), None);
+        assert_eq!(
+            completion_command(&item(Some("editor.action.triggerParameterHints"))),
+            Some(CompletionCommand::Editor(
+                EditorCommand::TriggerParameterHints
+            ))
+        );
+        let auto_import = item(Some("rust-analyzer.applyImport"));
+        assert_eq!(
+            completion_command(&auto_import),
+            Some(CompletionCommand::Server(
+                auto_import.command.as_ref().unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn resolved_code_action_applies_edit_before_command() {
+        let code_action: lsp::CodeAction = serde_json::from_value(json!({
+            "title": "Extract into function",
+            "edit": {
+                "changes": {
+                    "file:///project/src/main.rs": [{
+                        "range": {
+                            "start": { "line": 1, "character": 4 },
+                            "end": { "line": 1, "character": 15 },
+                        },
+                        "newText": "fun_name();",
+                    }],
+                },
+            },
+            "command": {
+                "title": "Rename",
+                "command": "editor.action.rename",
+            },
+            "data": { "id": 3 },
+        }))
+        .unwrap();
+
+        let effects: Vec<_> = code_action_effects(&code_action).collect();
+        assert_eq!(
+            effects,
+            [
+                CodeActionEffect::Edit(code_action.edit.as_ref().unwrap()),
+                CodeActionEffect::Command(code_action.command.as_ref().unwrap()),
+            ]
+        );
+
+        let command_only = lsp::CodeAction {
+            edit: None,
+            ..code_action.clone()
+        };
+        assert_eq!(
+            code_action_effects(&command_only).collect::<Vec<_>>(),
+            [CodeActionEffect::Command(
+                code_action.command.as_ref().unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn goto_responses_become_locations() {
+        let uri = lsp::Url::parse("file:///project/src/lib.rs").unwrap();
+        let range =
+            |line: u32| lsp::Range::new(lsp::Position::new(line, 0), lsp::Position::new(line, 8));
+
+        let scalar: Option<lsp::GotoDefinitionResponse> = serde_json::from_value(json!({
+            "uri": uri,
+            "range": range(3),
+        }))
+        .unwrap();
+        assert_eq!(
+            goto_response_to_locations(scalar),
+            [lsp::Location::new(uri.clone(), range(3))]
+        );
+
+        let array: Option<lsp::GotoDefinitionResponse> = serde_json::from_value(json!([
+            { "uri": uri, "range": range(4) },
+            { "uri": uri, "range": range(5) },
+        ]))
+        .unwrap();
+        assert!(matches!(array, Some(lsp::GotoDefinitionResponse::Array(_))));
+        assert_eq!(
+            goto_response_to_locations(array),
+            [
+                lsp::Location::new(uri.clone(), range(4)),
+                lsp::Location::new(uri.clone(), range(5)),
+            ]
+        );
+
+        let links: Option<lsp::GotoDefinitionResponse> = serde_json::from_value(json!([
+            {
+                "targetUri": uri,
+                "targetRange": range(10),
+                "targetSelectionRange": range(10),
+            },
+            {
+                "originSelectionRange": range(1),
+                "targetUri": uri,
+                "targetRange": range(20),
+                "targetSelectionRange": range(21),
+            },
+        ]))
+        .unwrap();
+        assert!(matches!(links, Some(lsp::GotoDefinitionResponse::Link(_))));
+        assert_eq!(
+            goto_response_to_locations(links),
+            [
+                lsp::Location::new(uri.clone(), range(10)),
+                lsp::Location::new(uri, range(20)),
+            ]
+        );
+
+        assert!(goto_response_to_locations(None).is_empty());
+    }
+
+    #[test]
+    fn location_links_keep_their_selection_range() {
+        let uri = lsp::Url::parse("file:///project/src/lib.rs").unwrap();
+        let range = |start: (u32, u32), end: (u32, u32)| {
+            lsp::Range::new(
+                lsp::Position::new(start.0, start.1),
+                lsp::Position::new(end.0, end.1),
+            )
+        };
+
+        let links: Option<lsp::GotoDefinitionResponse> = serde_json::from_value(json!([{
+            "originSelectionRange": range((7, 4), (7, 9)),
+            "targetUri": uri,
+            "targetRange": range((1, 0), (3, 1)),
+            "targetSelectionRange": range((1, 3), (1, 8)),
+        }]))
+        .unwrap();
+        let targets = goto_response_to_targets(links);
+        assert_eq!(
+            targets,
+            [GotoTarget {
+                location: lsp::Location::new(uri.clone(), range((1, 0), (3, 1))),
+                selection_range: range((1, 3), (1, 8)),
+                origin_selection_range: Some(range((7, 4), (7, 9))),
+            }]
+        );
+
+        let doc = Rope::from("// lib\nfn hello() {\n    todo!()\n}\n");
+        assert_eq!(
+            targets[0].selection(&doc, OffsetEncoding::Utf8),
+            Some(helix_core::Range::new(10, 15))
+        );
+
+        // plain locations are selected as a whole
+        let scalar = Some(lsp::GotoDefinitionResponse::Scalar(lsp::Location::new(
+            uri,
+            range((1, 0), (3, 1)),
+        )));
+        let targets = goto_response_to_targets(scalar);
+        assert_eq!(targets[0].selection_range, targets[0].location.range);
+        assert_eq!(targets[0].origin_selection_range, None);
+    }
+
+    #[test]
+    fn snippet_selects_every_tabstop() {
+        use crate::snippet;
+        use chrono::TimeZone;
+        use helix_core::Selection;
+
+        let doc = Rope::from("f\n");
+        let selection = Selection::point(1);
+        let snippet = snippet::parse("fn ${1:name}(${2:arg}) -> ${3|u8,i32|}$0").unwrap();
+
+        let ctx = snippet::SnippetContext {
+            path: None,
+            line: 0,
+            clipboard: &|| None,
+            now: chrono::Utc.timestamp_opt(0, 0).unwrap().into(),
+        };
+        let (transaction, tabstops) =
+            generate_transaction_from_snippet(&doc, &selection, -1, 0, snippet, "\n", true, &ctx);
+        let mut doc = doc;
+        assert!(transaction.apply(&mut doc));
+        assert_eq!(doc, "fn name(arg) -> u8\n");
+
+        let ranges: Vec<_> = tabstops
+            .iter()
+            .map(|selection| (selection.primary().from(), selection.primary().to()))
+            .collect();
+        assert_eq!(ranges, [(3, 7), (8, 11), (16, 18), (18, 18)]);
+        assert_eq!(transaction.selection(), Some(&tabstops[0]));
+    }
+
+    #[test]
+    fn snippet_tabstops_follow_their_numbers() {
+        use crate::snippet;
+        use chrono::TimeZone;
+        use helix_core::Selection;
+
+        let ctx = snippet::SnippetContext {
+            path: None,
+            line: 0,
+            clipboard: &|| None,
+            now: chrono::Utc.timestamp_opt(0, 0).unwrap().into(),
+        };
+        let tabstop_ranges = |snippet: &str| {
+            let doc = Rope::from("\n");
+            let snippet = snippet::parse(snippet).unwrap();
+            let (transaction, tabstops) = generate_transaction_from_snippet(
+                &doc,
+                &Selection::point(0),
+                0,
+                0,
+                snippet,
+                "\n",
+                true,
+                &ctx,
+            );
+            assert_eq!(transaction.selection(), tabstops.first());
+            tabstops
+                .iter()
+                .map(|selection| (selection.primary().from(), selection.primary().to()))
+                .collect::<Vec<_>>()
+        };
+
+        // `$2` comes first in the text, but `$1` is selected first and the final cursor is
+        // at the end
+        assert_eq!(
+            tabstop_ranges("let ${2:x} = ${1:y};"),
+            [(8, 9), (4, 5), (10, 10)]
+        );
+        // an explicit `$0` is the final cursor position wherever it is
+        assert_eq!(tabstop_ranges("if $0 {\n\t$1\n}"), [(7, 7), (3, 3)]);
+        // without tabstops the cursor ends up after the snippet
+        assert_eq!(tabstop_ranges("todo!()"), [(7, 7)]);
+    }
+
+    #[test]
+    fn apply_versioned_workspace_edit() {
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let text_edit = lsp::TextEdit::new(
+            lsp::Range::new(lsp::Position::new(1, 8), lsp::Position::new(1, 8)),
+            "mut ".to_string(),
+        );
+        let workspace_edit = lsp::WorkspaceEdit {
+            document_changes: Some(lsp::DocumentChanges::Edits(vec![lsp::TextDocumentEdit {
+                text_document: lsp::OptionalVersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: Some(3),
+                },
+                edits: vec![lsp::OneOf::Left(text_edit.clone())],
+            }])),
+            ..Default::default()
+        };
+
+        let mut applied = Vec::new();
+        let response = apply_workspace_edit(
+            &workspace_edit,
+            |_| Some(3),
+            |change| {
+                applied.push(change);
+                Ok(())
+            },
+        );
+        assert!(response.applied);
+        assert_eq!(
+            applied,
+            [WorkspaceEditChange::Edit {
+                uri: &uri,
+                version: Some(3),
+                edits: vec![text_edit],
+            }]
+        );
+
+        // the document changed since the server computed the edit
+        let response = apply_workspace_edit(
+            &workspace_edit,
+            |_| Some(4),
+            |change| panic!("applied {:?}", change),
+        );
+        assert!(!response.applied);
+        assert_eq!(response.failed_change, Some(0));
+        assert!(response.failure_reason.is_some());
+    }
+
+    #[test]
+    fn apply_workspace_edit_with_rename_file() {
+        let old_uri = lsp::Url::parse("file:///project/src/old.rs").unwrap();
+        let new_uri = lsp::Url::parse("file:///project/src/new.rs").unwrap();
+        let rename = lsp::ResourceOp::Rename(lsp::RenameFile {
+            old_uri,
+            new_uri: new_uri.clone(),
+            options: None,
+            annotation_id: None,
+        });
+        let text_edit = lsp::TextEdit::new(
+            lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(0, 0)),
+            "// moved\n".to_string(),
+        );
+        let workspace_edit = lsp::WorkspaceEdit {
+            document_changes: Some(lsp::DocumentChanges::Operations(vec![
+                lsp::DocumentChangeOperation::Op(rename.clone()),
+                lsp::DocumentChangeOperation::Edit(lsp::TextDocumentEdit {
+                    text_document: lsp::OptionalVersionedTextDocumentIdentifier {
+                        uri: new_uri.clone(),
+                        version: None,
+                    },
+                    edits: vec![lsp::OneOf::Left(text_edit.clone())],
+                }),
+            ])),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            workspace_edit_changes(&workspace_edit),
+            [
+                WorkspaceEditChange::ResourceOp(&rename),
+                WorkspaceEditChange::Edit {
+                    uri: &new_uri,
+                    version: None,
+                    edits: vec![text_edit],
+                },
+            ]
+        );
+
+        // changes after a failing one are not applied
+        let mut attempts = 0;
+        let response = apply_workspace_edit(
+            &workspace_edit,
+            |_| None,
+            |change| {
+                attempts += 1;
+                match change {
+                    WorkspaceEditChange::ResourceOp(_) => Err("target exists".to_string()),
+                    WorkspaceEditChange::Edit { .. } => Ok(()),
+                }
+            },
+        );
+        assert_eq!(attempts, 1);
+        assert_eq!(
+            response,
+            lsp::ApplyWorkspaceEditResponse {
+                applied: false,
+                failure_reason: Some("target exists".to_string()),
+                failed_change: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn decode_relative_semantic_tokens() {
+        // the start of the second token is relative to its line, since it is on another line
+        let tokens: lsp::SemanticTokens =
+            serde_json::from_value(json!({ "data": [2, 5, 3, 0, 3, 2, 4, 1, 1, 0] })).unwrap();
+        assert_eq!(
+            decode_semantic_tokens(&tokens.data),
+            [
+                AbsoluteSemanticToken {
+                    range: lsp::Range::new(lsp::Position::new(2, 5), lsp::Position::new(2, 8)),
+                    token_type: 0,
+                    token_modifiers_bitset: 3,
+                },
+                AbsoluteSemanticToken {
+                    range: lsp::Range::new(lsp::Position::new(4, 4), lsp::Position::new(4, 5)),
+                    token_type: 1,
+                    token_modifiers_bitset: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_to_ranges_skips_out_of_bounds_edits() {
+        use helix_core::Range;
+
+        let doc = Rope::from("fn main() {\n    let x = 1;\n}\n");
+        let edits = [
+            lsp::TextEdit::new(
+                lsp::Range::new(lsp::Position::new(1, 8), lsp::Position::new(1, 9)),
+                "count".to_string(),
+            ),
+            // past the last line
+            lsp::TextEdit::new(
+                lsp::Range::new(lsp::Position::new(7, 0), lsp::Position::new(7, 4)),
+                "main".to_string(),
+            ),
+        ];
+
+        assert_eq!(
+            lsp_edits_to_ranges(&doc, &edits, OffsetEncoding::Utf8),
+            [Range::new(20, 21)]
+        );
+    }
+
+    #[test]
+    fn nested_document_symbols_keep_their_hierarchy() {
+        use helix_core::Range;
+
+        let doc = Rope::from("struct Point {\n    x: i32,\n}\n\nfn main() {}\n");
+        let range = |start: (u32, u32), end: (u32, u32)| {
+            json!({
+                "start": { "line": start.0, "character": start.1 },
+                "end": { "line": end.0, "character": end.1 },
+            })
+        };
+        let response: lsp::DocumentSymbolResponse = serde_json::from_value(json!([
+            {
+                "name": "Point",
+                "kind": 23,
+                "range": range((0, 0), (2, 1)),
+                "selectionRange": range((0, 7), (0, 12)),
+                "children": [{
+                    "name": "x",
+                    "detail": "i32",
+                    "kind": 8,
+                    "range": range((1, 4), (1, 10)),
+                    "selectionRange": range((1, 4), (1, 5)),
+                }],
+            },
+            {
+                "name": "main",
+                "detail": "fn()",
+                "kind": 12,
+                "tags": [1],
+                "range": range((4, 0), (4, 12)),
+                "selectionRange": range((4, 3), (4, 7)),
+            },
+        ]))
+        .unwrap();
+        assert!(matches!(response, lsp::DocumentSymbolResponse::Nested(_)));
+
+        let symbols = document_symbols_to_tree(&doc, response, OffsetEncoding::Utf8);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, "Point");
+        assert_eq!(symbols[0].kind, lsp::SymbolKind::STRUCT);
+        assert_eq!(symbols[0].range, Range::new(0, 28));
+        assert_eq!(symbols[0].selection_range, Range::new(7, 12));
+        assert_eq!(symbols[0].children.len(), 1);
+        let field = &symbols[0].children[0];
+        assert_eq!(
+            (field.name.as_str(), field.detail.as_deref()),
+            ("x", Some("i32"))
+        );
+        assert_eq!(field.selection_range, Range::new(19, 20));
+        assert_eq!(symbols[1].name, "main");
+        assert_eq!(symbols[1].detail.as_deref(), Some("fn()"));
+        assert_eq!(symbols[1].tags, [lsp::SymbolTag::DEPRECATED]);
+        assert!(symbols[1].deprecated && !symbols[0].deprecated);
+    }
+
+    #[test]
+    fn flat_document_symbols_are_nested_by_range() {
+        use helix_core::Range;
+
+        let doc = Rope::from("struct Point {\n    x: i32,\n}\n\nfn main() {}\n");
+        let symbol = |name: &str, kind: u32, start: (u32, u32), end: (u32, u32)| {
+            json!({
+                "name": name,
+                "kind": kind,
+                "location": {
+                    "uri": "file:///project/src/main.rs",
+                    "range": {
+                        "start": { "line": start.0, "character": start.1 },
+                        "end": { "line": end.0, "character": end.1 },
+                    },
+                },
+                "deprecated": name == "main",
+            })
+        };
+        let response: lsp::DocumentSymbolResponse = serde_json::from_value(json!([
+            symbol("main", 12, (4, 0), (4, 12)),
+            symbol("x", 8, (1, 4), (1, 10)),
+            symbol("Point", 23, (0, 0), (2, 1)),
+            // past the end of the document
+            symbol("removed", 12, (9, 0), (9, 4)),
+        ]))
+        .unwrap();
+        assert!(matches!(response, lsp::DocumentSymbolResponse::Flat(_)));
+
+        let symbols = document_symbols_to_tree(&doc, response, OffsetEncoding::Utf8);
+        let names: Vec<_> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+        assert_eq!(names, ["Point", "main"]);
+        assert_eq!(symbols[0].children.len(), 1);
+        let field = &symbols[0].children[0];
+        assert_eq!(field.name, "x");
+        assert_eq!(field.kind, lsp::SymbolKind::FIELD);
+        assert_eq!(field.range, Range::new(19, 25));
+        // flat symbols only have one range
+        assert_eq!(field.selection_range, field.range);
+        assert!(field.children.is_empty());
+        assert!(symbols[1].deprecated && !symbols[0].deprecated);
+        assert_eq!(symbols[1].range, Range::new(30, 42));
+    }
+
+    #[test]
+    fn utf32_positions_stay_in_bounds() {
+        let doc = Rope::from("héllo\nwörld");
+        let pos = |line, character| {
+            lsp_pos_to_pos(
+                &doc,
+                lsp::Position::new(line, character),
+                OffsetEncoding::Utf32,
+            )
+        };
+
+        assert_eq!(pos(1, 2), Some(8));
+        // characters past the end of the last line are capped to the end of the document
+        assert_eq!(pos(1, 5), Some(doc.len_chars()));
+        assert_eq!(pos(1, 42), Some(doc.len_chars()));
+        assert_eq!(pos(1, u32::MAX), Some(doc.len_chars()));
+        // lines past the end of the document don't exist
+        assert_eq!(pos(2, 0), None);
+        assert_eq!(pos(u32::MAX, u32::MAX), None);
+    }
+
+    #[test]
+    fn transaction_edits_round_trip() {
+        let doc = Rope::from("fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n");
+        let edit = |start: (u32, u32), end: (u32, u32), new_text: &str| lsp::TextEdit {
+            range: lsp::Range::new(
+                lsp::Position::new(start.0, start.1),
+                lsp::Position::new(end.0, end.1),
+            ),
+            new_text: new_text.to_string(),
+        };
+        let edits = vec![
+            // replacement
+            edit((0, 3), (0, 7), "start"),
+            // insertion
+            edit((1, 8), (1, 8), "mut "),
+            // deletion spanning lines
+            edit((1, 14), (2, 22), ""),
+        ];
+
+        for offset_encoding in [
+            OffsetEncoding::Utf8,
+            OffsetEncoding::Utf16,
+            OffsetEncoding::Utf32,
+        ] {
+            let transaction = generate_transaction_from_edits(&doc, edits.clone(), offset_encoding);
+            let round_tripped = transaction_to_lsp_edits(&doc, &transaction, offset_encoding);
+            assert_eq!(round_tripped, edits);
+
+            let transaction =
+                generate_transaction_from_edits(&doc, round_tripped.clone(), offset_encoding);
+            assert_eq!(
+                transaction_to_lsp_edits(&doc, &transaction, offset_encoding),
+                round_tripped
+            );
+
+            let mut text = doc.clone();
+            assert!(transaction.apply(&mut text));
+            assert_eq!(text, "fn start() {\n    let mut x = 1;\n}\n");
+        }
+    }
+
+    #[test]
+    fn incremental_changes_match_full_text() {
+        use helix_core::Transaction;
+
+        let old = Rope::from("fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n");
+        // rename `x` in both places and append a line
+        let transaction = Transaction::change(
+            &old,
+            [
+                (20, 21, Some("count".into())),
+                (46, 47, Some("count".into())),
+                (52, 52, Some("// done\n".into())),
+            ]
+            .into_iter(),
+        );
+
+        let full = transaction_to_content_changes(
+            &old,
+            &transaction,
+            lsp::TextDocumentSyncKind::FULL,
+            OffsetEncoding::Utf8,
+        );
+        assert_eq!(full.len(), 1);
+        assert!(full[0].range.is_none());
+
+        let incremental = transaction_to_content_changes(
+            &old,
+            &transaction,
+            lsp::TextDocumentSyncKind::INCREMENTAL,
+            OffsetEncoding::Utf8,
+        );
+        assert_eq!(incremental.len(), 3);
+        assert!(incremental.iter().all(|change| change.text.len() < 10));
+
+        // the ranged changes apply one after the other, like the server would
+        let mut text = old.clone();
+        for change in &incremental {
+            let range =
+                lsp_range_to_range(&text, change.range.unwrap(), OffsetEncoding::Utf8).unwrap();
+            text.remove(range.from()..range.to());
+            text.insert(range.from(), &change.text);
+        }
+        assert_eq!(text.to_string(), full[0].text);
+        assert_eq!(
+            text,
+            "fn main() {\n    let count = 1;\n    println!(\"{}\", count);\n}\n// done\n"
+        );
+    }
+
+    #[test]
+    fn completion_kinds_are_categorized() {
+        assert_eq!(
+            completion_kind_category(lsp::CompletionItemKind::FUNCTION),
+            CompletionCategory::Function
+        );
+        assert_eq!(
+            completion_kind_category(lsp::CompletionItemKind::METHOD),
+            CompletionCategory::Function
+        );
+        assert_eq!(
+            completion_kind_category(lsp::CompletionItemKind::SNIPPET),
+            CompletionCategory::Snippet
+        );
+        // a kind from a future version of the spec
+        let kind: lsp::CompletionItemKind = serde_json::from_value(json!(42)).unwrap();
+        assert_eq!(completion_kind_category(kind), CompletionCategory::Other);
+        assert_eq!(CompletionCategory::Other.as_str(), "other");
+    }
+
+    #[test]
+    fn reversed_ranges_become_ordered_lsp_ranges() {
+        let doc = Rope::from("fn main() {\n    let x = 1;\n}\n");
+        // a selection made backwards, from the end of `let x` to the start of the line
+        let range = helix_core::Range::new(21, 12);
+        let expected = lsp::Range::new(lsp::Position::new(1, 0), lsp::Position::new(1, 9));
+        assert_eq!(
+            range_to_lsp_range(&doc, range, OffsetEncoding::Utf8),
+            expected
+        );
+        assert_eq!(ordered_lsp_range(expected.end, expected.start), expected);
+        assert_eq!(ordered_lsp_range(expected.start, expected.end), expected);
+    }
+
+    #[test]
+    fn disabled_code_actions_are_kept_apart() {
+        let actions: lsp::CodeActionResponse = serde_json::from_value(json!([
+            { "title": "Extract into function", "kind": "refactor.extract" },
+            {
+                "title": "Inline variable",
+                "kind": "refactor.inline",
+                "disabled": { "reason": "the variable is used more than once" },
+            },
+            { "title": "Run test", "command": "rust-analyzer.runSingle" },
+        ]))
+        .unwrap();
+
+        let (enabled, disabled) = partition_code_actions(actions);
+        assert_eq!(enabled.len(), 2);
+        assert!(matches!(
+            &enabled[1],
+            lsp::CodeActionOrCommand::Command(command) if command.title == "Run test"
+        ));
+        assert_eq!(disabled.len(), 1);
+        assert_eq!(disabled[0].title, "Inline variable");
+        assert_eq!(
+            disabled[0].disabled.as_ref().unwrap().reason,
+            "the variable is used more than once"
+        );
+    }
+
+    #[test]
+    fn insert_replace_edit_picks_range() {
+        use helix_core::Selection;
+
+        let doc = Rope::from("let x = foo_bar;\n");
+        // completing `foo` with the cursor in the middle of `foo_bar`
+        let cursor = 11;
+        let edit = lsp::CompletionTextEdit::InsertAndReplace(lsp::InsertReplaceEdit {
+            new_text: "foo_baz".to_string(),
+            insert: lsp::Range::new(lsp::Position::new(0, 8), lsp::Position::new(0, 11)),
+            replace: lsp::Range::new(lsp::Position::new(0, 8), lsp::Position::new(0, 15)),
+        });
+
+        let insert = completion_text_edit(&edit, false);
+        assert_eq!(insert.new_text, "foo_baz");
+        let offsets = completion_edit_offsets(&doc, insert.range, cursor, OffsetEncoding::Utf8);
+        assert_eq!(offsets, Some((-3, 0)));
+
+        let replace = completion_text_edit(&edit, true);
+        let offsets = completion_edit_offsets(&doc, replace.range, cursor, OffsetEncoding::Utf8);
+        assert_eq!(offsets, Some((-3, 4)));
+
+        let (start_offset, end_offset) = offsets.unwrap();
+        let mut text = doc.clone();
+        let transaction = generate_transaction_from_completion_edit(
+            &text,
+            &Selection::point(cursor),
+            start_offset,
+            end_offset,
+            replace.new_text,
+        );
+        assert!(transaction.apply(&mut text));
+        assert_eq!(text, "let x = foo_baz;\n");
+    }
+
+    #[test]
+    fn truncated_completions_are_incomplete() {
+        let items: Vec<_> = (0..5)
+            .map(|i| json!({ "label": format!("item{}", i) }))
+            .collect();
+        let mut completions: CompletionItems =
+            serde_json::from_value(json!({ "isIncomplete": false, "items": items })).unwrap();
+
+        completions.truncate(5);
+        assert_eq!(completions.items.len(), 5);
+        assert!(!completions.is_incomplete);
+
+        completions.truncate(2);
+        assert!(completions.is_incomplete);
+        let labels: Vec<_> = completions.items.iter().map(|item| &item.label).collect();
+        assert_eq!(labels, ["item0", "item1"]);
+    }
+
+    #[test]
+    fn completion_item_defaults_fill_in_omitted_edits() {
+        let doc = Rope::from("let x = vec.pu;\n");
+        let cursor = 14;
+        let response: CompletionItems = serde_json::from_value(json!({
+            "isIncomplete": false,
+            "itemDefaults": {
+                "editRange": {
+                    "start": { "line": 0, "character": 12 },
+                    "end": { "line": 0, "character": 14 },
+                },
+                "insertTextFormat": 2,
+            },
+            "items": [
+                { "label": "push", "textEditText": "push(${1:value})" },
+                { "label": "pop", "insertTextFormat": 1 },
+                {
+                    "label": "push_str",
+                    "textEdit": {
+                        "range": {
+                            "start": { "line": 0, "character": 14 },
+                            "end": { "line": 0, "character": 14 },
+                        },
+                        "newText": "push_str",
+                    },
+                },
+            ],
+        }))
+        .unwrap();
+
+        let edits: Vec<_> = response
+            .items
+            .iter()
+            .map(|item| completion_text_edit(item.text_edit.as_ref().unwrap(), false))
+            .collect();
+        assert_eq!(edits[0].new_text, "push(${1:value})");
+        assert_eq!(edits[1].new_text, "pop");
+        assert_eq!(
+            completion_edit_offsets(&doc, edits[0].range, cursor, OffsetEncoding::Utf8),
+            Some((-2, 0))
+        );
+        // items keep the properties they specify
+        assert_eq!(edits[2].new_text, "push_str");
+        assert_eq!(
+            completion_edit_offsets(&doc, edits[2].range, cursor, OffsetEncoding::Utf8),
+            Some((0, 0))
+        );
+        assert_eq!(
+            response.items[0].insert_text_format,
+            Some(lsp::InsertTextFormat::SNIPPET)
+        );
+        assert_eq!(
+            response.items[1].insert_text_format,
+            Some(lsp::InsertTextFormat::PLAIN_TEXT)
+        );
+
+        let response: CompletionItems =
+            serde_json::from_value(json!([{ "label": "push" }])).unwrap();
+        assert_eq!(response.items[0].text_edit, None);
+    }
+
+    #[test]
+    fn rapid_diagnostics_are_coalesced() {
+        use std::time::{Duration, Instant};
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let diagnostic = |message: &str| lsp::Diagnostic {
+            message: message.to_string(),
+            ..Default::default()
+        };
+        let window = Duration::from_millis(50);
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+
+        let mut store = DiagnosticStore::new();
+        for (millis, message) in [(0, "first"), (10, "second"), (20, "third")] {
+            store.insert_debounced(
+                0,
+                uri.clone(),
+                vec![diagnostic(message)],
+                window,
+                at(millis),
+            );
+        }
+        assert_eq!(store.next_flush(), Some(at(50)));
+        assert!(store.flush(at(40)).is_empty());
+        assert_eq!(store.get(&uri).count(), 0);
+
+        assert_eq!(store.flush(at(50)), vec![uri.clone()]);
+        let messages: Vec<_> = store.get(&uri).map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, ["third"]);
+        assert_eq!(store.next_flush(), None);
+        assert!(store.flush(at(100)).is_empty());
+    }
+
+    #[test]
+    fn parse_register_capability() {
+        let call = MethodCall::parse(
+            "client/registerCapability",
+            params(json!({
+                "registrations": [{
+                    "id": "watcher",
+                    "method": "workspace/didChangeWatchedFiles",
+                    "registerOptions": {
+                        "watchers": [{ "globPattern": "**/*.rs" }],
+                    },
+                }],
+            })),
+        )
+        .unwrap();
+
+        let registrations = match call {
+            MethodCall::RegisterCapability(params) => params.registrations,
+            call => panic!("unexpected method call {:?}", call),
+        };
+        assert_eq!(registrations.len(), 1);
+        assert_eq!(registrations[0].id, "watcher");
+        assert_eq!(registrations[0].method, "workspace/didChangeWatchedFiles");
+
+        let options: lsp::DidChangeWatchedFilesRegistrationOptions =
+            serde_json::from_value(registrations[0].register_options.clone().unwrap()).unwrap();
+        assert_eq!(
+            options.watchers[0].glob_pattern,
+            lsp::GlobPattern::String("**/*.rs".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_cancel_request() {
+        use super::Notification;
+
+        let notification = Notification::parse("$/cancelRequest", params(json!({ "id": 3 })));
+        assert_eq!(
+            notification.unwrap(),
+            Notification::Cancel(lsp::CancelParams {
+                id: lsp::NumberOrString::Number(3)
+            })
+        );
+
+        let notification =
+            Notification::parse("$/cancelRequest", params(json!({ "id": "config-1" })));
+        assert_eq!(
+            notification.unwrap(),
+            Notification::Cancel(lsp::CancelParams {
+                id: lsp::NumberOrString::String("config-1".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn parse_unregister_capability() {
+        let call = MethodCall::parse(
+            "client/unregisterCapability",
+            params(json!({
+                "unregisterations": [{
+                    "id": "watcher",
+                    "method": "workspace/didChangeWatchedFiles",
+                }],
+            })),
+        )
+        .unwrap();
+
+        assert_eq!(
+            call,
+            MethodCall::UnregisterCapability(lsp::UnregistrationParams {
+                unregisterations: vec![lsp::Unregistration {
+                    id: "watcher".to_string(),
+                    method: "workspace/didChangeWatchedFiles".to_string(),
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn converts_lsp_pos_to_pos() {
+        macro_rules! test_case {
+            ($doc:expr, ($x:expr, $y:expr) => $want:expr) => {
+                let doc = Rope::from($doc);
+                let pos = lsp::Position::new($x, $y);
+                assert_eq!($want, lsp_pos_to_pos(&doc, pos, OffsetEncoding::Utf16));
+                assert_eq!($want, lsp_pos_to_pos(&doc, pos, OffsetEncoding::Utf8))
+            };
+        }
+
+        test_case!("", (0, 0) => Some(0));
+        test_case!("", (0, 1) => Some(0));
+        test_case!("", (1, 0) => None);
+        test_case!("\n\n", (0, 0) => Some(0));
+        test_case!("\n\n", (1, 0) => Some(1));
+        test_case!("\n\n", (1, 1) => Some(1));
+        test_case!("\n\n", (2, 0) => Some(2));
+        test_case!("\n\n", (3, 0) => None);
+        test_case!("test\n\n\n\ncase", (4, 3) => Some(11));
+        test_case!("test\n\n\n\ncase", (4, 4) => Some(12));
+        test_case!("test\n\n\n\ncase", (4, 5) => Some(12));
+        test_case!("", (u32::MAX, u32::MAX) => None);
+    }
+
+    #[test]
+    fn lsp_pos_to_pos_splits_lines_like_lsp() {
+        // U+2028 (LINE SEPARATOR) isn't a line terminator in LSP
+        let doc = Rope::from("a\u{2028}b\nc");
+        for encoding in [
+            OffsetEncoding::Utf8,
+            OffsetEncoding::Utf16,
+            OffsetEncoding::Utf32,
+        ] {
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(0, 0), encoding),
+                Some(0)
+            );
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(1, 0), encoding),
+                Some(4)
+            );
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(1, 1), encoding),
+                Some(5)
+            );
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(2, 0), encoding),
+                None
+            );
+            // capped to the end of the first line, before the `\n`
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(0, 100), encoding),
+                Some(3)
+            );
+        }
+        // `b` follows the 3 byte separator
+        assert_eq!(
+            lsp_pos_to_pos(&doc, lsp::Position::new(0, 4), OffsetEncoding::Utf8),
+            Some(2)
+        );
+        assert_eq!(
+            lsp_pos_to_pos(&doc, lsp::Position::new(0, 2), OffsetEncoding::Utf16),
+            Some(2)
+        );
+
+        // a lone `\r` terminates a line, `\r\n` is a single terminator
+        let doc = Rope::from("a\rb\r\nc");
+        for encoding in [
+            OffsetEncoding::Utf8,
+            OffsetEncoding::Utf16,
+            OffsetEncoding::Utf32,
+        ] {
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(1, 0), encoding),
+                Some(2)
+            );
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(1, 5), encoding),
+                Some(3)
+            );
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(2, 0), encoding),
+                Some(5)
+            );
+            assert_eq!(
+                lsp_pos_to_pos(&doc, lsp::Position::new(3, 0), encoding),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn negotiated_offset_encodings_are_independent() {
+        let chain = OffsetEncoding::DEFAULT_FALLBACK_CHAIN;
+        let utf8_server = OffsetEncoding::negotiate(Some(&lsp::PositionEncodingKind::UTF8), chain);
+        let utf16_server = OffsetEncoding::negotiate(None, chain);
+        assert_eq!(utf8_server, OffsetEncoding::Utf8);
+        assert_eq!(utf16_server, OffsetEncoding::Utf16);
+
+        // An encoding that wasn't offered falls back to utf-16.
+        assert_eq!(
+            OffsetEncoding::negotiate(
+                Some(&lsp::PositionEncodingKind::UTF8),
+                &[OffsetEncoding::Utf16]
+            ),
+            OffsetEncoding::Utf16
+        );
+
+        // 🎄 is 4 bytes, 2 utf-16 code units and a single char.
+        let doc = Rope::from("🎄a\n");
+        assert_eq!(
+            pos_to_lsp_pos(&doc, 1, utf8_server),
+            lsp::Position::new(0, 4)
+        );
+        assert_eq!(
+            pos_to_lsp_pos(&doc, 1, utf16_server),
+            lsp::Position::new(0, 2)
+        );
+        assert_eq!(
+            lsp_pos_to_pos(&doc, lsp::Position::new(0, 4), utf8_server),
+            Some(1)
+        );
+        assert_eq!(
+            lsp_pos_to_pos(&doc, lsp::Position::new(0, 2), utf16_server),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn hover_language_string_becomes_fenced_block() {
+        let contents =
+            lsp::HoverContents::Scalar(lsp::MarkedString::LanguageString(lsp::LanguageString {
+                language: "rust".to_string(),
+                value: "fn main()".to_string(),
+            }));
+        assert_eq!(
+            hover_contents_to_markdown(contents, None, HtmlHandling::Strip),
+            "```rust\nfn main()\n```"
+        );
+    }
+
+    #[test]
+    fn hover_marked_string_array_is_joined() {
+        let contents = lsp::HoverContents::Array(vec![
+            lsp::MarkedString::LanguageString(lsp::LanguageString {
+                language: "rust".to_string(),
+                value: "core::option::Option".to_string(),
+            }),
+            lsp::MarkedString::String("The `Option` type.".to_string()),
+        ]);
+        assert_eq!(
+            hover_contents_to_markdown(contents, None, HtmlHandling::Strip),
+            "```rust\ncore::option::Option\n```\n\nThe `Option` type."
+        );
+    }
+
+    #[test]
+    fn hover_markup_content_is_kept() {
+        let markup = |kind: lsp::MarkupKind, value: &str| {
+            lsp::HoverContents::Markup(lsp::MarkupContent {
+                kind,
+                value: value.to_string(),
+            })
+        };
+        assert_eq!(
+            hover_contents_to_markdown(
+                markup(lsp::MarkupKind::Markdown, "```\nlet x: i32\n```"),
+                Some("rust"),
+                HtmlHandling::Strip
+            ),
+            "```rust\nlet x: i32\n```"
+        );
+        assert_eq!(
+            hover_contents_to_markdown(
+                markup(lsp::MarkupKind::PlainText, "let x: i32"),
+                Some("rust"),
+                HtmlHandling::Strip
+            ),
+            "let x: i32"
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_all_sends_shutdown_before_exit() {
+        use super::{jsonrpc, mock::MockServer, Registry};
+        use std::time::Duration;
+
+        let mut connection = MockServer::new()
+            .respond("initialize", json!({ "capabilities": {} }))
+            .respond("shutdown", serde_json::Value::Null)
+            .initialize()
+            .await
+            .unwrap();
+
+        let mut registry = Registry::new();
+        registry
+            .inner
+            .insert("source.mock".to_string(), (0, connection.client));
+        registry.shutdown_all(Duration::from_millis(100)).await;
+        assert!(registry.iter_clients().next().is_none());
+
+        let mut methods = Vec::new();
+        let received = async {
+            while let Some(call) = connection.received.recv().await {
+                match call {
+                    jsonrpc::Call::MethodCall(call) => methods.push(call.method),
+                    jsonrpc::Call::Notification(notification) => methods.push(notification.method),
+                    _ => (),
+                }
+                if methods.last().map_or(false, |method| method == "exit") {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), received)
+            .await
+            .expect("the server wasn't asked to exit");
+        assert_eq!(methods, ["initialize", "initialized", "shutdown", "exit"]);
+    }
+
+    #[tokio::test]
+    async fn clients_are_filtered_by_capability() {
+        use super::{mock::MockServer, Registry};
+        use std::sync::Arc;
+
+        let formatter = MockServer::new()
+            .respond(
+                "initialize",
+                json!({ "capabilities": { "documentFormattingProvider": true } }),
+            )
+            .initialize()
+            .await
+            .unwrap()
+            .client;
+        let linter = MockServer::new()
+            .respond(
+                "initialize",
+                json!({ "capabilities": { "hoverProvider": true } }),
+            )
+            .initialize()
+            .await
+            .unwrap()
+            .client;
+
+        let mut registry = Registry::new();
+        registry
+            .inner
+            .insert("source.rust".to_string(), (0, formatter.clone()));
+        registry
+            .inner
+            .insert("source.python".to_string(), (1, linter));
+
+        let formats = |capabilities: &lsp::ServerCapabilities| {
+            matches!(
+                capabilities.document_formatting_provider,
+                Some(lsp::OneOf::Left(true) | lsp::OneOf::Right(_))
+            )
+        };
+        let clients = registry.clients_with_capability("source.rust", formats);
+        assert_eq!(clients.len(), 1);
+        assert!(Arc::ptr_eq(&clients[0], &formatter));
+        assert!(registry
+            .clients_with_capability("source.python", formats)
+            .is_empty());
+        assert!(registry
+            .clients_with_capability("source.toml", |_| true)
+            .is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restart_by_id_replaces_the_server() {
+        use super::Registry;
+        use futures_util::StreamExt;
+        use helix_core::syntax::LanguageConfiguration;
+        use std::{path::PathBuf, sync::Arc, time::Duration};
+
+        let language_config: LanguageConfiguration = serde_json::from_value(json!({
+            "name": "mock",
+            "scope": "source.mock",
+            "file-types": ["mock"],
+            "roots": [],
+            "language-server": { "command": "cat" },
+        }))
+        .unwrap();
+        let language_config = Arc::new(language_config);
+
+        let mut registry = Registry::new();
+        let doc_path = PathBuf::from("/project/src/main.mock");
+        let client = registry
+            .get(&language_config, Some(&doc_path))
+            .unwrap()
+            .unwrap();
+        let old_id = client.id();
+
+        assert!(registry.restart_by_id(old_id + 1).unwrap().is_none());
+        let client = registry.restart_by_id(old_id).unwrap().unwrap();
+        let new_id = client.id();
+        assert_ne!(new_id, old_id);
+        assert!(registry.get_by_id(old_id).is_none());
+        assert_eq!(registry.get_by_id(new_id).unwrap().id(), new_id);
+
+        // `cat` echoes the initialize request of the new server through its stream
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some((id, _)) = registry.incoming.next().await {
+                if id == new_id {
+                    return true;
+                }
+            }
+            false
+        })
+        .await
+        .unwrap();
+        assert!(received);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn server_failing_to_initialize_is_replaced() {
+        use super::Registry;
+        use helix_core::syntax::LanguageConfiguration;
+        use std::{sync::Arc, time::Duration};
+
+        // `false` exits right away, so the initialize request fails
+        let language_config: LanguageConfiguration = serde_json::from_value(json!({
+            "name": "mock",
+            "scope": "source.mock",
+            "file-types": ["mock"],
+            "roots": [],
+            "language-server": { "command": "false" },
+        }))
+        .unwrap();
+        let language_config = Arc::new(language_config);
+
+        let mut registry = Registry::new();
+        let client = registry.get(&language_config, None).unwrap().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.initialize_error().is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!client.is_initialized());
+
+        let replacement = registry.get(&language_config, None).unwrap().unwrap();
+        assert_ne!(replacement.id(), client.id());
+        assert!(registry.get_by_id(client.id()).is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn initialization_options_depend_on_root() {
+        use super::{start_client, NewClientResult};
+        use helix_core::syntax::LanguageConfiguration;
+        use std::path::Path;
+
+        fn resolve(
+            language_config: &LanguageConfiguration,
+            root: &Path,
+        ) -> Option<serde_json::Value> {
+            let mut options = language_config.config.clone()?;
+            options["cargo"]["features"] = json!([root.file_name()?.to_str()?]);
+            Some(options)
+        }
+
+        let language_config: LanguageConfiguration = serde_json::from_value(json!({
+            "name": "mock",
+            "scope": "source.mock",
+            "file-types": ["mock"],
+            "roots": ["Cargo.toml"],
+            "config": { "cargo": { "buildScripts": { "enable": true } } },
+            "language-server": { "command": "cat" },
+        }))
+        .unwrap();
+        let ls_config = language_config.language_server.as_ref().unwrap();
+
+        let workspace =
+            std::env::temp_dir().join(format!("helix-lsp-roots-{}", std::process::id()));
+        let mut options = Vec::new();
+        for krate in ["parser", "cli"] {
+            let root = workspace.join(krate);
+            std::fs::create_dir_all(root.join("src")).unwrap();
+            std::fs::write(root.join("Cargo.toml"), "").unwrap();
+
+            let doc_path = root.join("src").join("main.rs");
+            let NewClientResult(client, _) =
+                start_client(0, &language_config, ls_config, &[&doc_path], resolve).unwrap();
+            options.push(client.config().cloned());
+        }
+        std::fs::remove_dir_all(&workspace).unwrap();
+
+        let expected = |feature: &str| json!({ "cargo": { "buildScripts": { "enable": true }, "features": [feature] } });
+        assert_eq!(options, [Some(expected("parser")), Some(expected("cli"))]);
+    }
+
+    #[tokio::test]
+    async fn tcp_transport_connects_to_the_configured_address() {
+        use super::{start_client, NewClientResult};
+        use helix_core::syntax::LanguageConfiguration;
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let language_config: LanguageConfiguration = serde_json::from_value(json!({
+            "name": "mock",
+            "scope": "source.mock",
+            "file-types": ["mock"],
+            "roots": [],
+            "language-server": {
+                "command": "mock-ls",
+                "transport": { "tcp": { "host": "127.0.0.1", "port": port } },
+            },
+        }))
+        .unwrap();
+        let ls_config = language_config.language_server.as_ref().unwrap();
+
+        let NewClientResult(_client, _) =
+            start_client(0, &language_config, ls_config, &[], |_, _| None).unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        // the client initializes the server over the connection
+        let mut header = [0; 16];
+        server.read_exact(&mut header).await.unwrap();
+        assert_eq!(&header, b"Content-Length: ");
+    }
+
+    #[tokio::test]
+    async fn unreachable_tcp_server_fails_to_initialize() {
+        use super::{start_client, Call, NewClientResult};
+        use helix_core::syntax::LanguageConfiguration;
+        use std::time::Duration;
+
+        // nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let language_config: LanguageConfiguration = serde_json::from_value(json!({
+            "name": "mock",
+            "scope": "source.mock",
+            "file-types": ["mock"],
+            "roots": [],
+            "language-server": {
+                "command": "mock-ls",
+                "transport": { "tcp": { "host": "127.0.0.1", "port": port } },
+            },
+        }))
+        .unwrap();
+        let ls_config = language_config.language_server.as_ref().unwrap();
+
+        // starting the client doesn't wait for the connection
+        let NewClientResult(client, mut incoming) =
+            start_client(0, &language_config, ls_config, &[], |_, _| None).unwrap();
+        assert!(!client.is_initialized());
+
+        let err = tokio::time::timeout(Duration::from_secs(5), client.initialized())
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().contains("could not connect"), "{}", err);
+
+        // the editor learns that the server is gone
+        let exited = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some((_, call)) = incoming.recv().await {
+                if matches!(call, Call::Notification(notification) if notification.method == "exit")
+                {
+                    return true;
+                }
+            }
+            false
+        })
+        .await
+        .unwrap();
+        assert!(exited);
+    }
+
+    /// A configuration of a TCP language server on `port` that reconnects.
+    fn reconnecting_tcp_config(port: u16) -> helix_core::syntax::LanguageConfiguration {
+        serde_json::from_value(json!({
+            "name": "mock",
+            "scope": "source.mock",
+            "file-types": ["mock"],
+            "roots": [],
+            "language-server": {
+                "command": "mock-ls",
+                "transport": { "tcp": { "host": "127.0.0.1", "port": port, "reconnect": true } },
+            },
+        }))
+        .unwrap()
+    }
+
+    /// Waits for the notification `method` the transport of the language server `id`
+    /// forwards to the editor.
+    async fn notified(registry: &mut super::Registry, id: usize, method: &str) {
+        use futures_util::StreamExt;
+
+        let notification = async {
+            while let Some((server_id, call)) = registry.incoming.next().await {
+                match call {
+                    super::Call::Notification(notification)
+                        if server_id == id && notification.method == method =>
+                    {
+                        return
+                    }
+                    _ => {}
+                }
+            }
+            panic!("the registry stopped listening");
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), notification)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn lost_tcp_connection_is_reconnected_and_reinitialized() {
+        use super::Registry;
+        use crate::mock::MockServer;
+        use std::{sync::Arc, time::Duration};
+        use tokio::sync::mpsc::unbounded_channel;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let language_config = Arc::new(reconnecting_tcp_config(port));
+
+        let mut registry = Registry::new();
+        let client = registry.get(&language_config, None).unwrap().unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        // resets the connection once the server is dropped
+        stream.set_linger(Some(Duration::ZERO)).unwrap();
+        let (received_tx, _received) = unbounded_channel();
+        let server =
+            tokio::spawn(MockServer::with_capabilities(json!({})).serve(stream, received_tx));
+        client.initialized().await.unwrap();
+
+        server.abort();
+        notified(&mut registry, client.id(), "exit").await;
+        assert!(client.connection_lost());
+        assert!(registry.reconnects(client.id()));
+
+        let reconnected = registry.reconnect(client.id()).unwrap().unwrap();
+        assert_ne!(reconnected.id(), client.id());
+        let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let (received_tx, mut received) = unbounded_channel();
+        tokio::spawn(MockServer::with_capabilities(json!({})).serve(stream, received_tx));
+
+        // the new connection is initialized from scratch
+        tokio::time::timeout(Duration::from_secs(5), reconnected.initialized())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            received.try_recv(),
+            Ok(jsonrpc::Call::MethodCall(request)) if request.method == "initialize"
+        ));
+        assert!(!reconnected.connection_lost());
+    }
+
+    #[tokio::test]
+    async fn closed_tcp_connection_is_not_reconnected() {
+        use super::Registry;
+        use crate::mock::MockServer;
+        use std::sync::Arc;
+        use tokio::sync::mpsc::unbounded_channel;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let language_config = Arc::new(reconnecting_tcp_config(port));
+
+        let mut registry = Registry::new();
+        let client = registry.get(&language_config, None).unwrap().unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (received_tx, mut received) = unbounded_channel();
+        let server =
+            tokio::spawn(MockServer::with_capabilities(json!({})).serve(stream, received_tx));
+        client.initialized().await.unwrap();
+        // unread data would reset the connection instead
+        while !matches!(
+            received.recv().await,
+            Some(jsonrpc::Call::Notification(notification)) if notification.method == "initialized"
+        ) {}
+
+        // the server shuts the connection down on purpose
+        server.abort();
+        notified(&mut registry, client.id(), "exit").await;
+        assert!(!client.connection_lost());
+        assert!(!registry.reconnects(client.id()));
+        assert!(registry.reconnect(client.id()).unwrap().is_none());
+    }
+
+    #[test]
+    fn reconnects_give_up_after_a_few_attempts() {
+        use super::RestartBackoff;
+        use std::time::{Duration, Instant};
+
+        let mut backoff = RestartBackoff::new();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        for attempt in 0..RestartBackoff::MAX_RECONNECTS {
+            assert_eq!(
+                backoff.reconnects("source.rust", at(attempt as u64)),
+                attempt
+            );
+            backoff.reconnect("source.rust", at(attempt as u64));
+        }
+        let last = RestartBackoff::MAX_RECONNECTS as u64 - 1;
+        assert_eq!(
+            backoff.reconnects("source.rust", at(last)),
+            RestartBackoff::MAX_RECONNECTS
+        );
+        // other servers are tracked separately
+        assert_eq!(backoff.reconnects("source.python", at(last)), 0);
+
+        // a connection that stayed up long enough starts over
+        let stable = last + RestartBackoff::STABLE_UPTIME.as_secs();
+        assert_eq!(backoff.reconnects("source.rust", at(stable)), 0);
+    }
+
+    #[tokio::test]
+    async fn dropped_tcp_connection_is_reconnected_once() {
+        use super::{Error, Registry};
+        use crate::mock::MockServer;
+        use std::{sync::Arc, time::Duration};
+        use tokio::sync::mpsc::unbounded_channel;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let language_config = Arc::new(reconnecting_tcp_config(port));
+
+        let mut registry = Registry::new();
+        let client = registry.get(&language_config, None).unwrap().unwrap();
+
+        // the connection to the server is reset, the editor reconnects once it learns that
+        // the server exited
+        let (stream, _) = listener.accept().await.unwrap();
+        stream.set_linger(Some(Duration::ZERO)).unwrap();
+        drop(stream);
+        notified(&mut registry, client.id(), "exit").await;
+        let client = registry.reconnect(client.id()).unwrap().unwrap();
+
+        let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        stream.set_linger(Some(Duration::ZERO)).unwrap();
+        let (received_tx, _received) = unbounded_channel();
+        let server =
+            tokio::spawn(MockServer::with_capabilities(json!({})).serve(stream, received_tx));
+        // the reconnect succeeded once the new connection is initialized
+        notified(&mut registry, client.id(), "initialized").await;
+        assert!(registry.finish_reconnect(client.id()));
+        assert!(!registry.finish_reconnect(client.id()));
+
+        // a connection that drops right away again isn't reconnected until the backoff passed
+        server.abort();
+        notified(&mut registry, client.id(), "exit").await;
+        assert!(matches!(
+            registry.reconnect(client.id()),
+            Err(Error::RestartTooSoon(_))
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), listener.accept())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rapid_restarts_back_off() {
+        use super::RestartBackoff;
+        use std::time::{Duration, Instant};
+
+        let mut backoff = RestartBackoff::new();
+        backoff.limit = Duration::from_secs(4);
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(backoff.restart("source.rust", at(0)), Ok(()));
+        assert_eq!(
+            backoff.restart("source.rust", start + Duration::from_millis(400)),
+            Err(Duration::from_millis(600))
+        );
+        // other servers are tracked separately
+        assert_eq!(backoff.restart("source.python", at(0)), Ok(()));
+
+        // the delay doubles with every restart, up to the limit
+        assert_eq!(backoff.restart("source.rust", at(1)), Ok(()));
+        assert!(backoff.restart("source.rust", at(2)).is_err());
+        assert_eq!(backoff.restart("source.rust", at(3)), Ok(()));
+        assert!(backoff.restart("source.rust", at(6)).is_err());
+        assert_eq!(backoff.restart("source.rust", at(7)), Ok(()));
+        assert!(backoff.restart("source.rust", at(10)).is_err());
+        assert_eq!(backoff.restart("source.rust", at(11)), Ok(()));
+
+        // a server that stayed up long enough starts over
+        let stable = 11 + RestartBackoff::STABLE_UPTIME.as_secs();
+        assert_eq!(backoff.restart("source.rust", at(stable)), Ok(()));
+        assert!(backoff.restart("source.rust", at(stable)).is_err());
+        assert_eq!(backoff.restart("source.rust", at(stable + 1)), Ok(()));
+    }
+
+    #[test]
+    fn unknown_completion_kind_falls_back() {
+        for kind in COMPLETION_ITEM_KINDS {
+            assert_ne!(completion_kind_label(*kind), "", "{:?} has no label", kind);
+        }
+
+        // a kind from a newer version of the spec
+        let item: lsp::CompletionItem = serde_json::from_value(json!({
+            "label": "println!",
+            "kind": 42,
+        }))
+        .unwrap();
+        let kind = item.kind.unwrap();
+        assert!(!COMPLETION_ITEM_KINDS.contains(&kind));
+        assert_eq!(completion_kind_label(kind), "");
+    }
+
+    #[test]
+    fn filter_workspace_symbols_by_kind() {
+        let symbol = |name: &str, kind: lsp::SymbolKind| {
+            json!({
+                "name": name,
+                "kind": kind,
+                "location": {
+                    "uri": "file:///project/src/lib.rs",
+                    "range": {
+                        "start": { "line": 0, "character": 0 },
+                        "end": { "line": 0, "character": 1 },
+                    },
+                },
+            })
+        };
+        let symbols = json!([
+            symbol("parse", lsp::SymbolKind::FUNCTION),
+            symbol("Parser", lsp::SymbolKind::STRUCT),
+            symbol("parse_args", lsp::SymbolKind::FUNCTION),
+            symbol("PARSE_LIMIT", lsp::SymbolKind::CONSTANT),
+        ]);
+        let flat =
+            lsp::WorkspaceSymbolResponse::Flat(serde_json::from_value(symbols.clone()).unwrap());
+        let nested = lsp::WorkspaceSymbolResponse::Nested(serde_json::from_value(symbols).unwrap());
+        let names = |response: lsp::WorkspaceSymbolResponse| match response {
+            lsp::WorkspaceSymbolResponse::Flat(symbols) => symbols
+                .into_iter()
+                .map(|symbol| symbol.name)
+                .collect::<Vec<_>>(),
+            lsp::WorkspaceSymbolResponse::Nested(symbols) => symbols
+                .into_iter()
+                .map(|symbol| symbol.name)
+                .collect::<Vec<_>>(),
+        };
+
+        for response in [flat, nested] {
+            assert_eq!(
+                names(filter_symbols_by_kind(
+                    response.clone(),
+                    &[lsp::SymbolKind::FUNCTION]
+                )),
+                ["parse", "parse_args"]
+            );
+            assert_eq!(
+                names(filter_symbols_by_kind(
+                    response.clone(),
+                    &[lsp::SymbolKind::CONSTANT, lsp::SymbolKind::STRUCT]
+                )),
+                ["Parser", "PARSE_LIMIT"]
+            );
+            assert_eq!(names(filter_symbols_by_kind(response, &[])).len(), 4);
+        }
+    }
+
+    #[test]
+    fn flatten_workspace_symbols() {
+        let uri = lsp::Url::parse("file:///project/src/lib.rs").unwrap();
+        let range = lsp::Range::new(lsp::Position::new(3, 4), lsp::Position::new(3, 9));
+        let nested = lsp::WorkspaceSymbolResponse::Nested(
+            serde_json::from_value(json!([
+                {
+                    "name": "parse",
+                    "kind": lsp::SymbolKind::FUNCTION,
+                    "containerName": "parser",
+                    "location": { "uri": uri, "range": range },
+                },
+                {
+                    "name": "Parser",
+                    "kind": lsp::SymbolKind::STRUCT,
+                    "location": { "uri": uri },
+                },
+            ]))
+            .unwrap(),
+        );
+
+        let symbols = workspace_symbols_to_flat(nested);
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, "parse");
+        assert_eq!(symbols[0].container_name.as_deref(), Some("parser"));
+        assert_eq!(symbols[0].location, lsp::Location::new(uri.clone(), range));
+        assert_eq!(symbols[1].name, "Parser");
+        assert_eq!(
+            symbols[1].location,
+            lsp::Location::new(uri, lsp::Range::default())
+        );
+
+        let flat = workspace_symbols_to_flat(lsp::WorkspaceSymbolResponse::Flat(symbols.clone()));
+        assert_eq!(flat, symbols);
+    }
+
+    #[test]
+    fn retrigger_signature_help() {
+        let triggers = vec!["(".to_string()];
+        let retriggers = vec![",".to_string()];
+        let active_signature_help = lsp::SignatureHelp {
+            signatures: vec![lsp::SignatureInformation {
+                label: "fn add(a: u32, b: u32) -> u32".to_string(),
+                documentation: None,
+                parameters: None,
+                active_parameter: None,
+            }],
+            active_signature: Some(0),
+            active_parameter: Some(0),
+        };
+
+        // retrigger characters only count while signature help is shown
+        assert_eq!(
+            signature_help_context(',', &triggers, &retriggers, None),
+            None
+        );
+        let context = signature_help_context('(', &triggers, &retriggers, None).unwrap();
+        assert!(!context.is_retrigger);
+
+        let context = signature_help_context(
+            ',',
+            &triggers,
+            &retriggers,
+            Some(active_signature_help.clone()),
+        )
+        .unwrap();
+        let params = lsp::SignatureHelpParams {
+            context: Some(context),
+            text_document_position_params: lsp::TextDocumentPositionParams {
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse("file:///project/src/main.rs").unwrap(),
+                },
+                position: lsp::Position::new(0, 11),
+            },
+            work_done_progress_params: Default::default(),
+        };
+        assert_eq!(
+            serde_json::to_value(params).unwrap()["context"],
+            json!({
+                "triggerKind": 2,
+                "triggerCharacter": ",",
+                "isRetrigger": true,
+                "activeSignatureHelp": active_signature_help,
+            })
+        );
+    }
+
+    #[test]
+    fn normalize_hover_markdown() {
+        let markdown =
+            "Some <b>bold</b> `Vec<T>`<br>\n```\nlet a = 1 < 2;\n```\n```python\npass\n```";
+        assert_eq!(
+            normalize_markdown(markdown, Some("rust"), HtmlHandling::Strip),
+            "Some bold `Vec<T>`\n```rust\nlet a = 1 < 2;\n```\n```python\npass\n```"
+        );
+        assert_eq!(
+            normalize_markdown("a <br> b", None, HtmlHandling::Escape),
+            "a &lt;br&gt; b"
+        );
+        assert_eq!(
+            normalize_markdown("a <br> b", None, HtmlHandling::Keep),
+            "a <br> b"
+        );
     }
 
     #[test]
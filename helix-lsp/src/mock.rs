@@ -0,0 +1,269 @@
+//! An in-memory language server to test [`Client`] request flows without spawning a
+//! server process.
+
+use crate::{jsonrpc, Call, Client, OffsetEncoding};
+use anyhow::Context;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+/// A language server answering requests with scripted results, by method.
+///
+/// Requests for other methods fail with `MethodNotFound`, notifications are only recorded.
+#[derive(Debug, Default)]
+pub(crate) struct MockServer {
+    results: HashMap<String, Value>,
+    /// Results computed from the params of the request, by method.
+    handlers: HashMap<String, fn(&Value) -> Value>,
+    /// Error codes the first request of a method fails with.
+    failures: HashMap<String, i64>,
+}
+
+/// A [`Client`] connected to a [`MockServer`].
+pub(crate) struct MockConnection {
+    pub client: Arc<Client>,
+    /// The calls of the server, as the transport forwards them to the editor.
+    pub incoming: UnboundedReceiver<(usize, Call)>,
+    /// Every request and notification the server received from the client.
+    pub received: UnboundedReceiver<jsonrpc::Call>,
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A server whose `initialize` response announces `capabilities`.
+    pub fn with_capabilities(capabilities: Value) -> Self {
+        Self::new().respond(
+            "initialize",
+            serde_json::json!({ "capabilities": capabilities }),
+        )
+    }
+
+    /// Answers every request for `method` with `result`.
+    pub fn respond(mut self, method: &str, result: Value) -> Self {
+        self.results.insert(method.to_string(), result);
+        self
+    }
+
+    /// Answers every request for `method` with the result `handler` computes from its params.
+    pub fn respond_with(mut self, method: &str, handler: fn(&Value) -> Value) -> Self {
+        self.handlers.insert(method.to_string(), handler);
+        self
+    }
+
+    /// Fails the first request for `method` with the error `code`.
+    pub fn fail_once(mut self, method: &str, code: i64) -> Self {
+        self.failures.insert(method.to_string(), code);
+        self
+    }
+
+    /// Connects a new client to the server and initializes it. The server keeps running in
+    /// the background until the client is dropped.
+    pub async fn initialize(self) -> anyhow::Result<MockConnection> {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_reader, client_writer) = tokio::io::split(client_io);
+        let (received_tx, received) = unbounded_channel();
+        tokio::spawn(self.serve(server_io, received_tx));
+
+        let (client, incoming, initialize_notify) = Client::connect(
+            None,
+            BufReader::new(client_reader),
+            client_writer,
+            tokio::io::empty(),
+            "mock",
+            None,
+            &[],
+            0,
+            5,
+            HashMap::new(),
+            &[],
+            OffsetEncoding::DEFAULT_FALLBACK_CHAIN.to_vec(),
+            false,
+        );
+        let client = Arc::new(client);
+        crate::initialize_client(client.clone(), initialize_notify).await?;
+
+        Ok(MockConnection {
+            client,
+            incoming,
+            received,
+        })
+    }
+
+    /// Answers the requests arriving through `io` until the client closes it, and records
+    /// every call to `received`.
+    pub async fn serve(
+        mut self,
+        io: impl AsyncRead + AsyncWrite + Send + 'static,
+        received: UnboundedSender<jsonrpc::Call>,
+    ) -> anyhow::Result<()> {
+        let (reader, mut writer) = tokio::io::split(io);
+        let mut reader = BufReader::new(reader);
+
+        loop {
+            let call = read_message(&mut reader).await?;
+            let output = match &call {
+                jsonrpc::Call::MethodCall(request) => Some(self.output(request)),
+                _ => None,
+            };
+            // record the call before answering so that it's visible once the client has
+            // the response, the test may not care about it though
+            let _ = received.send(call);
+            if let Some(output) = output {
+                write_message(&mut writer, &serde_json::to_string(&output)?).await?;
+            }
+        }
+    }
+
+    fn output(&mut self, request: &jsonrpc::MethodCall) -> jsonrpc::Output {
+        if let Some(code) = self.failures.remove(&request.method) {
+            return jsonrpc::Output::Failure(jsonrpc::Failure {
+                jsonrpc: Some(jsonrpc::Version::V2),
+                id: request.id.clone(),
+                error: jsonrpc::Error {
+                    code: code.into(),
+                    message: format!("{} failed", request.method),
+                    data: None,
+                },
+            });
+        }
+
+        let result = match self.handlers.get(&request.method) {
+            Some(handler) => Some(handler(&Value::from(request.params.clone()))),
+            None => self.results.get(&request.method).cloned(),
+        };
+        match result {
+            Some(result) => jsonrpc::Output::Success(jsonrpc::Success {
+                jsonrpc: Some(jsonrpc::Version::V2),
+                id: request.id.clone(),
+                result,
+            }),
+            None => jsonrpc::Output::Failure(jsonrpc::Failure {
+                jsonrpc: Some(jsonrpc::Version::V2),
+                id: request.id.clone(),
+                error: jsonrpc::Error {
+                    code: jsonrpc::ErrorCode::MethodNotFound,
+                    message: format!("Method not found: {}", request.method),
+                    data: None,
+                },
+            }),
+        }
+    }
+}
+
+pub(crate) async fn read_message(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> anyhow::Result<jsonrpc::Call> {
+    let mut content_length = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header).await? == 0 {
+            anyhow::bail!("client closed the stream");
+        }
+        if header == "\r\n" {
+            break;
+        }
+        if let Some(value) = header.trim().strip_prefix("Content-Length: ") {
+            content_length = Some(value.parse::<usize>()?);
+        }
+    }
+
+    let mut content = vec![0; content_length.context("missing content length")?];
+    reader.read_exact(&mut content).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+pub(crate) async fn write_message(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &str,
+) -> anyhow::Result<()> {
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n{}", message.len(), message).as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+mod tests {
+    use super::MockServer;
+    use crate::{jsonrpc, lsp, Call, OffsetEncoding};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn initialize_resolves_capabilities() {
+        let mut connection = MockServer::new()
+            .respond(
+                "initialize",
+                json!({
+                    "capabilities": { "hoverProvider": true, "positionEncoding": "utf-8" },
+                    "serverInfo": { "name": "mock" },
+                }),
+            )
+            .initialize()
+            .await
+            .unwrap();
+        let client = connection.client;
+
+        assert!(client.is_initialized());
+        // the transport lets the editor know that the server is ready
+        match connection.incoming.recv().await {
+            Some((_, Call::Notification(notification))) => {
+                assert_eq!(notification.method, "initialized")
+            }
+            call => panic!("expected the initialized notification, got {:?}", call),
+        }
+        assert_eq!(
+            client.capabilities().hover_provider,
+            Some(lsp::HoverProviderCapability::Simple(true))
+        );
+        assert_eq!(client.offset_encoding(), OffsetEncoding::Utf8);
+        assert_eq!(client.server_info().unwrap().name, "mock");
+    }
+
+    #[tokio::test]
+    async fn hover_returns_scripted_response() {
+        let mut connection = MockServer::with_capabilities(json!({ "hoverProvider": true }))
+            .respond(
+                "textDocument/hover",
+                json!({ "contents": { "kind": "markdown", "value": "`fn main()`" } }),
+            )
+            .initialize()
+            .await
+            .unwrap();
+
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let hover = connection
+            .client
+            .hover(uri, lsp::Position::new(0, 3))
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            hover.contents,
+            lsp::HoverContents::Markup(lsp::MarkupContent {
+                kind: lsp::MarkupKind::Markdown,
+                value: "`fn main()`".to_string(),
+            })
+        );
+
+        let methods: Vec<_> = std::iter::from_fn(|| connection.received.try_recv().ok())
+            .map(|call| match call {
+                jsonrpc::Call::MethodCall(call) => call.method,
+                jsonrpc::Call::Notification(notification) => notification.method,
+                call => panic!("unexpected call {:?}", call),
+            })
+            .collect();
+        assert_eq!(methods, ["initialize", "initialized", "textDocument/hover"]);
+    }
+}
@@ -0,0 +1,215 @@
+//! Notebook document sync, which lsp-types doesn't model yet. The cells of a notebook are
+//! synced as text documents embedded in the notebook, the notebook itself only carries the
+//! order and kind of its cells.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::lsp;
+
+/// The kind of a notebook cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NotebookCellKind(i32);
+
+impl NotebookCellKind {
+    /// A markup cell, formatted text like markdown.
+    pub const MARKUP: NotebookCellKind = NotebookCellKind(1);
+    /// A code cell, source code of the language of the notebook.
+    pub const CODE: NotebookCellKind = NotebookCellKind(2);
+}
+
+/// A cell of a notebook. Its contents are the text document `document`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCell {
+    pub kind: NotebookCellKind,
+    pub document: lsp::Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocument {
+    pub uri: lsp::Url,
+    /// The type of the notebook, e.g. `jupyter-notebook`.
+    pub notebook_type: String,
+    pub version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+    pub cells: Vec<NotebookCell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotebookDocumentIdentifier {
+    pub uri: lsp::Url,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionedNotebookDocumentIdentifier {
+    pub version: i32,
+    pub uri: lsp::Url,
+}
+
+/// The cells `delete_count` cells starting at `start` were replaced with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellArrayChange {
+    pub start: u32,
+    pub delete_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cells: Option<Vec<NotebookCell>>,
+}
+
+/// Cells that were added or removed, along with their text documents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellStructureChange {
+    pub array: NotebookCellArrayChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_open: Option<Vec<lsp::TextDocumentItem>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_close: Option<Vec<lsp::TextDocumentIdentifier>>,
+}
+
+/// Changes to the contents of a cell.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotebookCellTextContentChange {
+    pub document: lsp::VersionedTextDocumentIdentifier,
+    pub changes: Vec<lsp::TextDocumentContentChangeEvent>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentCellChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structure: Option<NotebookCellStructureChange>,
+    /// Cells whose kind or metadata changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<NotebookCell>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_content: Option<Vec<NotebookCellTextContentChange>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotebookDocumentChangeEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cells: Option<NotebookDocumentCellChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenNotebookDocumentParams {
+    pub notebook_document: NotebookDocument,
+    pub cell_text_documents: Vec<lsp::TextDocumentItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeNotebookDocumentParams {
+    pub notebook_document: VersionedNotebookDocumentIdentifier,
+    pub change: NotebookDocumentChangeEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidSaveNotebookDocumentParams {
+    pub notebook_document: NotebookDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidCloseNotebookDocumentParams {
+    pub notebook_document: NotebookDocumentIdentifier,
+    pub cell_text_documents: Vec<lsp::TextDocumentIdentifier>,
+}
+
+#[derive(Debug)]
+pub enum DidOpenNotebookDocument {}
+
+impl lsp::notification::Notification for DidOpenNotebookDocument {
+    type Params = DidOpenNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didOpen";
+}
+
+#[derive(Debug)]
+pub enum DidChangeNotebookDocument {}
+
+impl lsp::notification::Notification for DidChangeNotebookDocument {
+    type Params = DidChangeNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didChange";
+}
+
+#[derive(Debug)]
+pub enum DidSaveNotebookDocument {}
+
+impl lsp::notification::Notification for DidSaveNotebookDocument {
+    type Params = DidSaveNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didSave";
+}
+
+#[derive(Debug)]
+pub enum DidCloseNotebookDocument {}
+
+impl lsp::notification::Notification for DidCloseNotebookDocument {
+    type Params = DidCloseNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didClose";
+}
+
+/// The `notebookDocumentSync` capability of a server. The notebooks it is interested in
+/// are kept as sent, helix doesn't filter the notebooks it syncs.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentSyncOptions {
+    #[serde(default)]
+    pub notebook_selector: Vec<Value>,
+    /// Whether the server wants `notebookDocument/didSave`.
+    pub save: Option<bool>,
+}
+
+impl NotebookDocumentSyncOptions {
+    /// Reads the options from the raw `initialize` response of a server, `None` if it
+    /// doesn't sync notebooks.
+    pub(crate) fn from_initialize_result(response: &Value) -> Option<Self> {
+        let options = response.pointer("/capabilities/notebookDocumentSync")?;
+        match serde_json::from_value(options.clone()) {
+            Ok(options) => Some(options),
+            Err(err) => {
+                log::warn!("invalid notebookDocumentSync capability: {}", err);
+                None
+            }
+        }
+    }
+}
+
+/// Adds the notebook sync capabilities of helix to the serialized `initialize` params.
+pub(crate) fn advertise_capabilities(params: &mut Value) {
+    if let Some(capabilities) = params
+        .get_mut("capabilities")
+        .and_then(Value::as_object_mut)
+    {
+        capabilities.insert(
+            "notebookDocument".to_string(),
+            serde_json::json!({
+                "synchronization": {
+                    "dynamicRegistration": false,
+                    "executionSummarySupport": false,
+                },
+            }),
+        );
+    }
+}
+
+/// `initialize` with its params and result left as JSON, to pass along the parts of the
+/// notebook sync lsp-types doesn't know about.
+#[derive(Debug)]
+pub(crate) enum RawInitialize {}
+
+impl lsp::request::Request for RawInitialize {
+    type Params = Value;
+    type Result = Value;
+    const METHOD: &'static str = <lsp::request::Initialize as lsp::request::Request>::METHOD;
+}
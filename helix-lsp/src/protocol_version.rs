@@ -0,0 +1,137 @@
+//! Best-effort detection of the newest LSP version a server supports.
+//!
+//! Servers don't announce the protocol version they implement, but the capabilities they
+//! advertise were introduced in specific versions. Capabilities stay the real gate for each
+//! request; the detected version guards against sending requests of newer protocol versions
+//! that older servers reject as unknown methods.
+
+use crate::lsp;
+
+/// A version of the protocol, ordered from oldest to newest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProtocolVersion {
+    /// 3.14 or older.
+    V3_14,
+    V3_15,
+    V3_16,
+    V3_17,
+}
+
+/// Features that only exist since a specific version of the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolFeature {
+    SelectionRange,
+    WorkDoneProgress,
+    SemanticTokens,
+    CallHierarchy,
+    Moniker,
+    LinkedEditingRange,
+    FileOperations,
+    PositionEncoding,
+    PullDiagnostics,
+    InlayHints,
+    InlineValues,
+    TypeHierarchy,
+}
+
+impl ProtocolFeature {
+    /// The protocol version that introduced the feature.
+    pub fn since(self) -> ProtocolVersion {
+        match self {
+            Self::SelectionRange | Self::WorkDoneProgress => ProtocolVersion::V3_15,
+            Self::SemanticTokens
+            | Self::CallHierarchy
+            | Self::Moniker
+            | Self::LinkedEditingRange
+            | Self::FileOperations => ProtocolVersion::V3_16,
+            Self::PositionEncoding
+            | Self::PullDiagnostics
+            | Self::InlayHints
+            | Self::InlineValues
+            | Self::TypeHierarchy => ProtocolVersion::V3_17,
+        }
+    }
+}
+
+impl ProtocolVersion {
+    /// Guesses the newest protocol version a server supports from the response to
+    /// `initialize`: the newest version that introduced one of the advertised capabilities.
+    pub fn detect(response: &lsp::InitializeResult) -> Self {
+        let capabilities = &response.capabilities;
+
+        if capabilities.position_encoding.is_some()
+            || capabilities.diagnostic_provider.is_some()
+            || capabilities.inlay_hint_provider.is_some()
+            || capabilities.inline_value_provider.is_some()
+        {
+            return Self::V3_17;
+        }
+
+        let file_operations = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.file_operations.as_ref());
+        if capabilities.semantic_tokens_provider.is_some()
+            || capabilities.call_hierarchy_provider.is_some()
+            || capabilities.moniker_provider.is_some()
+            || capabilities.linked_editing_range_provider.is_some()
+            || file_operations.is_some()
+        {
+            return Self::V3_16;
+        }
+
+        if response.server_info.is_some() || capabilities.selection_range_provider.is_some() {
+            return Self::V3_15;
+        }
+
+        Self::V3_14
+    }
+
+    /// Whether `feature` exists in this version of the protocol.
+    pub fn supports(self, feature: ProtocolFeature) -> bool {
+        self >= feature.since()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProtocolFeature, ProtocolVersion};
+    use crate::lsp;
+
+    #[test]
+    fn newer_requests_are_blocked_for_older_servers() {
+        let response = lsp::InitializeResult {
+            capabilities: lsp::ServerCapabilities {
+                hover_provider: Some(lsp::HoverProviderCapability::Simple(true)),
+                selection_range_provider: Some(lsp::SelectionRangeProviderCapability::Simple(true)),
+                ..Default::default()
+            },
+            server_info: Some(lsp::ServerInfo {
+                name: "pyls".to_string(),
+                version: None,
+            }),
+        };
+        let version = ProtocolVersion::detect(&response);
+        assert_eq!(version, ProtocolVersion::V3_15);
+        assert!(version.supports(ProtocolFeature::SelectionRange));
+        assert!(!version.supports(ProtocolFeature::SemanticTokens));
+        assert!(!version.supports(ProtocolFeature::PullDiagnostics));
+
+        let response = lsp::InitializeResult {
+            capabilities: lsp::ServerCapabilities {
+                inlay_hint_provider: Some(lsp::OneOf::Left(true)),
+                ..Default::default()
+            },
+            server_info: None,
+        };
+        let version = ProtocolVersion::detect(&response);
+        assert_eq!(version, ProtocolVersion::V3_17);
+        assert!(version.supports(ProtocolFeature::PullDiagnostics));
+
+        let response = lsp::InitializeResult {
+            capabilities: Default::default(),
+            server_info: None,
+        };
+        assert_eq!(ProtocolVersion::detect(&response), ProtocolVersion::V3_14);
+    }
+}
@@ -0,0 +1,102 @@
+//! Caching of `textDocument/selectionRange` responses.
+//!
+//! A single response contains the whole chain of enclosing ranges around a position, so
+//! repeatedly expanding and shrinking the selection only needs one request as long as the
+//! document isn't edited in between.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::lsp;
+
+#[derive(Debug)]
+struct CachedDocument {
+    version: i32,
+    ranges: BTreeMap<lsp::Position, lsp::SelectionRange>,
+}
+
+/// Selection range chains received from a server, keyed by document, version and position.
+#[derive(Debug, Default)]
+pub struct SelectionRangeCache {
+    documents: HashMap<lsp::Url, CachedDocument>,
+}
+
+impl SelectionRangeCache {
+    pub fn get(
+        &self,
+        uri: &lsp::Url,
+        version: i32,
+        position: lsp::Position,
+    ) -> Option<lsp::SelectionRange> {
+        self.documents
+            .get(uri)
+            .filter(|document| document.version == version)
+            .and_then(|document| document.ranges.get(&position))
+            .cloned()
+    }
+
+    pub fn insert(
+        &mut self,
+        uri: lsp::Url,
+        version: i32,
+        position: lsp::Position,
+        selection_range: lsp::SelectionRange,
+    ) {
+        let document = self.documents.entry(uri).or_insert_with(|| CachedDocument {
+            version,
+            ranges: BTreeMap::new(),
+        });
+        // ranges of older versions point into text that doesn't exist anymore
+        if document.version != version {
+            document.version = version;
+            document.ranges.clear();
+        }
+        document.ranges.insert(position, selection_range);
+    }
+
+    /// Drops the cached ranges of `uri`, for example because the document was edited.
+    pub fn invalidate(&mut self, uri: &lsp::Url) {
+        self.documents.remove(uri);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32) -> lsp::Range {
+        lsp::Range::new(lsp::Position::new(0, start), lsp::Position::new(0, end))
+    }
+
+    #[test]
+    fn expand_and_shrink_reuse_cached_chain() {
+        let uri = lsp::Url::parse("file:///project/src/main.rs").unwrap();
+        let position = lsp::Position::new(0, 5);
+        let chain = lsp::SelectionRange {
+            range: range(4, 6),
+            parent: Some(Box::new(lsp::SelectionRange {
+                range: range(0, 10),
+                parent: None,
+            })),
+        };
+
+        let mut cache = SelectionRangeCache::default();
+        assert_eq!(cache.get(&uri, 1, position), None);
+        cache.insert(uri.clone(), 1, position, chain.clone());
+
+        // expanding and then shrinking again both walk the same cached chain
+        let expanded = cache.get(&uri, 1, position).unwrap();
+        assert_eq!(expanded.parent.as_deref().unwrap().range, range(0, 10));
+        assert_eq!(cache.get(&uri, 1, position), Some(chain.clone()));
+        assert_eq!(cache.get(&uri, 1, lsp::Position::new(0, 6)), None);
+
+        // an edit bumps the version of the document
+        assert_eq!(cache.get(&uri, 2, position), None);
+        cache.invalidate(&uri);
+        assert_eq!(cache.get(&uri, 1, position), None);
+
+        // caching a newer version drops the ranges of older ones
+        cache.insert(uri.clone(), 1, position, chain.clone());
+        cache.insert(uri.clone(), 2, lsp::Position::new(0, 6), chain);
+        assert_eq!(cache.get(&uri, 1, position), None);
+    }
+}
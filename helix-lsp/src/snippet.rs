@@ -1,7 +1,9 @@
 use std::borrow::Cow;
+use std::path::Path;
 
 use anyhow::{anyhow, Result};
-use helix_core::{smallvec, SmallVec};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike};
+use helix_core::{regex, smallvec, SmallVec};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum CaseChange {
@@ -52,7 +54,218 @@ pub struct Snippet<'a> {
 }
 
 pub fn parse(s: &str) -> Result<Snippet<'_>> {
-    parser::parse(s).map_err(|rest| anyhow!("Failed to parse snippet. Remaining input: {}", rest))
+    Snippet::parse(s).map_err(|err| anyhow!("Failed to parse snippet: {}", err))
+}
+
+/// Where and why a snippet couldn't be parsed, see [`Snippet::parse`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// The byte offset of the element that couldn't be parsed.
+    pub offset: usize,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.reason, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl<'a> Snippet<'a> {
+    /// Parses the snippet `s`, failing at the first `$` that doesn't start a valid tabstop,
+    /// placeholder, choice or variable.
+    pub fn parse(s: &'a str) -> Result<Self, ParseError> {
+        if s.is_empty() {
+            return Ok(Snippet {
+                elements: Vec::new(),
+            });
+        }
+        let rest = match parser::parse_prefix(s) {
+            Ok(("", snippet)) => return Ok(snippet),
+            Ok((rest, _)) | Err(rest) => rest,
+        };
+        Err(ParseError {
+            offset: s.len() - rest.len(),
+            reason: failure_reason(rest),
+        })
+    }
+
+    /// Parses the snippet `s` like [`Snippet::parse`], but a `$` that doesn't start a valid
+    /// element is kept as literal text, along with the text following it.
+    pub fn parse_lenient(s: &'a str) -> Self {
+        let mut elements = Vec::new();
+        let mut rest = s;
+        loop {
+            if let Ok((remaining, snippet)) = parser::parse_prefix(rest) {
+                elements.extend(snippet.elements);
+                rest = remaining;
+            }
+            let mut chars = rest.chars();
+            if chars.next().is_none() {
+                break;
+            }
+            let len = rest.len() - chars.as_str().len();
+            elements.push(SnippetElement::Text(&rest[..len]));
+            rest = &rest[len..];
+        }
+        Snippet { elements }
+    }
+}
+
+/// Describes why the snippet input `rest`, which starts with a `$` the parser stopped at,
+/// isn't valid.
+fn failure_reason(rest: &str) -> &'static str {
+    let (body, braced) = match rest.strip_prefix("${") {
+        Some(body) => (body, true),
+        None => match rest.strip_prefix('$') {
+            Some(body) => (body, false),
+            None => return "unexpected input",
+        },
+    };
+
+    let digits = body.len() - body.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 && body[..digits].parse::<usize>().is_err() {
+        return "tabstop number is out of range";
+    }
+    if braced && digits > 0 && !matches!(body[digits..].chars().next(), Some('}' | ':' | '|')) {
+        return "invalid tabstop number";
+    }
+
+    if !braced {
+        return "invalid `$` element";
+    }
+
+    // look for the `}` closing the `${`, skipping nested elements and escaped characters
+    let mut depth = 1;
+    let mut chars = body.chars().peekable();
+    loop {
+        match chars.next() {
+            Some('\\') => {
+                chars.next();
+            }
+            Some('$') if chars.peek() == Some(&'{') => {
+                chars.next();
+                depth += 1;
+            }
+            Some('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return "invalid `$` element";
+                }
+            }
+            Some(_) => (),
+            None => return "unterminated `${`",
+        }
+    }
+}
+
+/// The values of the variables a snippet can refer to, like `$TM_FILENAME` or `$CLIPBOARD`.
+#[derive(Clone, Copy)]
+pub struct SnippetContext<'a> {
+    /// The path of the document the snippet is inserted into.
+    pub path: Option<&'a Path>,
+    /// The zero-based line the snippet is inserted on.
+    pub line: usize,
+    /// Reads the contents of the clipboard. Only called if the snippet uses `$CLIPBOARD`.
+    pub clipboard: &'a dyn Fn() -> Option<String>,
+    /// The local time used by the `$CURRENT_*` variables, along with its offset from UTC.
+    pub now: DateTime<FixedOffset>,
+}
+
+impl SnippetContext<'_> {
+    /// Resolves the variable `name`. Returns `None` for unknown variables.
+    fn resolve(&self, name: &str) -> Option<String> {
+        let path = |part: fn(&Path) -> Option<&std::ffi::OsStr>| {
+            self.path
+                .and_then(part)
+                .map(|part| part.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        let value = match name {
+            "TM_FILENAME" => path(Path::file_name),
+            "TM_FILENAME_BASE" => path(Path::file_stem),
+            "TM_DIRECTORY" => path(|path| path.parent().map(Path::as_os_str)),
+            "TM_FILEPATH" => path(|path| Some(path.as_os_str())),
+            "TM_LINE_INDEX" => self.line.to_string(),
+            "TM_LINE_NUMBER" => (self.line + 1).to_string(),
+            "CLIPBOARD" => (self.clipboard)().unwrap_or_default(),
+            "CURRENT_YEAR" => self.now.year().to_string(),
+            "CURRENT_YEAR_SHORT" => format!("{:02}", self.now.year() % 100),
+            "CURRENT_MONTH" => format!("{:02}", self.now.month()),
+            "CURRENT_MONTH_NAME" => self.now.format("%B").to_string(),
+            "CURRENT_MONTH_NAME_SHORT" => self.now.format("%b").to_string(),
+            "CURRENT_DATE" => format!("{:02}", self.now.day()),
+            "CURRENT_DAY_NAME" => self.now.format("%A").to_string(),
+            "CURRENT_DAY_NAME_SHORT" => self.now.format("%a").to_string(),
+            "CURRENT_HOUR" => format!("{:02}", self.now.hour()),
+            "CURRENT_MINUTE" => format!("{:02}", self.now.minute()),
+            "CURRENT_SECOND" => format!("{:02}", self.now.second()),
+            "CURRENT_SECONDS_UNIX" => self.now.timestamp().to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+impl Regex<'_> {
+    /// Applies the transform to `value`. Returns `value` unchanged if the regex is invalid.
+    fn transform(&self, value: &str) -> String {
+        let options = self.options.unwrap_or_default();
+        let regex = match regex::RegexBuilder::new(self.value)
+            .case_insensitive(options.contains('i'))
+            .multi_line(options.contains('m'))
+            .build()
+        {
+            Ok(regex) => regex,
+            Err(err) => {
+                log::warn!("invalid snippet transform {:?}: {}", self.value, err);
+                return value.to_owned();
+            }
+        };
+
+        let replacement = |captures: &regex::Captures| {
+            let capture = |group: usize| captures.get(group).map_or("", |m| m.as_str());
+            let mut replacement = String::new();
+            for item in &self.replacement {
+                match item {
+                    FormatItem::Text(text) => replacement.push_str(text),
+                    FormatItem::Capture(group) => replacement.push_str(capture(*group)),
+                    FormatItem::CaseChange(group, case_change) => {
+                        let text = capture(*group);
+                        match case_change {
+                            CaseChange::Upcase => replacement.push_str(&text.to_uppercase()),
+                            CaseChange::Downcase => replacement.push_str(&text.to_lowercase()),
+                            CaseChange::Capitalize => {
+                                let mut chars = text.chars();
+                                if let Some(first) = chars.next() {
+                                    replacement.extend(first.to_uppercase());
+                                    replacement.push_str(chars.as_str());
+                                }
+                            }
+                        }
+                    }
+                    FormatItem::Conditional(group, if_text, else_text) => {
+                        let text = if captures.get(*group).is_some() {
+                            if_text
+                        } else {
+                            else_text
+                        };
+                        replacement.push_str(text.unwrap_or_default());
+                    }
+                }
+            }
+            replacement
+        };
+
+        if options.contains('g') {
+            regex.replace_all(value, replacement).into_owned()
+        } else {
+            regex.replace(value, replacement).into_owned()
+        }
+    }
 }
 
 fn render_elements(
@@ -62,6 +275,7 @@ fn render_elements(
     tabstops: &mut Vec<(usize, (usize, usize))>,
     newline_with_offset: &String,
     include_placeholer: bool,
+    ctx: &SnippetContext<'_>,
 ) {
     use SnippetElement::*;
 
@@ -77,15 +291,20 @@ fn render_elements(
                 *offset += text.chars().count();
                 insert.push_str(&text);
             }
-            &Variable {
-                name: _,
-                regex: _,
+            Variable {
+                name,
+                regex,
                 r#default,
             } => {
-                // TODO: variables. For now, fall back to the default, which defaults to "".
-                let text = r#default.unwrap_or_default();
+                // unknown or empty variables fall back to the default, which defaults to ""
+                let value = ctx.resolve(name).filter(|value| !value.is_empty());
+                let text = match (value, regex) {
+                    (Some(value), Some(regex)) => regex.transform(&value),
+                    (Some(value), None) => value,
+                    (None, _) => r#default.unwrap_or_default().to_owned(),
+                };
                 *offset += text.chars().count();
-                insert.push_str(text);
+                insert.push_str(&text);
             }
             &Tabstop { tabstop } => {
                 tabstops.push((tabstop, (*offset, *offset)));
@@ -103,16 +322,20 @@ fn render_elements(
                         tabstops,
                         newline_with_offset,
                         include_placeholer,
+                        ctx,
                     );
                 }
                 tabstops.push((*tabstop, (start_offset, *offset)));
             }
-            &Choice {
-                tabstop,
-                choices: _,
-            } => {
-                // TODO: choices
-                tabstops.push((tabstop, (*offset, *offset)));
+            Choice { tabstop, choices } => {
+                // choices are shown as a placeholder containing the first choice
+                let start_offset = *offset;
+                if include_placeholer {
+                    let text = choices.first().copied().unwrap_or_default();
+                    *offset += text.chars().count();
+                    insert.push_str(text);
+                }
+                tabstops.push((*tabstop, (start_offset, *offset)));
             }
         }
     }
@@ -123,6 +346,7 @@ pub fn render(
     snippet: &Snippet<'_>,
     newline_with_offset: String,
     include_placeholer: bool,
+    ctx: &SnippetContext<'_>,
 ) -> (String, Vec<SmallVec<[(usize, usize); 1]>>) {
     let mut insert = String::new();
     let mut tabstops = Vec::new();
@@ -135,8 +359,14 @@ pub fn render(
         &mut tabstops,
         &newline_with_offset,
         include_placeholer,
+        ctx,
     );
 
+    // the final cursor position defaults to the end of the snippet
+    if !tabstops.iter().any(|(n, _)| *n == 0) {
+        tabstops.push((0, (offset, offset)));
+    }
+
     // sort in ascending order (except for 0, which should always be the last one (per lsp doc))
     tabstops.sort_unstable_by_key(|(n, _)| if *n == 0 { usize::MAX } else { *n });
 
@@ -214,6 +444,24 @@ fn var<'a>() -> impl Parser<'a, Output = &'a str> {
         take_while(move |c| cs.into_iter().all(|c1| c != c1))
     }
 
+    /// Text in snippets, where `\$`, `\}` and `\\` stand for the literal characters. The text
+    /// ends at any of `cs`.
+    fn escaped_text<'a, const SIZE: usize>(
+        cs: [char; SIZE],
+    ) -> impl Parser<'a, Output = SnippetElement<'a>> {
+        // an escaped character is a separate text element, sliced out of the input
+        let escape = |input: &'a str| {
+            let mut chars = input.chars();
+            match (chars.next(), chars.next()) {
+                (Some('\\'), Some('$' | '}' | '\\')) => Ok((&input[2..], &input[1..2])),
+                _ => Err(input),
+            }
+        };
+        let text = take_while(move |c| c != '\\' && cs.into_iter().all(|c1| c != c1));
+        // a backslash that doesn't escape anything is kept
+        map(choice!(escape, text, "\\"), SnippetElement::Text)
+    }
+
     fn digit<'a>() -> impl Parser<'a, Output = usize> {
         filter_map(take_while(|c| c.is_ascii_digit()), |s| s.parse().ok())
     }
@@ -308,7 +556,7 @@ fn tabstop<'a>() -> impl Parser<'a, Output = SnippetElement<'a>> {
     }
 
     fn placeholder<'a>() -> impl Parser<'a, Output = SnippetElement<'a>> {
-        let text = map(text(['$', '}']), SnippetElement::Text);
+        let text = escaped_text(['$', '}']);
         map(
             seq!(
                 "${",
@@ -348,6 +596,12 @@ fn variable<'a>() -> impl Parser<'a, Output = SnippetElement<'a>> {
                 default: None,
                 regex: None,
             }),
+            // ${var}
+            map(seq!("${", var(), "}"), |values| SnippetElement::Variable {
+                name: values.1,
+                default: None,
+                regex: None,
+            }),
             // ${var:default}
             map(
                 seq!("${", var(), ":", take_until(|c| c == '}'), "}",),
@@ -377,20 +631,30 @@ fn anything<'a>() -> impl Parser<'a, Output = SnippetElement<'a>> {
     }
 
     fn snippet<'a>() -> impl Parser<'a, Output = Snippet<'a>> {
-        let text = map(text(['$']), SnippetElement::Text);
+        let text = escaped_text(['$']);
         map(one_or_more(choice!(anything(), text)), |parts| Snippet {
             elements: parts,
         })
     }
 
-    pub fn parse(s: &str) -> Result<Snippet, &str> {
-        snippet().parse(s).map(|(_input, elements)| elements)
+    /// Parses as much of `s` as possible. Returns the input that is left over.
+    pub fn parse_prefix(s: &str) -> Result<(&str, Snippet<'_>), &str> {
+        snippet().parse(s)
     }
 
     #[cfg(test)]
     mod test {
+        use std::path::Path;
+
+        use chrono::{FixedOffset, TimeZone};
+
         use super::SnippetElement::*;
         use super::*;
+        use crate::snippet::{render, ParseError, SnippetContext};
+
+        fn parse(s: &str) -> Result<Snippet<'_>, &str> {
+            parse_prefix(s).map(|(_input, snippet)| snippet)
+        }
 
         #[test]
         fn empty_string_is_error() {
@@ -504,6 +768,20 @@ fn parse_all() {
             );
         }
 
+        #[test]
+        fn parse_variable_in_braces() {
+            assert_eq!(
+                Ok(Snippet {
+                    elements: vec![Variable {
+                        name: "TM_FILENAME",
+                        default: None,
+                        regex: None
+                    }]
+                }),
+                parse("${TM_FILENAME}")
+            );
+        }
+
         #[test]
         fn regex_capture_replace() {
             assert_eq!(
@@ -521,5 +799,159 @@ fn regex_capture_replace() {
                 parse("${TM_FILENAME/(.*).+$/$1/}")
             );
         }
+
+        #[test]
+        fn parse_escaped_characters() {
+            assert_eq!(
+                Ok(Snippet {
+                    elements: vec![
+                        Text("echo "),
+                        Text("$"),
+                        Text("{not_a_var} "),
+                        Placeholder {
+                            tabstop: 1,
+                            value: vec![Text("a"), Text("}"), Text("b")],
+                        },
+                    ]
+                }),
+                parse("echo \\${not_a_var} ${1:a\\}b}")
+            );
+        }
+
+        fn render_with(snippet: &str, ctx: &SnippetContext) -> String {
+            render(
+                &crate::snippet::parse(snippet).unwrap(),
+                "\n".to_string(),
+                true,
+                ctx,
+            )
+            .0
+        }
+
+        fn clipboard() -> Option<String> {
+            Some("copied".to_string())
+        }
+
+        fn context(path: Option<&Path>) -> SnippetContext<'_> {
+            SnippetContext {
+                path,
+                line: 41,
+                clipboard: &clipboard,
+                now: FixedOffset::east_opt(3600)
+                    .unwrap()
+                    .with_ymd_and_hms(2023, 3, 7, 9, 5, 0)
+                    .unwrap(),
+            }
+        }
+
+        #[test]
+        fn filename_variables() {
+            let path = Path::new("/project/src/main.rs");
+            let ctx = context(Some(path));
+            assert_eq!(render_with("// $TM_FILENAME", &ctx), "// main.rs");
+            assert_eq!(render_with("mod ${TM_FILENAME_BASE};", &ctx), "mod main;");
+            assert_eq!(render_with("${TM_FILENAME/(.*)\\..+$/$1/}", &ctx), "main");
+            assert_eq!(
+                render_with("$TM_LINE_NUMBER: $CLIPBOARD", &ctx),
+                "42: copied"
+            );
+
+            // unsaved documents fall back to the default
+            let ctx = context(None);
+            assert_eq!(render_with("${TM_FILENAME:untitled}", &ctx), "untitled");
+            assert_eq!(render_with("[$TM_FILENAME]", &ctx), "[]");
+        }
+
+        #[test]
+        fn date_variables() {
+            let ctx = context(None);
+            assert_eq!(
+                render_with("$CURRENT_YEAR-$CURRENT_MONTH-$CURRENT_DATE", &ctx),
+                "2023-03-07"
+            );
+            assert_eq!(
+                render_with(
+                    "$CURRENT_DAY_NAME_SHORT $CURRENT_MONTH_NAME $CURRENT_HOUR:$CURRENT_MINUTE",
+                    &ctx
+                ),
+                "Tue March 09:05"
+            );
+            // 08:05 UTC
+            assert_eq!(render_with("$CURRENT_SECONDS_UNIX", &ctx), "1678176300");
+            // unknown variables are left empty
+            assert_eq!(render_with("[$UNKNOWN_VARIABLE]", &ctx), "[]");
+        }
+
+        #[test]
+        fn escaped_characters_are_literal() {
+            let ctx = context(None);
+            assert_eq!(render_with("\\${not_a_var}", &ctx), "${not_a_var}");
+            assert_eq!(render_with("cost: \\$$1", &ctx), "cost: $");
+            assert_eq!(render_with("C:\\\\Users\\\\$1", &ctx), "C:\\Users\\");
+            // backslashes that don't escape anything are kept
+            assert_eq!(render_with("\\n${1:\\d+}", &ctx), "\\n\\d+");
+        }
+
+        #[test]
+        fn unterminated_placeholder_is_reported() {
+            assert_eq!(
+                Snippet::parse("foo(${1:bar)"),
+                Err(ParseError {
+                    offset: 4,
+                    reason: "unterminated `${`",
+                })
+            );
+            assert_eq!(
+                crate::snippet::parse("foo(${1:bar)")
+                    .unwrap_err()
+                    .to_string(),
+                "Failed to parse snippet: unterminated `${` at byte 4"
+            );
+
+            // leniently, the bad region is kept as text
+            let snippet = Snippet::parse_lenient("foo(${1:bar) $2");
+            assert_eq!(
+                snippet.elements,
+                [
+                    Text("foo("),
+                    Text("$"),
+                    Text("{1:bar) "),
+                    Tabstop { tabstop: 2 }
+                ]
+            );
+            assert_eq!(
+                render(&snippet, "\n".to_string(), true, &context(None)).0,
+                "foo(${1:bar) "
+            );
+        }
+
+        #[test]
+        fn bad_tabstop_numbers_are_reported() {
+            assert_eq!(
+                Snippet::parse("a ${1x:b}"),
+                Err(ParseError {
+                    offset: 2,
+                    reason: "invalid tabstop number",
+                })
+            );
+            assert_eq!(
+                Snippet::parse("$1 $99999999999999999999999"),
+                Err(ParseError {
+                    offset: 3,
+                    reason: "tabstop number is out of range",
+                })
+            );
+            assert_eq!(
+                Snippet::parse_lenient("${1x:b}$0").elements,
+                [Text("$"), Text("{1x:b}"), Tabstop { tabstop: 0 }]
+            );
+
+            // valid snippets parse the same as before
+            assert!(Snippet::parse("").unwrap().elements.is_empty());
+            assert_eq!(
+                Snippet::parse("${1:a} \\${2").unwrap(),
+                Snippet::parse_lenient("${1:a} \\${2")
+            );
+        }
     }
 }
@@ -1,16 +1,20 @@
-use crate::{jsonrpc, Error, Result};
+use crate::{jsonrpc, Error, Result, ServerExitStatus};
 use anyhow::Context;
-use log::{error, info};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
 use tokio::{
-    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
-    process::{ChildStderr, ChildStdin, ChildStdout},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    process::Child,
     sync::{
         mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
-        Mutex, Notify,
+        oneshot, Mutex, Notify,
     },
 };
 
@@ -35,49 +39,264 @@ enum ServerMessage {
     Call(jsonrpc::Call),
 }
 
+/// The number of lines of the stderr output of a server that are kept.
+const STDERR_TAIL_LINES: usize = 50;
+
+/// The channels to a [`Transport`] returned by [`Transport::start`].
+#[derive(Debug)]
+pub struct StartedTransport {
+    /// The calls of the server, tagged with its id.
+    pub server_rx: UnboundedReceiver<(usize, jsonrpc::Call)>,
+    /// Messages to send to the server.
+    pub server_tx: UnboundedSender<Payload>,
+    /// Notified once the server is initialized to send the messages held back until then.
+    pub initialize_notify: Arc<Notify>,
+    pub transport: Arc<Transport>,
+}
+
 #[derive(Debug)]
 pub struct Transport {
     id: usize,
+    /// The name of the server, prefixed to its stderr output in the log.
+    name: String,
     pending_requests: Mutex<HashMap<jsonrpc::Id, Sender<Result<Value>>>>,
+    /// The number of entries in `pending_requests`, readable without locking.
+    pending_request_count: AtomicUsize,
+    last_response_at: std::sync::Mutex<Option<Instant>>,
+    /// Requests of the server that weren't answered yet, by id, and whether the server
+    /// cancelled them.
+    server_requests: std::sync::Mutex<HashMap<jsonrpc::Id, bool>>,
+    /// Ids of the `workspace/configuration` requests of the server that weren't answered yet.
+    pending_configuration_requests: Mutex<HashSet<jsonrpc::Id>>,
+    /// Receivers of the partial results streamed through `$/progress`, by partial result token.
+    partial_results: std::sync::Mutex<HashMap<lsp_types::ProgressToken, UnboundedSender<Value>>>,
+    /// Notifications the server sent before it finished initializing. They are forwarded
+    /// once it did, `None` afterwards.
+    early_notifications: std::sync::Mutex<Option<Vec<jsonrpc::Call>>>,
+    pub(crate) configuration_answered: Notify,
+    /// The last lines the server wrote to stderr.
+    stderr_tail: std::sync::Mutex<VecDeque<String>>,
+    /// The server process, shared with the client. `None` for servers that aren't a child
+    /// process.
+    process: Arc<Mutex<Option<Child>>>,
+    /// Whether the stream to the server broke, as opposed to the server closing it.
+    connection_lost: AtomicBool,
 }
 
 impl Transport {
-    pub fn start(
-        server_stdout: BufReader<ChildStdout>,
-        server_stdin: BufWriter<ChildStdin>,
-        server_stderr: BufReader<ChildStderr>,
+    fn new(id: usize, name: String) -> Self {
+        Self {
+            id,
+            name,
+            pending_requests: Mutex::new(HashMap::default()),
+            pending_request_count: AtomicUsize::new(0),
+            last_response_at: std::sync::Mutex::new(None),
+            server_requests: std::sync::Mutex::new(HashMap::default()),
+            pending_configuration_requests: Mutex::new(HashSet::default()),
+            partial_results: std::sync::Mutex::new(HashMap::default()),
+            early_notifications: std::sync::Mutex::new(Some(Vec::new())),
+            configuration_answered: Notify::new(),
+            stderr_tail: std::sync::Mutex::new(VecDeque::with_capacity(STDERR_TAIL_LINES)),
+            process: Arc::default(),
+            connection_lost: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts talking to the server once the stream to it arrives through `connection`.
+    /// Messages are held back until then, and the server is reported as exited if the sender
+    /// is dropped instead.
+    pub fn start<R, W>(
+        connection: oneshot::Receiver<(R, W)>,
+        server_stderr: impl AsyncBufRead + Unpin + Send + 'static,
         id: usize,
-    ) -> (
-        UnboundedReceiver<(usize, jsonrpc::Call)>,
-        UnboundedSender<Payload>,
-        Arc<Notify>,
-    ) {
+        name: String,
+        process: Arc<Mutex<Option<Child>>>,
+    ) -> StartedTransport
+    where
+        R: AsyncBufRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
         let (client_tx, rx) = unbounded_channel();
         let (tx, client_rx) = unbounded_channel();
         let notify = Arc::new(Notify::new());
 
-        let transport = Self {
-            id,
-            pending_requests: Mutex::new(HashMap::default()),
-        };
+        let transport = Arc::new(Self {
+            process,
+            ..Self::new(id, name)
+        });
 
-        let transport = Arc::new(transport);
-
-        tokio::spawn(Self::recv(
-            transport.clone(),
-            server_stdout,
-            client_tx.clone(),
-        ));
         tokio::spawn(Self::err(transport.clone(), server_stderr));
-        tokio::spawn(Self::send(
+        let (task_transport, task_notify) = (transport.clone(), notify.clone());
+        tokio::spawn(async move {
+            let (server_stdout, server_stdin) = match connection.await {
+                Ok(connection) => connection,
+                Err(_) => return task_transport.close(&client_tx).await,
+            };
+            tokio::spawn(Self::recv(
+                task_transport.clone(),
+                server_stdout,
+                client_tx.clone(),
+            ));
+            tokio::spawn(Self::send(
+                task_transport,
+                server_stdin,
+                client_tx,
+                client_rx,
+                task_notify,
+            ));
+        });
+
+        StartedTransport {
+            server_rx: rx,
+            server_tx: tx,
+            initialize_notify: notify,
             transport,
-            server_stdin,
-            client_tx,
-            client_rx,
-            notify.clone(),
-        ));
+        }
+    }
 
-        (rx, tx, notify)
+    /// The number of requests sent to the server that weren't answered yet.
+    pub fn pending_requests(&self) -> usize {
+        self.pending_request_count.load(Ordering::Relaxed)
+    }
+
+    /// When the server last answered a request.
+    pub fn last_response_at(&self) -> Option<Instant> {
+        *self.last_response_at.lock().unwrap()
+    }
+
+    /// Whether the stream to the server broke, e.g. because the network connection to it was
+    /// reset, rather than being closed by the server.
+    pub fn connection_lost(&self) -> bool {
+        self.connection_lost.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_connection_lost(&self) {
+        self.connection_lost.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the server process to exit once it closed its stdout. There is no exit
+    /// status for servers that keep running after closing it.
+    async fn exit_status(&self) -> Option<ServerExitStatus> {
+        let mut process = self.process.lock().await;
+        match tokio::time::timeout(Duration::from_secs(1), process.as_mut()?.wait()).await {
+            Ok(Ok(status)) => Some(status.into()),
+            _ => None,
+        }
+    }
+
+    /// The last lines the server wrote to stderr, oldest first.
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.stderr_tail.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Sends the partial results the server streams for `token` to the returned receiver
+    /// instead of forwarding them as progress notifications. Partial results are sent before
+    /// the response of the request they belong to.
+    pub fn register_partial_results(
+        &self,
+        token: lsp_types::ProgressToken,
+    ) -> UnboundedReceiver<Value> {
+        let (tx, rx) = unbounded_channel();
+        self.partial_results.lock().unwrap().insert(token, tx);
+        rx
+    }
+
+    pub fn unregister_partial_results(&self, token: &lsp_types::ProgressToken) {
+        self.partial_results.lock().unwrap().remove(token);
+    }
+
+    /// Sends the value of a `$/progress` notification to the receiver of its partial result
+    /// token. Returns `false` if the token isn't a registered partial result token.
+    fn send_partial_result(&self, params: &jsonrpc::Params) -> bool {
+        let mut params = match Value::from(params.clone()) {
+            Value::Object(params) => params,
+            _ => return false,
+        };
+        let token: lsp_types::ProgressToken = match params
+            .get("token")
+            .and_then(|token| serde_json::from_value(token.clone()).ok())
+        {
+            Some(token) => token,
+            None => return false,
+        };
+
+        match self.partial_results.lock().unwrap().get(&token) {
+            Some(tx) => {
+                let value = params.remove("value").unwrap_or_default();
+                // the request might have timed out already
+                let _ = tx.send(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the request the server cancelled through `$/cancelRequest` as cancelled, so
+    /// that its reply is replaced by a `RequestCancelled` error.
+    async fn cancel_server_request(&self, params: &jsonrpc::Params) {
+        let id = match params.clone().parse::<lsp_types::CancelParams>() {
+            Ok(lsp_types::CancelParams {
+                id: lsp_types::NumberOrString::Number(id),
+            }) => match u64::try_from(id) {
+                Ok(id) => jsonrpc::Id::Num(id),
+                Err(_) => return,
+            },
+            Ok(lsp_types::CancelParams {
+                id: lsp_types::NumberOrString::String(id),
+            }) => jsonrpc::Id::Str(id),
+            Err(err) => {
+                error!("Invalid $/cancelRequest params: {}", err);
+                return;
+            }
+        };
+
+        if let Some(cancelled) = self.server_requests.lock().unwrap().get_mut(&id) {
+            *cancelled = true;
+        }
+        // the server doesn't wait for its configuration anymore
+        if self.pending_configuration_requests.lock().await.remove(&id) {
+            self.configuration_answered.notify_one();
+        }
+    }
+
+    /// Records the reply to a request of the server. Replies to requests the server
+    /// cancelled are replaced with a `RequestCancelled` error, since the server still
+    /// expects a response but discards the result.
+    fn answer_server_request(&self, output: jsonrpc::Output) -> jsonrpc::Output {
+        let id = match &output {
+            jsonrpc::Output::Success(jsonrpc::Success { id, .. })
+            | jsonrpc::Output::Failure(jsonrpc::Failure { id, .. }) => id,
+        };
+        match self.server_requests.lock().unwrap().remove(id) {
+            Some(true) => {
+                info!("Dropping the reply to cancelled request {}", id);
+                jsonrpc::Output::Failure(jsonrpc::Failure {
+                    jsonrpc: Some(jsonrpc::Version::V2),
+                    id: id.clone(),
+                    error: jsonrpc::Error {
+                        code: jsonrpc::ErrorCode::ServerError(
+                            lsp_types::error_codes::REQUEST_CANCELLED,
+                        ),
+                        message: "request cancelled by the server".to_string(),
+                        data: None,
+                    },
+                })
+            }
+            _ => output,
+        }
+    }
+
+    /// Forwards the notifications the server sent before it finished initializing, in the
+    /// order they arrived in. Later notifications are forwarded right away.
+    fn release_early_notifications(&self, client_tx: &UnboundedSender<(usize, jsonrpc::Call)>) {
+        // keep the lock while forwarding so that newer notifications can't overtake them
+        let mut early_notifications = self.early_notifications.lock().unwrap();
+        for call in early_notifications.take().unwrap_or_default() {
+            if client_tx.send((self.id, call)).is_err() {
+                error!("Could not forward a notification sent during initialization");
+                break;
+            }
+        }
     }
 
     async fn recv_server_message(
@@ -100,11 +319,26 @@ async fn recv_server_message(
 
             let header = buffer.trim();
 
-            let parts = header.split_once(": ");
+            let parts = header.split_once(':');
 
             match parts {
-                Some(("Content-Length", value)) => {
-                    content_length = Some(value.parse().context("invalid content length")?);
+                Some((name, value)) if name.eq_ignore_ascii_case("Content-Length") => {
+                    content_length = Some(value.trim().parse().context("invalid content length")?);
+                }
+                Some((name, value)) if name.eq_ignore_ascii_case("Content-Type") => {
+                    // UTF-8 is the only encoding in use, servers may still spell it `utf8`
+                    let charset = value
+                        .split(';')
+                        .find_map(|param| param.trim().strip_prefix("charset="));
+                    match charset {
+                        Some(charset)
+                            if !charset.eq_ignore_ascii_case("utf-8")
+                                && !charset.eq_ignore_ascii_case("utf8") =>
+                        {
+                            warn!("Unsupported charset {:?}, decoding as UTF-8", charset);
+                        }
+                        _ => {}
+                    }
                 }
                 Some((_, _)) => {}
                 None => {
@@ -133,6 +367,7 @@ async fn recv_server_message(
     }
 
     async fn recv_server_error(
+        &self,
         err: &mut (impl AsyncBufRead + Unpin + Send),
         buffer: &mut String,
     ) -> Result<()> {
@@ -140,34 +375,55 @@ async fn recv_server_error(
         if err.read_line(buffer).await? == 0 {
             return Err(Error::StreamClosed);
         };
-        error!("err <- {:?}", buffer);
+        let line = buffer.trim_end_matches(&['\r', '\n'][..]);
+        debug!("{} err <- {}", self.name, line);
+
+        let mut stderr_tail = self.stderr_tail.lock().unwrap();
+        if stderr_tail.len() == STDERR_TAIL_LINES {
+            stderr_tail.pop_front();
+        }
+        stderr_tail.push_back(line.to_string());
 
         Ok(())
     }
 
     async fn send_payload_to_server(
         &self,
-        server_stdin: &mut BufWriter<ChildStdin>,
+        server_stdin: &mut (impl AsyncWrite + Unpin + Send),
         payload: Payload,
     ) -> Result<()> {
         //TODO: reuse string
+        let mut answers_configuration = false;
         let json = match payload {
             Payload::Request { chan, value } => {
-                self.pending_requests
-                    .lock()
-                    .await
-                    .insert(value.id.clone(), chan);
+                let mut pending_requests = self.pending_requests.lock().await;
+                pending_requests.insert(value.id.clone(), chan);
+                self.pending_request_count
+                    .store(pending_requests.len(), Ordering::Relaxed);
                 serde_json::to_string(&value)?
             }
             Payload::Notification(value) => serde_json::to_string(&value)?,
-            Payload::Response(error) => serde_json::to_string(&error)?,
+            Payload::Response(output) => {
+                let output = self.answer_server_request(output);
+                let id = match &output {
+                    jsonrpc::Output::Success(jsonrpc::Success { id, .. })
+                    | jsonrpc::Output::Failure(jsonrpc::Failure { id, .. }) => id,
+                };
+                answers_configuration = self.pending_configuration_requests.lock().await.remove(id);
+                serde_json::to_string(&output)?
+            }
         };
-        self.send_string_to_server(server_stdin, json).await
+        self.send_string_to_server(server_stdin, json).await?;
+
+        if answers_configuration {
+            self.configuration_answered.notify_one();
+        }
+        Ok(())
     }
 
     async fn send_string_to_server(
         &self,
-        server_stdin: &mut BufWriter<ChildStdin>,
+        server_stdin: &mut (impl AsyncWrite + Unpin + Send),
         request: String,
     ) -> Result<()> {
         info!("-> {}", request);
@@ -193,6 +449,47 @@ async fn process_server_message(
         match msg {
             ServerMessage::Output(output) => self.process_request_response(output).await?,
             ServerMessage::Call(call) => {
+                use lsp_types::notification::{Cancel, Notification, Progress};
+                use lsp_types::request::{Request, WorkspaceConfiguration};
+                if let jsonrpc::Call::Notification(jsonrpc::Notification {
+                    method, params, ..
+                }) = &call
+                {
+                    if method == Progress::METHOD && self.send_partial_result(params) {
+                        return Ok(());
+                    }
+                    if method == Cancel::METHOD {
+                        self.cancel_server_request(params).await;
+                    }
+                }
+                // Non-compliant servers may send notifications before they answered the
+                // `initialize` request. Handling them before the capabilities of the server
+                // are known could confuse the editor, so they are delayed. Notifications
+                // injected by the transport itself have no `jsonrpc` version.
+                if let jsonrpc::Call::Notification(jsonrpc::Notification {
+                    jsonrpc: Some(_), ..
+                }) = &call
+                {
+                    if let Some(early_notifications) =
+                        self.early_notifications.lock().unwrap().as_mut()
+                    {
+                        info!("Language server not initialized, delaying notification");
+                        early_notifications.push(call);
+                        return Ok(());
+                    }
+                }
+                if let jsonrpc::Call::MethodCall(jsonrpc::MethodCall { method, id, .. }) = &call {
+                    self.server_requests
+                        .lock()
+                        .unwrap()
+                        .insert(id.clone(), false);
+                    if method == WorkspaceConfiguration::METHOD {
+                        self.pending_configuration_requests
+                            .lock()
+                            .await
+                            .insert(id.clone());
+                    }
+                }
                 client_tx
                     .send((self.id, call))
                     .context("failed to send a message to server")?;
@@ -214,7 +511,16 @@ async fn process_request_response(&self, output: jsonrpc::Output) -> Result<()>
             }
         };
 
-        if let Some(tx) = self.pending_requests.lock().await.remove(&id) {
+        *self.last_response_at.lock().unwrap() = Some(Instant::now());
+
+        let tx = {
+            let mut pending_requests = self.pending_requests.lock().await;
+            let tx = pending_requests.remove(&id);
+            self.pending_request_count
+                .store(pending_requests.len(), Ordering::Relaxed);
+            tx
+        };
+        if let Some(tx) = tx {
             match tx.send(result).await {
                 Ok(_) => (),
                 Err(_) => error!(
@@ -233,9 +539,43 @@ async fn process_request_response(&self, output: jsonrpc::Output) -> Result<()>
         Ok(())
     }
 
+    /// Fails the outstanding requests once the stream to the server is closed, and lets the
+    /// client know that the server exited.
+    async fn close(&self, client_tx: &UnboundedSender<(usize, jsonrpc::Call)>) {
+        // Close any outstanding requests.
+        self.pending_request_count.store(0, Ordering::Relaxed);
+        for (id, tx) in self.pending_requests.lock().await.drain() {
+            match tx.send(Err(Error::StreamClosed)).await {
+                Ok(_) => (),
+                Err(_) => {
+                    error!("Could not close request on a closed channel (id={:?})", id)
+                }
+            }
+        }
+
+        // Hack: inject a terminated notification so we trigger code that needs to happen after exit
+        use lsp_types::notification::Notification as _;
+        let params = match self.exit_status().await.map(serde_json::to_value) {
+            Some(Ok(Value::Object(status))) => jsonrpc::Params::Map(status),
+            _ => jsonrpc::Params::None,
+        };
+        let notification =
+            ServerMessage::Call(jsonrpc::Call::Notification(jsonrpc::Notification {
+                jsonrpc: None,
+                method: lsp_types::notification::Exit::METHOD.to_string(),
+                params,
+            }));
+        match self.process_server_message(client_tx, notification).await {
+            Ok(_) => {}
+            Err(err) => {
+                error!("err: <- {:?}", err);
+            }
+        }
+    }
+
     async fn recv(
         transport: Arc<Self>,
-        mut server_stdout: BufReader<ChildStdout>,
+        mut server_stdout: impl AsyncBufRead + Unpin + Send,
         client_tx: UnboundedSender<(usize, jsonrpc::Call)>,
     ) {
         let mut recv_buffer = String::new();
@@ -251,33 +591,28 @@ async fn recv(
                     };
                 }
                 Err(Error::StreamClosed) => {
-                    // Close any outstanding requests.
-                    for (id, tx) in transport.pending_requests.lock().await.drain() {
-                        match tx.send(Err(Error::StreamClosed)).await {
-                            Ok(_) => (),
-                            Err(_) => {
-                                error!("Could not close request on a closed channel (id={:?})", id)
-                            }
-                        }
-                    }
-
-                    // Hack: inject a terminated notification so we trigger code that needs to happen after exit
-                    use lsp_types::notification::Notification as _;
-                    let notification =
-                        ServerMessage::Call(jsonrpc::Call::Notification(jsonrpc::Notification {
-                            jsonrpc: None,
-                            method: lsp_types::notification::Exit::METHOD.to_string(),
-                            params: jsonrpc::Params::None,
-                        }));
-                    match transport
-                        .process_server_message(&client_tx, notification)
-                        .await
-                    {
-                        Ok(_) => {}
-                        Err(err) => {
-                            error!("err: <- {:?}", err);
-                        }
-                    }
+                    transport.close(&client_tx).await;
+                    break;
+                }
+                // A malformed message is skipped: it was read up to its end, so the next
+                // message starts with its own headers.
+                Err(err @ (Error::Parse(_) | Error::Other(_))) => {
+                    error!(
+                        "Skipping malformed message from the language server: {}",
+                        err
+                    );
+                }
+                // `read_line` consumes header lines that aren't valid UTF-8
+                Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::InvalidData => {
+                    error!(
+                        "Skipping malformed header from the language server: {}",
+                        err
+                    );
+                }
+                Err(Error::IO(err)) => {
+                    error!("Lost the connection to the language server: {}", err);
+                    transport.set_connection_lost();
+                    transport.close(&client_tx).await;
                     break;
                 }
                 Err(err) => {
@@ -288,10 +623,13 @@ async fn recv(
         }
     }
 
-    async fn err(_transport: Arc<Self>, mut server_stderr: BufReader<ChildStderr>) {
+    async fn err(transport: Arc<Self>, mut server_stderr: impl AsyncBufRead + Unpin + Send) {
         let mut recv_buffer = String::new();
         loop {
-            match Self::recv_server_error(&mut server_stderr, &mut recv_buffer).await {
+            match transport
+                .recv_server_error(&mut server_stderr, &mut recv_buffer)
+                .await
+            {
                 Ok(_) => {}
                 Err(err) => {
                     error!("err: <- {:?}", err);
@@ -303,7 +641,7 @@ async fn err(_transport: Arc<Self>, mut server_stderr: BufReader<ChildStderr>) {
 
     async fn send(
         transport: Arc<Self>,
-        mut server_stdin: BufWriter<ChildStdin>,
+        mut server_stdin: impl AsyncWrite + Unpin + Send,
         client_tx: UnboundedSender<(usize, jsonrpc::Call)>,
         mut client_rx: UnboundedReceiver<Payload>,
         initialize_notify: Arc<Notify>,
@@ -327,6 +665,8 @@ fn is_initialize(payload: &Payload) -> bool {
                 {
                     true
                 }
+                // the server may be waiting for the response before it finishes initializing
+                Payload::Response(_) => true,
                 _ => false,
             }
         }
@@ -354,6 +694,7 @@ fn is_initialize(payload: &Payload) -> bool {
                             error!("err: <- {:?}", err);
                         }
                     }
+                    transport.release_early_notifications(&client_tx);
 
                     // drain the pending queue and send payloads to server
                     for msg in pending_messages.drain(..) {
@@ -369,12 +710,19 @@ fn is_initialize(payload: &Payload) -> bool {
                 msg = client_rx.recv() => {
                     if let Some(msg) = msg {
                         if is_pending && !is_initialize(&msg) {
-                            // ignore notifications
-                            if let Payload::Notification(_) = msg {
-                                continue;
+                            // Document sync notifications are delivered once the server is
+                            // initialized, so that it doesn't miss opened documents or changes.
+                            // The queue is dropped with the transport if initializing fails.
+                            // Other notifications are ignored.
+                            match &msg {
+                                Payload::Notification(notification)
+                                    if notification.method.starts_with("textDocument/")
+                                        || notification.method.starts_with("notebookDocument/") => {}
+                                Payload::Notification(_) => continue,
+                                _ => (),
                             }
 
-                            log::info!("Language server not initialized, delaying request");
+                            log::info!("Language server not initialized, delaying message");
                             pending_messages.push(msg);
                         } else {
                             match transport.send_payload_to_server(&mut server_stdin, msg).await {
@@ -393,3 +741,307 @@ fn is_initialize(payload: &Payload) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Payload, ServerMessage, StartedTransport, Transport};
+    use crate::{jsonrpc, lsp, Client};
+    use serde_json::json;
+    use std::sync::Arc;
+    use tokio::io::{AsyncWriteExt, BufReader};
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+    use tokio::sync::oneshot;
+
+    fn connected<R, W>(reader: R, writer: W) -> oneshot::Receiver<(R, W)> {
+        let (tx, rx) = oneshot::channel();
+        let _ = tx.send((reader, writer));
+        rx
+    }
+
+    #[tokio::test]
+    async fn partial_results_are_assembled_with_the_response() {
+        let location = |line: u32| lsp::Location {
+            uri: lsp::Url::parse("file:///project/src/main.rs").unwrap(),
+            range: lsp::Range::new(lsp::Position::new(line, 0), lsp::Position::new(line, 4)),
+        };
+        let progress = |token: &str, value: serde_json::Value| -> ServerMessage {
+            serde_json::from_value(json!({
+                "jsonrpc": "2.0",
+                "method": "$/progress",
+                "params": { "token": token, "value": value },
+            }))
+            .unwrap()
+        };
+
+        let transport = Transport::new(0, "test".to_string());
+        let token = lsp::ProgressToken::String("references".to_string());
+        let partials = transport.register_partial_results(token.clone());
+        let (client_tx, mut client_rx) = unbounded_channel();
+        transport.release_early_notifications(&client_tx);
+
+        for message in [
+            progress("references", json!([location(1)])),
+            progress("references", json!([location(2)])),
+            progress("indexing", json!({ "kind": "report", "percentage": 50 })),
+        ] {
+            transport
+                .process_server_message(&client_tx, message)
+                .await
+                .unwrap();
+        }
+        transport.unregister_partial_results(&token);
+
+        // only the work done progress is forwarded to the editor
+        let (_, call) = client_rx.try_recv().unwrap();
+        assert!(matches!(
+            call,
+            crate::jsonrpc::Call::Notification(notification)
+                if serde_json::Value::from(notification.params.clone())["token"] == "indexing"
+        ));
+        assert!(client_rx.try_recv().is_err());
+
+        let response = Client::assemble_partial_results(partials, json!([location(3)]));
+        let locations: Vec<lsp::Location> = serde_json::from_value(response).unwrap();
+        assert_eq!(locations, [location(1), location(2), location(3)]);
+    }
+
+    #[tokio::test]
+    async fn notifications_before_initialization_are_delayed() {
+        let log_message = |message: &str| -> ServerMessage {
+            serde_json::from_value(json!({
+                "jsonrpc": "2.0",
+                "method": "window/logMessage",
+                "params": { "type": 3, "message": message },
+            }))
+            .unwrap()
+        };
+        let message = |call: jsonrpc::Call| match call {
+            jsonrpc::Call::Notification(notification) => {
+                serde_json::Value::from(notification.params)["message"].clone()
+            }
+            call => panic!("expected a notification, got {:?}", call),
+        };
+
+        let transport = Transport::new(0, "test".to_string());
+        let (client_tx, mut client_rx) = unbounded_channel();
+        let (initialize_tx, mut initialize_rx) = channel(1);
+        transport
+            .pending_requests
+            .lock()
+            .await
+            .insert(jsonrpc::Id::Num(0), initialize_tx);
+
+        transport
+            .process_server_message(&client_tx, log_message("starting"))
+            .await
+            .unwrap();
+        assert!(client_rx.try_recv().is_err());
+
+        // the initialize response is still matched to its request
+        let response = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "result": { "capabilities": {} },
+            "id": 0,
+        }))
+        .unwrap();
+        transport
+            .process_server_message(&client_tx, response)
+            .await
+            .unwrap();
+        let result = initialize_rx.recv().await.unwrap().unwrap();
+        assert_eq!(result, json!({ "capabilities": {} }));
+
+        transport.release_early_notifications(&client_tx);
+        assert_eq!(message(client_rx.try_recv().unwrap().1), "starting");
+        transport
+            .process_server_message(&client_tx, log_message("started"))
+            .await
+            .unwrap();
+        assert_eq!(message(client_rx.try_recv().unwrap().1), "started");
+    }
+
+    #[tokio::test]
+    async fn malformed_messages_are_skipped() {
+        let (mut server_stdout, client_stdout) = tokio::io::duplex(1024);
+        let StartedTransport {
+            server_rx: mut client_rx,
+            ..
+        } = Transport::start(
+            connected(BufReader::new(client_stdout), tokio::io::sink()),
+            tokio::io::empty(),
+            0,
+            "test".to_string(),
+            Arc::default(),
+        );
+
+        let request = r#"{"jsonrpc":"2.0","method":"window/workDoneProgress/create","params":{"token":"indexing"},"id":1}"#;
+        let mut stream = Vec::new();
+        // a line that isn't valid UTF-8
+        stream.extend_from_slice(b"\xff\xfe garbage\r\n");
+        // a truncated message
+        stream.extend_from_slice(b"Content-Length: 10\r\n\r\n{\"jsonrpc\"");
+        stream.extend_from_slice(
+            format!("Content-Length: {}\r\n\r\n{}", request.len(), request).as_bytes(),
+        );
+        server_stdout.write_all(&stream).await.unwrap();
+
+        match client_rx.recv().await {
+            Some((_, jsonrpc::Call::MethodCall(call))) => {
+                assert_eq!(call.method, "window/workDoneProgress/create");
+                assert_eq!(call.id, jsonrpc::Id::Num(1));
+            }
+            call => panic!("expected the valid request, got {:?}", call),
+        }
+    }
+
+    #[tokio::test]
+    async fn content_type_header_is_accepted() {
+        let (mut server_stdout, client_stdout) = tokio::io::duplex(1024);
+        let StartedTransport {
+            server_rx: mut client_rx,
+            ..
+        } = Transport::start(
+            connected(BufReader::new(client_stdout), tokio::io::sink()),
+            tokio::io::empty(),
+            0,
+            "test".to_string(),
+            Arc::default(),
+        );
+
+        let content_type = "Content-Type: application/vscode-jsonrpc; charset=utf-8\r\n";
+        for id in [1, 2] {
+            let request = json!({
+                "jsonrpc": "2.0",
+                "method": "window/workDoneProgress/create",
+                "params": { "token": "indexing" },
+                "id": id,
+            })
+            .to_string();
+            let content_length = format!("Content-Length: {}\r\n", request.len());
+            // the order of the headers doesn't matter
+            let headers = match id {
+                1 => format!("{}{}", content_type, content_length),
+                _ => format!("{}{}", content_length, content_type),
+            };
+            server_stdout
+                .write_all(format!("{}\r\n{}", headers, request).as_bytes())
+                .await
+                .unwrap();
+
+            match client_rx.recv().await {
+                Some((_, jsonrpc::Call::MethodCall(call))) => {
+                    assert_eq!(call.id, jsonrpc::Id::Num(id))
+                }
+                call => panic!("expected request {}, got {:?}", id, call),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_to_cancelled_requests_are_dropped() {
+        let message =
+            |value: serde_json::Value| -> ServerMessage { serde_json::from_value(value).unwrap() };
+        let reply = |id: u64| {
+            jsonrpc::Output::Success(jsonrpc::Success {
+                jsonrpc: Some(jsonrpc::Version::V2),
+                id: jsonrpc::Id::Num(id),
+                result: json!([{ "checkOnSave": false }]),
+            })
+        };
+
+        let transport = Transport::new(0, "test".to_string());
+        let (client_tx, mut client_rx) = unbounded_channel();
+        transport.release_early_notifications(&client_tx);
+
+        for id in [1, 2] {
+            transport
+                .process_server_message(
+                    &client_tx,
+                    message(json!({
+                        "jsonrpc": "2.0",
+                        "method": "workspace/configuration",
+                        "params": { "items": [{ "section": "rust-analyzer" }] },
+                        "id": id,
+                    })),
+                )
+                .await
+                .unwrap();
+        }
+        transport
+            .process_server_message(
+                &client_tx,
+                message(json!({
+                    "jsonrpc": "2.0",
+                    "method": "$/cancelRequest",
+                    "params": { "id": 1 },
+                })),
+            )
+            .await
+            .unwrap();
+
+        // the editor still sees the cancellation
+        let calls: Vec<_> = std::iter::from_fn(|| client_rx.try_recv().ok()).collect();
+        assert!(matches!(
+            &calls[2].1,
+            jsonrpc::Call::Notification(notification) if notification.method == "$/cancelRequest"
+        ));
+
+        match transport.answer_server_request(reply(1)) {
+            jsonrpc::Output::Failure(failure) => {
+                assert_eq!(failure.id, jsonrpc::Id::Num(1));
+                assert_eq!(failure.error.code.code(), -32800);
+            }
+            output => panic!("expected the reply to be dropped, got {:?}", output),
+        }
+        assert_eq!(transport.answer_server_request(reply(2)), reply(2));
+    }
+
+    #[tokio::test]
+    async fn document_sync_is_delayed_until_initialized() {
+        let notification = |method: &str| {
+            Payload::Notification(jsonrpc::Notification {
+                jsonrpc: Some(jsonrpc::Version::V2),
+                method: method.to_string(),
+                params: jsonrpc::Params::Map(serde_json::Map::new()),
+            })
+        };
+
+        let (_server_stdout, client_stdout) = tokio::io::duplex(1024);
+        let (client_stdin, server_stdin) = tokio::io::duplex(4096);
+        let mut server_stdin = BufReader::new(server_stdin);
+        let StartedTransport {
+            server_tx: tx,
+            initialize_notify: notify,
+            ..
+        } = Transport::start(
+            connected(BufReader::new(client_stdout), client_stdin),
+            tokio::io::empty(),
+            0,
+            "test".to_string(),
+            Arc::default(),
+        );
+
+        tx.send(notification("workspace/didChangeConfiguration"))
+            .unwrap();
+        tx.send(notification("textDocument/didOpen")).unwrap();
+        tx.send(notification("textDocument/didChange")).unwrap();
+
+        let mut buffer = String::new();
+        let early = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Transport::recv_server_message(&mut server_stdin, &mut buffer),
+        )
+        .await;
+        assert!(early.is_err(), "nothing is sent before initialization");
+
+        notify.notify_one();
+        for method in ["textDocument/didOpen", "textDocument/didChange"] {
+            match Transport::recv_server_message(&mut server_stdin, &mut buffer).await {
+                Ok(ServerMessage::Call(jsonrpc::Call::Notification(notification))) => {
+                    assert_eq!(notification.method, method)
+                }
+                message => panic!("expected {}, got {:?}", method, message),
+            }
+        }
+    }
+}
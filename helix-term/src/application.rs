@@ -5,7 +5,7 @@
     path::get_relative_path,
     pos_at_coords, syntax, Selection,
 };
-use helix_lsp::{lsp, util::lsp_pos_to_pos, LspProgressMap};
+use helix_lsp::{lsp, util::lsp_pos_to_pos, LspProgressMap, ProgressChange};
 use helix_view::{
     align_view,
     document::DocumentSavedEventResult,
@@ -23,7 +23,7 @@
     commands::apply_workspace_edit,
     compositor::{Compositor, Event},
     config::Config,
-    job::Jobs,
+    job::{Callback, Jobs},
     keymap::Keymaps,
     ui::{self, overlay::overlayed},
 };
@@ -48,6 +48,39 @@
 type Signals = futures_util::stream::Empty<()>;
 
 const LSP_DEADLINE: Duration = Duration::from_millis(16);
+/// Progress that wasn't updated for this long is assumed to have been abandoned by the server.
+const LSP_PROGRESS_MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Reconnects to the TCP language server `server_id` whose connection broke and moves its
+/// documents over to the new connection, which replays them once it is initialized. The
+/// reconnect is reported as successful once the new connection is initialized.
+///
+/// Returns how long to wait before trying again if the server was reconnected too recently.
+/// The server is removed, and the reconnect reported as failed, if connecting fails or was
+/// retried too often.
+fn reconnect_language_server(editor: &mut Editor, server_id: usize) -> Option<Duration> {
+    match editor.language_servers.reconnect(server_id) {
+        Ok(Some(_)) => {
+            // a previous attempt may not have gotten its server initialized
+            let document_ids: Vec<_> = editor
+                .documents()
+                .filter(|doc| doc.language_server_id() == Some(server_id))
+                .map(|doc| doc.id())
+                .collect();
+            for document_id in document_ids {
+                editor.refresh_language_server(document_id);
+            }
+            None
+        }
+        Ok(None) => None,
+        Err(helix_lsp::Error::RestartTooSoon(delay)) => Some(delay),
+        Err(err) => {
+            editor.set_error(format!("Failed to reconnect to language server: {}", err));
+            editor.language_servers.remove_by_id(server_id);
+            None
+        }
+    }
+}
 
 #[cfg(not(feature = "integration"))]
 use tui::backend::CrosstermBackend;
@@ -467,6 +500,20 @@ pub async fn handle_signals(&mut self, signal: i32) {
     }
 
     pub async fn handle_idle_timeout(&mut self) {
+        let stale = self.lsp_progress.prune_stale(LSP_PROGRESS_MAX_AGE);
+        if !stale.is_empty() {
+            let editor_view = self
+                .compositor
+                .find::<ui::EditorView>()
+                .expect("expected at least one EditorView");
+            for (server_id, _) in stale {
+                if !self.lsp_progress.is_progressing(server_id) {
+                    editor_view.spinners_mut().get_or_create(server_id).stop();
+                }
+            }
+            self.editor.needs_redraw = true;
+        }
+
         let mut cx = crate::compositor::Context {
             editor: &mut self.editor,
             jobs: &mut self.jobs,
@@ -569,6 +616,12 @@ pub async fn handle_editor_event(&mut self, event: EditorEvent) -> bool {
                     self.render().await;
                 }
             }
+            EditorEvent::DiagnosticsDebounced => {
+                for uri in self.editor.diagnostics.flush(Instant::now()) {
+                    self.refresh_document_diagnostics(&uri);
+                }
+                self.render().await;
+            }
             EditorEvent::IdleTimer => {
                 self.editor.clear_idle_timer();
                 self.handle_idle_timeout().await;
@@ -619,6 +672,132 @@ pub async fn handle_terminal_events(
         }
     }
 
+    /// Converts the stored diagnostics of every server for `uri` and shows them in the
+    /// document of `uri`, if it is open.
+    fn refresh_document_diagnostics(&mut self, uri: &lsp::Url) {
+        let path = match uri.to_file_path() {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+
+        let doc = match self.editor.document_by_path(&path) {
+            Some(doc) => doc,
+            None => return,
+        };
+        let lang_conf = doc.language_config();
+        let text = doc.text();
+
+        // The document shows the diagnostics of every server that published some,
+        // each converted with the offset encoding of the server it came from.
+        let diagnostics = self
+            .editor
+            .diagnostics
+            .diagnostics_by_server(uri)
+            .into_iter()
+            .flat_map(|(id, diagnostics)| {
+                let language_server = self
+                    .editor
+                    .language_servers
+                    .get_by_id(id)
+                    .filter(|language_server| language_server.is_initialized());
+                diagnostics
+                    .into_iter()
+                    .map(move |diagnostic| (language_server, diagnostic))
+            })
+            .filter_map(|(language_server, diagnostic)| {
+                use helix_core::diagnostic::{Diagnostic, Range, Severity::*};
+                use lsp::DiagnosticSeverity;
+
+                let language_server = if let Some(language_server) = language_server {
+                    language_server
+                } else {
+                    log::warn!(
+                        "Discarding diagnostic because language server is not initialized: {:?}",
+                        diagnostic
+                    );
+                    return None;
+                };
+
+                // TODO: convert inside server
+                let start = if let Some(start) = lsp_pos_to_pos(
+                    text,
+                    diagnostic.range.start,
+                    language_server.offset_encoding(),
+                ) {
+                    start
+                } else {
+                    log::warn!("lsp position out of bounds - {:?}", diagnostic);
+                    return None;
+                };
+
+                let end = if let Some(end) = lsp_pos_to_pos(
+                    text,
+                    diagnostic.range.end,
+                    language_server.offset_encoding(),
+                ) {
+                    end
+                } else {
+                    log::warn!("lsp position out of bounds - {:?}", diagnostic);
+                    return None;
+                };
+
+                let severity = diagnostic.severity.map(|severity| match severity {
+                    DiagnosticSeverity::ERROR => Error,
+                    DiagnosticSeverity::WARNING => Warning,
+                    DiagnosticSeverity::INFORMATION => Info,
+                    DiagnosticSeverity::HINT => Hint,
+                    severity => unreachable!("unrecognized diagnostic severity: {:?}", severity),
+                });
+
+                if let Some(lang_conf) = lang_conf {
+                    if let Some(severity) = severity {
+                        if severity < lang_conf.diagnostic_severity {
+                            return None;
+                        }
+                    }
+                };
+
+                let code = match diagnostic.code.clone() {
+                    Some(x) => match x {
+                        lsp::NumberOrString::Number(x) => Some(NumberOrString::Number(x)),
+                        lsp::NumberOrString::String(x) => Some(NumberOrString::String(x)),
+                    },
+                    None => None,
+                };
+
+                let tags = if let Some(ref tags) = diagnostic.tags {
+                    let new_tags = tags
+                        .iter()
+                        .filter_map(|tag| match *tag {
+                            lsp::DiagnosticTag::DEPRECATED => Some(DiagnosticTag::Deprecated),
+                            lsp::DiagnosticTag::UNNECESSARY => Some(DiagnosticTag::Unnecessary),
+                            _ => None,
+                        })
+                        .collect();
+
+                    new_tags
+                } else {
+                    Vec::new()
+                };
+
+                Some(Diagnostic {
+                    range: Range { start, end },
+                    line: diagnostic.range.start.line as usize,
+                    message: diagnostic.message.clone(),
+                    severity,
+                    code,
+                    tags,
+                    source: diagnostic.source.clone(),
+                    data: diagnostic.data.clone(),
+                })
+            })
+            .collect();
+
+        if let Some(doc) = self.editor.document_by_path_mut(&path) {
+            doc.set_diagnostics(diagnostics);
+        }
+    }
+
     pub async fn handle_language_server_message(
         &mut self,
         call: helix_lsp::Call,
@@ -641,6 +820,10 @@ pub async fn handle_language_server_message(
 
                 match notification {
                     Notification::Initialized => {
+                        if self.editor.language_servers.finish_reconnect(server_id) {
+                            self.editor.set_status("Reconnected to language server");
+                        }
+
                         let language_server =
                             match self.editor.language_servers.get_by_id(server_id) {
                                 Some(language_server) => language_server,
@@ -661,135 +844,44 @@ pub async fn handle_language_server_message(
                             doc.language_server().map(|server| server.id()) == Some(server_id)
                         });
 
-                        // trigger textDocument/didOpen for docs that are already open
+                        // The documents were opened while the server was starting, changes made
+                        // before it reported its capabilities weren't sent though. Those
+                        // documents are reopened with their current contents.
                         for doc in docs {
                             let url = match doc.url() {
                                 Some(url) => url,
                                 None => continue, // skip documents with no path
                             };
 
+                            let close = match language_server.synced_version(&url) {
+                                Some(version) if version == doc.version() => continue,
+                                Some(_) => {
+                                    Some(language_server.text_document_did_close(doc.identifier()))
+                                }
+                                None => None,
+                            };
+
                             let language_id =
                                 doc.language_id().map(ToOwned::to_owned).unwrap_or_default();
 
-                            tokio::spawn(language_server.text_document_did_open(
+                            let open = language_server.text_document_did_open(
                                 url,
                                 doc.version(),
                                 doc.text(),
                                 language_id,
-                            ));
+                            );
+                            tokio::spawn(async move {
+                                if let Some(close) = close {
+                                    close.await?;
+                                }
+                                open.await
+                            });
                         }
                     }
                     Notification::PublishDiagnostics(mut params) => {
-                        let path = match params.uri.to_file_path() {
-                            Ok(path) => path,
-                            Err(_) => {
-                                log::error!("Unsupported file URI: {}", params.uri);
-                                return;
-                            }
-                        };
-                        let doc = self.editor.document_by_path_mut(&path);
-
-                        if let Some(doc) = doc {
-                            let lang_conf = doc.language_config();
-                            let text = doc.text();
-
-                            let diagnostics = params
-                                .diagnostics
-                                .iter()
-                                .filter_map(|diagnostic| {
-                                    use helix_core::diagnostic::{Diagnostic, Range, Severity::*};
-                                    use lsp::DiagnosticSeverity;
-
-                                    let language_server = if let Some(language_server) = doc.language_server() {
-                                        language_server
-                                    } else {
-                                        log::warn!("Discarding diagnostic because language server is not initialized: {:?}", diagnostic);
-                                        return None;
-                                    };
-
-                                    // TODO: convert inside server
-                                    let start = if let Some(start) = lsp_pos_to_pos(
-                                        text,
-                                        diagnostic.range.start,
-                                        language_server.offset_encoding(),
-                                    ) {
-                                        start
-                                    } else {
-                                        log::warn!("lsp position out of bounds - {:?}", diagnostic);
-                                        return None;
-                                    };
-
-                                    let end = if let Some(end) = lsp_pos_to_pos(
-                                        text,
-                                        diagnostic.range.end,
-                                        language_server.offset_encoding(),
-                                    ) {
-                                        end
-                                    } else {
-                                        log::warn!("lsp position out of bounds - {:?}", diagnostic);
-                                        return None;
-                                    };
-
-                                    let severity =
-                                        diagnostic.severity.map(|severity| match severity {
-                                            DiagnosticSeverity::ERROR => Error,
-                                            DiagnosticSeverity::WARNING => Warning,
-                                            DiagnosticSeverity::INFORMATION => Info,
-                                            DiagnosticSeverity::HINT => Hint,
-                                            severity => unreachable!(
-                                                "unrecognized diagnostic severity: {:?}",
-                                                severity
-                                            ),
-                                        });
-
-                                    if let Some(lang_conf) = lang_conf {
-                                        if let Some(severity) = severity {
-                                            if severity < lang_conf.diagnostic_severity {
-                                                return None;
-                                            }
-                                        }
-                                    };
-
-                                    let code = match diagnostic.code.clone() {
-                                        Some(x) => match x {
-                                            lsp::NumberOrString::Number(x) => {
-                                                Some(NumberOrString::Number(x))
-                                            }
-                                            lsp::NumberOrString::String(x) => {
-                                                Some(NumberOrString::String(x))
-                                            }
-                                        },
-                                        None => None,
-                                    };
-
-                                    let tags = if let Some(ref tags) = diagnostic.tags {
-                                        let new_tags = tags.iter().filter_map(|tag| {
-                                            match *tag {
-                                                lsp::DiagnosticTag::DEPRECATED => Some(DiagnosticTag::Deprecated),
-                                                lsp::DiagnosticTag::UNNECESSARY => Some(DiagnosticTag::Unnecessary),
-                                                _ => None
-                                            }
-                                        }).collect();
-
-                                        new_tags
-                                    } else {
-                                        Vec::new()
-                                    };
-
-                                    Some(Diagnostic {
-                                        range: Range { start, end },
-                                        line: diagnostic.range.start.line as usize,
-                                        message: diagnostic.message.clone(),
-                                        severity,
-                                        code,
-                                        tags,
-                                        source: diagnostic.source.clone(),
-                                        data: diagnostic.data.clone(),
-                                    })
-                                })
-                                .collect();
-
-                            doc.set_diagnostics(diagnostics);
+                        if params.uri.to_file_path().is_err() {
+                            log::error!("Unsupported file URI: {}", params.uri);
+                            return;
                         }
 
                         // Sort diagnostics first by severity and then by line numbers.
@@ -801,9 +893,25 @@ pub async fn handle_language_server_message(
                         // Insert the original lsp::Diagnostics here because we may have no open document
                         // for diagnosic message and so we can't calculate the exact position.
                         // When using them later in the diagnostics picker, we calculate them on-demand.
-                        self.editor
-                            .diagnostics
-                            .insert(params.uri, params.diagnostics);
+                        let debounce = self.editor.config().lsp.diagnostics_debounce;
+                        if debounce.is_zero() {
+                            self.editor.diagnostics.insert(
+                                server_id,
+                                params.uri.clone(),
+                                params.diagnostics,
+                            );
+                            self.refresh_document_diagnostics(&params.uri);
+                        } else {
+                            // Servers often publish several times in a row while analyzing,
+                            // only the latest diagnostics are applied once the window passes.
+                            self.editor.diagnostics.insert_debounced(
+                                server_id,
+                                params.uri,
+                                params.diagnostics,
+                                debounce,
+                                Instant::now(),
+                            );
+                        }
                     }
                     Notification::ShowMessage(params) => {
                         log::warn!("unhandled window/showMessage: {:?}", params);
@@ -820,41 +928,37 @@ pub async fn handle_language_server_message(
                             .compositor
                             .find::<ui::EditorView>()
                             .expect("expected at least one EditorView");
-                        let lsp::ProgressParams { token, value } = params;
+                        let token = params.token.clone();
+                        let token_d: &dyn std::fmt::Display = match &token {
+                            lsp::NumberOrString::Number(n) => n,
+                            lsp::NumberOrString::String(s) => s,
+                        };
 
-                        let lsp::ProgressParamsValue::WorkDone(work) = value;
-                        let parts = match &work {
-                            lsp::WorkDoneProgress::Begin(lsp::WorkDoneProgressBegin {
+                        let (parts, ended) = match self.lsp_progress.apply(server_id, params) {
+                            ProgressChange::Updated {
                                 title,
                                 message,
                                 percentage,
-                                ..
-                            }) => (Some(title), message, percentage),
-                            lsp::WorkDoneProgress::Report(lsp::WorkDoneProgressReport {
-                                message,
-                                percentage,
-                                ..
-                            }) => (None, message, percentage),
-                            lsp::WorkDoneProgress::End(lsp::WorkDoneProgressEnd { message }) => {
-                                if message.is_some() {
-                                    (None, message, &None)
-                                } else {
-                                    self.lsp_progress.end_progress(server_id, &token);
-                                    if !self.lsp_progress.is_progressing(server_id) {
-                                        editor_view.spinners_mut().get_or_create(server_id).stop();
-                                    }
-                                    self.editor.clear_status();
-
-                                    // we want to render to clear any leftover spinners or messages
-                                    return;
-                                }
-                            }
+                            } => (
+                                (
+                                    title.map(String::from),
+                                    message.map(String::from),
+                                    percentage,
+                                ),
+                                false,
+                            ),
+                            ProgressChange::Ended { message } => ((None, message, None), true),
                         };
 
-                        let token_d: &dyn std::fmt::Display = match &token {
-                            lsp::NumberOrString::Number(n) => n,
-                            lsp::NumberOrString::String(s) => s,
-                        };
+                        if ended && !self.lsp_progress.is_progressing(server_id) {
+                            editor_view.spinners_mut().get_or_create(server_id).stop();
+                        }
+                        if ended && parts.1.is_none() {
+                            self.editor.clear_status();
+
+                            // we want to render to clear any leftover spinners or messages
+                            return;
+                        }
 
                         let status = match parts {
                             (Some(title), Some(message), Some(percentage)) => {
@@ -881,15 +985,6 @@ pub async fn handle_language_server_message(
                             (None, None, None) => format!("[{}]", token_d),
                         };
 
-                        if let lsp::WorkDoneProgress::End(_) = work {
-                            self.lsp_progress.end_progress(server_id, &token);
-                            if !self.lsp_progress.is_progressing(server_id) {
-                                editor_view.spinners_mut().get_or_create(server_id).stop();
-                            }
-                        } else {
-                            self.lsp_progress.update(server_id, token, work);
-                        }
-
                         if self.config.load().editor.lsp.display_messages {
                             self.editor.set_status(status);
                         }
@@ -897,31 +992,66 @@ pub async fn handle_language_server_message(
                     Notification::ProgressMessage(_params) => {
                         // do nothing
                     }
-                    Notification::Exit => {
-                        self.editor.set_status("Language server exited");
-
-                        // Clear any diagnostics for documents with this server open.
-                        let urls: Vec<_> = self
+                    Notification::Cancel(_params) => {
+                        // requests of the server are answered right away, the transport
+                        // takes care of replies that are still in flight
+                    }
+                    Notification::Exit(status) => {
+                        let initialize_error = self
                             .editor
-                            .documents_mut()
-                            .filter_map(|doc| {
-                                if doc.language_server().map(|server| server.id())
-                                    == Some(server_id)
-                                {
-                                    doc.set_diagnostics(Vec::new());
-                                    doc.url()
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect();
-
-                        for url in urls {
-                            self.editor.diagnostics.remove(&url);
+                            .language_servers
+                            .get_by_id(server_id)
+                            .and_then(|server| server.initialize_error())
+                            .map(String::from);
+                        // also retries a connection that failed while initializing
+                        let reconnect = self.editor.language_servers.reconnects(server_id);
+                        match (initialize_error, status) {
+                            _ if reconnect => self.editor.set_status(
+                                "Lost the connection to the language server, reconnecting",
+                            ),
+                            (Some(err), _) => {
+                                self.editor.set_error(format!(
+                                    "Language server failed to initialize: {}",
+                                    err
+                                ));
+                            }
+                            (None, Some(status)) if !status.success() => {
+                                self.editor
+                                    .set_error(format!("Language server exited with {}", status));
+                            }
+                            _ => self.editor.set_status("Language server exited"),
                         }
 
-                        // Remove the language server from the registry.
-                        self.editor.language_servers.remove_by_id(server_id);
+                        // Clear any diagnostics for documents with this server open.
+                        for doc in self.editor.documents_mut() {
+                            if doc.language_server().map(|server| server.id()) == Some(server_id) {
+                                doc.set_diagnostics(Vec::new());
+                            }
+                        }
+                        self.editor.diagnostics.remove_server(server_id);
+
+                        if reconnect {
+                            if let Some(delay) =
+                                reconnect_language_server(&mut self.editor, server_id)
+                            {
+                                // the connection dropped again soon after reconnecting
+                                self.jobs.callback(async move {
+                                    tokio::time::sleep(delay).await;
+                                    let call = move |editor: &mut Editor| {
+                                        if reconnect_language_server(editor, server_id).is_some() {
+                                            editor.set_error(
+                                                "Failed to reconnect to language server",
+                                            );
+                                            editor.language_servers.remove_by_id(server_id);
+                                        }
+                                    };
+                                    Ok(Callback::Editor(Box::new(call)))
+                                });
+                            }
+                        } else {
+                            // Remove the language server from the registry.
+                            self.editor.language_servers.remove_by_id(server_id);
+                        }
                     }
                 }
             }
@@ -968,23 +1098,54 @@ pub async fn handle_language_server_message(
                         Ok(serde_json::Value::Null)
                     }
                     Ok(MethodCall::ApplyWorkspaceEdit(params)) => {
-                        apply_workspace_edit(
-                            &mut self.editor,
-                            helix_lsp::OffsetEncoding::Utf8,
-                            &params.edit,
-                        );
+                        let offset_encoding =
+                            match self.editor.language_servers.get_by_id(server_id) {
+                                Some(language_server) => language_server.offset_encoding(),
+                                None => {
+                                    warn!("can't find language server with id `{}`", server_id);
+                                    return;
+                                }
+                            };
 
-                        Ok(json!(lsp::ApplyWorkspaceEditResponse {
-                            applied: true,
-                            failure_reason: None,
-                            failed_change: None,
-                        }))
+                        // Edits are expressed in the encoding negotiated with the server
+                        // that sent them, which may differ between servers.
+                        let response =
+                            apply_workspace_edit(&mut self.editor, offset_encoding, &params.edit);
+
+                        if let Some(language_server) =
+                            self.editor.language_servers.get_by_id(server_id)
+                        {
+                            tokio::spawn(language_server.reply_apply_edit(id, response));
+                        }
+                        return;
                     }
                     Ok(MethodCall::WorkspaceFolders) => {
                         let language_server =
                             self.editor.language_servers.get_by_id(server_id).unwrap();
 
-                        Ok(json!(language_server.workspace_folders()))
+                        Ok(json!(language_server.workspace_folders_response()))
+                    }
+                    Ok(MethodCall::RegisterCapability(params)) => {
+                        if let Some(language_server) =
+                            self.editor.language_servers.get_by_id(server_id)
+                        {
+                            for registration in params.registrations {
+                                language_server.register_capability(registration);
+                            }
+                        }
+
+                        Ok(serde_json::Value::Null)
+                    }
+                    Ok(MethodCall::UnregisterCapability(params)) => {
+                        if let Some(language_server) =
+                            self.editor.language_servers.get_by_id(server_id)
+                        {
+                            for unregistration in &params.unregisterations {
+                                language_server.unregister_capability(unregistration);
+                            }
+                        }
+
+                        Ok(serde_json::Value::Null)
                     }
                     Ok(MethodCall::WorkspaceConfiguration(params)) => {
                         let result: Vec<_> = params
@@ -285,6 +285,8 @@ pub fn doc(&self) -> &str {
         file_picker_in_current_buffer_directory, "Open file picker at current buffers's directory",
         file_picker_in_current_directory, "Open file picker at current working directory",
         code_action, "Perform code action",
+        organize_imports, "Organize imports",
+        fix_all, "Fix all auto-fixable problems",
         buffer_picker, "Open buffer picker",
         jumplist_picker, "Open jumplist picker",
         symbol_picker, "Open symbol picker",
@@ -3171,7 +3173,8 @@ fn language_server_completion(cx: &mut Context, ch: char) {
     }
 
     fn signature_help(cx: &mut Context, ch: char) {
-        use helix_lsp::lsp;
+        use helix_lsp::util::signature_help_context;
+
         // if ch matches signature_help char, trigger
         let doc = doc_mut!(cx.editor);
         // The language_server!() macro is not used here since it will
@@ -3182,29 +3185,61 @@ fn signature_help(cx: &mut Context, ch: char) {
             None => return,
         };
 
-        let capabilities = language_server.capabilities();
+        // lsp doesn't tell us when to close the signature help, so we request
+        // the help information again after common close triggers which should
+        // return None, which in turn closes the popup.
+        const CLOSE_TRIGGERS: &[char] = &[')', ';', '.'];
 
-        if let lsp::ServerCapabilities {
-            signature_help_provider:
-                Some(lsp::SignatureHelpOptions {
-                    trigger_characters: Some(triggers),
-                    // TODO: retrigger_characters
-                    ..
-                }),
-            ..
-        } = capabilities
-        {
-            // TODO: what if trigger is multiple chars long
-            let is_trigger = triggers.iter().any(|trigger| trigger.contains(ch));
-            // lsp doesn't tell us when to close the signature help, so we request
-            // the help information again after common close triggers which should
-            // return None, which in turn closes the popup.
-            let close_triggers = &[')', ';', '.'];
-
-            if is_trigger || close_triggers.contains(&ch) {
-                super::signature_help_impl(cx, SignatureHelpInvoked::Automatic);
-            }
+        // TODO: what if trigger is multiple chars long
+        let may_trigger = language_server
+            .signature_help_trigger_characters()
+            .iter()
+            .chain(language_server.signature_help_retrigger_characters())
+            .any(|trigger| trigger.contains(ch));
+        if !may_trigger && !CLOSE_TRIGGERS.contains(&ch) {
+            return;
         }
+
+        // Whether `ch` updates the shown signature help or opens a new one depends on the
+        // popup, which is only reachable through the compositor.
+        let callback = cx.callback.take();
+        cx.callback = Some(Box::new(
+            move |compositor: &mut Compositor, cx: &mut compositor::Context| {
+                if let Some(callback) = callback {
+                    callback(compositor, cx);
+                }
+
+                let active_signature_help = ui::lsp::SignatureHelp::visible_popup(compositor)
+                    .and_then(|popup| popup.contents().signature_help().cloned());
+                let language_server = match doc!(cx.editor).language_server() {
+                    Some(language_server) => language_server,
+                    None => return,
+                };
+                let context = signature_help_context(
+                    ch,
+                    language_server.signature_help_trigger_characters(),
+                    language_server.signature_help_retrigger_characters(),
+                    active_signature_help,
+                );
+                if context.is_none() && !CLOSE_TRIGGERS.contains(&ch) {
+                    return;
+                }
+
+                let mut cx = Context {
+                    register: None,
+                    count: None,
+                    editor: cx.editor,
+                    callback: None,
+                    on_next_key_callback: None,
+                    jobs: cx.jobs,
+                };
+                super::signature_help_impl_with_context(
+                    &mut cx,
+                    SignatureHelpInvoked::Automatic,
+                    context,
+                );
+            },
+        ));
     }
 
     // The default insert hook: simply insert the character
@@ -3978,8 +4013,6 @@ fn unindent(cx: &mut Context) {
 }
 
 fn format_selections(cx: &mut Context) {
-    use helix_lsp::{lsp, util::range_to_lsp_range};
-
     let (view, doc) = current!(cx.editor);
 
     // via lsp if available
@@ -3990,13 +4023,9 @@ fn format_selections(cx: &mut Context) {
         None => return,
     };
 
-    let ranges: Vec<lsp::Range> = doc
-        .selection(view.id)
-        .iter()
-        .map(|range| range_to_lsp_range(doc.text(), *range, language_server.offset_encoding()))
-        .collect();
+    let selection = doc.selection(view.id);
 
-    if ranges.len() != 1 {
+    if selection.len() != 1 {
         cx.editor
             .set_error("format_selections only supports a single selection for now");
         return;
@@ -4005,13 +4034,13 @@ fn format_selections(cx: &mut Context) {
     // TODO: handle fails
     // TODO: concurrent map over all ranges
 
-    let range = ranges[0];
+    let range = selection.primary();
 
-    let request = match language_server.text_document_range_formatting(
-        doc.identifier(),
+    let request = match language_server.range_formatting(
+        doc.identifier().uri,
+        doc.text(),
         range,
-        lsp::FormattingOptions::default(),
-        None,
+        doc.formatting_options(),
     ) {
         Some(future) => future,
         None => {
@@ -4155,7 +4184,7 @@ fn remove_primary_selection(cx: &mut Context) {
 }
 
 pub fn completion(cx: &mut Context) {
-    use helix_lsp::{lsp, util::pos_to_lsp_pos};
+    use helix_lsp::util::{pos_to_lsp_pos, CompletionItems};
 
     let (view, doc) = current!(cx.editor);
 
@@ -4207,6 +4236,10 @@ pub fn completion(cx: &mut Context) {
 
     let trigger_doc = doc.id();
     let trigger_view = view.id;
+    let max_completion_items = doc
+        .language_config()
+        .and_then(|config| config.language_server.as_ref())
+        .and_then(|config| config.max_completion_items);
 
     // FIXME: The commands Context can only have a single callback
     // which means it gets overwritten when executing keybindings
@@ -4224,7 +4257,7 @@ pub fn completion(cx: &mut Context) {
 
     cx.callback(
         future,
-        move |editor, compositor, response: Option<lsp::CompletionResponse>| {
+        move |editor, compositor, response: Option<CompletionItems>| {
             let (view, doc) = current_ref!(editor);
             // check if the completion request is stale.
             //
@@ -4235,15 +4268,15 @@ pub fn completion(cx: &mut Context) {
                 return;
             }
 
-            let items = match response {
-                Some(lsp::CompletionResponse::Array(items)) => items,
-                // TODO: do something with is_incomplete
-                Some(lsp::CompletionResponse::List(lsp::CompletionList {
-                    is_incomplete: _is_incomplete,
-                    items,
-                })) => items,
-                None => Vec::new(),
-            };
+            // TODO: do something with is_incomplete
+            let items = response
+                .map(|mut response| {
+                    if let Some(max) = max_completion_items {
+                        response.truncate(max);
+                    }
+                    response.items
+                })
+                .unwrap_or_default();
 
             if items.is_empty() {
                 // editor.set_error("No completion available");
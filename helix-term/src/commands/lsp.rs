@@ -5,7 +5,7 @@
         self, CodeAction, CodeActionOrCommand, CodeActionTriggerKind, DiagnosticSeverity,
         NumberOrString,
     },
-    util::{diagnostic_to_lsp_diagnostic, lsp_range_to_range, range_to_lsp_range},
+    util::{self, diagnostic_to_lsp_diagnostic, lsp_range_to_range, range_to_lsp_range},
     OffsetEncoding,
 };
 use tui::{
@@ -16,10 +16,15 @@
 use super::{align_view, push_jump, Align, Context, Editor, Open};
 
 use helix_core::{path, Selection};
-use helix_view::{document::Mode, editor::Action, theme::Style};
+use helix_view::{
+    document::Mode,
+    editor::Action,
+    theme::{Modifier, Style},
+};
 
 use crate::{
     compositor::{self, Compositor},
+    job,
     ui::{
         self, lsp::SignatureHelp, overlay::overlayed, DynamicPicker, FileLocation, FilePicker,
         Popup, PromptEvent,
@@ -27,7 +32,12 @@
 };
 
 use std::{
-    borrow::Cow, cmp::Ordering, collections::BTreeMap, fmt::Write, path::PathBuf, sync::Arc,
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::PathBuf,
+    sync::Arc,
 };
 
 /// Gets the language server that is attached to a document, and
@@ -343,7 +353,7 @@ fn nested_to_flat(
     let current_url = doc.url();
     let offset_encoding = language_server.offset_encoding();
 
-    let future = match language_server.document_symbols(doc.identifier()) {
+    let future = match language_server.text_document_document_symbol(doc.identifier()) {
         Some(future) => future,
         None => {
             cx.editor
@@ -382,7 +392,7 @@ pub fn workspace_symbol_picker(cx: &mut Context) {
     let current_url = doc.url();
     let language_server = language_server!(cx.editor, doc);
     let offset_encoding = language_server.offset_encoding();
-    let future = match language_server.workspace_symbols("".to_string()) {
+    let future = match language_server.workspace_symbols("".to_string(), None) {
         Some(future) => future,
         None => {
             cx.editor
@@ -391,48 +401,53 @@ pub fn workspace_symbol_picker(cx: &mut Context) {
         }
     };
 
-    cx.callback(
-        future,
-        move |_editor, compositor, response: Option<Vec<lsp::SymbolInformation>>| {
-            let symbols = response.unwrap_or_default();
-            let picker = sym_picker(symbols, current_url, offset_encoding);
-            let get_symbols = |query: String, editor: &mut Editor| {
-                let doc = doc!(editor);
-                let language_server = match doc.language_server() {
-                    Some(s) => s,
-                    None => {
-                        // This should not generally happen since the picker will not
-                        // even open in the first place if there is no server.
-                        return async move { Err(anyhow::anyhow!("LSP not active")) }.boxed();
-                    }
-                };
-                let symbol_request = match language_server.workspace_symbols(query) {
-                    Some(future) => future,
-                    None => {
-                        // This should also not happen since the language server must have
-                        // supported workspace symbols before to reach this block.
-                        return async move {
-                            Err(anyhow::anyhow!(
-                                "Language server does not support workspace symbols"
-                            ))
+    let callback = async move {
+        let symbols = future
+            .await?
+            .map(util::workspace_symbols_to_flat)
+            .unwrap_or_default();
+        let call: job::Callback = job::Callback::EditorCompositor(Box::new(
+            move |_editor: &mut Editor, compositor: &mut Compositor| {
+                let picker = sym_picker(symbols, current_url, offset_encoding);
+                let get_symbols = |query: String, editor: &mut Editor| {
+                    let doc = doc!(editor);
+                    let language_server = match doc.language_server() {
+                        Some(s) => s,
+                        None => {
+                            // This should not generally happen since the picker will not
+                            // even open in the first place if there is no server.
+                            return async move { Err(anyhow::anyhow!("LSP not active")) }.boxed();
                         }
-                        .boxed();
-                    }
-                };
-
-                let future = async move {
-                    let json = symbol_request.await?;
-                    let response: Option<Vec<lsp::SymbolInformation>> =
-                        serde_json::from_value(json)?;
+                    };
+                    let symbol_request = match language_server.workspace_symbols(query, None) {
+                        Some(future) => future,
+                        None => {
+                            // This should also not happen since the language server must have
+                            // supported workspace symbols before to reach this block.
+                            return async move {
+                                Err(anyhow::anyhow!(
+                                    "Language server does not support workspace symbols"
+                                ))
+                            }
+                            .boxed();
+                        }
+                    };
 
-                    Ok(response.unwrap_or_default())
+                    let future = async move {
+                        let response = symbol_request.await?;
+                        Ok(response
+                            .map(util::workspace_symbols_to_flat)
+                            .unwrap_or_default())
+                    };
+                    future.boxed()
                 };
-                future.boxed()
-            };
-            let dyn_picker = DynamicPicker::new(picker, Box::new(get_symbols));
-            compositor.push(Box::new(overlayed(dyn_picker)))
-        },
-    )
+                let dyn_picker = DynamicPicker::new(picker, Box::new(get_symbols));
+                compositor.push(Box::new(overlayed(dyn_picker)))
+            },
+        ));
+        Ok(call)
+    };
+    cx.jobs.callback(callback);
 }
 
 pub fn diagnostics_picker(cx: &mut Context) {
@@ -440,12 +455,7 @@ pub fn diagnostics_picker(cx: &mut Context) {
     let language_server = language_server!(cx.editor, doc);
     if let Some(current_url) = doc.url() {
         let offset_encoding = language_server.offset_encoding();
-        let diagnostics = cx
-            .editor
-            .diagnostics
-            .get(&current_url)
-            .cloned()
-            .unwrap_or_default();
+        let diagnostics = cx.editor.diagnostics.get(&current_url).cloned().collect();
         let picker = diag_picker(
             cx,
             [(current_url.clone(), diagnostics)].into(),
@@ -462,7 +472,13 @@ pub fn workspace_diagnostics_picker(cx: &mut Context) {
     let language_server = language_server!(cx.editor, doc);
     let current_url = doc.url();
     let offset_encoding = language_server.offset_encoding();
-    let diagnostics = cx.editor.diagnostics.clone();
+    let mut diagnostics: BTreeMap<lsp::Url, Vec<lsp::Diagnostic>> = BTreeMap::new();
+    for (url, diagnostic) in cx.editor.diagnostics.iter() {
+        diagnostics
+            .entry(url.clone())
+            .or_default()
+            .push(diagnostic.clone());
+    }
     let picker = diag_picker(
         cx,
         diagnostics,
@@ -477,6 +493,15 @@ impl ui::menu::Item for lsp::CodeActionOrCommand {
     type Data = ();
     fn format(&self, _data: &Self::Data) -> Row {
         match self {
+            lsp::CodeActionOrCommand::CodeAction(lsp::CodeAction {
+                title,
+                disabled: Some(disabled),
+                ..
+            }) => Span::styled(
+                format!("{} ({})", title, disabled.reason),
+                Style::default().add_modifier(Modifier::DIM),
+            )
+            .into(),
             lsp::CodeActionOrCommand::CodeAction(action) => action.title.as_str().into(),
             lsp::CodeActionOrCommand::Command(command) => command.title.as_str().into(),
         }
@@ -579,21 +604,12 @@ pub fn code_action(cx: &mut Context) {
     cx.callback(
         future,
         move |editor, compositor, response: Option<lsp::CodeActionResponse>| {
-            let mut actions = match response {
-                Some(a) => a,
+            let (mut actions, disabled) = match response {
+                Some(a) => util::partition_code_actions(a),
                 None => return,
             };
 
-            // remove disabled code actions
-            actions.retain(|action| {
-                matches!(
-                    action,
-                    CodeActionOrCommand::Command(_)
-                        | CodeActionOrCommand::CodeAction(CodeAction { disabled: None, .. })
-                )
-            });
-
-            if actions.is_empty() {
+            if actions.is_empty() && disabled.is_empty() {
                 editor.set_status("No code actions available");
                 return;
             }
@@ -632,8 +648,10 @@ pub fn code_action(cx: &mut Context) {
                     .cmp(&action_prefered(action2))
                     .reverse()
             });
+            // disabled code actions are shown last, along with why they can't be applied
+            actions.extend(disabled.into_iter().map(CodeActionOrCommand::CodeAction));
 
-            let mut picker = ui::Menu::new(actions, (), move |editor, code_action, event| {
+            let mut picker = ui::Menu::new(actions, (), move |cx, code_action, event| {
                 if event != PromptEvent::Validate {
                     return;
                 }
@@ -644,20 +662,41 @@ pub fn code_action(cx: &mut Context) {
                 match code_action {
                     lsp::CodeActionOrCommand::Command(command) => {
                         log::debug!("code action command: {:?}", command);
-                        execute_lsp_command(editor, command.clone());
+                        execute_lsp_command(cx.editor, command.clone());
+                    }
+                    lsp::CodeActionOrCommand::CodeAction(CodeAction {
+                        disabled: Some(disabled),
+                        ..
+                    }) => {
+                        cx.editor.set_error(disabled.reason.clone());
                     }
                     lsp::CodeActionOrCommand::CodeAction(code_action) => {
                         log::debug!("code action: {:?}", code_action);
-                        if let Some(ref workspace_edit) = code_action.edit {
-                            log::debug!("edit: {:?}", workspace_edit);
-                            apply_workspace_edit(editor, offset_encoding, workspace_edit);
-                        }
-
-                        // if code action provides both edit and command first the edit
-                        // should be applied and then the command
-                        if let Some(command) = &code_action.command {
-                            execute_lsp_command(editor, command.clone());
-                        }
+                        // servers may leave computing the edit to a resolve request
+                        let language_server = match doc!(cx.editor).language_server() {
+                            Some(language_server) if code_action.edit.is_none() => language_server,
+                            _ => {
+                                apply_code_action(cx.editor, code_action, offset_encoding);
+                                return;
+                            }
+                        };
+
+                        let future = language_server.resolve_code_action(code_action.clone());
+                        let code_action = code_action.clone();
+                        cx.jobs.callback(async move {
+                            let code_action = match future.await {
+                                Ok(code_action) => code_action,
+                                Err(err) => {
+                                    log::error!("Failed to resolve code action: {}", err);
+                                    code_action
+                                }
+                            };
+                            let call: job::Callback =
+                                job::Callback::Editor(Box::new(move |editor: &mut Editor| {
+                                    apply_code_action(editor, &code_action, offset_encoding);
+                                }));
+                            Ok(call)
+                        });
                     }
                 }
             });
@@ -669,6 +708,65 @@ pub fn code_action(cx: &mut Context) {
     )
 }
 
+/// Applies the edit of `code_action` and then runs its command.
+fn apply_code_action(
+    editor: &mut Editor,
+    code_action: &CodeAction,
+    offset_encoding: OffsetEncoding,
+) {
+    for effect in util::code_action_effects(code_action) {
+        match effect {
+            util::CodeActionEffect::Edit(workspace_edit) => {
+                log::debug!("edit: {:?}", workspace_edit);
+                apply_workspace_edit(editor, offset_encoding, workspace_edit);
+            }
+            util::CodeActionEffect::Command(command) => {
+                execute_lsp_command(editor, command.clone());
+            }
+        }
+    }
+}
+
+pub fn organize_imports(cx: &mut Context) {
+    source_action(
+        cx,
+        lsp::CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+        "No imports to organize",
+    );
+}
+
+pub fn fix_all(cx: &mut Context) {
+    source_action(cx, lsp::CodeActionKind::SOURCE_FIX_ALL, "Nothing to fix");
+}
+
+/// Applies the source action of `kind` to the whole document, or shows `nothing_to_do` if
+/// the language server has no such action for it.
+fn source_action(cx: &mut Context, kind: lsp::CodeActionKind, nothing_to_do: &'static str) {
+    let doc = doc!(cx.editor);
+    let language_server = language_server!(cx.editor, doc);
+    let offset_encoding = language_server.offset_encoding();
+
+    let future = match language_server.source_action(doc.identifier(), doc.text(), kind) {
+        Some(future) => future,
+        None => {
+            cx.editor
+                .set_error("Language server does not support code actions");
+            return;
+        }
+    };
+
+    let callback = async move {
+        let code_action = future.await?;
+        let call: job::Callback =
+            job::Callback::Editor(Box::new(move |editor: &mut Editor| match code_action {
+                Some(code_action) => apply_code_action(editor, &code_action, offset_encoding),
+                None => editor.set_status(nothing_to_do),
+            }));
+        Ok(call)
+    };
+    cx.jobs.callback(callback);
+}
+
 impl ui::menu::Item for lsp::Command {
     type Data = ();
     fn format(&self, _data: &Self::Data) -> Row {
@@ -682,13 +780,14 @@ pub fn execute_lsp_command(editor: &mut Editor, cmd: lsp::Command) {
 
     // the command is executed on the server and communicated back
     // to the client asynchronously using workspace edits
-    let future = match language_server.command(cmd) {
-        Some(future) => future,
-        None => {
-            editor.set_error("Language server does not support executing commands");
-            return;
-        }
-    };
+    let future =
+        match language_server.execute_command(cmd.command, cmd.arguments.unwrap_or_default()) {
+            Some(future) => future,
+            None => {
+                editor.set_error("Language server does not support executing commands");
+                return;
+            }
+        };
 
     tokio::spawn(async move {
         let res = future.await;
@@ -756,123 +855,82 @@ pub fn apply_document_resource_op(op: &lsp::ResourceOp) -> std::io::Result<()> {
     }
 }
 
+/// Applies `workspace_edit` to the open documents, opening the documents it edits if
+/// needed. Failures are reported to the user, the returned response tells the server.
 pub fn apply_workspace_edit(
     editor: &mut Editor,
     offset_encoding: OffsetEncoding,
     workspace_edit: &lsp::WorkspaceEdit,
-) {
-    let mut apply_edits = |uri: &helix_lsp::Url, text_edits: Vec<lsp::TextEdit>| {
-        let path = match uri.to_file_path() {
-            Ok(path) => path,
-            Err(_) => {
-                let err = format!("unable to convert URI to filepath: {}", uri);
-                log::error!("{}", err);
-                editor.set_error(err);
-                return;
-            }
-        };
-
-        let current_view_id = view!(editor).id;
-        let doc_id = match editor.open(&path, Action::Load) {
-            Ok(doc_id) => doc_id,
-            Err(err) => {
-                let err = format!("failed to open document: {}: {}", uri, err);
-                log::error!("{}", err);
-                editor.set_error(err);
-                return;
-            }
-        };
-
-        let doc = doc_mut!(editor, &doc_id);
+) -> lsp::ApplyWorkspaceEditResponse {
+    log::debug!("workspace edit: {:?}", workspace_edit);
+    let versions: HashMap<_, _> = editor
+        .documents()
+        .filter_map(|doc| Some((doc.url()?, doc.version())))
+        .collect();
+
+    let mut apply_edits =
+        |uri: &helix_lsp::Url, text_edits: Vec<lsp::TextEdit>| -> Result<(), String> {
+            let path = uri
+                .to_file_path()
+                .map_err(|_| format!("unable to convert URI to filepath: {}", uri))?;
+
+            let current_view_id = view!(editor).id;
+            let doc_id = editor
+                .open(&path, Action::Load)
+                .map_err(|err| format!("failed to open document: {}: {}", uri, err))?;
+
+            let doc = doc_mut!(editor, &doc_id);
+
+            // Need to determine a view for apply/append_changes_to_history
+            let selections = doc.selections();
+            let view_id = if selections.contains_key(&current_view_id) {
+                // use current if possible
+                current_view_id
+            } else {
+                // Hack: we take the first available view_id
+                selections
+                    .keys()
+                    .next()
+                    .copied()
+                    .expect("No view_id available")
+            };
 
-        // Need to determine a view for apply/append_changes_to_history
-        let selections = doc.selections();
-        let view_id = if selections.contains_key(&current_view_id) {
-            // use current if possible
-            current_view_id
-        } else {
-            // Hack: we take the first available view_id
-            selections
-                .keys()
-                .next()
-                .copied()
-                .expect("No view_id available")
+            let transaction = helix_lsp::util::generate_transaction_from_edits(
+                doc.text(),
+                text_edits,
+                offset_encoding,
+            );
+            let view = view_mut!(editor, view_id);
+            doc.apply(&transaction, view.id);
+            doc.append_changes_to_history(view);
+            Ok(())
         };
 
-        let transaction = helix_lsp::util::generate_transaction_from_edits(
-            doc.text(),
-            text_edits,
-            offset_encoding,
-        );
-        let view = view_mut!(editor, view_id);
-        doc.apply(&transaction, view.id);
-        doc.append_changes_to_history(view);
-    };
+    let response = util::apply_workspace_edit(
+        workspace_edit,
+        |uri| versions.get(uri).copied(),
+        |change| match change {
+            util::WorkspaceEditChange::Edit { uri, edits, .. } => apply_edits(uri, edits),
+            util::WorkspaceEditChange::ResourceOp(op) => apply_document_resource_op(op)
+                .map_err(|err| format!("failed to apply resource operation: {}", err)),
+        },
+    );
 
-    if let Some(ref changes) = workspace_edit.changes {
-        log::debug!("workspace changes: {:?}", changes);
-        for (uri, text_edits) in changes {
-            let text_edits = text_edits.to_vec();
-            apply_edits(uri, text_edits)
-        }
-        return;
-        // Not sure if it works properly, it'll be safer to just panic here to avoid breaking some parts of code on which code actions will be used
-        // TODO: find some example that uses workspace changes, and test it
-        // for (url, edits) in changes.iter() {
-        //     let file_path = url.origin().ascii_serialization();
-        //     let file_path = std::path::PathBuf::from(file_path);
-        //     let file = std::fs::File::open(file_path).unwrap();
-        //     let mut text = Rope::from_reader(file).unwrap();
-        //     let transaction = edits_to_changes(&text, edits);
-        //     transaction.apply(&mut text);
-        // }
+    if let Some(reason) = &response.failure_reason {
+        let err = format!("failed to apply workspace edit: {}", reason);
+        log::error!("{}", err);
+        editor.set_error(err);
     }
+    response
+}
 
-    if let Some(ref document_changes) = workspace_edit.document_changes {
-        match document_changes {
-            lsp::DocumentChanges::Edits(document_edits) => {
-                for document_edit in document_edits {
-                    let edits = document_edit
-                        .edits
-                        .iter()
-                        .map(|edit| match edit {
-                            lsp::OneOf::Left(text_edit) => text_edit,
-                            lsp::OneOf::Right(annotated_text_edit) => {
-                                &annotated_text_edit.text_edit
-                            }
-                        })
-                        .cloned()
-                        .collect();
-                    apply_edits(&document_edit.text_document.uri, edits);
-                }
-            }
-            lsp::DocumentChanges::Operations(operations) => {
-                log::debug!("document changes - operations: {:?}", operations);
-                for operation in operations {
-                    match operation {
-                        lsp::DocumentChangeOperation::Op(op) => {
-                            apply_document_resource_op(op).unwrap();
-                        }
-
-                        lsp::DocumentChangeOperation::Edit(document_edit) => {
-                            let edits = document_edit
-                                .edits
-                                .iter()
-                                .map(|edit| match edit {
-                                    lsp::OneOf::Left(text_edit) => text_edit,
-                                    lsp::OneOf::Right(annotated_text_edit) => {
-                                        &annotated_text_edit.text_edit
-                                    }
-                                })
-                                .cloned()
-                                .collect();
-                            apply_edits(&document_edit.text_document.uri, edits);
-                        }
-                    }
-                }
-            }
-        }
-    }
+/// The locations to jump to for the `response` of a goto request. Location links are jumped
+/// to their target selection range, e.g. the name of a function rather than its whole body.
+fn goto_locations(response: Option<lsp::GotoDefinitionResponse>) -> Vec<lsp::Location> {
+    util::goto_response_to_targets(response)
+        .into_iter()
+        .map(|target| lsp::Location::new(target.location.uri, target.selection_range))
+        .collect()
 }
 
 fn goto_impl(
@@ -904,21 +962,6 @@ fn goto_impl(
     }
 }
 
-fn to_locations(definitions: Option<lsp::GotoDefinitionResponse>) -> Vec<lsp::Location> {
-    match definitions {
-        Some(lsp::GotoDefinitionResponse::Scalar(location)) => vec![location],
-        Some(lsp::GotoDefinitionResponse::Array(locations)) => locations,
-        Some(lsp::GotoDefinitionResponse::Link(locations)) => locations
-            .into_iter()
-            .map(|location_link| lsp::Location {
-                uri: location_link.target_uri,
-                range: location_link.target_range,
-            })
-            .collect(),
-        None => Vec::new(),
-    }
-}
-
 pub fn goto_declaration(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
     let language_server = language_server!(cx.editor, doc);
@@ -938,7 +981,7 @@ pub fn goto_declaration(cx: &mut Context) {
     cx.callback(
         future,
         move |editor, compositor, response: Option<lsp::GotoDefinitionResponse>| {
-            let items = to_locations(response);
+            let items = goto_locations(response);
             goto_impl(editor, compositor, items, offset_encoding);
         },
     );
@@ -963,7 +1006,7 @@ pub fn goto_definition(cx: &mut Context) {
     cx.callback(
         future,
         move |editor, compositor, response: Option<lsp::GotoDefinitionResponse>| {
-            let items = to_locations(response);
+            let items = goto_locations(response);
             goto_impl(editor, compositor, items, offset_encoding);
         },
     );
@@ -988,7 +1031,7 @@ pub fn goto_type_definition(cx: &mut Context) {
     cx.callback(
         future,
         move |editor, compositor, response: Option<lsp::GotoDefinitionResponse>| {
-            let items = to_locations(response);
+            let items = goto_locations(response);
             goto_impl(editor, compositor, items, offset_encoding);
         },
     );
@@ -1013,7 +1056,7 @@ pub fn goto_implementation(cx: &mut Context) {
     cx.callback(
         future,
         move |editor, compositor, response: Option<lsp::GotoDefinitionResponse>| {
-            let items = to_locations(response);
+            let items = goto_locations(response);
             goto_impl(editor, compositor, items, offset_encoding);
         },
     );
@@ -1026,7 +1069,7 @@ pub fn goto_reference(cx: &mut Context) {
 
     let pos = doc.position(view.id, offset_encoding);
 
-    let future = match language_server.goto_reference(doc.identifier(), pos, None) {
+    let future = match language_server.goto_reference(doc.identifier(), pos, true, None, None) {
         Some(future) => future,
         None => {
             cx.editor
@@ -1050,11 +1093,29 @@ pub enum SignatureHelpInvoked {
     Automatic,
 }
 
+/// Runs a command of the editor a language server asked for.
+pub fn execute_editor_command(cx: &mut Context, command: util::EditorCommand) {
+    match command {
+        util::EditorCommand::TriggerParameterHints => {
+            signature_help_impl(cx, SignatureHelpInvoked::Automatic)
+        }
+        util::EditorCommand::TriggerSuggest => super::completion(cx),
+    }
+}
+
 pub fn signature_help(cx: &mut Context) {
     signature_help_impl(cx, SignatureHelpInvoked::Manual)
 }
 
 pub fn signature_help_impl(cx: &mut Context, invoked: SignatureHelpInvoked) {
+    signature_help_impl_with_context(cx, invoked, None)
+}
+
+pub fn signature_help_impl_with_context(
+    cx: &mut Context,
+    invoked: SignatureHelpInvoked,
+    context: Option<lsp::SignatureHelpContext>,
+) {
     let (view, doc) = current!(cx.editor);
     let was_manually_invoked = invoked == SignatureHelpInvoked::Manual;
 
@@ -1074,16 +1135,17 @@ pub fn signature_help_impl(cx: &mut Context, invoked: SignatureHelpInvoked) {
 
     let pos = doc.position(view.id, offset_encoding);
 
-    let future = match language_server.text_document_signature_help(doc.identifier(), pos, None) {
-        Some(f) => f,
-        None => {
-            if was_manually_invoked {
-                cx.editor
-                    .set_error("Language server does not support signature-help");
+    let future =
+        match language_server.text_document_signature_help(doc.identifier(), pos, context, None) {
+            Some(f) => f,
+            None => {
+                if was_manually_invoked {
+                    cx.editor
+                        .set_error("Language server does not support signature-help");
+                }
+                return;
             }
-            return;
-        }
-    };
+        };
 
     cx.callback(
         future,
@@ -1163,6 +1225,7 @@ pub fn signature_help_impl(cx: &mut Context, invoked: SignatureHelpInvoked) {
                 }
             };
             contents.set_active_param_range(active_param_range());
+            contents.set_signature_help(response);
 
             let old_popup = compositor.find_id::<Popup<SignatureHelp>>(SignatureHelp::ID);
             let popup = Popup::new(SignatureHelp::ID, contents)
@@ -1182,6 +1245,7 @@ pub fn hover(cx: &mut Context) {
     // TODO: factor out a doc.position_identifier() that returns lsp::TextDocumentPositionIdentifier
 
     let pos = doc.position(view.id, offset_encoding);
+    let language = doc.language_name().map(ToOwned::to_owned);
 
     let future = match language_server.text_document_hover(doc.identifier(), pos, None) {
         Some(future) => future,
@@ -1198,28 +1262,11 @@ pub fn hover(cx: &mut Context) {
             if let Some(hover) = response {
                 // hover.contents / .range <- used for visualizing
 
-                fn marked_string_to_markdown(contents: lsp::MarkedString) -> String {
-                    match contents {
-                        lsp::MarkedString::String(contents) => contents,
-                        lsp::MarkedString::LanguageString(string) => {
-                            if string.language == "markdown" {
-                                string.value
-                            } else {
-                                format!("```{}\n{}\n```", string.language, string.value)
-                            }
-                        }
-                    }
-                }
-
-                let contents = match hover.contents {
-                    lsp::HoverContents::Scalar(contents) => marked_string_to_markdown(contents),
-                    lsp::HoverContents::Array(contents) => contents
-                        .into_iter()
-                        .map(marked_string_to_markdown)
-                        .collect::<Vec<_>>()
-                        .join("\n\n"),
-                    lsp::HoverContents::Markup(contents) => contents.value,
-                };
+                let contents = util::hover_contents_to_markdown(
+                    hover.contents,
+                    language.as_deref(),
+                    util::HtmlHandling::Strip,
+                );
 
                 // skip if contents empty
 
@@ -1286,17 +1333,20 @@ fn create_rename_prompt(editor: &Editor, prefill: String) -> Box<ui::Prompt> {
 
                 let pos = doc.position(view.id, offset_encoding);
 
-                let future =
-                    match language_server.rename_symbol(doc.identifier(), pos, input.to_string()) {
-                        Some(future) => future,
-                        None => {
-                            cx.editor
-                                .set_error("Language server does not support symbol renaming");
-                            return;
-                        }
-                    };
+                let url = doc.url().unwrap();
+                let future = match language_server.rename(url, pos, input.to_string()) {
+                    Some(future) => future,
+                    None => {
+                        cx.editor
+                            .set_error("Language server does not support symbol renaming");
+                        return;
+                    }
+                };
                 match block_on(future) {
-                    Ok(edits) => apply_workspace_edit(cx.editor, offset_encoding, &edits),
+                    Ok(Some(edits)) => {
+                        apply_workspace_edit(cx.editor, offset_encoding, &edits);
+                    }
+                    Ok(None) => cx.editor.set_error("No symbol to rename at the cursor"),
                     Err(err) => cx.editor.set_error(err.to_string()),
                 }
             },
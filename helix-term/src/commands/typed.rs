@@ -1329,13 +1329,12 @@ fn lsp_restart(
         return Ok(());
     }
 
-    let (_view, doc) = current!(cx.editor);
+    let (_view, doc) = current_ref!(cx.editor);
     let config = doc
         .language_config()
         .context("LSP not defined for the current document")?;
 
     let scope = config.scope.clone();
-    cx.editor.language_servers.restart(config, doc.path())?;
 
     // This collect is needed because refresh_language_server would need to re-borrow editor.
     let document_ids_to_refresh: Vec<DocumentId> = cx
@@ -1347,6 +1346,19 @@ fn lsp_restart(
         })
         .collect();
 
+    // The current document's root comes first so it is used as the server's root.
+    let doc_paths: Vec<_> = doc
+        .path()
+        .into_iter()
+        .chain(
+            document_ids_to_refresh
+                .iter()
+                .filter_map(|id| cx.editor.documents[id].path()),
+        )
+        .map(|path| path.as_path())
+        .collect();
+    cx.editor.language_servers.restart(config, &doc_paths)?;
+
     for document_id in document_ids_to_refresh {
         cx.editor.refresh_language_server(document_id);
     }
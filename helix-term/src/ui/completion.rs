@@ -1,5 +1,6 @@
 use crate::compositor::{Component, Context, Event, EventResult};
 use helix_view::{
+    clipboard::{ClipboardProvider, ClipboardType},
     document::SavePoint,
     editor::CompleteAction,
     theme::{Modifier, Style},
@@ -7,7 +8,7 @@
 };
 use tui::{buffer::Buffer as Surface, text::Span};
 
-use std::{borrow::Cow, sync::Arc};
+use std::{borrow::Cow, cell::Cell, rc::Rc, sync::Arc};
 
 use helix_core::{Change, Transaction};
 use helix_view::{graphics::Rect, Document, Editor};
@@ -47,38 +48,7 @@ fn format(&self, _data: &Self::Data) -> menu::Row {
                     Style::default()
                 },
             )),
-            menu::Cell::from(match self.kind {
-                Some(lsp::CompletionItemKind::TEXT) => "text",
-                Some(lsp::CompletionItemKind::METHOD) => "method",
-                Some(lsp::CompletionItemKind::FUNCTION) => "function",
-                Some(lsp::CompletionItemKind::CONSTRUCTOR) => "constructor",
-                Some(lsp::CompletionItemKind::FIELD) => "field",
-                Some(lsp::CompletionItemKind::VARIABLE) => "variable",
-                Some(lsp::CompletionItemKind::CLASS) => "class",
-                Some(lsp::CompletionItemKind::INTERFACE) => "interface",
-                Some(lsp::CompletionItemKind::MODULE) => "module",
-                Some(lsp::CompletionItemKind::PROPERTY) => "property",
-                Some(lsp::CompletionItemKind::UNIT) => "unit",
-                Some(lsp::CompletionItemKind::VALUE) => "value",
-                Some(lsp::CompletionItemKind::ENUM) => "enum",
-                Some(lsp::CompletionItemKind::KEYWORD) => "keyword",
-                Some(lsp::CompletionItemKind::SNIPPET) => "snippet",
-                Some(lsp::CompletionItemKind::COLOR) => "color",
-                Some(lsp::CompletionItemKind::FILE) => "file",
-                Some(lsp::CompletionItemKind::REFERENCE) => "reference",
-                Some(lsp::CompletionItemKind::FOLDER) => "folder",
-                Some(lsp::CompletionItemKind::ENUM_MEMBER) => "enum_member",
-                Some(lsp::CompletionItemKind::CONSTANT) => "constant",
-                Some(lsp::CompletionItemKind::STRUCT) => "struct",
-                Some(lsp::CompletionItemKind::EVENT) => "event",
-                Some(lsp::CompletionItemKind::OPERATOR) => "operator",
-                Some(lsp::CompletionItemKind::TYPE_PARAMETER) => "type_param",
-                Some(kind) => {
-                    log::error!("Received unknown completion item kind: {:?}", kind);
-                    ""
-                }
-                None => "",
-            }),
+            menu::Cell::from(self.kind.map_or("", util::completion_kind_label)),
             // self.detail.as_deref().unwrap_or("")
             // self.label_details
             //     .as_ref()
@@ -94,6 +64,8 @@ pub struct Completion {
     start_offset: usize,
     #[allow(dead_code)]
     trigger_offset: usize,
+    /// A command of the editor the accepted item asked for, run once the menu is closed.
+    editor_command: Rc<Cell<Option<util::EditorCommand>>>,
     // TODO: maintain a completioncontext with trigger kind & trigger char
 }
 
@@ -111,8 +83,12 @@ pub fn new(
         // Sort completion items according to their preselect status (given by the LSP server)
         items.sort_by_key(|item| !item.preselect.unwrap_or(false));
 
+        let editor_command = Rc::new(Cell::new(None));
+        let accepted_editor_command = editor_command.clone();
+
         // Then create the menu
-        let menu = Menu::new(items, (), move |editor: &mut Editor, item, event| {
+        let menu = Menu::new(items, (), move |cx: &mut Context, item, event| {
+            #[allow(clippy::too_many_arguments)]
             fn item_to_transaction(
                 doc: &Document,
                 view_id: ViewId,
@@ -121,31 +97,26 @@ fn item_to_transaction(
                 start_offset: usize,
                 trigger_offset: usize,
                 include_placeholder: bool,
+                replace: bool,
+                clipboard_provider: &dyn ClipboardProvider,
             ) -> Transaction {
                 use helix_lsp::snippet;
                 let selection = doc.selection(view_id);
 
                 let (start_offset, end_offset, new_text) = if let Some(edit) = &item.text_edit {
-                    let edit = match edit {
-                        lsp::CompletionTextEdit::Edit(edit) => edit.clone(),
-                        lsp::CompletionTextEdit::InsertAndReplace(item) => {
-                            // TODO: support using "insert" instead of "replace" via user config
-                            lsp::TextEdit::new(item.replace, item.new_text.clone())
-                        }
-                    };
+                    let edit = util::completion_text_edit(edit, replace);
                     let text = doc.text().slice(..);
                     let primary_cursor = selection.primary().cursor(text);
 
-                    let start_offset =
-                        match util::lsp_pos_to_pos(doc.text(), edit.range.start, offset_encoding) {
-                            Some(start) => start as i128 - primary_cursor as i128,
-                            None => return Transaction::new(doc.text()),
-                        };
-                    let end_offset =
-                        match util::lsp_pos_to_pos(doc.text(), edit.range.end, offset_encoding) {
-                            Some(end) => end as i128 - primary_cursor as i128,
-                            None => return Transaction::new(doc.text()),
-                        };
+                    let (start_offset, end_offset) = match util::completion_edit_offsets(
+                        doc.text(),
+                        edit.range,
+                        primary_cursor,
+                        offset_encoding,
+                    ) {
+                        Some(offsets) => offsets,
+                        None => return Transaction::new(doc.text()),
+                    };
 
                     (start_offset, end_offset, edit.new_text)
                 } else {
@@ -175,25 +146,34 @@ fn item_to_transaction(
                         Some(lsp::InsertTextFormat::SNIPPET)
                     )
                 {
-                    match snippet::parse(&new_text) {
-                        Ok(snippet) => util::generate_transaction_from_snippet(
-                            doc.text(),
-                            selection,
-                            start_offset,
-                            end_offset,
-                            snippet,
-                            doc.line_ending.as_str(),
-                            include_placeholder,
-                        ),
-                        Err(err) => {
-                            log::error!(
-                                "Failed to parse snippet: {:?}, remaining output: {}",
-                                &new_text,
-                                err
-                            );
-                            Transaction::new(doc.text())
-                        }
-                    }
+                    // a malformed snippet is inserted with its bad parts as literal text
+                    let snippet = snippet::Snippet::parse(&new_text).unwrap_or_else(|err| {
+                        log::warn!("Failed to parse snippet {:?}: {}", &new_text, err);
+                        snippet::Snippet::parse_lenient(&new_text)
+                    });
+                    let clipboard = || {
+                        clipboard_provider
+                            .get_contents(ClipboardType::Clipboard)
+                            .ok()
+                    };
+                    let ctx = snippet::SnippetContext {
+                        path: doc.path().map(|path| path.as_path()),
+                        // the line is set for every cursor
+                        line: 0,
+                        clipboard: &clipboard,
+                        now: chrono::Local::now().into(),
+                    };
+                    let (transaction, _tabstops) = util::generate_transaction_from_snippet(
+                        doc.text(),
+                        selection,
+                        start_offset,
+                        end_offset,
+                        snippet,
+                        doc.line_ending.as_str(),
+                        include_placeholder,
+                        &ctx,
+                    );
+                    transaction
                 } else {
                     util::generate_transaction_from_completion_edit(
                         doc.text(),
@@ -212,6 +192,8 @@ fn completion_changes(transaction: &Transaction, trigger_offset: usize) -> Vec<C
                     .collect()
             }
 
+            let editor = &mut *cx.editor;
+            let replace = editor.config().completion_replace;
             let (view, doc) = current!(editor);
 
             // if more text was entered, remove it
@@ -233,6 +215,8 @@ fn completion_changes(transaction: &Transaction, trigger_offset: usize) -> Vec<C
                         start_offset,
                         trigger_offset,
                         true,
+                        replace,
+                        editor.clipboard_provider.as_ref(),
                     );
 
                     // initialize a savepoint
@@ -255,6 +239,8 @@ fn completion_changes(transaction: &Transaction, trigger_offset: usize) -> Vec<C
                         start_offset,
                         trigger_offset,
                         false,
+                        replace,
+                        editor.clipboard_provider.as_ref(),
                     );
 
                     doc.apply(&transaction, view.id);
@@ -290,6 +276,21 @@ fn completion_changes(transaction: &Transaction, trigger_offset: usize) -> Vec<C
                             doc.apply(&transaction, view.id);
                         }
                     }
+
+                    // run the command of the item now that its edits are applied
+                    let item = resolved_item
+                        .as_ref()
+                        .filter(|item| item.command.is_some())
+                        .unwrap_or(item);
+                    match util::completion_command(item) {
+                        Some(util::CompletionCommand::Server(command)) => {
+                            commands::execute_lsp_command(editor, command.clone());
+                        }
+                        Some(util::CompletionCommand::Editor(command)) => {
+                            accepted_editor_command.set(Some(command));
+                        }
+                        None => (),
+                    }
                 }
             };
         });
@@ -300,6 +301,7 @@ fn completion_changes(transaction: &Transaction, trigger_offset: usize) -> Vec<C
             popup,
             start_offset,
             trigger_offset,
+            editor_command,
         };
 
         // need to recompute immediately in case start_offset != trigger_offset
@@ -308,6 +310,12 @@ fn completion_changes(transaction: &Transaction, trigger_offset: usize) -> Vec<C
         completion
     }
 
+    /// Takes the command of the editor the accepted item asked for, see
+    /// [`util::completion_command`].
+    pub fn take_editor_command(&self) -> Option<util::EditorCommand> {
+        self.editor_command.take()
+    }
+
     fn resolve_completion_item(
         doc: &Document,
         completion_item: lsp::CompletionItem,
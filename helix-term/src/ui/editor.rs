@@ -1251,6 +1251,7 @@ fn handle_event(
                         Mode::Insert => {
                             // let completion swallow the event if necessary
                             let mut consumed = false;
+                            let mut editor_command = None;
                             if let Some(completion) = &mut self.completion {
                                 // use a fake context here
                                 let mut cx = Context {
@@ -1265,11 +1266,16 @@ fn handle_event(
 
                                     if callback.is_some() {
                                         // assume close_fn
+                                        editor_command = completion.take_editor_command();
                                         self.clear_completion(cx.editor);
                                     }
                                 }
                             }
 
+                            if let Some(command) = editor_command {
+                                commands::execute_editor_command(&mut cx, command);
+                            }
+
                             // if completion didn't take the event, we pass it onto commands
                             if !consumed {
                                 if let Some(compl) = cx.editor.last_completion.take() {
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use helix_core::syntax;
+use helix_lsp::lsp;
 use helix_view::graphics::{Margin, Rect, Style};
 use tui::buffer::Buffer;
 use tui::widgets::{BorderType, Paragraph, Widget, Wrap};
@@ -16,6 +17,8 @@ pub struct SignatureHelp {
     signature_doc: Option<String>,
     /// Part of signature text
     active_param_range: Option<(usize, usize)>,
+    /// The response this popup was built from, sent back to the server on retrigger.
+    signature_help: Option<lsp::SignatureHelp>,
 
     language: String,
     config_loader: Arc<syntax::Loader>,
@@ -29,6 +32,7 @@ pub fn new(signature: String, language: String, config_loader: Arc<syntax::Loade
             signature,
             signature_doc: None,
             active_param_range: None,
+            signature_help: None,
             language,
             config_loader,
         }
@@ -42,6 +46,14 @@ pub fn set_active_param_range(&mut self, offset: Option<(usize, usize)>) {
         self.active_param_range = offset;
     }
 
+    pub fn set_signature_help(&mut self, signature_help: lsp::SignatureHelp) {
+        self.signature_help = Some(signature_help);
+    }
+
+    pub fn signature_help(&self) -> Option<&lsp::SignatureHelp> {
+        self.signature_help.as_ref()
+    }
+
     pub fn visible_popup(compositor: &mut Compositor) -> Option<&mut Popup<Self>> {
         compositor.find_id::<Popup<Self>>(Self::ID)
     }
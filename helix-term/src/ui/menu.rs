@@ -11,7 +11,7 @@
 use fuzzy_matcher::skim::SkimMatcherV2 as Matcher;
 use fuzzy_matcher::FuzzyMatcher;
 
-use helix_view::{graphics::Rect, Editor};
+use helix_view::graphics::Rect;
 use tui::layout::Constraint;
 
 pub trait Item {
@@ -43,7 +43,7 @@ fn format(&self, root_path: &Self::Data) -> Row {
     }
 }
 
-pub type MenuCallback<T> = Box<dyn Fn(&mut Editor, Option<&T>, MenuEvent)>;
+pub type MenuCallback<T> = Box<dyn Fn(&mut Context, Option<&T>, MenuEvent)>;
 
 pub struct Menu<T: Item> {
     options: Vec<T>,
@@ -73,7 +73,7 @@ impl<T: Item> Menu<T> {
     pub fn new(
         options: Vec<T>,
         editor_data: <T as Item>::Data,
-        callback_fn: impl Fn(&mut Editor, Option<&T>, MenuEvent) + 'static,
+        callback_fn: impl Fn(&mut Context, Option<&T>, MenuEvent) + 'static,
     ) -> Self {
         let matches = (0..options.len()).map(|i| (i, 0)).collect();
         Self {
@@ -250,24 +250,24 @@ fn handle_event(&mut self, event: &Event, cx: &mut Context) -> EventResult {
         match event {
             // esc or ctrl-c aborts the completion and closes the menu
             key!(Esc) | ctrl!('c') => {
-                (self.callback_fn)(cx.editor, self.selection(), MenuEvent::Abort);
+                (self.callback_fn)(cx, self.selection(), MenuEvent::Abort);
                 return EventResult::Consumed(close_fn);
             }
             // arrow up/ctrl-p/shift-tab prev completion choice (including updating the doc)
             shift!(Tab) | key!(Up) | ctrl!('p') => {
                 self.move_up();
-                (self.callback_fn)(cx.editor, self.selection(), MenuEvent::Update);
+                (self.callback_fn)(cx, self.selection(), MenuEvent::Update);
                 return EventResult::Consumed(None);
             }
             key!(Tab) | key!(Down) | ctrl!('n') => {
                 // arrow down/ctrl-n/tab advances completion choice (including updating the doc)
                 self.move_down();
-                (self.callback_fn)(cx.editor, self.selection(), MenuEvent::Update);
+                (self.callback_fn)(cx, self.selection(), MenuEvent::Update);
                 return EventResult::Consumed(None);
             }
             key!(Enter) => {
                 if let Some(selection) = self.selection() {
-                    (self.callback_fn)(cx.editor, Some(selection), MenuEvent::Validate);
+                    (self.callback_fn)(cx, Some(selection), MenuEvent::Validate);
                     return EventResult::Consumed(close_fn);
                 } else {
                     return EventResult::Ignored(close_fn);
@@ -278,7 +278,7 @@ fn handle_event(&mut self, event: &Event, cx: &mut Context) -> EventResult {
             //     modifiers: KeyModifiers::NONE,
             // } => {
             //     self.insert_char(c);
-            //     (self.callback_fn)(cx.editor, &self.line, MenuEvent::Update);
+            //     (self.callback_fn)(cx, &self.line, MenuEvent::Update);
             // }
 
             // / -> edit_filter?
@@ -263,9 +263,8 @@ fn render_workspace_diagnostics<F>(context: &mut RenderContext, write: F)
         context
             .editor
             .diagnostics
-            .values()
-            .flatten()
-            .fold((0, 0), |mut counts, diag| {
+            .iter()
+            .fold((0, 0), |mut counts, (_, diag)| {
                 match diag.severity {
                     Some(DiagnosticSeverity::WARNING) => counts.0 += 1,
                     Some(DiagnosticSeverity::ERROR) | None => counts.1 += 1,
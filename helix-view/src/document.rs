@@ -519,11 +519,7 @@ pub fn format(&self) -> Option<BoxFuture<'static, Result<Transaction, FormatterE
 
         let request = language_server.text_document_formatting(
             self.identifier(),
-            lsp::FormattingOptions {
-                tab_size: self.tab_width() as u32,
-                insert_spaces: matches!(self.indent_style, IndentStyle::Spaces(_)),
-                ..Default::default()
-            },
+            self.formatting_options(),
             None,
         )?;
 
@@ -541,6 +537,23 @@ pub fn format(&self) -> Option<BoxFuture<'static, Result<Transaction, FormatterE
         Some(fut.boxed())
     }
 
+    /// The options for formatting the document with its language server. The indentation
+    /// follows the document's and the whitespace at the end of lines and of the document is
+    /// kept as it is, like when saving the document.
+    pub fn formatting_options(&self) -> lsp::FormattingOptions {
+        let text = self.text.slice(..);
+        let ends_with_newline = text.len_chars() > 0 && text.char(text.len_chars() - 1) == '\n';
+
+        lsp::FormattingOptions {
+            tab_size: self.indent_width() as u32,
+            insert_spaces: matches!(self.indent_style, IndentStyle::Spaces(_)),
+            trim_trailing_whitespace: Some(false),
+            insert_final_newline: Some(ends_with_newline),
+            trim_final_newlines: Some(false),
+            ..Default::default()
+        }
+    }
+
     pub fn save<P: Into<PathBuf>>(
         &mut self,
         path: Option<P>,
@@ -1141,6 +1154,11 @@ pub fn language_server(&self) -> Option<&helix_lsp::Client> {
         server.is_initialized().then_some(server)
     }
 
+    /// The id of the language server, even if it isn't initialized yet.
+    pub fn language_server_id(&self) -> Option<usize> {
+        self.language_server.as_ref().map(|server| server.id())
+    }
+
     pub fn diff_handle(&self) -> Option<&DiffHandle> {
         self.diff_handle.as_ref()
     }
@@ -1539,6 +1557,37 @@ fn changeset_to_changes() {
         );
     }
 
+    #[test]
+    fn formatting_options_follow_indent() {
+        let mut doc = Document::from(
+            Rope::from("fn main() {\n  println!();\n}\n"),
+            None,
+            Arc::new(ArcSwap::new(Arc::new(Config::default()))),
+        );
+        doc.indent_style = IndentStyle::Spaces(2);
+        assert_eq!(
+            serde_json::to_value(doc.formatting_options()).unwrap(),
+            serde_json::json!({
+                "tabSize": 2,
+                "insertSpaces": true,
+                "trimTrailingWhitespace": false,
+                "insertFinalNewline": true,
+                "trimFinalNewlines": false,
+            })
+        );
+
+        let mut doc = Document::from(
+            Rope::from("fn main() {\n\tprintln!();\n}"),
+            None,
+            Arc::new(ArcSwap::new(Arc::new(Config::default()))),
+        );
+        doc.indent_style = IndentStyle::Tabs;
+        let options = doc.formatting_options();
+        assert_eq!(options.tab_size, 4);
+        assert!(!options.insert_spaces);
+        assert_eq!(options.insert_final_newline, Some(false));
+    }
+
     #[test]
     fn test_line_ending() {
         assert_eq!(
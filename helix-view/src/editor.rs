@@ -13,7 +13,7 @@
 use helix_vcs::DiffProviderRegistry;
 
 use futures_util::stream::select_all::SelectAll;
-use futures_util::{future, StreamExt};
+use futures_util::StreamExt;
 use helix_lsp::Call;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
@@ -251,6 +251,9 @@ pub struct Config {
     )]
     pub idle_timeout: Duration,
     pub completion_trigger_len: u8,
+    /// Whether completions replace the rest of the word after the cursor, if the language
+    /// server allows both. Defaults to false.
+    pub completion_replace: bool,
     /// Whether to display infoboxes. Defaults to true.
     pub auto_info: bool,
     pub file_picker: FilePickerConfig,
@@ -342,6 +345,13 @@ pub struct LspConfig {
     pub auto_signature_help: bool,
     /// Display docs under signature help popup
     pub display_signature_help_docs: bool,
+    /// Time window over which diagnostics published in quick succession for the same
+    /// document are coalesced, only the latest ones are applied. Zero disables coalescing.
+    #[serde(
+        serialize_with = "serialize_duration_millis",
+        deserialize_with = "deserialize_duration_millis"
+    )]
+    pub diagnostics_debounce: Duration,
 }
 
 impl Default for LspConfig {
@@ -351,6 +361,7 @@ fn default() -> Self {
             display_messages: false,
             auto_signature_help: true,
             display_signature_help_docs: true,
+            diagnostics_debounce: Duration::ZERO,
         }
     }
 }
@@ -723,6 +734,7 @@ fn default() -> Self {
             auto_save: false,
             idle_timeout: Duration::from_millis(400),
             completion_trigger_len: 2,
+            completion_replace: false,
             auto_info: true,
             file_picker: FilePickerConfig::default(),
             statusline: StatusLineConfig::default(),
@@ -797,7 +809,7 @@ pub struct Editor {
     pub macro_recording: Option<(char, Vec<KeyEvent>)>,
     pub macro_replaying: Vec<char>,
     pub language_servers: helix_lsp::Registry,
-    pub diagnostics: BTreeMap<lsp::Url, Vec<lsp::Diagnostic>>,
+    pub diagnostics: helix_lsp::DiagnosticStore,
     pub diff_providers: DiffProviderRegistry,
 
     pub debugger: Option<dap::Client>,
@@ -870,6 +882,7 @@ pub enum EditorEvent {
     ConfigEvent(ConfigEvent),
     LanguageServerMessage((usize, Call)),
     DebuggerEvent(dap::Payload),
+    DiagnosticsDebounced,
     IdleTimer,
 }
 
@@ -935,7 +948,7 @@ pub fn new(
             macro_replaying: Vec::new(),
             theme: theme_loader.default(),
             language_servers: helix_lsp::Registry::new(),
-            diagnostics: BTreeMap::new(),
+            diagnostics: helix_lsp::DiagnosticStore::new(),
             diff_providers: DiffProviderRegistry::default(),
             debugger: None,
             debugger_events: SelectAll::new(),
@@ -1110,7 +1123,7 @@ fn launch_language_server(&mut self, doc_id: DocumentId) -> Option<()> {
 
                 let language_id = doc.language_id().map(ToOwned::to_owned).unwrap_or_default();
 
-                // TODO: this now races with on_init code if the init happens too quickly
+                // delivered once the server is initialized if it's still starting
                 tokio::spawn(language_server.text_document_did_open(
                     doc_url,
                     doc.version(),
@@ -1529,25 +1542,20 @@ pub fn cursor(&self) -> (Option<Position>, CursorKind) {
     /// Closes language servers with timeout. The default timeout is 10000 ms, use
     /// `timeout` parameter to override this.
     pub async fn close_language_servers(
-        &self,
+        &mut self,
         timeout: Option<u64>,
     ) -> Result<(), tokio::time::error::Elapsed> {
-        tokio::time::timeout(
-            Duration::from_millis(timeout.unwrap_or(3000)),
-            future::join_all(
-                self.language_servers
-                    .iter_clients()
-                    .map(|client| client.force_shutdown()),
-            ),
-        )
-        .await
-        .map(|_| ())
+        let timeout = Duration::from_millis(timeout.unwrap_or(3000));
+        // servers get half of the time to answer `shutdown` and the other half to exit
+        tokio::time::timeout(timeout, self.language_servers.shutdown_all(timeout / 2)).await
     }
 
     pub async fn wait_event(&mut self) -> EditorEvent {
         // the loop only runs once or twice and would be better implemented with a recursion + const generic
         // however due to limitations with async functions that can not be implemented right now
         loop {
+            let diagnostics_flush = self.diagnostics.next_flush().map(Instant::from_std);
+
             tokio::select! {
                 biased;
 
@@ -1564,6 +1572,9 @@ pub async fn wait_event(&mut self) -> EditorEvent {
                 Some(event) = self.debugger_events.next() => {
                     return EditorEvent::DebuggerEvent(event)
                 }
+                _ = tokio::time::sleep_until(diagnostics_flush.unwrap_or_else(Instant::now)), if diagnostics_flush.is_some() => {
+                    return EditorEvent::DiagnosticsDebounced
+                }
 
                 _ = self.redraw_handle.0.notified() => {
                     if  !self.needs_redraw{